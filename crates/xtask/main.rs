@@ -19,6 +19,8 @@ enum Commands {
         args: Option<String>,
     },
     PreCommit {},
+    /// Fail if `sqlx-data.json` is out of date with the queries in the crate.
+    SqlxCheck {},
     ResetDb {
         ///Automatic confirmation. Without this option, you will be prompted before dropping your database.
         #[clap(short)]
@@ -89,6 +91,15 @@ fn main() -> Result<(), AnyErr> {
                 "failed to create api.oas3.json file"
             );
         }
+        Commands::SqlxCheck {} => {
+            assert!(
+                show_cmd(cargo_cmd().args(&["sqlx", "prepare", "--check", "--", "--lib",]))
+                    .status()
+                    .unwrap()
+                    .success(),
+                "sqlx-data.json is out of date, run `cargo xtask pre-commit`"
+            );
+        }
         Commands::ResetDb { yes: no_confirm } => {
             let mut sqlx_cmd = cargo_cmd();
             sqlx_cmd.args(&["sqlx", "database", "reset"]);
@@ -0,0 +1,14 @@
+CREATE TABLE user_roles (
+    user_id       UUID           NOT NULL,
+    role          TEXT           NOT NULL,
+
+    PRIMARY KEY(user_id, role),
+    -- roles are not worth keeping around in a `_deleted` table
+    CONSTRAINT fk_user_id  FOREIGN KEY(user_id) REFERENCES users(id) ON DELETE CASCADE
+) INHERITS (__common);
+
+CREATE TRIGGER maintain_updated_at_user_roles
+    BEFORE UPDATE
+    ON user_roles
+    FOR EACH ROW
+    EXECUTE PROCEDURE maintain_updated_at();
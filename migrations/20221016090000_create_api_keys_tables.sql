@@ -0,0 +1,18 @@
+CREATE TABLE api_keys (
+    -- hex encoded SHA-256 of the key, the key itself is only ever shown to its owner
+    key_hash      TEXT           NOT NULL,
+    user_id       UUID           NOT NULL,
+    scopes        TEXT[]         NOT NULL    DEFAULT '{}',
+    -- NULL for keys that don't expire
+    expires_at    TIMESTAMPTZ,
+
+    PRIMARY KEY(key_hash),
+    -- keys go with their user same as roles do
+    CONSTRAINT fk_user_id  FOREIGN KEY(user_id) REFERENCES users(id) ON DELETE CASCADE
+) INHERITS (__common);
+
+CREATE TRIGGER maintain_updated_at_api_keys
+    BEFORE UPDATE
+    ON api_keys
+    FOR EACH ROW
+    EXECUTE PROCEDURE maintain_updated_at();
@@ -0,0 +1,13 @@
+CREATE TABLE counters (
+    -- e.g. `user_views:<id>`, up to the code doing the counting
+    key           TEXT           NOT NULL,
+    count         BIGINT         NOT NULL    DEFAULT 0,
+
+    PRIMARY KEY(key)
+) INHERITS (__common);
+
+CREATE TRIGGER maintain_updated_at_counters
+    BEFORE UPDATE
+    ON counters
+    FOR EACH ROW
+    EXECUTE PROCEDURE maintain_updated_at();
@@ -0,0 +1,32 @@
+CREATE FUNCTION upsert_user(
+  new_id UUID,
+  new_username CITEXT,
+  new_email CITEXT,
+  new_pic_url TEXT,
+  new_pass_hash TEXT,
+  OUT le_user users,
+  OUT created BOOLEAN
+)
+AS $body$
+    BEGIN
+        -- `xmax` is only set on rows that were updated so it tells the two apart
+        INSERT INTO users (
+            id, username, email, pic_url
+        ) VALUES (
+            new_id, new_username, new_email, new_pic_url
+        )
+        ON CONFLICT (id) DO UPDATE SET
+            username = EXCLUDED.username,
+            email = EXCLUDED.email,
+            pic_url = EXCLUDED.pic_url
+        RETURNING xmax = 0 INTO created;
+        SELECT * INTO le_user FROM users WHERE id = new_id;
+        INSERT INTO credentials (
+            user_id, pass_hash
+        ) VALUES (
+            new_id, new_pass_hash
+        )
+        ON CONFLICT (user_id) DO UPDATE SET
+            pass_hash = EXCLUDED.pass_hash;
+    END;
+$body$ LANGUAGE PLpgSQL;
@@ -1,8 +1,8 @@
 use deps::*;
 
-use crate::{DocumentedEndpoint, EndpointWrapper, HttpEndpoint};
+use crate::{DocumentedEndpoint, EndpointWrapper};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Session {
     pub token: String,
     pub user_id: uuid::Uuid,
@@ -11,10 +11,31 @@ pub struct Session {
     pub updated_at: time::OffsetDateTime,
 }
 
+/// A row of the `api_keys` table. See [`api_key::ApiKey`] for the extractor.
+#[derive(Debug, Clone)]
+pub struct ApiKeyRecord {
+    pub key_hash: String,
+    pub user_id: uuid::Uuid,
+    pub scopes: Vec<String>,
+    /// `None` for keys that don't expire.
+    pub expires_at: Option<time::OffsetDateTime>,
+    pub created_at: time::OffsetDateTime,
+    pub updated_at: time::OffsetDateTime,
+}
+
+/// The user behind a valid bearer token. See [`crate::utils::RequestContext`].
+#[derive(Debug, Clone)]
+pub struct AuthUser {
+    pub id: uuid::Uuid,
+    pub token: std::sync::Arc<str>,
+}
+
 #[derive(Debug, Clone)]
 pub enum Resource {
     User { id: uuid::Uuid },
     Users,
+    Maintenance,
+    Config,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -24,9 +45,67 @@ pub enum Action {
     Delete,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Deserialize)]
+#[serde(crate = "serde", rename_all = "camelCase")]
 pub enum Role {
     SuperAdmin,
+    Basic,
+}
+
+impl std::str::FromStr for Role {
+    type Err = ();
+
+    /// Expects the same camelCase names used by the [`serde`] impls.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "superAdmin" => Ok(Self::SuperAdmin),
+            "basic" => Ok(Self::Basic),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Maps each [`Role`] to the operation ids it's allowed to access. The
+/// operation ids are the ones in the spec, see [`crate::operation_id`].
+///
+/// Loaded from a json file of the form: `{ "basic": ["GetUser", "ListUsers"] }`.
+#[derive(Debug, Default, Clone, serde::Deserialize)]
+#[serde(crate = "serde")]
+pub struct PermissionsMatrix(
+    pub std::collections::HashMap<Role, std::collections::HashSet<String>>,
+);
+
+impl PermissionsMatrix {
+    pub fn from_path(path: impl AsRef<std::path::Path>) -> eyre::Result<Self> {
+        let file = std::fs::File::open(path)?;
+        Ok(serde_json::from_reader(std::io::BufReader::new(file))?)
+    }
+
+    /// Returns true if any of the `roles` is allowed the `operation_id`.
+    pub fn allows(&self, roles: &[Role], operation_id: &str) -> bool {
+        roles.iter().any(|role| {
+            self.0
+                .get(role)
+                .map(|ops| ops.contains(operation_id))
+                .unwrap_or_default()
+        })
+    }
+}
+
+/// Errors with [`authorize::Error::Forbidden`] unless the user has the `role`.
+/// For endpoints whose access isn't left to the [`PermissionsMatrix`] since
+/// there might not be one.
+pub async fn require_role(
+    ctx: &crate::Context,
+    user_id: uuid::Uuid,
+    role: Role,
+) -> Result<(), authorize::Error> {
+    let roles = ctx.guard_db(ctx.db.user_roles(user_id)).await?;
+    if roles.contains(&role) {
+        Ok(())
+    } else {
+        Err(authorize::Error::Forbidden)
+    }
 }
 
 pub const TAG: crate::Tag = crate::Tag {
@@ -34,11 +113,15 @@ pub const TAG: crate::Tag = crate::Tag {
     desc: "The authentication and authorization services.",
 };
 
+pub mod api_key;
 pub mod authenticate;
 pub mod authorize;
+pub mod signature;
 
 pub fn router() -> axum::Router {
-    axum::Router::new().merge(EndpointWrapper::new(authenticate::Authenticate))
+    axum::Router::new()
+        .merge(EndpointWrapper::new(authenticate::Authenticate))
+        .layer(axum::middleware::from_fn(crate::method_not_allowed_body))
 }
 
 pub fn components(
@@ -48,11 +131,8 @@ pub fn components(
     builder
 }
 
-pub fn paths(builder: utoipa::openapi::PathsBuilder) -> utoipa::openapi::PathsBuilder {
-    builder.path(
-        crate::axum_path_str_to_openapi(authenticate::Authenticate::PATH),
-        authenticate::Authenticate::path_item(),
-    )
+pub fn paths(paths: crate::SpecPaths) -> crate::SpecPaths {
+    paths.endpoint::<authenticate::Authenticate>()
 }
 
 pub mod testing {
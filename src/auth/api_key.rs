@@ -0,0 +1,258 @@
+//! Keys for machine clients. They're sent in the [`HEADER`] and checked against
+//! the hashes in the `api_keys` table so a leaked table doesn't leak usable keys.
+
+use deps::*;
+
+use axum::{
+    extract::{FromRequest, RequestParts},
+    http::StatusCode,
+    response::{IntoResponse, Json, Response},
+};
+
+use crate::{DocumentedParameter, ParameterDoc, SharedContext};
+
+pub const HEADER: &str = "todo_apikey";
+
+/// Hex encoded SHA-256 as stored in [`crate::auth::ApiKeyRecord::key_hash`]. The
+/// keys are random enough that a slow hash like the one for passwords buys nothing.
+pub fn hash_key(key: &str) -> String {
+    use sha2::Digest;
+    sha2::Sha256::digest(key.as_bytes())
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// Extracts and validates the key in the [`HEADER`], rejecting the request with a
+/// `401` if it's missing, unknown or expired.
+#[derive(Debug, Clone)]
+pub struct ApiKey {
+    pub user_id: uuid::Uuid,
+    pub scopes: Vec<String>,
+}
+
+impl ApiKey {
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scopes.iter().any(|granted| granted == scope)
+    }
+
+    /// For handlers to check before acting on behalf of the key.
+    pub fn require_scope(&self, scope: &str) -> Result<(), Error> {
+        if self.has_scope(scope) {
+            Ok(())
+        } else {
+            Err(Error::MissingScope {
+                scope: scope.into(),
+            })
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error, serde::Serialize, utoipa::ToSchema)]
+#[serde(crate = "serde", tag = "error", rename_all = "camelCase")]
+pub enum Error {
+    /// Missing, unknown and expired keys are not told apart.
+    #[error("invalid api key")]
+    InvalidKey,
+    #[error("api key lacks scope: {scope:?}")]
+    MissingScope { scope: String },
+    #[error("database overloaded")]
+    Overloaded,
+    #[error("internal server error: {message:?}")]
+    Internal { message: String },
+}
+
+crate::impl_from_db_err!(Error);
+
+impl From<&Error> for StatusCode {
+    fn from(err: &Error) -> Self {
+        use Error::*;
+        match err {
+            InvalidKey => Self::UNAUTHORIZED,
+            MissingScope { .. } => Self::FORBIDDEN,
+            Overloaded => Self::SERVICE_UNAVAILABLE,
+            Internal { .. } => Self::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl crate::utils::ErrorCode for Error {
+    fn code(&self) -> &'static str {
+        use Error::*;
+        match self {
+            InvalidKey => "auth.invalid_api_key",
+            MissingScope { .. } => "auth.missing_scope",
+            Overloaded => "service.overloaded",
+            Internal { .. } => "service.internal",
+        }
+    }
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        (
+            Into::<StatusCode>::into(&self),
+            Json(crate::utils::WithCode::new(&self)),
+        )
+            .into_response()
+    }
+}
+
+/// Looks up the unexpired key.
+pub async fn validate(ctx: &crate::Context, key: &str) -> Result<ApiKey, Error> {
+    let record = ctx
+        .guard_db(ctx.db.api_key(&hash_key(key)))
+        .await
+        .map_err(|err| match err {
+            sqlx::Error::RowNotFound => Error::InvalidKey,
+            _ => err.into(),
+        })?;
+    if matches!(record.expires_at, Some(expires_at) if expires_at < ctx.clock.now()) {
+        tracing::debug!(user_id = %record.user_id, "expired api key used");
+        return Err(Error::InvalidKey);
+    }
+    Ok(ApiKey {
+        user_id: record.user_id,
+        scopes: record.scopes,
+    })
+}
+
+#[async_trait::async_trait]
+impl<B> FromRequest<B> for ApiKey
+where
+    B: Send,
+{
+    type Rejection = Error;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let ctx = req
+            .extensions()
+            .get::<SharedContext>()
+            .cloned()
+            .ok_or_else(|| {
+                tracing::error!("context missing from request extensions");
+                Error::Internal {
+                    message: "context missing".into(),
+                }
+            })?;
+        let key = req
+            .headers()
+            .get(HEADER)
+            .and_then(|val| val.to_str().ok())
+            .ok_or(Error::InvalidKey)?;
+        validate(&ctx, key).await
+    }
+}
+
+impl DocumentedParameter for ApiKey {
+    const HAS_API_KEY: bool = true;
+    fn to_openapi(_op_id: &str, _path: &str) -> Vec<ParameterDoc> {
+        vec![]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use deps::*;
+
+    use super::*;
+    use crate::auth::ApiKeyRecord;
+    use crate::user::testing::*;
+    use crate::utils::testing::*;
+
+    const VALID_KEY: &str = "0b4c6e2a-valid";
+    const EXPIRED_KEY: &str = "7f1d93c5-expired";
+
+    fn app() -> axum::Router {
+        let now = time::OffsetDateTime::now_utc();
+        let record = |key: &str, expires_at| ApiKeyRecord {
+            key_hash: hash_key(key),
+            user_id: USER_01_ID,
+            scopes: vec!["users:read".into()],
+            expires_at,
+            created_at: now,
+            updated_at: now,
+        };
+        let db = InMemoryDb::fixture()
+            .with_api_key(record(VALID_KEY, Some(now + time::Duration::days(1))))
+            .with_api_key(record(EXPIRED_KEY, Some(now - time::Duration::days(1))));
+        axum::Router::new()
+            .route(
+                "/keyed",
+                axum::routing::get(|key: ApiKey| async move {
+                    axum::Json(serde_json::json!({
+                        "userId": key.user_id,
+                        "scopes": key.scopes,
+                    }))
+                }),
+            )
+            .layer(axum::Extension(std::sync::Arc::new(crate::Context::mock(
+                db,
+            ))))
+    }
+
+    crate::table_tests! {
+        api_key tokio,
+        (key, status, expected),
+        {
+            setup_tracing_once();
+            let key: Option<&str> = key;
+            let mut req = http::Request::builder().method("GET").uri("/keyed");
+            if let Some(key) = key {
+                req = req.header(HEADER, key);
+            }
+            let resp = app()
+                .oneshot(req.body(Default::default()).unwrap_or_log())
+                .await
+                .unwrap_or_log();
+            assert_eq!(resp.status(), status);
+            let body = hyper::body::to_bytes(resp.into_body())
+                .await
+                .unwrap_or_log();
+            let body = serde_json::from_slice(&body).unwrap_or_log();
+            check_json(("expected", &expected), ("response", &body));
+        },
+    }
+
+    api_key! {
+        accepts_valid_key: (
+            Some(VALID_KEY),
+            StatusCode::OK,
+            serde_json::json!({
+                "userId": USER_01_ID,
+                "scopes": ["users:read"],
+            }),
+        ),
+        rejects_expired_key: (
+            Some(EXPIRED_KEY),
+            StatusCode::UNAUTHORIZED,
+            serde_json::json!({
+                "error": "invalidKey",
+                "code": "auth.invalid_api_key",
+            }),
+        ),
+        rejects_unknown_key: (
+            Some("not-a-key"),
+            StatusCode::UNAUTHORIZED,
+            serde_json::json!({ "error": "invalidKey" }),
+        ),
+        rejects_missing_key: (
+            None,
+            StatusCode::UNAUTHORIZED,
+            serde_json::json!({ "error": "invalidKey" }),
+        ),
+    }
+
+    #[test]
+    fn checks_scopes() {
+        let key = ApiKey {
+            user_id: USER_01_ID,
+            scopes: vec!["users:read".into()],
+        };
+        key.require_scope("users:read").unwrap();
+        assert!(matches!(
+            key.require_scope("users:write"),
+            Err(Error::MissingScope { .. })
+        ));
+    }
+}
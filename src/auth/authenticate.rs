@@ -21,8 +21,8 @@ pub struct Request {
 pub struct Response {
     pub user_id: uuid::Uuid,
     pub token: String,
-    #[schema(example = 1234567)]
-    #[serde(with = "time::serde::timestamp")]
+    #[schema(example = "2022-09-19T14:55:58Z")]
+    #[serde(with = "crate::utils::rfc3339")]
     pub expires_at: time::OffsetDateTime,
 }
 
@@ -31,10 +31,14 @@ pub struct Response {
 pub enum Error {
     #[error("credentials rejected")]
     CredentialsRejected,
+    #[error("database overloaded")]
+    Overloaded,
     #[error("internal server error: {message:?}")]
     Internal { message: String },
 }
 
+crate::impl_from_db_err!(Error);
+
 #[async_trait::async_trait]
 impl Endpoint for Authenticate {
     type Request = Request;
@@ -46,8 +50,11 @@ impl Endpoint for Authenticate {
         ctx: &crate::Context,
         request: Self::Request,
     ) -> Result<Self::Response, Self::Error> {
-        let result = sqlx::query!(
-            r#"
+        let pool = ctx.pool()?;
+        let result = ctx
+            .guard_db(
+                sqlx::query!(
+                    r#"
 SELECT user_id, pass_hash
 FROM credentials
 WHERE user_id = (
@@ -56,16 +63,15 @@ WHERE user_id = (
     WHERE email = $1::TEXT::CITEXT OR username = $1::TEXT::CITEXT
 )
         "#,
-            &request.identifier,
-        )
-        .fetch_one(&ctx.db_pool)
-        .await
-        .map_err(|err| match &err {
-            sqlx::Error::RowNotFound => Error::CredentialsRejected,
-            _ => Error::Internal {
-                message: format!("db error: {err}"),
-            },
-        })?;
+                    &request.identifier,
+                )
+                .fetch_one(&pool),
+            )
+            .await
+            .map_err(|err| match err {
+                sqlx::Error::RowNotFound => Error::CredentialsRejected,
+                _ => err.into(),
+            })?;
         let pass_valid =
             argon2::verify_encoded(&result.pass_hash[..], request.password.as_bytes()).unwrap();
         if !pass_valid {
@@ -73,11 +79,14 @@ WHERE user_id = (
         }
 
         let user_id = result.user_id;
-        let expires_at =
-            time::OffsetDateTime::now_utc().saturating_add(ctx.config.auth_token_lifespan);
-        let token = uuid::Uuid::new_v4().to_string();
-        sqlx::query!(
-            r#"
+        let expires_at = ctx
+            .clock
+            .now()
+            .saturating_add(ctx.config.auth_token_lifespan);
+        let token = ctx.ids.new_id().to_string();
+        ctx.guard_db(
+            sqlx::query!(
+                r#"
 INSERT INTO sessions (token, user_id, expires_at)
 VALUES (
     $1,
@@ -85,15 +94,13 @@ VALUES (
     $3
 )
         "#,
-            &token,
-            &user_id,
-            &expires_at
+                &token,
+                &user_id,
+                &expires_at
+            )
+            .execute(&pool),
         )
-        .execute(&ctx.db_pool)
-        .await
-        .map_err(|err| Error::Internal {
-            message: format!("db error: {err}"),
-        })?;
+        .await?;
 
         Ok(Response {
             user_id,
@@ -136,6 +143,7 @@ impl DocumentedEndpoint for Authenticate {
     fn errors() -> Vec<ErrorResponse<Self::Error>> {
         vec![
             ("Credentials rejected", Error::CredentialsRejected),
+            ("Overloaded", Error::Overloaded),
             (
                 "Internal server error",
                 Error::Internal {
@@ -151,11 +159,23 @@ impl From<&Error> for axum::http::StatusCode {
         use Error::*;
         match err {
             CredentialsRejected { .. } => Self::BAD_REQUEST,
+            Overloaded => Self::SERVICE_UNAVAILABLE,
             Internal { .. } => Self::INTERNAL_SERVER_ERROR,
         }
     }
 }
 
+impl crate::utils::ErrorCode for Error {
+    fn code(&self) -> &'static str {
+        use Error::*;
+        match self {
+            CredentialsRejected => "auth.credentials_rejected",
+            Overloaded => "service.overloaded",
+            Internal { .. } => "service.internal",
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use deps::*;
@@ -191,7 +211,7 @@ mod tests {
             let body = resp.into_body();
             let body = hyper::body::to_bytes(body).await.unwrap_or_log();
             let body: serde_json::Value = serde_json::from_slice(&body).unwrap_or_log();
-            assert!(body["expiresAt"].is_number());
+            assert!(body["expiresAt"].is_string());
             assert!(body["token"].is_string());
             assert_eq!(USER_01_ID.to_string(), body["userId"].as_str().unwrap());
 
@@ -240,7 +260,7 @@ mod tests {
             let body = resp.into_body();
             let body = hyper::body::to_bytes(body).await.unwrap_or_log();
             let body: serde_json::Value = serde_json::from_slice(&body).unwrap_or_log();
-            assert!(body["expiresAt"].is_number());
+            assert!(body["expiresAt"].is_string());
             assert!(body["token"].is_string());
             assert_eq!(USER_01_ID.to_string(), body["userId"].as_str().unwrap());
 
@@ -377,4 +397,64 @@ mod tests {
         }
         ctx.close().await;
     }
+
+    #[tokio::test]
+    async fn session_expiry_and_token_come_from_context() {
+        let mut ctx = TestContext::new(crate::function!()).await;
+        {
+            let now = time::OffsetDateTime::from_unix_timestamp(1_893_456_000).unwrap();
+            ctx.ctx_mut().clock = std::sync::Arc::new(FixedClock::new(now));
+            ctx.ctx_mut().ids = std::sync::Arc::new(SequentialIds::default());
+            let shared = ctx.ctx();
+            let resp = crate::Endpoint::handle(
+                &crate::auth::authenticate::Authenticate,
+                &shared,
+                crate::auth::authenticate::Request {
+                    identifier: USER_01_USERNAME.into(),
+                    password: "password".into(),
+                },
+            )
+            .await
+            .unwrap_or_log();
+            let expires_at = now + shared.config.auth_token_lifespan;
+            assert_eq!(resp.expires_at, expires_at);
+            assert_eq!(resp.token, uuid::Uuid::from_u128(1).to_string());
+
+            let stored: time::OffsetDateTime =
+                sqlx::query_scalar("SELECT expires_at FROM sessions WHERE token = $1")
+                    .bind(&resp.token)
+                    .fetch_one(&shared.db_pool)
+                    .await
+                    .unwrap_or_log();
+            assert_eq!(stored, expires_at);
+        }
+        ctx.close().await;
+    }
+
+    #[tokio::test]
+    async fn sessions_expire_by_context_clock() {
+        setup_tracing_once();
+        let clock = std::sync::Arc::new(FixedClock::new(time::OffsetDateTime::now_utc()));
+        let mut ctx = crate::Context::mock(InMemoryDb::fixture());
+        ctx.clock = clock.clone();
+        let authorize = |ctx: std::sync::Arc<crate::Context>| async move {
+            crate::Endpoint::handle(
+                &crate::auth::authorize::Authorize,
+                &ctx,
+                crate::auth::authorize::Request {
+                    auth_token: USER_01_SESSION.into(),
+                    resource: crate::auth::Resource::Users,
+                    action: crate::auth::Action::Read,
+                },
+            )
+            .await
+        };
+        let ctx = std::sync::Arc::new(ctx);
+        assert!(authorize(ctx.clone()).await.is_ok());
+        clock.advance(time::Duration::hours(2));
+        assert!(matches!(
+            authorize(ctx).await,
+            Err(crate::auth::authorize::Error::InvalidToken)
+        ));
+    }
 }
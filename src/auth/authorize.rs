@@ -22,10 +22,16 @@ pub enum Error {
     Unauthorized,
     #[error("invalid token")]
     InvalidToken,
+    #[error("forbidden")]
+    Forbidden,
+    #[error("database overloaded")]
+    Overloaded,
     #[error("internal server error: {message:?}")]
     Internal { message: String },
 }
 
+crate::impl_from_db_err!(Error);
+
 #[async_trait::async_trait]
 impl crate::Endpoint for Authorize {
     type Request = Request;
@@ -39,34 +45,52 @@ impl crate::Endpoint for Authorize {
         request: Self::Request,
     ) -> Result<Self::Response, Self::Error> {
         // TODO: roles support
-        // TODO: cache db access
-        let session = sqlx::query_as!(
-            super::Session,
-            r#"
-SELECT * 
-FROM sessions
-WHERE token = $1
-            "#,
-            &request.auth_token[..]
-        )
-        .fetch_one(&ctx.db_pool)
+        if let Some(id) = crate::utils::current_auth_user(&request.auth_token[..]) {
+            return Ok(id);
+        }
+        session_user(ctx, &request.auth_token[..]).await
+    }
+}
+
+/// Resolves the user behind an unexpired session `token`.
+pub async fn session_user(ctx: &crate::Context, token: &str) -> Result<uuid::Uuid, Error> {
+    // TODO: cache db access
+    let session = ctx
+        .guard_db(ctx.db.session(token))
         .await
         .map_err(|err| match err {
             sqlx::Error::RowNotFound => Error::InvalidToken,
-            _ => Error::Internal {
-                message: format!("{err}"),
-            },
+            _ => err.into(),
         })?;
-        if session.expires_at < time::OffsetDateTime::now_utc() {
-            return Err(Error::InvalidToken);
-        }
-        Ok(session.user_id)
+    if session.expires_at < ctx.clock.now() {
+        return Err(Error::InvalidToken);
+    }
+    Ok(session.user_id)
+}
+
+/// Check the [`crate::Config::permissions`] matrix for whether the user has
+/// a role that allows the operation. Always passes if no matrix is configured.
+#[tracing::instrument(skip(ctx))]
+pub async fn check_permissions(
+    ctx: &crate::Context,
+    user_id: uuid::Uuid,
+    operation_id: &str,
+) -> Result<(), Error> {
+    let matrix = match ctx.config.permissions.as_ref() {
+        Some(matrix) => matrix,
+        None => return Ok(()),
+    };
+    let roles = ctx.guard_db(ctx.db.user_roles(user_id)).await?;
+    if matrix.allows(&roles, operation_id) {
+        Ok(())
+    } else {
+        Err(Error::Forbidden)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    // use deps::*;
+    use deps::*;
 
     use crate::auth::*;
     use crate::utils::testing::*;
@@ -116,4 +140,89 @@ mod tests {
             }
         ),
     }
+
+    fn permissions_fixture_ctx() -> crate::SharedContext {
+        let db = InMemoryDb::fixture().with_role(USER_01_ID, Role::Basic);
+        let mut ctx = crate::Context::mock(db);
+        ctx.config.permissions = Some(PermissionsMatrix(
+            [(Role::Basic, ["GetUser".to_string()].into_iter().collect())]
+                .into_iter()
+                .collect(),
+        ));
+        std::sync::Arc::new(ctx)
+    }
+
+    crate::table_tests! {
+        permissions_matrix tokio,
+        (method, status),
+        {
+            let app = crate::user::router().layer(axum::Extension(permissions_fixture_ctx()));
+            let resp = app
+                .oneshot(
+                    http::Request::builder()
+                        .method(method)
+                        .uri(format!("/users/{USER_01_ID}"))
+                        .header(
+                            http::header::AUTHORIZATION,
+                            format!("Bearer {USER_01_SESSION}"),
+                        )
+                        .body(Default::default())
+                        .unwrap_or_log(),
+                )
+                .await
+                .unwrap_or_log();
+            assert_eq!(resp.status(), status);
+        },
+    }
+
+    permissions_matrix! {
+        allows_permitted_operation: ("GET", StatusCode::OK),
+        forbids_unpermitted_operation: ("DELETE", StatusCode::FORBIDDEN),
+    }
+
+    #[derive(Debug, Clone, Copy)]
+    struct Renamed;
+
+    #[async_trait::async_trait]
+    impl crate::AuthenticatedEndpoint for Renamed {
+        type Request = std::sync::Arc<str>;
+        type Response = ();
+        type Error = authorize::Error;
+
+        const OPERATION_ID: Option<&'static str> = Some("fetchOwnUser");
+
+        fn authorize_request(&self, auth_token: &Self::Request) -> authorize::Request {
+            authorize::Request {
+                auth_token: auth_token.clone(),
+                resource: Resource::User { id: USER_01_ID },
+                action: Action::Read,
+            }
+        }
+
+        async fn handle(
+            &self,
+            _ctx: &crate::Context,
+            _accessing_user: uuid::Uuid,
+            _request: Self::Request,
+        ) -> Result<Self::Response, Self::Error> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn permissions_use_the_custom_operation_id() {
+        setup_tracing_once();
+        for (permitted, allowed) in [("fetchOwnUser", true), ("Renamed", false)] {
+            let db = InMemoryDb::fixture().with_role(USER_01_ID, Role::Basic);
+            let mut ctx = crate::Context::mock(db);
+            ctx.config.permissions = Some(PermissionsMatrix(
+                [(Role::Basic, [permitted.to_string()].into_iter().collect())]
+                    .into_iter()
+                    .collect(),
+            ));
+            let result = Endpoint::handle(&Renamed, &ctx, USER_01_SESSION.into()).await;
+            assert_eq!(result.is_ok(), allowed, "{permitted}: {result:?}");
+        }
+        assert_eq!(crate::operation_id::<Renamed>(), "fetchOwnUser");
+    }
 }
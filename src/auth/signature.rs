@@ -0,0 +1,363 @@
+//! Request signing for integrations that can't rely on bearer tokens alone. If
+//! [`crate::Config::request_signing_key`] is set, [`verify_signature`] wants every
+//! request to carry an HMAC-SHA256 over it in the [`SIGNATURE`] header, along with
+//! the [`TIMESTAMP`] and [`NONCE`] that went into it. Requests too far from the
+//! present or with a nonce seen before are turned away so a captured request can't
+//! be played again.
+
+use deps::*;
+
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Json, Response},
+};
+use hmac::Mac;
+
+use crate::SharedContext;
+
+/// Hex encoded HMAC-SHA256 of the [`signed_message`].
+pub const SIGNATURE: &str = "x-signature";
+/// Unix seconds at which the request was signed.
+pub const TIMESTAMP: &str = "x-signature-timestamp";
+/// Unique per request, up to the client.
+pub const NONCE: &str = "x-signature-nonce";
+
+type HmacSha256 = hmac::Hmac<sha2::Sha256>;
+
+#[derive(Debug, thiserror::Error, serde::Serialize, utoipa::ToSchema)]
+#[serde(crate = "serde", tag = "error", rename_all = "camelCase")]
+pub enum Error {
+    #[error("missing signature header: {name:?}")]
+    MissingHeader { name: String },
+    /// Doesn't match or isn't hex.
+    #[error("invalid signature")]
+    InvalidSignature,
+    #[error("signature timestamp outside the window")]
+    StaleTimestamp,
+    #[error("signature nonce already used")]
+    ReplayedNonce,
+    #[error("error reading request body: {message:?}")]
+    BodyRead { message: String },
+    #[error("internal server error: {message:?}")]
+    Internal { message: String },
+}
+
+impl From<&Error> for StatusCode {
+    fn from(err: &Error) -> Self {
+        use Error::*;
+        match err {
+            MissingHeader { .. } | InvalidSignature | StaleTimestamp | ReplayedNonce => {
+                Self::UNAUTHORIZED
+            }
+            BodyRead { .. } => Self::BAD_REQUEST,
+            Internal { .. } => Self::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl crate::utils::ErrorCode for Error {
+    fn code(&self) -> &'static str {
+        use Error::*;
+        match self {
+            MissingHeader { .. } => "auth.missing_signature",
+            InvalidSignature => "auth.invalid_signature",
+            StaleTimestamp => "auth.stale_signature",
+            ReplayedNonce => "auth.replayed_nonce",
+            BodyRead { .. } => "request.body_read",
+            Internal { .. } => "service.internal",
+        }
+    }
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        (
+            Into::<StatusCode>::into(&self),
+            Json(crate::utils::WithCode::new(&self)),
+        )
+            .into_response()
+    }
+}
+
+/// What the [`SIGNATURE`] is computed over: the method, path and query, timestamp
+/// and nonce each on their own line followed by the raw body.
+pub fn signed_message(
+    method: &str,
+    path_and_query: &str,
+    timestamp: i64,
+    nonce: &str,
+    body: &[u8],
+) -> Vec<u8> {
+    let mut message = format!("{method}\n{path_and_query}\n{timestamp}\n{nonce}\n").into_bytes();
+    message.extend_from_slice(body);
+    message
+}
+
+/// The hex encoded [`SIGNATURE`] for the [`signed_message`], for clients and tests.
+pub fn sign(key: &str, message: &[u8]) -> String {
+    let mut mac = HmacSha256::new_from_slice(key.as_bytes()).expect("hmac takes keys of any size");
+    mac.update(message);
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 == 1 {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|ii| u8::from_str_radix(hex.get(ii..ii + 2)?, 16).ok())
+        .collect()
+}
+
+/// Middleware checking the signature of requests if
+/// [`crate::Config::request_signing_key`] is set. Expects the [`SharedContext`] in
+/// the request extensions.
+pub async fn verify_signature(
+    req: axum::http::Request<axum::body::Body>,
+    next: axum::middleware::Next<axum::body::Body>,
+) -> Response {
+    let ctx = match req.extensions().get::<SharedContext>() {
+        Some(ctx) => ctx.clone(),
+        None => return next.run(req).await,
+    };
+    let key = match &ctx.config.request_signing_key {
+        Some(key) if !crate::health::is_infra_path(req.uri().path()) => key.clone(),
+        _ => return next.run(req).await,
+    };
+    let (parts, body) = req.into_parts();
+    let body = match hyper::body::to_bytes(body).await {
+        Ok(body) => body,
+        Err(err) => {
+            return Error::BodyRead {
+                message: err.to_string(),
+            }
+            .into_response()
+        }
+    };
+    if let Err(err) = verify(&ctx, &key, &parts, &body).await {
+        tracing::debug!(%err, "rejected signed request");
+        return err.into_response();
+    }
+    next.run(axum::http::Request::from_parts(parts, body.into()))
+        .await
+}
+
+async fn verify(
+    ctx: &crate::Context,
+    key: &str,
+    parts: &axum::http::request::Parts,
+    body: &[u8],
+) -> Result<(), Error> {
+    let header = |name: &str| {
+        parts
+            .headers
+            .get(name)
+            .and_then(|val| val.to_str().ok())
+            .ok_or_else(|| Error::MissingHeader { name: name.into() })
+    };
+    let signature = decode_hex(header(SIGNATURE)?).ok_or(Error::InvalidSignature)?;
+    let timestamp: i64 = header(TIMESTAMP)?
+        .parse()
+        .map_err(|_| Error::StaleTimestamp)?;
+    let nonce = header(NONCE)?;
+
+    let path_and_query = parts
+        .uri
+        .path_and_query()
+        .map(|val| val.as_str())
+        .unwrap_or("/");
+    let mut mac = HmacSha256::new_from_slice(key.as_bytes()).expect("hmac takes keys of any size");
+    mac.update(&signed_message(
+        parts.method.as_str(),
+        path_and_query,
+        timestamp,
+        nonce,
+        body,
+    ));
+    // constant time
+    mac.verify_slice(&signature)
+        .map_err(|_| Error::InvalidSignature)?;
+
+    // only checked once the signature shows the values weren't tampered with
+    let window = ctx.config.signature_window;
+    let age = ctx.clock.now().unix_timestamp() - timestamp;
+    if age.unsigned_abs() > window.as_secs() {
+        return Err(Error::StaleTimestamp);
+    }
+    // requests past the window are stale anyway so the nonces needn't outlive it
+    let cache_key = format!("signature-nonce:{nonce}");
+    let fresh = ctx
+        .cache
+        .set_if_absent(&cache_key, &true, window * 2)
+        .await
+        .map_err(|err| Error::Internal {
+            message: err.to_string(),
+        })?;
+    if !fresh {
+        return Err(Error::ReplayedNonce);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use deps::*;
+
+    use super::*;
+    use crate::utils::testing::*;
+
+    const KEY: &str = "s3cr3t";
+
+    fn app() -> axum::Router {
+        let mut ctx = crate::Context::mock(InMemoryDb::fixture());
+        ctx.config.request_signing_key = Some(KEY.into());
+        axum::Router::new()
+            .route(
+                "/signed",
+                axum::routing::post(|body: String| async move { body }),
+            )
+            .route("/ready", axum::routing::get(|| async { "ready" }))
+            .route(
+                "/admin/maintenance",
+                axum::routing::put(|| async { "toggled" }),
+            )
+            .layer(axum::middleware::from_fn(verify_signature))
+            .layer(axum::Extension(std::sync::Arc::new(ctx)))
+    }
+
+    fn signed(timestamp: i64, nonce: &str) -> http::Request<axum::body::Body> {
+        let body = r#"{"amount":100}"#;
+        let message = signed_message("POST", "/signed?dry=1", timestamp, nonce, body.as_bytes());
+        http::Request::builder()
+            .method("POST")
+            .uri("/signed?dry=1")
+            .header(SIGNATURE, sign(KEY, &message))
+            .header(TIMESTAMP, timestamp.to_string())
+            .header(NONCE, nonce)
+            .body(body.into())
+            .unwrap_or_log()
+    }
+
+    async fn error_of(resp: axum::response::Response) -> serde_json::Value {
+        let body = hyper::body::to_bytes(resp.into_body())
+            .await
+            .unwrap_or_log();
+        serde_json::from_slice(&body).unwrap_or_log()
+    }
+
+    #[tokio::test]
+    async fn accepts_valid_signature() {
+        setup_tracing_once();
+        let now = time::OffsetDateTime::now_utc().unix_timestamp();
+        let resp = app().oneshot(signed(now, "n-1")).await.unwrap_or_log();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(resp.into_body())
+            .await
+            .unwrap_or_log();
+        assert_eq!(&body[..], br#"{"amount":100}"#);
+    }
+
+    #[tokio::test]
+    async fn infra_paths_go_unsigned() {
+        setup_tracing_once();
+        let app = app();
+        for (method, uri, status) in [
+            ("GET", "/ready", StatusCode::OK),
+            ("PUT", "/admin/maintenance", StatusCode::OK),
+            ("POST", "/signed", StatusCode::UNAUTHORIZED),
+        ] {
+            let resp = app
+                .clone()
+                .oneshot(
+                    http::Request::builder()
+                        .method(method)
+                        .uri(uri)
+                        .body(Default::default())
+                        .unwrap_or_log(),
+                )
+                .await
+                .unwrap_or_log();
+            assert_eq!(resp.status(), status, "failed on {uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_replayed_nonce() {
+        setup_tracing_once();
+        let app = app();
+        let now = time::OffsetDateTime::now_utc().unix_timestamp();
+        let resp = app
+            .clone()
+            .oneshot(signed(now, "n-1"))
+            .await
+            .unwrap_or_log();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = app.oneshot(signed(now, "n-1")).await.unwrap_or_log();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        check_json(
+            (
+                "expected",
+                &serde_json::json!({ "error": "replayedNonce", "code": "auth.replayed_nonce" }),
+            ),
+            ("response", &error_of(resp).await),
+        );
+    }
+
+    #[tokio::test]
+    async fn concurrent_replays_let_one_through() {
+        setup_tracing_once();
+        let app = app();
+        let now = time::OffsetDateTime::now_utc().unix_timestamp();
+        let statuses =
+            futures::future::join_all((0..8).map(|_| app.clone().oneshot(signed(now, "n-race"))))
+                .await
+                .into_iter()
+                .map(|resp| resp.unwrap_or_log().status())
+                .collect::<Vec<_>>();
+        assert_eq!(
+            statuses
+                .iter()
+                .filter(|status| **status == StatusCode::OK)
+                .count(),
+            1,
+            "{statuses:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_stale_timestamp() {
+        setup_tracing_once();
+        let long_ago = time::OffsetDateTime::now_utc().unix_timestamp() - 60 * 60;
+        let resp = app().oneshot(signed(long_ago, "n-1")).await.unwrap_or_log();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        check_json(
+            (
+                "expected",
+                &serde_json::json!({ "error": "staleTimestamp", "code": "auth.stale_signature" }),
+            ),
+            ("response", &error_of(resp).await),
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_tampered_body() {
+        setup_tracing_once();
+        let now = time::OffsetDateTime::now_utc().unix_timestamp();
+        let mut req = signed(now, "n-1");
+        *req.body_mut() = r#"{"amount":100000}"#.into();
+        let resp = app().oneshot(req).await.unwrap_or_log();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        check_json(
+            (
+                "expected",
+                &serde_json::json!({ "error": "invalidSignature" }),
+            ),
+            ("response", &error_of(resp).await),
+        );
+    }
+}
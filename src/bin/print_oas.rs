@@ -3,10 +3,5 @@ use deps::*;
 use template_rust_web_api::*;
 
 fn main() {
-    println!(
-        "{}",
-        <ApiDoc as utoipa::OpenApi>::openapi()
-            .to_pretty_json()
-            .unwrap()
-    );
+    println!("{}", serde_json::to_string_pretty(&openapi_json()).unwrap());
 }
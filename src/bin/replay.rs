@@ -0,0 +1,66 @@
+//! Re-issues the requests of a recording made through `RECORD_EXCHANGES`, in
+//! order, and reports the statuses that differ from the recorded ones.
+//!
+//! `replay <recording.jsonl> [base url, defaults to http://localhost:8080]`
+//!
+//! Credentials are redacted in recordings, set `REPLAY_AUTHORIZATION` to send an
+//! `Authorization` header along with every request.
+
+use deps::*;
+
+use template_rust_web_api::*;
+
+fn main() {
+    dotenvy::dotenv().ok();
+    setup_tracing().unwrap();
+
+    let mut args = std::env::args().skip(1);
+    let path = args
+        .next()
+        .expect("usage: replay <recording.jsonl> [base url]");
+    let base_url = args
+        .next()
+        .unwrap_or_else(|| "http://localhost:8080".into());
+    let authorization = std::env::var("REPLAY_AUTHORIZATION").ok();
+
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap_or_log()
+        .block_on(async {
+            let client = hyper::Client::new();
+            let recording = tokio::fs::read_to_string(path).await.unwrap_or_log();
+            let mut mismatches = 0;
+            for line in recording.lines().filter(|line| !line.trim().is_empty()) {
+                let exchange: utils::recording::Exchange =
+                    serde_json::from_str(line).unwrap_or_log();
+                let mut req = exchange.request.to_request(&base_url).unwrap_or_log();
+                if let Some(authorization) = &authorization {
+                    req.headers_mut().insert(
+                        axum::http::header::AUTHORIZATION,
+                        authorization.parse().unwrap_or_log(),
+                    );
+                }
+                let status = client.request(req).await.unwrap_or_log().status();
+                if status.as_u16() == exchange.response.status {
+                    tracing::info!(
+                        method = %exchange.request.method,
+                        uri = %exchange.request.uri,
+                        %status,
+                        "replayed"
+                    );
+                } else {
+                    mismatches += 1;
+                    tracing::warn!(
+                        method = %exchange.request.method,
+                        uri = %exchange.request.uri,
+                        %status,
+                        recorded = exchange.response.status,
+                        request_id = exchange.request_id.as_deref(),
+                        "replayed with a different status"
+                    );
+                }
+            }
+            tracing::info!(mismatches, "done");
+        })
+}
@@ -13,6 +13,9 @@ fn main() {
         .build()
         .unwrap_or_log()
         .block_on(async {
+            // parsed the same way when reloaded
+            let reloadable =
+                reload::Reloadable::from_env(|key| std::env::var(key).ok()).unwrap_or_log();
             let config = Config {
                 pass_salt_hash: uuid::Uuid::new_v4().as_bytes().to_vec(),
                 argon2_conf: argon2::Config::default(),
@@ -23,38 +26,342 @@ fn main() {
                         .unwrap_or_log(),
                     0,
                 ),
+                permissions: std::env::var("PERMISSIONS_PATH")
+                    .ok()
+                    .map(|path| auth::PermissionsMatrix::from_path(path).unwrap_or_log()),
+                trim_trailing_slash: std::env::var("TRIM_TRAILING_SLASH")
+                    .map(|val| val.parse().unwrap_or_log())
+                    .unwrap_or(true),
+                db_max_connections: std::env::var("DB_MAX_CONNECTIONS")
+                    .map(|val| val.parse().unwrap_or_log())
+                    .unwrap_or(10),
+                pool_metrics_interval: std::time::Duration::from_secs(
+                    std::env::var("POOL_METRICS_INTERVAL_SECS")
+                        .map(|val| val.parse::<std::num::NonZeroU64>().unwrap_or_log().get())
+                        .unwrap_or(15),
+                ),
+                response_cache_capacity: std::env::var("RESPONSE_CACHE_CAPACITY")
+                    .map(|val| val.parse().unwrap_or_log())
+                    .unwrap_or(1024),
+                security_headers: std::env::var("SECURITY_HEADERS")
+                    .map(|val| val.parse().unwrap_or_log())
+                    .unwrap_or(true),
+                slow_query_threshold: std::time::Duration::from_millis(
+                    std::env::var("SLOW_QUERY_THRESHOLD_MS")
+                        .map(|val| val.parse().unwrap_or_log())
+                        .unwrap_or(500),
+                ),
+                tls: match (
+                    std::env::var("TLS_CERT_PATH"),
+                    std::env::var("TLS_KEY_PATH"),
+                ) {
+                    (Ok(cert_path), Ok(key_path)) => Some(serve::TlsConfig {
+                        cert_path: cert_path.into(),
+                        key_path: key_path.into(),
+                    }),
+                    (Err(_), Err(_)) => None,
+                    // serving plain http when half the tls setup is there is
+                    // most likely not what was intended
+                    _ => panic!("TLS_CERT_PATH and TLS_KEY_PATH need to be set together"),
+                },
+                locales: std::env::var("LOCALES")
+                    .unwrap_or_else(|_| "en".into())
+                    .split(',')
+                    .map(|locale| locale.trim().to_string())
+                    .collect(),
+                bulk_delete_max: std::env::var("BULK_DELETE_MAX")
+                    .map(|val| val.parse().unwrap_or_log())
+                    .unwrap_or(100),
+                pretty_json: std::env::var("PRETTY_JSON")
+                    .map(|val| val.parse().unwrap_or_log())
+                    .unwrap_or(false),
+                db_breaker_threshold: std::env::var("DB_BREAKER_THRESHOLD")
+                    .map(|val| val.parse().unwrap_or_log())
+                    .unwrap_or(5),
+                db_breaker_cooldown: std::time::Duration::from_millis(
+                    std::env::var("DB_BREAKER_COOLDOWN_MS")
+                        .map(|val| val.parse().unwrap_or_log())
+                        .unwrap_or(5000),
+                ),
+                max_uri_len: std::env::var("MAX_URI_LEN")
+                    .map(|val| val.parse().unwrap_or_log())
+                    .unwrap_or(8192),
+                rate_limit: reloadable.rate_limit,
+                request_timeout: std::env::var("REQUEST_TIMEOUT_MS")
+                    .map(|val| val.parse().unwrap_or_log())
+                    .map(|val: u64| (val > 0).then(|| std::time::Duration::from_millis(val)))
+                    .unwrap_or(Some(std::time::Duration::from_secs(30))),
+                metrics_bind_addr: std::env::var("METRICS_BIND_ADDR")
+                    .ok()
+                    .map(|val| val.parse().unwrap_or_log()),
+                metrics_required: std::env::var("METRICS_REQUIRED")
+                    .map(|val| val.parse().unwrap_or_log())
+                    .unwrap_or(false),
+                redis_url: std::env::var("REDIS_URL").ok(),
+                cache_capacity: std::env::var("CACHE_CAPACITY")
+                    .map(|val| val.parse().unwrap_or_log())
+                    .unwrap_or(10_000),
+                shutdown_timeout: std::time::Duration::from_millis(
+                    std::env::var("SHUTDOWN_TIMEOUT_MS")
+                        .map(|val| val.parse().unwrap_or_log())
+                        .unwrap_or(10_000),
+                ),
+                cors_origins: std::env::var("CORS_ORIGINS")
+                    .map(|val| {
+                        val.split(',')
+                            .map(|origin| origin.trim().to_string())
+                            .filter(|origin| !origin.is_empty())
+                            .collect()
+                    })
+                    .unwrap_or_default(),
+                multi_tenant: std::env::var("MULTI_TENANT")
+                    .map(|val| val.parse().unwrap_or_log())
+                    .unwrap_or(false),
+                tenant_base_domain: std::env::var("TENANT_BASE_DOMAIN").ok(),
+                max_tenants: std::env::var("MAX_TENANTS")
+                    .map(|val| val.parse().unwrap_or_log())
+                    .unwrap_or(100),
+                body_limit: std::env::var("BODY_LIMIT")
+                    .map(|val| val.parse().unwrap_or_log())
+                    .unwrap_or(1024 * 1024),
+                upload_body_limit: std::env::var("UPLOAD_BODY_LIMIT")
+                    .map(|val| val.parse().unwrap_or_log())
+                    .unwrap_or(50 * 1024 * 1024),
+                maintenance_mode: std::env::var("MAINTENANCE_MODE")
+                    .map(|val| val.parse().unwrap_or_log())
+                    .unwrap_or(false),
+                auto_migrate: std::env::var("AUTO_MIGRATE")
+                    .map(|val| val.parse().unwrap_or_log())
+                    .unwrap_or(false),
+                strict_request_bodies: std::env::var("STRICT_REQUEST_BODIES")
+                    .map(|val| val.parse().unwrap_or_log())
+                    .unwrap_or(false),
+                trusted_proxies: std::env::var("TRUSTED_PROXIES")
+                    .map(|val| {
+                        val.split(',')
+                            .map(str::trim)
+                            .filter(|cidr| !cidr.is_empty())
+                            .map(|cidr| cidr.parse().unwrap_or_log())
+                            .collect()
+                    })
+                    .unwrap_or_default(),
+                request_signing_key: std::env::var("REQUEST_SIGNING_KEY").ok(),
+                signature_window: std::time::Duration::from_secs(
+                    std::env::var("SIGNATURE_WINDOW_SECS")
+                        .map(|val| val.parse().unwrap_or_log())
+                        .unwrap_or(300),
+                ),
+                tcp_keepalive: std::env::var("TCP_KEEPALIVE_SECS")
+                    .ok()
+                    .map(|val| std::time::Duration::from_secs(val.parse().unwrap_or_log())),
+                http2_keepalive_interval: std::env::var("HTTP2_KEEPALIVE_INTERVAL_SECS")
+                    .ok()
+                    .map(|val| std::time::Duration::from_secs(val.parse().unwrap_or_log())),
+                max_connections: std::env::var("MAX_CONNECTIONS")
+                    .ok()
+                    .map(|val| val.parse().unwrap_or_log()),
+                counter_flush_interval: std::time::Duration::from_millis(
+                    std::env::var("COUNTER_FLUSH_INTERVAL_MS")
+                        .map(|val| val.parse::<std::num::NonZeroU64>().unwrap_or_log().get())
+                        .unwrap_or(5_000),
+                ),
+                counter_flush_size: std::env::var("COUNTER_FLUSH_SIZE")
+                    .map(|val| val.parse().unwrap_or_log())
+                    .unwrap_or(1_000),
+                expose_internal_errors: std::env::var("EXPOSE_INTERNAL_ERRORS")
+                    .map(|val| val.parse().unwrap_or_log())
+                    .unwrap_or(cfg!(debug_assertions)),
+                max_json_depth: std::env::var("MAX_JSON_DEPTH")
+                    .map(|val| val.parse().unwrap_or_log())
+                    .unwrap_or(64),
+                max_json_elements: std::env::var("MAX_JSON_ELEMENTS")
+                    .map(|val| val.parse().unwrap_or_log())
+                    .unwrap_or(100_000),
+                enabled_features: reloadable.enabled_features,
+                slow_request_threshold: std::env::var("SLOW_REQUEST_THRESHOLD_MS")
+                    .ok()
+                    .map(|val| std::time::Duration::from_millis(val.parse().unwrap_or_log())),
+                redacted_fields: std::env::var("REDACTED_FIELDS")
+                    .map(|val| {
+                        val.split(',')
+                            .map(|field| field.trim().to_string())
+                            .filter(|field| !field.is_empty())
+                            .collect()
+                    })
+                    .unwrap_or_else(|_| {
+                        utils::slow_requests::DEFAULT_REDACTED_FIELDS
+                            .iter()
+                            .map(|field| field.to_string())
+                            .collect()
+                    }),
+                compress_responses: std::env::var("COMPRESS_RESPONSES")
+                    .map(|val| val.parse().unwrap_or_log())
+                    .unwrap_or(true),
+                uncompressed_content_types: std::env::var("UNCOMPRESSED_CONTENT_TYPES")
+                    .map(|val| {
+                        val.split(',')
+                            .map(|content_type| content_type.trim().to_string())
+                            .filter(|content_type| !content_type.is_empty())
+                            .collect()
+                    })
+                    .unwrap_or_else(|_| {
+                        utils::compression::DEFAULT_UNCOMPRESSED_CONTENT_TYPES
+                            .iter()
+                            .map(|content_type| content_type.to_string())
+                            .collect()
+                    }),
+                record_exchanges: std::env::var("RECORD_EXCHANGES").ok().map(|val| {
+                    match val.as_str() {
+                        "log" => utils::recording::RecordTo::Log,
+                        path => utils::recording::RecordTo::File(path.into()),
+                    }
+                }),
+                metrics_token: std::env::var("METRICS_TOKEN").ok(),
+                warmup_connections: std::env::var("WARMUP_CONNECTIONS")
+                    .map(|val| val.parse().unwrap_or_log())
+                    .unwrap_or(0),
+                dependency_timeout: std::time::Duration::from_millis(
+                    std::env::var("DEPENDENCY_TIMEOUT_MS")
+                        .map(|val| val.parse().unwrap_or_log())
+                        .unwrap_or(3_000),
+                ),
+                required_dependencies: std::env::var("REQUIRED_DEPENDENCIES")
+                    .map(|val| {
+                        val.split(',')
+                            .map(|name| name.trim().to_string())
+                            .filter(|name| !name.is_empty())
+                            .collect()
+                    })
+                    .unwrap_or_default(),
             };
-            let db_url = std::env::var("DATABASE_URL").unwrap_or_log();
-            let db_pool = sqlx::PgPool::connect(&db_url).await.unwrap_or_log();
-            let ctx = Context { db_pool, config };
+            let mut db_opts: sqlx::postgres::PgConnectOptions = std::env::var("DATABASE_URL")
+                .unwrap_or_log()
+                .parse()
+                .unwrap_or_log();
+            db::log_slow_queries(&mut db_opts, &config);
+            let db_pool = sqlx::postgres::PgPoolOptions::new()
+                .max_connections(config.db_max_connections)
+                .connect_with(db_opts)
+                .await
+                .unwrap_or_log();
+            if config.auto_migrate {
+                db::MIGRATOR.run(&db_pool).await.unwrap_or_log();
+            }
+            let ctx = Context::new(db_pool, config);
+            ctx.warm_up().await.unwrap_or_log();
+            let trim_slash = ctx.config.trim_trailing_slash;
+            let security_headers = ctx.config.security_headers;
+            let compress_responses = ctx.config.compress_responses;
+            let uncompressed_content_types = ctx.config.uncompressed_content_types.clone();
+            let tls = ctx.config.tls.clone();
+            let conn_config = serve::ConnectionConfig::from(&ctx.config);
+            let shutdown_timeout = ctx.config.shutdown_timeout;
+            let db_pool = ctx.db_pool.clone();
             let ctx = std::sync::Arc::new(ctx);
-            let app = axum::Router::new()
-                .merge(utoipa_swagger_ui::SwaggerUi::new("/swagger-ui/*tail").url(
-                    "/api-doc/openapi.json",
-                    <ApiDoc as utoipa::OpenApi>::openapi(),
+            let handle = axum_server::Handle::new();
+            let metrics_exporter =
+                metrics::spawn_exporter(ctx.clone(), handle.clone()).unwrap_or_log();
+            let (counters_shutdown, counters_shutdown_rx) = tokio::sync::watch::channel(false);
+            let counter_flusher = counters::spawn_flusher(ctx.clone(), counters_shutdown_rx);
+            let mut scheduler = scheduler::Scheduler::new(ctx.clone());
+            scheduler.every(
+                "pool_metrics",
+                ctx.config.pool_metrics_interval,
+                |ctx| async move { ctx.sample_pool_metrics().await },
+            );
+            let docs = if security_headers {
+                utils::security_headers::docs_layer(docs_router())
+            } else {
+                docs_router()
+            };
+            let app = RouterBuilder::new()
+                .layer(axum::middleware::from_fn(auth::signature::verify_signature))
+                .merge(docs)
+                .build();
+            #[cfg(debug_assertions)]
+            let app = app.route_layer(utils::schema_check::api_doc_layer());
+            let app = if security_headers {
+                utils::security_headers::layer(app)
+            } else {
+                app
+            };
+            let app = if compress_responses {
+                utils::compression::layer(app, &uncompressed_content_types)
+            } else {
+                app
+            };
+            let app = app
+                .layer(axum::middleware::from_fn(utils::enforce_deadline))
+                .layer(axum::middleware::from_fn(utils::render_internal_errors))
+                .layer(axum::middleware::from_fn(
+                    utils::slow_requests::log_slow_requests,
                 ))
-                .merge(user::router())
-                .merge(auth::router())
-                .layer(axum::Extension(ctx))
-                .layer(
-                    tower_http::trace::TraceLayer::new_for_http()
-                        .on_response(
-                            tower_http::trace::DefaultOnResponse::new()
-                                .level(tracing::Level::INFO)
-                                .latency_unit(tower_http::LatencyUnit::Micros),
-                        )
-                        .on_failure(
-                            tower_http::trace::DefaultOnFailure::new()
-                                .level(tracing::Level::ERROR)
-                                .latency_unit(tower_http::LatencyUnit::Micros),
-                        ),
-                );
+                .layer(axum::middleware::from_fn(
+                    utils::recording::record_exchanges,
+                ))
+                .layer(axum::middleware::from_fn(utils::populate_request_context))
+                .layer(axum::middleware::from_fn(utils::rate_limit))
+                .layer(axum::middleware::from_fn(tenant::resolve_tenant))
+                .layer(axum::middleware::from_fn(utils::limit_body))
+                .layer(axum::middleware::from_fn(reject_long_uri))
+                .layer(axum::middleware::from_fn(
+                    maintenance::reject_during_maintenance,
+                ));
+            let app = utils::with_cors(app).layer(axum::Extension(ctx)).layer(
+                tower_http::trace::TraceLayer::new_for_http()
+                    .on_response(
+                        tower_http::trace::DefaultOnResponse::new()
+                            .level(tracing::Level::INFO)
+                            .latency_unit(tower_http::LatencyUnit::Micros),
+                    )
+                    .on_failure(
+                        tower_http::trace::DefaultOnFailure::new()
+                            .level(tracing::Level::ERROR)
+                            .latency_unit(tower_http::LatencyUnit::Micros),
+                    ),
+            );
+
+            let app = trim_trailing_slash(app, trim_slash);
 
             let address = std::net::SocketAddr::from((std::net::Ipv4Addr::UNSPECIFIED, 8080));
-            tracing::info!("Server listening at {address:?}");
-            axum::Server::bind(&address)
-                .serve(app.into_make_service())
-                .await
+            tracing::info!(tls = tls.is_some(), "Server listening at {address:?}");
+            // one deadline for all of shutdown, not a timeout per phase
+            let (deadline_tx, mut deadline_rx) = tokio::sync::oneshot::channel();
+            tokio::spawn({
+                let handle = handle.clone();
+                async move {
+                    tokio::signal::ctrl_c().await.unwrap_or_log();
+                    tracing::info!("shutting down");
+                    deadline_tx
+                        .send(tokio::time::Instant::now() + shutdown_timeout)
+                        .ok();
+                    handle.graceful_shutdown(Some(shutdown_timeout));
+                }
+            });
+            let result = serve::serve(app, address, tls.as_ref(), &conn_config, handle).await;
+            let deadline = deadline_rx
+                .try_recv()
+                .unwrap_or_else(|_| tokio::time::Instant::now() + shutdown_timeout);
+            let background = async {
+                if let Some(exporter) = metrics_exporter {
+                    exporter.await.unwrap_or_log();
+                }
+                scheduler.shutdown().await;
+                counters_shutdown.send(true).ok();
+                counter_flusher.await.unwrap_or_log();
+            };
+            if tokio::time::timeout_at(deadline, background).await.is_err() {
+                tracing::warn!(
+                    ?shutdown_timeout,
+                    "background tasks didn't stop in time, abandoning them"
+                );
+            }
+            db::close_pool(
+                &db_pool,
+                deadline.saturating_duration_since(tokio::time::Instant::now()),
+            )
+            .await;
+            result
         })
         .unwrap_or_log()
 }
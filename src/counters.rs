@@ -0,0 +1,282 @@
+//! Buffered counters for hot paths like view counts. [`CounterBuffer::increment`]
+//! only adds to a map in memory and [`spawn_flusher`] writes the sums to the
+//! `counters` table in one statement every
+//! [`crate::Config::counter_flush_interval`], or sooner once
+//! [`crate::Config::counter_flush_size`] keys are pending. Whatever is left is
+//! flushed on shutdown.
+//!
+//! Increments pending when the process dies without shutting down are lost so
+//! don't count anything here that has to be exact. So are increments of new keys
+//! once [`CounterBuffer::with_capacity`] keys are pending, e.g. while the db is
+//! down.
+//!
+//! The `counters` table is shared by all tenants, keys made within a
+//! [`crate::tenant::current_tenant`] are prefixed with it.
+
+use deps::*;
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::SharedContext;
+
+fn scoped_key(key: &str) -> String {
+    match crate::tenant::current_tenant() {
+        Some(tenant) => format!("{tenant}:{key}"),
+        None => key.to_string(),
+    }
+}
+
+/// Default of the pending keys a [`CounterBuffer`] holds at most.
+pub const MAX_PENDING_KEYS: usize = 100_000;
+
+#[derive(Debug)]
+pub struct CounterBuffer {
+    pending: parking_lot::Mutex<HashMap<String, i64>>,
+    /// Pending keys past which the flusher is woken early, never if zero.
+    max_pending: usize,
+    capacity: usize,
+    full: tokio::sync::Notify,
+    flushes: AtomicU64,
+    dropped: AtomicU64,
+}
+
+impl Default for CounterBuffer {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+impl CounterBuffer {
+    pub fn new(max_pending: usize) -> Self {
+        Self::with_capacity(max_pending, MAX_PENDING_KEYS)
+    }
+
+    /// Holding at most `capacity` pending keys.
+    pub fn with_capacity(max_pending: usize, capacity: usize) -> Self {
+        Self {
+            pending: Default::default(),
+            max_pending,
+            capacity,
+            full: Default::default(),
+            flushes: Default::default(),
+            dropped: Default::default(),
+        }
+    }
+
+    pub fn increment(&self, key: &str, by: i64) {
+        let key = scoped_key(key);
+        let len = {
+            let mut pending = self.pending.lock();
+            if !self.add(&mut pending, &key, by) {
+                tracing::warn!(%key, "counter buffer full, dropping increment");
+            }
+            pending.len()
+        };
+        if self.max_pending > 0 && len >= self.max_pending {
+            self.full.notify_one();
+        }
+    }
+
+    /// Sum of the increments not yet flushed for `key`.
+    pub fn pending(&self, key: &str) -> i64 {
+        self.pending
+            .lock()
+            .get(&scoped_key(key))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Adds `by` to `key` unless that's a new key and `pending` is at capacity,
+    /// counting it as dropped then.
+    fn add(&self, pending: &mut HashMap<String, i64>, key: &str, by: i64) -> bool {
+        if let Some(count) = pending.get_mut(key) {
+            *count = count.saturating_add(by);
+        } else if pending.len() < self.capacity {
+            pending.insert(key.to_owned(), by);
+        } else {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        true
+    }
+
+    /// Flushes that made it to the db.
+    pub fn flushes(&self) -> u64 {
+        self.flushes.load(Ordering::Relaxed)
+    }
+
+    /// Increments lost to the buffer being full.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Adds the pending increments to the `counters` table in a single statement.
+    /// On failure they're put back to be retried with the next flush, as far as
+    /// the capacity allows.
+    pub async fn flush(&self, db_pool: &sqlx::postgres::PgPool) -> Result<(), sqlx::Error> {
+        let pending = std::mem::take(&mut *self.pending.lock());
+        if pending.is_empty() {
+            return Ok(());
+        }
+        let (keys, counts): (Vec<_>, Vec<_>) = pending.iter().map(|(k, v)| (k.clone(), *v)).unzip();
+        // `UNNEST` over arrays so that the batch size doesn't change the statement
+        let result = sqlx::query(
+            r#"
+INSERT INTO counters (key, count)
+SELECT * FROM UNNEST($1::TEXT[], $2::BIGINT[])
+ON CONFLICT (key) DO UPDATE SET count = counters.count + EXCLUDED.count
+            "#,
+        )
+        .bind(&keys)
+        .bind(&counts)
+        .execute(db_pool)
+        .await;
+        match result {
+            Ok(_) => {
+                self.flushes.fetch_add(1, Ordering::Relaxed);
+                tracing::debug!(keys = keys.len(), "flushed counters");
+                Ok(())
+            }
+            Err(err) => {
+                let mut current = self.pending.lock();
+                for (key, count) in pending {
+                    self.add(&mut current, &key, count);
+                }
+                Err(err)
+            }
+        }
+    }
+}
+
+/// Flushes the [`crate::Context::counters`] every
+/// [`crate::Config::counter_flush_interval`] or when they fill up, until `shutdown`
+/// changes or its sender is dropped. Flushes once more on the way out so await it
+/// before closing the `db_pool`.
+pub fn spawn_flusher(
+    ctx: SharedContext,
+    mut shutdown: tokio::sync::watch::Receiver<bool>,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let period = ctx.config.counter_flush_interval;
+        // nothing to flush right away
+        let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = ctx.counters.full.notified() => {}
+                _ = shutdown.changed() => break,
+            }
+            if let Err(err) = ctx.counters.flush(&ctx.db_pool).await {
+                tracing::warn!(?err, "unable to flush counters");
+            }
+        }
+        if let Err(err) = ctx.counters.flush(&ctx.db_pool).await {
+            tracing::error!(?err, "unable to flush counters on shutdown");
+        }
+        tracing::debug!("counter flusher stopped");
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use deps::*;
+
+    use crate::utils::testing::*;
+
+    async fn count(ctx: &crate::Context, key: &str) -> Option<i64> {
+        sqlx::query_scalar("SELECT count FROM counters WHERE key = $1")
+            .bind(key)
+            .fetch_optional(&ctx.db_pool)
+            .await
+            .unwrap_or_log()
+    }
+
+    #[tokio::test]
+    async fn adds_to_the_counts() {
+        let ctx = TestContext::new(crate::function!()).await;
+        {
+            let ctx = ctx.ctx();
+            for _ in 0..100 {
+                ctx.counters.increment("views:a", 1);
+                ctx.counters.increment("views:b", 2);
+            }
+            ctx.counters.flush(&ctx.db_pool).await.unwrap_or_log();
+            assert_eq!(count(&ctx, "views:a").await, Some(100));
+            assert_eq!(count(&ctx, "views:b").await, Some(200));
+
+            // adds to what's there
+            ctx.counters.increment("views:a", 5);
+            ctx.counters.flush(&ctx.db_pool).await.unwrap_or_log();
+            assert_eq!(count(&ctx, "views:a").await, Some(105));
+            assert_eq!(ctx.counters.pending("views:a"), 0);
+        }
+        ctx.close().await;
+    }
+
+    #[tokio::test]
+    async fn flushes_early_once_full() {
+        let mut ctx = TestContext::new(crate::function!()).await;
+        {
+            // the interval is a minute out so only filling up flushes
+            ctx.ctx_mut().counters = crate::counters::CounterBuffer::new(2);
+            let ctx = ctx.ctx();
+            let (shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(false);
+            let flusher = crate::counters::spawn_flusher(ctx.clone(), shutdown_rx);
+            for _ in 0..100 {
+                ctx.counters.increment("views:a", 1);
+            }
+            assert_eq!(ctx.counters.flushes(), 0);
+            ctx.counters.increment("views:b", 2);
+            tokio::time::timeout(std::time::Duration::from_secs(5), async {
+                while ctx.counters.flushes() == 0 {
+                    tokio::task::yield_now().await;
+                }
+            })
+            .await
+            .expect("not flushed once full");
+            assert_eq!(ctx.counters.flushes(), 1);
+            assert_eq!(count(&ctx, "views:a").await, Some(100));
+            assert_eq!(count(&ctx, "views:b").await, Some(2));
+            shutdown_tx.send(true).unwrap_or_log();
+            flusher.await.unwrap_or_log();
+        }
+        ctx.close().await;
+    }
+
+    #[test]
+    fn drops_new_keys_past_the_capacity() {
+        let counters = crate::counters::CounterBuffer::with_capacity(0, 2);
+        counters.increment("views:a", 1);
+        counters.increment("views:b", 1);
+        counters.increment("views:c", 1);
+        counters.increment("views:a", 1);
+        assert_eq!(counters.pending("views:a"), 2);
+        assert_eq!(counters.pending("views:c"), 0);
+        assert_eq!(counters.dropped(), 1);
+    }
+
+    #[tokio::test]
+    async fn shutdown_flushes_pending() {
+        let ctx = TestContext::new(crate::function!()).await;
+        {
+            let ctx = ctx.ctx();
+            let (shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(false);
+            let flusher = crate::counters::spawn_flusher(ctx.clone(), shutdown_rx);
+            // the first tick is a minute out so only shutting down flushes
+            for _ in 0..10 {
+                ctx.counters.increment("views:a", 1);
+            }
+            assert_eq!(count(&ctx, "views:a").await, None);
+
+            shutdown_tx.send(true).unwrap_or_log();
+            tokio::time::timeout(std::time::Duration::from_secs(5), flusher)
+                .await
+                .expect("flusher didn't stop")
+                .unwrap_or_log();
+            assert_eq!(count(&ctx, "views:a").await, Some(10));
+        }
+        ctx.close().await;
+    }
+}
@@ -0,0 +1,522 @@
+//! Data access that handlers go through instead of hitting the pool directly.
+//! This allows tests to swap in a fake store and exercise endpoint logic
+//! without a live Postgres.
+
+use deps::*;
+
+use crate::{
+    auth::{ApiKeyRecord, Role, Session},
+    tenant::{Tenant, TenantError},
+    user::User,
+};
+
+/// Has sqlx log statements slower than [`crate::Config::slow_query_threshold`] at
+/// `warn` with the elapsed time and a truncated summary of the SQL. sqlx logs
+/// through the `log` crate which `tracing_subscriber` forwards as events in the
+/// current span so they end up correlated with the request that ran them.
+pub fn log_slow_queries(opts: &mut sqlx::postgres::PgConnectOptions, config: &crate::Config) {
+    use sqlx::ConnectOptions;
+    opts.log_slow_statements("WARN".parse().unwrap(), config.slow_query_threshold);
+}
+
+/// Closes the pool, waiting at most `timeout` for checked out connections to be
+/// returned. Those that aren't by then are logged and left behind so a hung one
+/// can't hold up shutdown. Returns whether everything closed in time.
+pub async fn close_pool(pool: &sqlx::postgres::PgPool, timeout: std::time::Duration) -> bool {
+    match tokio::time::timeout(timeout, pool.close()).await {
+        Ok(()) => true,
+        Err(_) => {
+            tracing::warn!(
+                ?timeout,
+                unclosed = pool.size(),
+                idle = pool.num_idle(),
+                "db pool didn't close in time, abandoning connections"
+            );
+            false
+        }
+    }
+}
+
+/// Target of the event [`crate::Context::acquire`] logs.
+pub const ACQUIRE_TARGET: &str = "db::acquire";
+
+/// A pooled connection tied to the request it was acquired for. Derefs to the
+/// connection so it can be handed to queries as `&mut *conn`.
+#[derive(Debug)]
+pub struct DbConn {
+    conn: sqlx::pool::PoolConnection<sqlx::Postgres>,
+    span: tracing::Span,
+}
+
+impl DbConn {
+    pub fn new(conn: sqlx::pool::PoolConnection<sqlx::Postgres>, span: tracing::Span) -> Self {
+        Self { conn, span }
+    }
+
+    /// Carries the `request_id` of the request the connection was acquired for.
+    /// Instrument queries with it so that sqlx's logs of them do too.
+    pub fn span(&self) -> &tracing::Span {
+        &self.span
+    }
+}
+
+impl std::ops::Deref for DbConn {
+    type Target = sqlx::PgConnection;
+
+    fn deref(&self) -> &Self::Target {
+        &self.conn
+    }
+}
+
+impl std::ops::DerefMut for DbConn {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.conn
+    }
+}
+
+/// A transaction from [`crate::Context::begin`]. [`DbTx::commit`] rolls back
+/// instead when the request is a [`crate::utils::dry_run::is_dry_run`] and
+/// dropping it uncommitted rolls back too, so writes made through it are never
+/// applied on dry runs. Derefs to the transaction so it can be handed to
+/// queries as `&mut *tx`.
+#[derive(Debug)]
+pub struct DbTx(sqlx::Transaction<'static, sqlx::Postgres>);
+
+impl DbTx {
+    pub fn new(tx: sqlx::Transaction<'static, sqlx::Postgres>) -> Self {
+        Self(tx)
+    }
+
+    pub async fn commit(self) -> Result<(), sqlx::Error> {
+        if crate::utils::dry_run::is_dry_run() {
+            self.0.rollback().await
+        } else {
+            self.0.commit().await
+        }
+    }
+
+    pub async fn rollback(self) -> Result<(), sqlx::Error> {
+        self.0.rollback().await
+    }
+}
+
+impl std::ops::Deref for DbTx {
+    type Target = sqlx::Transaction<'static, sqlx::Postgres>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl std::ops::DerefMut for DbTx {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+/// The `migrations` directory, embedded at build time.
+pub static MIGRATOR: sqlx::migrate::Migrator = sqlx::migrate!("./migrations");
+
+/// How the schema of the db compares to the [`MIGRATOR`] of this build.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, utoipa::ToSchema)]
+#[serde(crate = "serde", rename_all = "camelCase")]
+pub struct MigrationStatus {
+    /// Latest of the known migrations that's been applied.
+    #[schema(example = 20221016090000_i64)]
+    pub applied_version: Option<i64>,
+    /// Latest migration this build knows of.
+    #[schema(example = 20221016090000_i64)]
+    pub expected_version: Option<i64>,
+    /// Versions this build knows of that are yet to be applied, oldest first.
+    pub pending: Vec<i64>,
+}
+
+pub async fn migration_status(
+    pool: &sqlx::postgres::PgPool,
+) -> Result<MigrationStatus, sqlx::migrate::MigrateError> {
+    use sqlx::migrate::Migrate;
+    let mut conn = pool.acquire().await?;
+    // a fresh db won't have the table yet, looked up rather than created so that
+    // readiness probes stay read only
+    let has_table: bool = sqlx::query_scalar("SELECT to_regclass('_sqlx_migrations') IS NOT NULL")
+        .fetch_one(&mut *conn)
+        .await?;
+    let applied = if has_table {
+        conn.list_applied_migrations()
+            .await?
+            .into_iter()
+            .map(|migration| migration.version)
+            .collect::<std::collections::HashSet<_>>()
+    } else {
+        Default::default()
+    };
+    let known = MIGRATOR
+        .iter()
+        .filter(|migration| !migration.migration_type.is_down_migration())
+        .map(|migration| migration.version)
+        .collect::<Vec<_>>();
+    Ok(MigrationStatus {
+        applied_version: known
+            .iter()
+            .copied()
+            .filter(|version| applied.contains(version))
+            .max(),
+        expected_version: known.iter().copied().max(),
+        pending: known
+            .into_iter()
+            .filter(|version| !applied.contains(version))
+            .collect(),
+    })
+}
+
+#[async_trait::async_trait]
+pub trait DbBackend: std::fmt::Debug + Send + Sync + 'static {
+    /// Should return [`sqlx::Error::RowNotFound`] if no session is found.
+    async fn session(&self, token: &str) -> Result<Session, sqlx::Error>;
+    /// Should return [`sqlx::Error::RowNotFound`] if no user is found.
+    async fn user(&self, id: uuid::Uuid) -> Result<User, sqlx::Error>;
+    async fn user_roles(&self, user_id: uuid::Uuid) -> Result<Vec<Role>, sqlx::Error>;
+    /// Should return [`sqlx::Error::RowNotFound`] if no key has the hash.
+    async fn api_key(&self, key_hash: &str) -> Result<ApiKeyRecord, sqlx::Error>;
+    /// Gets `tenant` ready to be made current, see [`crate::tenant::TenantDb`].
+    /// Backends that aren't scoped by tenant take any.
+    async fn admit(&self, _tenant: &Tenant) -> Result<(), TenantError> {
+        Ok(())
+    }
+    /// The Postgres pool behind the backend for the queries the other methods
+    /// don't cover, scoped like they are. Go through [`crate::Context::pool`].
+    fn pg_pool(&self) -> Option<sqlx::postgres::PgPool> {
+        None
+    }
+    /// The pools opened per [`crate::tenant::Tenant`] on top of the
+    /// [`DbBackend::pg_pool`], for the metrics.
+    fn tenant_pools(&self) -> Vec<sqlx::postgres::PgPool> {
+        vec![]
+    }
+}
+
+#[async_trait::async_trait]
+impl DbBackend for sqlx::postgres::PgPool {
+    fn pg_pool(&self) -> Option<sqlx::postgres::PgPool> {
+        Some(self.clone())
+    }
+
+    async fn session(&self, token: &str) -> Result<Session, sqlx::Error> {
+        sqlx::query_as!(
+            Session,
+            r#"
+SELECT * 
+FROM sessions
+WHERE token = $1
+            "#,
+            token
+        )
+        .fetch_one(self)
+        .await
+    }
+
+    async fn user(&self, id: uuid::Uuid) -> Result<User, sqlx::Error> {
+        sqlx::query_as!(
+            User,
+            r#"
+SELECT 
+    id,
+    created_at,
+    updated_at,
+    email::TEXT as "email!",
+    username::TEXT as "username!",
+    pic_url
+FROM users
+WHERE id = $1::uuid
+            "#,
+            &id
+        )
+        .fetch_one(self)
+        .await
+    }
+
+    async fn user_roles(&self, user_id: uuid::Uuid) -> Result<Vec<Role>, sqlx::Error> {
+        let roles = sqlx::query_scalar!(
+            r#"
+SELECT role
+FROM user_roles
+WHERE user_id = $1
+            "#,
+            user_id
+        )
+        .fetch_all(self)
+        .await?;
+        Ok(roles
+            .into_iter()
+            .filter_map(|role| match role.parse() {
+                Ok(role) => Some(role),
+                Err(()) => {
+                    tracing::warn!(%user_id, %role, "unrecognized role found in db");
+                    None
+                }
+            })
+            .collect())
+    }
+
+    async fn api_key(&self, key_hash: &str) -> Result<ApiKeyRecord, sqlx::Error> {
+        sqlx::query_as!(
+            ApiKeyRecord,
+            r#"
+SELECT
+    key_hash,
+    user_id,
+    scopes,
+    expires_at,
+    created_at,
+    updated_at
+FROM api_keys
+WHERE key_hash = $1
+            "#,
+            key_hash
+        )
+        .fetch_one(self)
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use deps::*;
+
+    use crate::utils::testing::*;
+
+    use std::sync::Arc;
+
+    /// Collects the messages of the `warn` events sqlx logs for queries.
+    #[derive(Debug, Clone, Default)]
+    struct SlowQueries(Arc<parking_lot::Mutex<Vec<String>>>);
+
+    #[derive(Default)]
+    struct Visitor {
+        target: Option<String>,
+        message: Option<String>,
+    }
+
+    impl tracing::field::Visit for Visitor {
+        fn record_str(&mut self, field: &tracing::field::Field, value: &str) {
+            match field.name() {
+                "log.target" => self.target = Some(value.to_string()),
+                "message" => self.message = Some(value.to_string()),
+                _ => {}
+            }
+        }
+
+        fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+            if field.name() == "message" {
+                self.message = Some(format!("{value:?}"));
+            }
+        }
+    }
+
+    impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for SlowQueries {
+        fn on_event(
+            &self,
+            event: &tracing::Event<'_>,
+            _ctx: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            if *event.metadata().level() != tracing::Level::WARN {
+                return;
+            }
+            let mut visitor = Visitor::default();
+            event.record(&mut visitor);
+            if let (Some("sqlx::query"), Some(message)) =
+                (visitor.target.as_deref(), visitor.message)
+            {
+                self.0.lock().push(message);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn logs_queries_past_the_threshold() {
+        let ctx = TestContext::new(crate::function!()).await;
+        {
+            use tracing_subscriber::layer::SubscriberExt;
+            let slow_queries = SlowQueries::default();
+            let _guard = tracing::subscriber::set_default(
+                tracing_subscriber::registry().with(slow_queries.clone()),
+            );
+            let shared = ctx.ctx();
+            let sleep = shared.config.slow_query_threshold.as_secs_f64() * 2.;
+
+            sqlx::query("SELECT 1")
+                .execute(&shared.db_pool)
+                .await
+                .unwrap_or_log();
+            assert!(slow_queries.0.lock().is_empty());
+
+            sqlx::query(&format!("SELECT pg_sleep({sleep})"))
+                .execute(&shared.db_pool)
+                .await
+                .unwrap_or_log();
+            let logged = slow_queries.0.lock().clone();
+            assert_eq!(logged.len(), 1, "{logged:?}");
+            assert!(logged[0].contains("elapsed"), "{logged:?}");
+        }
+        ctx.close().await;
+    }
+
+    /// A request that can't get a connection within the `acquire_timeout` is shed
+    /// with a `503` rather than piling up behind the slow one.
+    #[tokio::test]
+    async fn pool_exhaustion_is_503() {
+        use crate::user::testing::*;
+        let ctx = TestContext::new(crate::function!()).await;
+        {
+            let db_pool = sqlx::postgres::PgPoolOptions::new()
+                .max_connections(1)
+                .acquire_timeout(std::time::Duration::from_millis(200))
+                .connect_lazy_with(ctx.ctx().db_pool.connect_options().clone());
+            // take the only connection before the request goes out
+            let mut conn = db_pool.acquire().await.unwrap_or_log();
+            let slow = tokio::spawn(async move {
+                sqlx::query("SELECT pg_sleep(1)")
+                    .execute(&mut conn)
+                    .await
+                    .unwrap_or_log();
+            });
+            let app = crate::user::router().layer(axum::Extension(Arc::new(crate::Context::new(
+                db_pool,
+                test_config(),
+            ))));
+            let resp = app
+                .oneshot(
+                    http::Request::builder()
+                        .method("GET")
+                        .uri(format!("/users/{USER_01_ID}"))
+                        .header(
+                            http::header::AUTHORIZATION,
+                            format!("Bearer {USER_01_SESSION}"),
+                        )
+                        .body(Default::default())
+                        .unwrap_or_log(),
+                )
+                .await
+                .unwrap_or_log();
+            assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+            assert_eq!(
+                resp.headers()[http::header::RETRY_AFTER],
+                crate::RETRY_AFTER_SECS.to_string()
+            );
+            let body = hyper::body::to_bytes(resp.into_body())
+                .await
+                .unwrap_or_log();
+            let body = serde_json::from_slice(&body).unwrap_or_log();
+            check_json(
+                ("expected", &serde_json::json!({ "error": "overloaded" })),
+                ("response", &body),
+            );
+            slow.await.unwrap_or_log();
+        }
+        ctx.close().await;
+    }
+
+    #[tokio::test]
+    async fn dry_run_commits_roll_back() {
+        let ctx = TestContext::new(crate::function!()).await;
+        {
+            let shared = ctx.ctx();
+            let insert = |key: &'static str| {
+                let shared = shared.clone();
+                async move {
+                    let mut tx = shared.begin().await.unwrap_or_log();
+                    sqlx::query("INSERT INTO counters (key) VALUES ($1)")
+                        .bind(key)
+                        .execute(&mut *tx)
+                        .await
+                        .unwrap_or_log();
+                    tx.commit().await.unwrap_or_log();
+                }
+            };
+            let count = |key: &'static str| {
+                sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM counters WHERE key = $1")
+                    .bind(key)
+                    .fetch_one(&shared.db_pool)
+            };
+            crate::utils::dry_run::with_dry_run(insert("dry")).await;
+            insert("wet").await;
+            assert_eq!(count("dry").await.unwrap_or_log(), 0);
+            assert_eq!(count("wet").await.unwrap_or_log(), 1);
+        }
+        ctx.close().await;
+    }
+
+    #[tokio::test]
+    async fn close_pool_gives_up_on_held_connections() {
+        let ctx = TestContext::new(crate::function!()).await;
+        {
+            let db_pool = sqlx::postgres::PgPoolOptions::new()
+                .max_connections(2)
+                .connect_lazy_with(ctx.ctx().db_pool.connect_options().clone());
+            let conn = db_pool.acquire().await.unwrap_or_log();
+            let closed = tokio::time::timeout(
+                std::time::Duration::from_secs(5),
+                super::close_pool(&db_pool, std::time::Duration::from_millis(100)),
+            )
+            .await
+            .expect("shutdown hung on the held connection");
+            assert!(!closed);
+            assert!(db_pool.is_closed());
+            drop(conn);
+        }
+        ctx.close().await;
+    }
+
+    /// The query macros check against `sqlx-data.json` when `SQLX_OFFLINE` is set
+    /// so a migration can change what a query returns without the build noticing.
+    #[tokio::test]
+    async fn offline_query_cache_matches_schema() {
+        use sqlx::Executor;
+        let ctx = TestContext::new(crate::function!()).await;
+        {
+            let cache: serde_json::Value =
+                serde_json::from_str(include_str!("../sqlx-data.json")).unwrap_or_log();
+            let cache = cache.as_object().unwrap();
+            assert_eq!(cache["db"], "PostgreSQL");
+            for (_, entry) in cache.iter().filter(|(key, _)| *key != "db") {
+                let query = entry["query"].as_str().unwrap();
+                let describe = (&ctx.ctx().db_pool).describe(query).await.unwrap_or_log();
+                assert_eq!(
+                    serde_json::to_value(&describe).unwrap_or_log(),
+                    entry["describe"],
+                    "stale cache for query: {query}, run `cargo sqlx prepare -- --lib`"
+                );
+            }
+        }
+        ctx.close().await;
+    }
+
+    #[tokio::test]
+    async fn acquire_logs_the_request_id() {
+        let ctx = TestContext::new(crate::function!()).await;
+        {
+            use tracing::Instrument;
+            use tracing_subscriber::layer::SubscriberExt;
+            let captured = CapturedEvents::new(super::ACQUIRE_TARGET);
+            let _guard = tracing::subscriber::set_default(
+                tracing_subscriber::registry().with(captured.clone()),
+            );
+            let shared = ctx.ctx();
+            let mut conn = crate::utils::with_request_id("req-0451".into(), shared.acquire())
+                .await
+                .unwrap_or_log();
+            let span = conn.span().clone();
+            sqlx::query("SELECT 1")
+                .execute(&mut *conn)
+                .instrument(span)
+                .await
+                .unwrap_or_log();
+
+            let events = captured.events();
+            assert_eq!(events.len(), 1, "{events:?}");
+            assert_eq!(events[0]["request_id"], "req-0451");
+            assert!(events[0]["wait_ms"].is_u64(), "{events:?}");
+        }
+        ctx.close().await;
+    }
+}
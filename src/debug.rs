@@ -0,0 +1,310 @@
+//! Endpoints for poking at a development instance. The module only exists in
+//! debug builds, `lib.rs` declares it and merges its [`router`], [`paths`] and
+//! [`components`] under `#[cfg(debug_assertions)]`, so release builds neither
+//! serve nor document them. Add to it like to any other endpoint module.
+
+use deps::*;
+
+use crate::*;
+
+use serde::{Deserialize, Serialize};
+
+pub const TAG: Tag = Tag {
+    name: "debug",
+    desc: "Introspection, only served by debug builds.",
+};
+
+pub fn router() -> axum::Router {
+    axum::Router::new()
+        .merge(EndpointWrapper::new(DumpConfig))
+        .layer(axum::middleware::from_fn(crate::method_not_allowed_body))
+}
+
+pub fn components(builder: openapi::ComponentsBuilder) -> openapi::ComponentsBuilder {
+    let builder = DumpConfig::components(builder);
+    builder.schema(
+        type_name_raw::<ConfigDump>(),
+        <ConfigDump as utoipa::ToSchema>::schema(),
+    )
+}
+
+pub fn paths(paths: crate::SpecPaths) -> crate::SpecPaths {
+    paths.endpoint::<DumpConfig>()
+}
+
+/// The [`Config`] in effect, secrets masked. Only for [`auth::Role::SuperAdmin`]s.
+#[derive(Debug, Clone, Copy)]
+pub struct DumpConfig;
+
+#[derive(Debug)]
+pub struct Request {
+    pub auth_token: std::sync::Arc<str>,
+}
+
+/// The `Debug` of each [`Config`] field by its name.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(crate = "serde")]
+pub struct ConfigDump(pub std::collections::BTreeMap<String, String>);
+
+impl utoipa::ToSchema for ConfigDump {
+    fn schema() -> openapi::Schema {
+        openapi::Schema::Object(
+            openapi::schema::ObjectBuilder::new()
+                .description(Some("The `Debug` of each config field by its name."))
+                .additional_properties(Some(
+                    openapi::schema::ObjectBuilder::new().schema_type(openapi::SchemaType::String),
+                ))
+                .build(),
+        )
+    }
+}
+
+impl ConfigDump {
+    /// Destructures the [`Config`] so that new fields have to be added here, to
+    /// one list or the other, to compile.
+    pub fn new(ctx: &Context) -> Self {
+        macro_rules! dump {
+            (shown: [$($field:ident),* $(,)?], masked: [$($secret:ident),* $(,)?]) => {{
+                let Config {
+                    $($field,)*
+                    $($secret: _,)*
+                } = &ctx.config;
+                let mut dump = std::collections::BTreeMap::new();
+                $(dump.insert(stringify!($field).to_string(), format!("{:?}", $field));)*
+                $(dump.insert(stringify!($secret).to_string(), MASK.to_string());)*
+                dump
+            }};
+        }
+        let mut dump = dump!(
+            shown: [
+                auth_token_lifespan,
+                permissions,
+                trim_trailing_slash,
+                db_max_connections,
+                pool_metrics_interval,
+                response_cache_capacity,
+                security_headers,
+                slow_query_threshold,
+                tls,
+                locales,
+                bulk_delete_max,
+                db_breaker_threshold,
+                db_breaker_cooldown,
+                pretty_json,
+                max_uri_len,
+                rate_limit,
+                request_timeout,
+                metrics_bind_addr,
+                metrics_required,
+                shutdown_timeout,
+                cors_origins,
+                multi_tenant,
+                tenant_base_domain,
+                max_tenants,
+                body_limit,
+                upload_body_limit,
+                maintenance_mode,
+                auto_migrate,
+                strict_request_bodies,
+                trusted_proxies,
+                signature_window,
+                tcp_keepalive,
+                http2_keepalive_interval,
+                max_connections,
+                counter_flush_interval,
+                counter_flush_size,
+                expose_internal_errors,
+                max_json_depth,
+                max_json_elements,
+                enabled_features,
+                slow_request_threshold,
+                redacted_fields,
+                compress_responses,
+                uncompressed_content_types,
+                record_exchanges,
+                warmup_connections,
+                dependency_timeout,
+                required_dependencies,
+                cache_capacity,
+            ],
+            masked: [
+                pass_salt_hash,
+                argon2_conf,
+                redis_url,
+                request_signing_key,
+                metrics_token,
+            ]
+        );
+        // what's in effect after a reload
+        dump.insert(
+            "enabled_features".into(),
+            format!("{:?}", ctx.enabled_features()),
+        );
+        dump.insert("rate_limit".into(), format!("{:?}", ctx.rate_limit()));
+        Self(dump)
+    }
+}
+
+#[derive(Debug, thiserror::Error, Serialize, utoipa::ToSchema)]
+#[serde(crate = "serde", tag = "error", rename_all = "camelCase")]
+pub enum Error {
+    #[error("acess denied")]
+    AccessDenied,
+    #[error("forbidden")]
+    Forbidden,
+    #[error("database overloaded")]
+    Overloaded,
+    #[error("internal server error: {message:?}")]
+    Internal { message: String },
+}
+
+crate::impl_from_auth_err!(Error);
+crate::impl_from_db_err!(Error);
+
+#[async_trait::async_trait]
+impl AuthenticatedEndpoint for DumpConfig {
+    type Request = Request;
+    type Response = Ref<ConfigDump>;
+    type Error = Error;
+
+    fn authorize_request(&self, request: &Self::Request) -> auth::authorize::Request {
+        auth::authorize::Request {
+            auth_token: request.auth_token.clone(),
+            resource: auth::Resource::Config,
+            action: auth::Action::Read,
+        }
+    }
+
+    #[tracing::instrument(skip(ctx))]
+    async fn handle(
+        &self,
+        ctx: &crate::Context,
+        accessing_user: uuid::Uuid,
+        _request: Self::Request,
+    ) -> Result<Self::Response, Self::Error> {
+        auth::require_role(ctx, accessing_user, auth::Role::SuperAdmin).await?;
+        Ok(ConfigDump::new(ctx).into())
+    }
+}
+
+impl From<&Error> for StatusCode {
+    fn from(err: &Error) -> Self {
+        use Error::*;
+        match err {
+            AccessDenied => Self::UNAUTHORIZED,
+            Forbidden => Self::FORBIDDEN,
+            Overloaded => Self::SERVICE_UNAVAILABLE,
+            Internal { .. } => Self::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl crate::utils::ErrorCode for Error {
+    fn code(&self) -> &'static str {
+        use Error::*;
+        match self {
+            AccessDenied => "auth.access_denied",
+            Forbidden => "auth.forbidden",
+            Overloaded => "service.overloaded",
+            Internal { .. } => "service.internal",
+        }
+    }
+}
+
+impl HttpEndpoint for DumpConfig {
+    const METHOD: Method = Method::Get;
+    const PATH: &'static str = "/debug/dump-config";
+
+    type HttpRequest = (BearerToken,);
+
+    fn request((BearerToken(token),): Self::HttpRequest) -> Result<Self::Request, Self::Error> {
+        Ok(self::Request { auth_token: token })
+    }
+
+    fn response(Ref(resp): Self::Response) -> axum::response::Response {
+        Json(resp).into_response()
+    }
+}
+
+impl DocumentedEndpoint for DumpConfig {
+    const TAG: &'static Tag = &TAG;
+    const SUMMARY: &'static str = "Dump the config in effect, secrets masked.";
+
+    fn success_examples() -> Vec<serde_json::Value> {
+        vec![serde_json::json!({
+            "db_max_connections": "10",
+            "enabled_features": "[\"bulkExport\"]",
+            "request_signing_key": MASK,
+        })]
+    }
+
+    fn errors() -> Vec<ErrorResponse<Self::Error>> {
+        vec![
+            ("Access denied", Error::AccessDenied),
+            ("Forbidden", Error::Forbidden),
+            ("Overloaded", Error::Overloaded),
+            (
+                "Internal server error",
+                Error::Internal {
+                    message: "internal server error".to_string(),
+                },
+            ),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use deps::*;
+
+    use crate::user::testing::*;
+    use crate::utils::testing::*;
+
+    #[test]
+    fn documented_in_debug_builds() {
+        let spec = crate::openapi_json();
+        assert!(spec["paths"]["/debug/dump-config"]["get"].is_object());
+    }
+
+    #[tokio::test]
+    async fn dumps_config_masking_secrets() {
+        setup_tracing_once();
+        let mut ctx = crate::Context::mock(
+            InMemoryDb::fixture().with_role(USER_01_ID, crate::auth::Role::SuperAdmin),
+        );
+        ctx.config.request_signing_key = Some("shared secret".into());
+        let resp = crate::RouterBuilder::new()
+            .build()
+            .layer(axum::Extension(std::sync::Arc::new(ctx)))
+            .oneshot(
+                http::Request::builder()
+                    .method("GET")
+                    .uri("/debug/dump-config")
+                    .header(
+                        http::header::AUTHORIZATION,
+                        format!("Bearer {USER_01_SESSION}"),
+                    )
+                    .body(Default::default())
+                    .unwrap_or_log(),
+            )
+            .await
+            .unwrap_or_log();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(resp.into_body())
+            .await
+            .unwrap_or_log();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap_or_log();
+        check_json(
+            (
+                "expected",
+                &serde_json::json!({
+                    "db_max_connections": "5",
+                    "request_signing_key": crate::utils::MASK,
+                    "pass_salt_hash": crate::utils::MASK,
+                }),
+            ),
+            ("response", &body),
+        );
+        assert!(!body.to_string().contains("shared secret"));
+    }
+}
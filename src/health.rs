@@ -0,0 +1,710 @@
+use deps::*;
+
+use crate::*;
+
+use crate::db::MigrationStatus;
+
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, utoipa::ToSchema)]
+#[serde(crate = "serde", rename_all = "camelCase")]
+pub enum HealthStatus {
+    Up,
+    Degraded,
+    Down,
+}
+
+#[derive(Debug, Serialize, utoipa::ToSchema)]
+#[serde(crate = "serde", rename_all = "camelCase")]
+pub struct ComponentHealth {
+    #[schema(example = "db")]
+    pub name: String,
+    pub status: HealthStatus,
+    /// How long the probe took in milliseconds.
+    pub latency_ms: f64,
+    /// Size of the connection pool if the component has one.
+    pub connections: Option<u32>,
+    pub idle_connections: Option<u32>,
+    /// What went wrong if the component isn't up.
+    pub message: Option<String>,
+}
+
+#[derive(Debug, Serialize, utoipa::ToSchema)]
+#[serde(crate = "serde", rename_all = "camelCase")]
+pub struct HealthReport {
+    /// The worst status of all the components.
+    pub status: HealthStatus,
+    pub components: Vec<ComponentHealth>,
+    /// Missing if it couldn't be determined.
+    pub migrations: Option<MigrationStatus>,
+}
+
+impl HealthReport {
+    pub fn new(components: Vec<ComponentHealth>) -> Self {
+        Self {
+            status: components
+                .iter()
+                .map(|comp| comp.status)
+                .max()
+                .unwrap_or(HealthStatus::Up),
+            components,
+            migrations: None,
+        }
+    }
+}
+
+/// Probes slower than this are reported as [`HealthStatus::Degraded`].
+pub const DEGRADED_LATENCY: std::time::Duration = std::time::Duration::from_millis(500);
+/// Probes slower than this are reported as [`HealthStatus::Down`].
+pub const PROBE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(3);
+
+/// The infrastructure rather than the API: the probe, the metrics, the admin
+/// endpoints and the docs. They're served during maintenance, outside of any
+/// tenant and without a request signature, so that the orchestrator, the scraper
+/// and the operators needn't pick a tenant or know the key. The admin endpoints
+/// still want a superadmin. There's no separate `/health`, [`Ready`] is the probe.
+pub const INFRA_PATHS: &[&str] = &[
+    <Ready as HttpEndpoint>::PATH,
+    "/metrics",
+    <maintenance::SetMaintenance as HttpEndpoint>::PATH,
+    <reload::Reload as HttpEndpoint>::PATH,
+    "/api-doc/openapi.json",
+    "/api-doc/openapi.yaml",
+    "/asyncapi.json",
+];
+
+/// Like the [`INFRA_PATHS`] but for everything under them, Swagger UI's assets.
+pub const INFRA_PREFIXES: &[&str] = &["/swagger-ui/"];
+
+pub fn is_infra_path(path: &str) -> bool {
+    INFRA_PATHS.contains(&path) || INFRA_PREFIXES.iter().any(|prefix| path.starts_with(prefix))
+}
+
+/// An external service the readiness depends on, registered in
+/// [`Context::dependency_checks`]. Failing or timing out the probe takes the
+/// service down if it's among the [`Config::required_dependencies`], degrades it
+/// otherwise. Being slow degrades it either way.
+#[async_trait::async_trait]
+pub trait DependencyCheck: std::fmt::Debug + Send + Sync + 'static {
+    /// Reported as the name of the component.
+    fn name(&self) -> &str;
+
+    /// What went wrong if it isn't usable.
+    async fn check(&self) -> Result<(), String>;
+}
+
+/// Whether [`Context::warm_up`] is still pending.
+#[derive(Debug, Default)]
+pub struct Warmup(std::sync::atomic::AtomicBool);
+
+impl Warmup {
+    pub fn new(pending: bool) -> Self {
+        Self(pending.into())
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.0.load(std::sync::atomic::Ordering::Acquire)
+    }
+}
+
+impl Context {
+    pub async fn health_check(&self) -> HealthReport {
+        let (migrations_health, migrations) = self.migrations_health().await;
+        let mut components = vec![self.db_health().await, migrations_health];
+        components.extend(
+            futures::future::join_all(
+                self.dependency_checks
+                    .iter()
+                    .map(|check| self.dependency_health(check.as_ref())),
+            )
+            .await,
+        );
+        if self.warmup.is_pending() {
+            components.push(ComponentHealth {
+                name: "warmup".into(),
+                status: HealthStatus::Down,
+                latency_ms: 0.,
+                connections: None,
+                idle_connections: None,
+                message: Some("warming up".into()),
+            });
+        }
+        let mut report = HealthReport::new(components);
+        report.migrations = migrations;
+        report
+    }
+
+    /// Opens [`Config::warmup_connections`] db connections, run after the
+    /// migrations and before the listener so the first requests don't wait on
+    /// them. [`health::Ready`] reports ready once it's done.
+    pub async fn warm_up(&self) -> Result<(), sqlx::Error> {
+        self.warm_up_with(async {}).await
+    }
+
+    /// [`Context::warm_up`] that also awaits `prime`, say filling caches,
+    /// alongside the connections.
+    pub async fn warm_up_with(&self, prime: impl Future<Output = ()>) -> Result<(), sqlx::Error> {
+        let count = self
+            .config
+            .warmup_connections
+            .min(self.config.db_max_connections);
+        let start = std::time::Instant::now();
+        let (conns, ()) = futures::join!(
+            futures::future::try_join_all((0..count).map(|_| self.db_pool.acquire())),
+            prime
+        );
+        // back to the pool as idle ones
+        drop(conns?);
+        self.warmup
+            .0
+            .store(false, std::sync::atomic::Ordering::Release);
+        tracing::info!(
+            connections = count,
+            latency_ms = start.elapsed().as_millis() as u64,
+            "warmed up"
+        );
+        Ok(())
+    }
+
+    /// Pending migrations make the service [`HealthStatus::Down`] unless
+    /// [`Config::auto_migrate`] is on, in which case they're about to be applied.
+    async fn migrations_health(&self) -> (ComponentHealth, Option<db::MigrationStatus>) {
+        let start = std::time::Instant::now();
+        let result = tokio::time::timeout(PROBE_TIMEOUT, db::migration_status(&self.db_pool)).await;
+        let latency = start.elapsed();
+        let (status, message, migrations) = match result {
+            Ok(Ok(migrations)) if migrations.pending.is_empty() => {
+                (HealthStatus::Up, None, Some(migrations))
+            }
+            Ok(Ok(migrations)) => (
+                if self.config.auto_migrate {
+                    HealthStatus::Degraded
+                } else {
+                    HealthStatus::Down
+                },
+                Some(format!("{} pending migrations", migrations.pending.len())),
+                Some(migrations),
+            ),
+            Ok(Err(err)) => (HealthStatus::Down, Some(err.to_string()), None),
+            Err(_) => (
+                HealthStatus::Down,
+                Some("probe timed out".to_string()),
+                None,
+            ),
+        };
+        (
+            ComponentHealth {
+                name: "migrations".into(),
+                status,
+                latency_ms: latency.as_secs_f64() * 1000.,
+                connections: None,
+                idle_connections: None,
+                message,
+            },
+            migrations,
+        )
+    }
+
+    async fn dependency_health(&self, check: &dyn DependencyCheck) -> ComponentHealth {
+        let start = std::time::Instant::now();
+        let result = tokio::time::timeout(self.config.dependency_timeout, check.check()).await;
+        let latency = start.elapsed();
+        let failed = if self
+            .config
+            .required_dependencies
+            .iter()
+            .any(|name| name == check.name())
+        {
+            HealthStatus::Down
+        } else {
+            HealthStatus::Degraded
+        };
+        let (status, message) = match result {
+            Ok(Ok(())) if latency > DEGRADED_LATENCY => (HealthStatus::Degraded, None),
+            Ok(Ok(())) => (HealthStatus::Up, None),
+            Ok(Err(err)) => (failed, Some(err)),
+            Err(_) => (failed, Some("probe timed out".to_string())),
+        };
+        ComponentHealth {
+            name: check.name().into(),
+            status,
+            latency_ms: latency.as_secs_f64() * 1000.,
+            connections: None,
+            idle_connections: None,
+            message,
+        }
+    }
+
+    async fn db_health(&self) -> ComponentHealth {
+        let start = std::time::Instant::now();
+        let result = tokio::time::timeout(
+            PROBE_TIMEOUT,
+            sqlx::query("SELECT 1").execute(&self.db_pool),
+        )
+        .await;
+        let latency = start.elapsed();
+        let (status, message) = match result {
+            Ok(Ok(_)) if latency > DEGRADED_LATENCY => (HealthStatus::Degraded, None),
+            Ok(Ok(_)) => (HealthStatus::Up, None),
+            Ok(Err(err)) => (HealthStatus::Down, Some(err.to_string())),
+            Err(_) => (HealthStatus::Down, Some("probe timed out".to_string())),
+        };
+        ComponentHealth {
+            name: "db".into(),
+            status,
+            latency_ms: latency.as_secs_f64() * 1000.,
+            connections: Some(self.db_pool.size()),
+            idle_connections: Some(self.db_pool.num_idle() as u32),
+            message,
+        }
+    }
+}
+
+pub const TAG: Tag = Tag {
+    name: "health",
+    desc: "Service health reporting.",
+};
+
+pub fn router() -> axum::Router {
+    axum::Router::new()
+        .merge(EndpointWrapper::new(Ready))
+        .layer(axum::middleware::from_fn(crate::method_not_allowed_body))
+}
+
+pub fn components(builder: openapi::ComponentsBuilder) -> openapi::ComponentsBuilder {
+    let builder = Ready::components(builder);
+    builder
+        .schema(
+            type_name_raw::<HealthStatus>(),
+            <HealthStatus as utoipa::ToSchema>::schema(),
+        )
+        .schema(
+            type_name_raw::<ComponentHealth>(),
+            <ComponentHealth as utoipa::ToSchema>::schema(),
+        )
+        .schema(
+            type_name_raw::<HealthReport>(),
+            <HealthReport as utoipa::ToSchema>::schema(),
+        )
+        .schema(
+            type_name_raw::<db::MigrationStatus>(),
+            <db::MigrationStatus as utoipa::ToSchema>::schema(),
+        )
+}
+
+pub fn paths(paths: crate::SpecPaths) -> crate::SpecPaths {
+    paths.endpoint::<Ready>()
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Ready;
+
+#[derive(Debug, Serialize, thiserror::Error, utoipa::ToSchema)]
+#[serde(crate = "serde", rename_all = "camelCase", tag = "error")]
+pub enum Error {
+    #[error("service unavailable: {report:?}")]
+    Unavailable { report: HealthReport },
+}
+
+#[async_trait::async_trait]
+impl Endpoint for Ready {
+    type Request = ();
+    type Response = Ref<HealthReport>;
+    type Error = Error;
+
+    async fn handle(
+        &self,
+        ctx: &crate::Context,
+        _request: Self::Request,
+    ) -> Result<Self::Response, Self::Error> {
+        let report = ctx.health_check().await;
+        if report.status == HealthStatus::Down {
+            Err(Error::Unavailable { report })
+        } else {
+            Ok(report.into())
+        }
+    }
+}
+
+impl From<&Error> for StatusCode {
+    fn from(err: &Error) -> Self {
+        use Error::*;
+        match err {
+            Unavailable { .. } => Self::SERVICE_UNAVAILABLE,
+        }
+    }
+}
+
+impl crate::utils::ErrorCode for Error {
+    fn code(&self) -> &'static str {
+        use Error::*;
+        match self {
+            Unavailable { .. } => "health.unavailable",
+        }
+    }
+}
+
+impl HttpEndpoint for Ready {
+    const METHOD: Method = Method::Get;
+    const PATH: &'static str = "/ready";
+
+    type HttpRequest = ();
+
+    fn request(_: Self::HttpRequest) -> Result<Self::Request, Self::Error> {
+        Ok(())
+    }
+
+    fn response(Ref(resp): Self::Response) -> axum::response::Response {
+        Json(resp).into_response()
+    }
+}
+
+impl DocumentedEndpoint for Ready {
+    const TAG: &'static Tag = &TAG;
+    const SUMMARY: &'static str = "Readiness probe.";
+
+    fn success_examples() -> Vec<serde_json::Value> {
+        let mut report = HealthReport::new(vec![
+            ComponentHealth {
+                name: "db".into(),
+                status: HealthStatus::Up,
+                latency_ms: 1.5,
+                connections: Some(2),
+                idle_connections: Some(1),
+                message: None,
+            },
+            ComponentHealth {
+                name: "migrations".into(),
+                status: HealthStatus::Up,
+                latency_ms: 2.,
+                connections: None,
+                idle_connections: None,
+                message: None,
+            },
+        ]);
+        report.migrations = Some(db::MigrationStatus {
+            applied_version: Some(20221016090000),
+            expected_version: Some(20221016090000),
+            pending: vec![],
+        });
+        [report]
+            .into_iter()
+            .map(serde_json::to_value)
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    fn errors() -> Vec<ErrorResponse<Self::Error>> {
+        vec![(
+            "Service unavailable",
+            Error::Unavailable {
+                report: HealthReport::new(vec![ComponentHealth {
+                    name: "db".into(),
+                    status: HealthStatus::Down,
+                    latency_ms: 3000.,
+                    connections: Some(0),
+                    idle_connections: Some(0),
+                    message: Some("probe timed out".into()),
+                }]),
+            },
+        )]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use deps::*;
+
+    use crate::utils::testing::*;
+
+    crate::integration_table_tests! {
+        ready_when_all_up: {
+            uri: "/ready",
+            method: "GET",
+            status: StatusCode::OK,
+            router: crate::health::router(),
+            check_json: serde_json::json!({
+                "components": [{ "name": "db" }, { "name": "migrations" }],
+            }),
+        },
+    }
+
+    #[tokio::test]
+    async fn unavailable_when_db_down() {
+        setup_tracing_once();
+        let mut ctx = crate::Context::mock(InMemoryDb::default());
+        // nothing's listening on port 1
+        ctx.db_pool = sqlx::postgres::PgPoolOptions::new()
+            .acquire_timeout(std::time::Duration::from_millis(250))
+            .connect_lazy_with(
+                sqlx::postgres::PgConnectOptions::new()
+                    .host("127.0.0.1")
+                    .port(1),
+            );
+        let app = crate::health::router().layer(axum::Extension(std::sync::Arc::new(ctx)));
+        let resp = app
+            .oneshot(
+                http::Request::builder()
+                    .method("GET")
+                    .uri("/ready")
+                    .body(Default::default())
+                    .unwrap_or_log(),
+            )
+            .await
+            .unwrap_or_log();
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body = hyper::body::to_bytes(resp.into_body())
+            .await
+            .unwrap_or_log();
+        let body = serde_json::from_slice(&body).unwrap_or_log();
+        check_json(
+            (
+                "expected",
+                &serde_json::json!({
+                    "error": "unavailable",
+                    "report": {
+                        "status": "down",
+                        "components": [
+                            { "name": "db", "status": "down" },
+                            { "name": "migrations", "status": "down" },
+                        ],
+                    },
+                }),
+            ),
+            ("response", &body),
+        );
+    }
+
+    #[tokio::test]
+    async fn not_ready_until_warmed_up() {
+        let ctx = TestContext::new(crate::function!()).await;
+        {
+            let mut config = test_config();
+            config.warmup_connections = 3;
+            let warmed = std::sync::Arc::new(crate::Context::new(
+                sqlx::postgres::PgPoolOptions::new()
+                    .max_connections(config.db_max_connections)
+                    .connect_lazy_with(ctx.ctx().db_pool.connect_options().clone()),
+                config,
+            ));
+            let ready = || {
+                crate::health::router()
+                    .layer(axum::Extension(warmed.clone()))
+                    .oneshot(
+                        http::Request::builder()
+                            .method("GET")
+                            .uri("/ready")
+                            .body(Default::default())
+                            .unwrap_or_log(),
+                    )
+            };
+            let resp = ready().await.unwrap_or_log();
+            assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+            let body = hyper::body::to_bytes(resp.into_body())
+                .await
+                .unwrap_or_log();
+            let body = serde_json::from_slice(&body).unwrap_or_log();
+            check_json(
+                (
+                    "expected",
+                    &serde_json::json!({
+                        "report": {
+                            "components": [
+                                { "name": "db", "status": "up" },
+                                { "name": "migrations", "status": "up" },
+                                { "name": "warmup", "status": "down" },
+                            ],
+                        },
+                    }),
+                ),
+                ("response", &body),
+            );
+
+            warmed.warm_up().await.unwrap_or_log();
+            assert!(warmed.db_pool.size() >= 3, "{}", warmed.db_pool.size());
+            // the pool takes the connections back on spawned tasks
+            for _ in 0..100 {
+                if warmed.db_pool.num_idle() >= 3 {
+                    break;
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+            assert!(
+                warmed.db_pool.num_idle() >= 3,
+                "{}",
+                warmed.db_pool.num_idle()
+            );
+            assert_eq!(ready().await.unwrap_or_log().status(), StatusCode::OK);
+        }
+        ctx.close().await;
+    }
+
+    #[derive(Debug)]
+    struct Payments(Result<(), &'static str>, std::time::Duration);
+
+    #[async_trait::async_trait]
+    impl crate::health::DependencyCheck for Payments {
+        fn name(&self) -> &str {
+            "payments"
+        }
+
+        async fn check(&self) -> Result<(), String> {
+            tokio::time::sleep(self.1).await;
+            self.0.map_err(String::from)
+        }
+    }
+
+    #[tokio::test]
+    async fn probes_dependencies() {
+        let ctx = TestContext::new(crate::function!()).await;
+        {
+            for (check, required, status, expected) in [
+                (
+                    Payments(Err("connection refused"), std::time::Duration::ZERO),
+                    false,
+                    StatusCode::OK,
+                    serde_json::json!({
+                        "status": "degraded",
+                        "components": [
+                            { "name": "db", "status": "up" },
+                            { "name": "migrations", "status": "up" },
+                            {
+                                "name": "payments",
+                                "status": "degraded",
+                                "message": "connection refused",
+                            },
+                        ],
+                    }),
+                ),
+                (
+                    // past the dependency_timeout
+                    Payments(Ok(()), std::time::Duration::from_secs(60)),
+                    false,
+                    StatusCode::OK,
+                    serde_json::json!({
+                        "status": "degraded",
+                        "components": [
+                            {},
+                            {},
+                            { "name": "payments", "status": "degraded", "message": "probe timed out" },
+                        ],
+                    }),
+                ),
+                (
+                    Payments(Err("connection refused"), std::time::Duration::ZERO),
+                    true,
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    serde_json::json!({
+                        "error": "unavailable",
+                        "report": {
+                            "status": "down",
+                            "components": [{}, {}, { "name": "payments", "status": "down" }],
+                        },
+                    }),
+                ),
+            ] {
+                let mut config = test_config();
+                if required {
+                    config.required_dependencies = vec!["payments".into()];
+                }
+                let mut probed = crate::Context::new(ctx.ctx().db_pool.clone(), config);
+                probed.dependency_checks.push(std::sync::Arc::new(check));
+                let resp = crate::health::router()
+                    .layer(axum::Extension(std::sync::Arc::new(probed)))
+                    .oneshot(
+                        http::Request::builder()
+                            .method("GET")
+                            .uri("/ready")
+                            .body(Default::default())
+                            .unwrap_or_log(),
+                    )
+                    .await
+                    .unwrap_or_log();
+                assert_eq!(resp.status(), status);
+                let body = hyper::body::to_bytes(resp.into_body())
+                    .await
+                    .unwrap_or_log();
+                let body = serde_json::from_slice(&body).unwrap_or_log();
+                check_json(("expected", &expected), ("response", &body));
+            }
+        }
+        ctx.close().await;
+    }
+
+    #[tokio::test]
+    async fn reports_pending_migrations() {
+        let ctx = TestContext::new(crate::function!()).await;
+        {
+            let latest = crate::db::MIGRATOR
+                .iter()
+                .map(|migration| migration.version)
+                .max()
+                .unwrap();
+            // roll the bookkeeping back a migration, the schema itself doesn't matter
+            sqlx::query("DELETE FROM _sqlx_migrations WHERE version = $1")
+                .bind(latest)
+                .execute(&ctx.ctx().db_pool)
+                .await
+                .unwrap_or_log();
+            let app = crate::health::router().layer(axum::Extension(ctx.ctx()));
+            let resp = app
+                .oneshot(
+                    http::Request::builder()
+                        .method("GET")
+                        .uri("/ready")
+                        .body(Default::default())
+                        .unwrap_or_log(),
+                )
+                .await
+                .unwrap_or_log();
+            assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+            let body = hyper::body::to_bytes(resp.into_body())
+                .await
+                .unwrap_or_log();
+            let body = serde_json::from_slice(&body).unwrap_or_log();
+            check_json(
+                (
+                    "expected",
+                    &serde_json::json!({
+                        "error": "unavailable",
+                        "report": {
+                            "status": "down",
+                            "components": [
+                                { "name": "db", "status": "up" },
+                                { "name": "migrations", "status": "down" },
+                            ],
+                            "migrations": {
+                                "expectedVersion": latest,
+                                "pending": [latest],
+                            },
+                        },
+                    }),
+                ),
+                ("response", &body),
+            );
+        }
+        ctx.close().await;
+    }
+
+    #[tokio::test]
+    async fn migration_probe_is_read_only() {
+        let ctx = TestContext::new(crate::function!()).await;
+        {
+            let pool = &ctx.ctx().db_pool;
+            sqlx::query("DROP TABLE _sqlx_migrations")
+                .execute(pool)
+                .await
+                .unwrap_or_log();
+            let status = crate::db::migration_status(pool).await.unwrap_or_log();
+            assert_eq!(status.applied_version, None);
+            assert_eq!(status.pending.len(), crate::db::MIGRATOR.iter().count());
+            let has_table: bool =
+                sqlx::query_scalar("SELECT to_regclass('_sqlx_migrations') IS NOT NULL")
+                    .fetch_one(pool)
+                    .await
+                    .unwrap_or_log();
+            assert!(!has_table);
+        }
+        ctx.close().await;
+    }
+}
@@ -7,7 +7,18 @@ use dylink;
 use deps::*;
 
 pub mod auth;
+pub mod counters;
+pub mod db;
+#[cfg(debug_assertions)]
+pub mod debug;
+pub mod health;
 pub mod macros;
+pub mod maintenance;
+pub mod metrics;
+pub mod reload;
+pub mod scheduler;
+pub mod serve;
+pub mod tenant;
 pub mod user;
 pub mod utils;
 
@@ -28,13 +39,21 @@ pub fn setup_tracing() -> eyre::Result<()> {
         std::env::set_var("RUST_LOG", "info");
     }
 
-    tracing_subscriber::fmt()
-        // .pretty()
-        .compact()
-        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
-        .with_timer(tracing_subscriber::fmt::time::uptime())
+    use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+    // behind a reload layer so the level can be changed at runtime, see `reload`
+    let (filter, log_filter) =
+        tracing_subscriber::reload::Layer::new(tracing_subscriber::EnvFilter::from_default_env());
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(
+            tracing_subscriber::fmt::layer()
+                // .pretty()
+                .compact()
+                .with_timer(tracing_subscriber::fmt::time::uptime()),
+        )
         .try_init()
         .map_err(|err| eyre::eyre!(err))?;
+    reload::set_log_filter(log_filter);
 
     Ok(())
 }
@@ -44,12 +63,273 @@ pub struct Config {
     pub pass_salt_hash: Vec<u8>,
     pub argon2_conf: argon2::Config<'static>,
     pub auth_token_lifespan: time::Duration,
+    /// Permissions aren't enforced if this is `None`.
+    pub permissions: Option<auth::PermissionsMatrix>,
+    /// Route `/users/` the same as `/users`. See [`trim_trailing_slash`].
+    pub trim_trailing_slash: bool,
+    /// Used to build the `db_pool` and reported in the [`metrics::PoolMetrics`].
+    pub db_max_connections: u32,
+    /// How often the [`scheduler::Scheduler`] runs [`Context::sample_pool_metrics`].
+    /// Not zero.
+    pub pool_metrics_interval: std::time::Duration,
+    /// Most responses kept by the [`ResponseCache`]. Zero disables caching.
+    pub response_cache_capacity: usize,
+    /// Whether to add the [`utils::security_headers`] to responses.
+    pub security_headers: bool,
+    /// Statements taking longer than this are logged at `warn`. See
+    /// [`db::log_slow_queries`].
+    pub slow_query_threshold: std::time::Duration,
+    /// Serve over HTTPS if set. See [`serve::serve`].
+    pub tls: Option<serve::TlsConfig>,
+    /// Locales requests can negotiate through `Accept-Language`, the first one
+    /// being the fallback. See [`utils::RequestContext`].
+    pub locales: Vec<String>,
+    /// Most ids a single [`user`] bulk delete may ask for.
+    pub bulk_delete_max: usize,
+    /// Consecutive outage errors that open the [`CircuitBreaker`]. Zero disables it.
+    pub db_breaker_threshold: u32,
+    /// How long the [`CircuitBreaker`] stays open before probing the db again.
+    pub db_breaker_cooldown: std::time::Duration,
+    /// Indent all json responses. Debug builds also do so on `?pretty=true`. See
+    /// [`utils::pretty_json`].
+    pub pretty_json: bool,
+    /// Longest request target accepted by [`reject_long_uri`].
+    pub max_uri_len: usize,
+    /// Requests allowed per client in a window, unlimited if not set. See
+    /// [`utils::rate_limit`]. Can be [`reload`]ed.
+    pub rate_limit: Option<RateLimit>,
+    /// How long a request may take before [`utils::enforce_deadline`] gives up on
+    /// it, no limit if not set.
+    pub request_timeout: Option<std::time::Duration>,
+    /// Serve the [`metrics::router`] on its own at this address, say one only
+    /// reachable internally, instead of on the main listener. See
+    /// [`metrics::spawn_exporter`].
+    pub metrics_bind_addr: Option<std::net::SocketAddr>,
+    /// Refuse to start if the metrics exporter can't be set up instead of
+    /// carrying on without it.
+    pub metrics_required: bool,
+    /// Back [`Context::cache`] with this redis instead of memory. Needs the
+    /// `redis-cache` feature.
+    pub redis_url: Option<String>,
+    /// Most entries the in memory [`Context::cache`] holds. Zero disables it, and
+    /// with it signed requests as their nonces can't be checked.
+    pub cache_capacity: usize,
+    /// How long shutdown as a whole waits on in-flight requests, background tasks
+    /// and then the `db_pool` before moving on regardless. See [`db::close_pool`].
+    pub shutdown_timeout: std::time::Duration,
+    /// Origins allowed to make cross origin requests, `*` for any. CORS is off if
+    /// empty. See [`utils::cors`].
+    pub cors_origins: Vec<String>,
+    /// Scope every request to a [`tenant::Tenant`], see [`tenant::resolve_tenant`].
+    pub multi_tenant: bool,
+    /// Requests to `<tenant>.<tenant_base_domain>` are scoped to the tenant when
+    /// they don't carry the [`tenant::HEADER`].
+    pub tenant_base_domain: Option<String>,
+    /// Most tenants with a pool open at once, see [`tenant::TenantDb`]. Their
+    /// pools share the [`Config::db_max_connections`] between them.
+    pub max_tenants: usize,
+    /// Largest request body accepted, in bytes, except for uploads. See
+    /// [`utils::limit_body`].
+    pub body_limit: usize,
+    /// Largest `multipart/form-data` body accepted, in bytes.
+    pub upload_body_limit: usize,
+    /// Start out in [`maintenance`] mode.
+    pub maintenance_mode: bool,
+    /// Apply pending [`db::MIGRATOR`] migrations on startup, and to the schema of
+    /// each tenant when it's first admitted. If off, pending ones fail the
+    /// readiness probe, see [`health::Ready`], and the requests of the tenant.
+    pub auto_migrate: bool,
+    /// Reject json bodies with fields the endpoint doesn't know of instead of
+    /// ignoring them. See [`HttpEndpoint::STRICT_BODY`].
+    pub strict_request_bodies: bool,
+    /// Proxies whose `Forwarded` and `X-Forwarded-For` headers are believed. See
+    /// [`utils::ClientIp`].
+    pub trusted_proxies: Vec<utils::Cidr>,
+    /// Require requests to be signed with this key, see [`auth::signature`].
+    pub request_signing_key: Option<String>,
+    /// How far a signed request's timestamp may be from the present.
+    pub signature_window: std::time::Duration,
+    /// Idle time before TCP keepalive probes are sent on accepted connections,
+    /// off if not set.
+    pub tcp_keepalive: Option<std::time::Duration>,
+    /// How often to ping HTTP/2 clients, off if not set.
+    pub http2_keepalive_interval: Option<std::time::Duration>,
+    /// Connections served at once, unlimited if not set. See [`serve::serve`].
+    pub max_connections: Option<usize>,
+    /// How often [`counters::spawn_flusher`] writes the buffered counters out.
+    /// Not zero.
+    pub counter_flush_interval: std::time::Duration,
+    /// Buffered counter keys past which they're written out early, never if zero.
+    pub counter_flush_size: usize,
+    /// Send the details of `5xx` errors to clients instead of a generic message.
+    /// Meant for development. See [`utils::render_internal_errors`].
+    pub expose_internal_errors: bool,
+    /// How deeply json bodies may nest, unlimited if zero. See
+    /// [`utils::JsonLimits`].
+    pub max_json_depth: usize,
+    /// Values a json body may hold, unlimited if zero.
+    pub max_json_elements: usize,
+    /// Flags of the [`HttpEndpoint::FEATURE_FLAG`] gated endpoints to serve. See
+    /// [`utils::feature_flags`]. Can be [`reload`]ed.
+    pub enabled_features: Vec<String>,
+    /// Requests taking longer than this are logged by
+    /// [`utils::slow_requests::log_slow_requests`], off if not set.
+    pub slow_request_threshold: Option<std::time::Duration>,
+    /// Json fields whose values are masked wherever bodies are logged, e.g. in
+    /// [`utils::slow_requests`].
+    pub redacted_fields: Vec<String>,
+    /// Compress responses the client accepts compressed. See
+    /// [`utils::compression`].
+    pub compress_responses: bool,
+    /// Content types, or prefixes thereof, of the responses never to compress.
+    pub uncompressed_content_types: Vec<String>,
+    /// Record every request and its response, debug builds only. See
+    /// [`utils::recording`].
+    pub record_exchanges: Option<utils::recording::RecordTo>,
+    /// Require this bearer token to scrape the [`metrics::router`].
+    pub metrics_token: Option<String>,
+    /// Db connections [`Context::warm_up`] opens before traffic is let in.
+    /// [`health::Ready`] reports not ready until it's done if it's not zero.
+    pub warmup_connections: u32,
+    /// How long each of the [`Context::dependency_checks`] gets before it's
+    /// counted as failed.
+    pub dependency_timeout: std::time::Duration,
+    /// Names of the [`health::DependencyCheck`]s whose failures take the service
+    /// down. The failures of the rest only degrade it.
+    pub required_dependencies: Vec<String>,
 }
 
 #[derive(Debug)]
 pub struct Context {
     pub db_pool: sqlx::postgres::PgPool,
+    pub db: std::sync::Arc<dyn db::DbBackend>,
     pub config: Config,
+    pub metrics: metrics::Metrics,
+    pub response_cache: ResponseCache,
+    /// Use this instead of [`time::OffsetDateTime::now_utc`].
+    pub clock: std::sync::Arc<dyn Clock>,
+    /// Use this instead of [`uuid::Uuid::new_v4`].
+    pub ids: std::sync::Arc<dyn IdGen>,
+    /// See [`Context::guard_db`].
+    pub db_breaker: CircuitBreaker,
+    /// Applied to the responses of every endpoint, see [`ResponseTransformer`].
+    pub response_transformers: Vec<std::sync::Arc<dyn ResponseTransformer>>,
+    /// See [`HttpEndpoint::SINGLEFLIGHT`].
+    pub singleflight: Singleflight,
+    pub rate_limiter: RateLimiter,
+    /// See [`utils::cache`].
+    pub cache: std::sync::Arc<dyn Cache>,
+    /// See [`maintenance`].
+    pub maintenance: maintenance::MaintenanceMode,
+    /// See [`counters`].
+    pub counters: counters::CounterBuffer,
+    /// See [`Context::warm_up`].
+    pub warmup: health::Warmup,
+    /// External services probed by [`health::Ready`].
+    pub dependency_checks: Vec<std::sync::Arc<dyn health::DependencyCheck>>,
+    /// The [`reload::Reloadable`] config as last reloaded.
+    pub live_config: reload::LiveConfig,
+}
+
+impl Context {
+    pub fn new(db_pool: sqlx::postgres::PgPool, config: Config) -> Self {
+        let clock: std::sync::Arc<dyn Clock> = std::sync::Arc::new(SystemClock);
+        Self {
+            db: if config.multi_tenant {
+                std::sync::Arc::new(tenant::TenantDb::postgres(
+                    db_pool.clone(),
+                    config.db_max_connections,
+                    config.max_tenants,
+                    config.auto_migrate,
+                ))
+            } else {
+                std::sync::Arc::new(db_pool.clone())
+            },
+            db_pool,
+            response_cache: ResponseCache::new(config.response_cache_capacity),
+            cache: utils::cache_from_config(&config, clock.clone()),
+            maintenance: maintenance::MaintenanceMode::new(config.maintenance_mode),
+            counters: counters::CounterBuffer::new(config.counter_flush_size),
+            warmup: health::Warmup::new(config.warmup_connections > 0),
+            db_breaker: CircuitBreaker::new(
+                config.db_breaker_threshold,
+                config.db_breaker_cooldown,
+            ),
+            config,
+            metrics: Default::default(),
+            clock,
+            ids: std::sync::Arc::new(RandomIds),
+            response_transformers: vec![],
+            dependency_checks: vec![],
+            live_config: Default::default(),
+            singleflight: Singleflight::new(),
+            rate_limiter: RateLimiter::new(),
+        }
+    }
+
+    /// Runs a db operation through the [`CircuitBreaker`] so that an unreachable
+    /// database fails requests fast instead of each waiting out the pool timeout.
+    /// The operation is dropped with a [`sqlx::Error::PoolTimedOut`] if it outlives
+    /// the [`utils::current_deadline`].
+    pub async fn guard_db<T>(
+        &self,
+        op: impl Future<Output = Result<T, sqlx::Error>>,
+    ) -> Result<T, sqlx::Error> {
+        let pool = self.pool().ok();
+        let op = self
+            .db_breaker
+            .guard(self.clock.as_ref(), pool.as_ref(), op);
+        match current_deadline() {
+            Some(deadline) => tokio::time::timeout_at(deadline, op)
+                .await
+                .unwrap_or(Err(sqlx::Error::PoolTimedOut)),
+            None => op.await,
+        }
+    }
+
+    /// The pool for queries that [`Context::db`] doesn't cover, scoped to the
+    /// [`tenant::current_tenant`] like it is. Errors rather than falling back to
+    /// the [`Context::db_pool`] if the tenant has none.
+    pub fn pool(&self) -> Result<sqlx::postgres::PgPool, sqlx::Error> {
+        if !self.config.multi_tenant {
+            return Ok(self.db_pool.clone());
+        }
+        self.db.pg_pool().ok_or_else(|| {
+            sqlx::Error::Configuration(
+                format!("no pool for tenant {:?}", tenant::current_tenant()).into(),
+            )
+        })
+    }
+
+    /// A connection from the [`Context::pool`], through [`Context::guard_db`],
+    /// for handlers that run several queries on one. How long the pool kept it
+    /// waiting is logged under [`db::ACQUIRE_TARGET`] with the
+    /// [`utils::current_request_id`].
+    pub async fn acquire(&self) -> Result<db::DbConn, sqlx::Error> {
+        let request_id = current_request_id();
+        let span = tracing::info_span!("db_conn", request_id = request_id.as_deref());
+        let start = std::time::Instant::now();
+        let pool = self.pool()?;
+        let conn = self.guard_db(pool.acquire()).await?;
+        let wait = start.elapsed();
+        tracing::debug!(
+            target: db::ACQUIRE_TARGET,
+            parent: &span,
+            request_id = request_id.as_deref(),
+            wait_ms = wait.as_millis() as u64,
+            "db connection acquired"
+        );
+        Ok(db::DbConn::new(conn, span))
+    }
+
+    /// A transaction on the [`Context::pool`], through [`Context::guard_db`].
+    /// Handlers of [`HttpEndpoint::DRY_RUN`] endpoints make their writes in one
+    /// as it's rolled back rather than committed on dry runs, see [`db::DbTx`].
+    pub async fn begin(&self) -> Result<db::DbTx, sqlx::Error> {
+        let pool = self.pool()?;
+        let tx = self.guard_db(pool.begin()).await?;
+        Ok(db::DbTx::new(tx))
+    }
 }
 
 pub type SharedContext = std::sync::Arc<Context>;
@@ -57,6 +337,41 @@ pub type SharedContext = std::sync::Arc<Context>;
 shadow_rs::shadow!(build);
 
 pub struct ApiDoc;
+
+fn external_docs((description, url): (&str, &str)) -> openapi::external_docs::ExternalDocs {
+    openapi::external_docs::ExternalDocsBuilder::new()
+        .url(url)
+        .description(Some(description))
+        .build()
+}
+
+impl ApiDoc {
+    /// The paths of every module along with their [`OperationExtras`].
+    pub fn paths() -> SpecPaths {
+        let paths = SpecPaths::new();
+        let paths = user::paths(paths);
+        let paths = auth::paths(paths);
+        let paths = health::paths(paths);
+        let paths = maintenance::paths(paths);
+        let paths = reload::paths(paths);
+        #[cfg(debug_assertions)]
+        let paths = debug::paths(paths);
+        paths
+    }
+
+    /// Lints the spec for the problems that trip up codegen, duplicate operation
+    /// ids included. Building the spec doesn't check for any of them so this is
+    /// meant to be asserted on in CI.
+    pub fn validate() -> Result<(), Vec<utils::spec_lint::SpecIssue>> {
+        let issues = utils::spec_lint::lint(&openapi_json());
+        if issues.is_empty() {
+            Ok(())
+        } else {
+            Err(issues)
+        }
+    }
+}
+
 impl utoipa::OpenApi for ApiDoc {
     fn openapi() -> openapi::OpenApi {
         let mut openapi = openapi::OpenApiBuilder::new()
@@ -64,22 +379,14 @@ impl utoipa::OpenApi for ApiDoc {
                 openapi::InfoBuilder::new()
                     .title(build::PROJECT_NAME)
                     .version(build::PKG_VERSION)
-                    .description(Some(format!(
-                        r#"{}
-
-Notes:
-- Time values are integers despite the `string($date-time)` type shown here.
-                        "#,
-                        build::PKG_DESCRIPTION
-                    )))
+                    .description(Some(build::PKG_DESCRIPTION))
                     .build(),
             )
-            .paths({
-                let builder = openapi::path::PathsBuilder::new();
-                let builder = user::paths(builder);
-                let builder = auth::paths(builder);
-                builder.build()
-            })
+            .external_docs(
+                (!env!("CARGO_PKG_REPOSITORY").is_empty())
+                    .then(|| external_docs(("Source and guides", env!("CARGO_PKG_REPOSITORY")))),
+            )
+            .paths(Self::paths().builder.build())
             .components(Some({
                 let builder = openapi::ComponentsBuilder::new();
                 let builder = builder
@@ -98,17 +405,44 @@ Notes:
                     .schema(
                         type_name_raw::<ValidationError>(),
                         <utils::ValidationError as utoipa::ToSchema>::schema(),
-                    );
+                    )
+                    .schema(
+                        type_name_raw::<Decimal>(),
+                        <utils::Decimal as utoipa::ToSchema>::schema(),
+                    )
+                    .schema(
+                        type_name_raw::<RoutingError>(),
+                        <RoutingError as utoipa::ToSchema>::schema(),
+                    )
+                    .schema(
+                        type_name_raw::<ApiError>(),
+                        add_code_property(
+                            <utils::ApiError as utoipa::ToSchema>::schema(),
+                            "ApiErrorCode",
+                        ),
+                    )
+                    .schema("ApiErrorCode", codes_schema(ApiError::codes()));
                 let builder = user::components(builder);
                 let builder = auth::components(builder);
+                let builder = health::components(builder);
+                let builder = maintenance::components(builder);
+                let builder = reload::components(builder);
+                #[cfg(debug_assertions)]
+                let builder = debug::components(builder);
                 builder.build()
             }))
             .tags(Some([
                 auth::TAG.into(),
                 user::TAG.into(),
+                health::TAG.into(),
+                maintenance::TAG.into(),
                 DEFAULT_TAG.into(),
             ]))
             .build();
+        #[cfg(debug_assertions)]
+        if let Some(tags) = openapi.tags.as_mut() {
+            tags.push(debug::TAG.into());
+        }
         if let Some(components) = openapi.components.as_mut() {
             use utoipa::openapi::security::*;
             components.add_security_scheme(
@@ -116,18 +450,282 @@ Notes:
                 SecurityScheme::Http(openapi::security::Http::new(
                     openapi::security::HttpAuthScheme::Bearer,
                 )),
-            )
+            );
+            components.add_security_scheme(
+                "api_key",
+                SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new(auth::api_key::HEADER))),
+            );
         }
         openapi
     }
 }
 
+/// The [`ApiDoc`] as served, with the [`OperationExtras`] expanded and the
+/// [`add_decimal_format`] and [`document_links`] added.
+pub fn openapi_json() -> serde_json::Value {
+    let mut spec = serde_json::to_value(<ApiDoc as utoipa::OpenApi>::openapi())
+        .expect("unable to serialize the OpenAPI spec");
+    let extras = ApiDoc::paths().extras;
+    named_examples::expand(&mut spec, &extras);
+    vendor_extensions::expand(&mut spec, &extras);
+    document_links(&mut spec);
+    add_decimal_format(&mut spec);
+    spec
+}
+
+/// The [`asyncapi`] document for the streaming endpoints. Each module adds its
+/// [`asyncapi::DocumentedChannel`]s here like it does its paths to the [`ApiDoc`].
+pub fn asyncapi_json() -> serde_json::Value {
+    asyncapi::DocumentBuilder::new().build(build::PROJECT_NAME, build::PKG_VERSION)
+}
+
+/// The [`openapi_json`] minus the endpoints whose [`HttpEndpoint::FEATURE_FLAG`]
+/// isn't enabled in the `ctx`, all of them if there's none.
+fn served_spec(
+    spec: &serde_json::Value,
+    ctx: Option<Extension<SharedContext>>,
+) -> serde_json::Value {
+    let mut spec = spec.clone();
+    let enabled_features = ctx
+        .map(|Extension(ctx)| ctx.enabled_features())
+        .unwrap_or_default();
+    feature_flags::strip_disabled(&mut spec, &enabled_features);
+    spec
+}
+
+/// Swagger UI and the json spec it's pointed at plus the same spec as YAML. The
+/// streaming endpoints are described separately at `/asyncapi.json`.
+pub fn docs_router() -> axum::Router {
+    let spec = std::sync::Arc::new(openapi_json());
+    let asyncapi = asyncapi_json();
+    // not `SwaggerUi::url` as that serves the spec without the named examples expanded
+    let swagger_conf =
+        std::sync::Arc::new(utoipa_swagger_ui::Config::from("/api-doc/openapi.json"));
+    axum::Router::new()
+        .route(
+            "/swagger-ui/*tail",
+            axum::routing::get(move |Path(tail): Path<String>| async move {
+                match utoipa_swagger_ui::serve(&tail[1..], swagger_conf) {
+                    Ok(Some(file)) => (
+                        [(axum::http::header::CONTENT_TYPE, file.content_type)],
+                        file.bytes.into_owned(),
+                    )
+                        .into_response(),
+                    Ok(None) => StatusCode::NOT_FOUND.into_response(),
+                    Err(err) => {
+                        (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response()
+                    }
+                }
+            }),
+        )
+        .route(
+            "/api-doc/openapi.json",
+            axum::routing::get({
+                let spec = spec.clone();
+                move |ctx: Option<Extension<SharedContext>>| async move {
+                    response::Json(served_spec(&spec, ctx))
+                }
+            }),
+        )
+        .route(
+            "/asyncapi.json",
+            axum::routing::get(move || async move { response::Json(asyncapi) }),
+        )
+        .route(
+            "/api-doc/openapi.yaml",
+            axum::routing::get(move |ctx: Option<Extension<SharedContext>>| async move {
+                (
+                    [(axum::http::header::CONTENT_TYPE, "application/yaml")],
+                    serde_yaml::to_string(&served_spec(&spec, ctx))
+                        .expect("unable to serialize spec to yaml"),
+                )
+            }),
+        )
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_yaml_spec_matches_json() {
+    use crate::utils::testing::*;
+    let app = docs_router();
+    let mut specs = vec![];
+    for uri in ["/api-doc/openapi.json", "/api-doc/openapi.yaml"] {
+        let resp = app
+            .clone()
+            .oneshot(
+                http::Request::builder()
+                    .method("GET")
+                    .uri(uri)
+                    .body(Default::default())
+                    .unwrap_or_log(),
+            )
+            .await
+            .unwrap_or_log();
+        assert_eq!(resp.status(), StatusCode::OK, "failed on {uri}");
+        specs.push((
+            resp.headers()
+                .get(http::header::CONTENT_TYPE)
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .to_string(),
+            hyper::body::to_bytes(resp.into_body())
+                .await
+                .unwrap_or_log(),
+        ));
+    }
+    assert_eq!(specs[1].0, "application/yaml");
+    let json: serde_json::Value = serde_json::from_slice(&specs[0].1).unwrap_or_log();
+    let yaml: serde_json::Value = serde_yaml::from_slice(&specs[1].1).unwrap_or_log();
+    assert_eq!(json, yaml);
+}
+
+#[cfg(all(test, not(debug_assertions)))]
+#[test]
+fn test_release_spec_has_no_debug_endpoints() {
+    let spec = openapi_json();
+    let paths = spec["paths"].as_object().unwrap();
+    assert!(
+        paths.keys().all(|path| !path.starts_with("/debug/")),
+        "{:?}",
+        paths.keys().collect::<Vec<_>>()
+    );
+}
+
+/// Composes the endpoint derived routers with whatever else a consumer of the
+/// template needs before handing back the final [`axum::Router`].
+#[derive(Debug)]
+pub struct RouterBuilder {
+    router: axum::Router,
+}
+
+impl Default for RouterBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RouterBuilder {
+    /// Starts with the routers of all the endpoint modules merged together, the
+    /// `debug` one only in debug builds. The docs aren't included, see
+    /// [`docs_router`].
+    pub fn new() -> Self {
+        let router = axum::Router::new()
+            .merge(user::router())
+            .merge(auth::router())
+            .merge(health::router())
+            .merge(maintenance::router())
+            .merge(reload::router())
+            .merge(metrics::public_router());
+        #[cfg(debug_assertions)]
+        let router = router.merge(debug::router());
+        Self { router }
+    }
+
+    pub fn route<T>(mut self, path: &str, service: T) -> Self
+    where
+        T: tower::Service<
+                axum::http::Request<axum::body::Body>,
+                Response = axum::response::Response,
+                Error = std::convert::Infallible,
+            > + Clone
+            + Send
+            + 'static,
+        T::Future: Send + 'static,
+    {
+        self.router = self.router.route(path, service);
+        self
+    }
+
+    pub fn merge(mut self, other: impl Into<axum::Router>) -> Self {
+        self.router = self.router.merge(other);
+        self
+    }
+
+    /// Only wraps the routes added so far.
+    pub fn layer<L, NewResBody>(mut self, layer: L) -> Self
+    where
+        L: tower::Layer<axum::routing::Route>,
+        L::Service: tower::Service<
+                axum::http::Request<axum::body::Body>,
+                Response = axum::http::Response<NewResBody>,
+                Error = std::convert::Infallible,
+            > + Clone
+            + Send
+            + 'static,
+        <L::Service as tower::Service<axum::http::Request<axum::body::Body>>>::Future:
+            Send + 'static,
+        NewResBody: axum::body::HttpBody<Data = axum::body::Bytes> + Send + 'static,
+        NewResBody::Error: Into<axum::BoxError>,
+    {
+        self.router = self.router.layer(layer);
+        self
+    }
+
+    /// Adds the [`not_found`] fallback. Nothing can be merged into the result
+    /// since axum doesn't allow merging two routers that have fallbacks.
+    pub fn build(self) -> axum::Router {
+        use axum::handler::Handler;
+        self.router.fallback(not_found.into_service())
+    }
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_router_builder_custom_routes() {
+    use crate::user::testing::*;
+    use crate::utils::testing::*;
+    setup_tracing_once();
+    let app = RouterBuilder::new()
+        .route("/custom", axum::routing::get(|| async { "custom" }))
+        .build()
+        .layer(axum::Extension(std::sync::Arc::new(Context::mock(
+            InMemoryDb::fixture(),
+        ))));
+    let resp = app
+        .clone()
+        .oneshot(
+            http::Request::builder()
+                .method("GET")
+                .uri("/custom")
+                .body(Default::default())
+                .unwrap_or_log(),
+        )
+        .await
+        .unwrap_or_log();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body = hyper::body::to_bytes(resp.into_body())
+        .await
+        .unwrap_or_log();
+    assert_eq!(&body[..], b"custom");
+
+    let resp = app
+        .oneshot(
+            http::Request::builder()
+                .method("GET")
+                .uri(format!("/users/{USER_01_ID}"))
+                .header(
+                    http::header::AUTHORIZATION,
+                    format!("Bearer {USER_01_SESSION}"),
+                )
+                .body(Default::default())
+                .unwrap_or_log(),
+        )
+        .await
+        .unwrap_or_log();
+    assert_eq!(resp.status(), StatusCode::OK);
+}
+
 #[async_trait::async_trait]
 pub trait Endpoint: Send + Sync + 'static {
     type Request: Send + Sync + 'static;
     type Response;
     type Error;
 
+    /// Stable, client facing operation id, see [`operation_id`]. Set it on the
+    /// [`AuthenticatedEndpoint`] impl instead for those.
+    const OPERATION_ID: Option<&'static str> = None;
+
     async fn handle(
         &self,
         ctx: &crate::Context,
@@ -141,6 +739,9 @@ pub trait AuthenticatedEndpoint: Send + Sync + 'static {
     type Response;
     type Error: From<auth::authorize::Error>;
 
+    /// Becomes the [`Endpoint::OPERATION_ID`].
+    const OPERATION_ID: Option<&'static str> = None;
+
     fn authorize_request(&self, request: &Self::Request) -> crate::auth::authorize::Request;
 
     async fn handle(
@@ -165,6 +766,8 @@ where
     type Response = T::Response;
     type Error = T::Error;
 
+    const OPERATION_ID: Option<&'static str> = <T as AuthenticatedEndpoint>::OPERATION_ID;
+
     async fn handle(
         &self,
         ctx: &crate::Context,
@@ -176,44 +779,339 @@ where
                 .handle(ctx, auth_args)
                 .await?
         };
+        crate::auth::authorize::check_permissions(ctx, accessing_user, operation_id::<Self>())
+            .await?;
         self.handle(ctx, accessing_user, request).await
     }
 }
 
+/// The [`Endpoint::OPERATION_ID`] or else the [`utils::type_name_raw`] of `T`.
+/// It's the operation id in the spec and what the [`auth::PermissionsMatrix`] is
+/// looked up by.
+pub fn operation_id<T: Endpoint>() -> &'static str {
+    T::OPERATION_ID.unwrap_or_else(type_name_raw::<T>)
+}
+
 pub trait HttpEndpoint: Endpoint + Clone
 where
-    Self::Error: serde::Serialize,
+    Self::Error: serde::Serialize + ErrorCode,
     for<'a> &'a Self::Error: Into<StatusCode>,
 {
     const METHOD: Method;
     const PATH: &'static str;
-    type HttpRequest: axum::extract::FromRequest<axum::body::Body> + Send + Sync + 'static;
+    type HttpRequest: axum::extract::FromRequest<axum::body::Body>
+        + DocumentedParameter
+        + Send
+        + Sync
+        + 'static;
     // FIXME: this is superflous and can be covered by the `response` call
     const SUCCESS_CODE: StatusCode = StatusCode::OK;
+    /// Successful responses to `GET` and `HEAD` requests are served from the
+    /// [`ResponseCache`] for this long if set.
+    const CACHE_TTL: Option<std::time::Duration> = None;
+    /// Whether the `Authorization` header is part of the cache key. Ignored if the
+    /// [`HttpEndpoint::HttpRequest`] takes credentials as a hit would skip their
+    /// authorization.
+    const CACHE_PER_AUTH: bool = true;
+    /// Most requests handled at once if set. Requests past that are shed with a
+    /// `503` instead of being queued.
+    const MAX_CONCURRENCY: Option<usize> = None;
+    /// Honor `Prefer: return=minimal` by answering successes with an empty `204`
+    /// and a `Preference-Applied` header. Meant for writes.
+    const RETURN_MINIMAL: bool = false;
+    /// Record successful requests in the [`audit`] trail under this action name if
+    /// set. Meant for writes.
+    const AUDIT_ACTION: Option<&'static str> = None;
+    /// Run responses through the [`Context::response_transformers`]. Opt out for
+    /// responses whose shape can't change, e.g. streams.
+    const TRANSFORM_RESPONSE: bool = true;
+    /// Share the response of an in-flight `GET` or `HEAD` with the identical ones
+    /// that come in while it runs instead of handling each. Identical as in same
+    /// [`ResponseCache::key`]. Meant for hot reads.
+    const SINGLEFLIGHT: bool = false;
+    /// Answer `Range` requests with a slice of the body, see [`byte_range`]. Meant
+    /// for endpoints returning files or other byte streams.
+    const ACCEPT_RANGES: bool = false;
+    /// Honor `?dry_run=true` and `Prefer: dry-run` by handling the request with
+    /// [`dry_run::is_dry_run`] set. Handlers must make their writes through
+    /// [`Context::begin`] whose transaction is rolled back on dry runs. Successes
+    /// carry a `Preference-Applied` header and aren't audited. Meant for writes.
+    const DRY_RUN: bool = false;
+    /// Reject json bodies carrying fields that aren't in the request schema with a
+    /// `400` instead of ignoring them. Overrides [`Config::strict_request_bodies`]
+    /// if set. Only the top level fields are checked and only for bodies with an
+    /// object schema in the [`ApiDoc`].
+    const STRICT_BODY: Option<bool> = None;
+    /// Set along with [`HttpEndpoint::links`] so that the [`LINKS_FIELD`] is
+    /// documented on the success response.
+    const HAS_LINKS: bool = false;
+    /// Document a `201` next to the [`HttpEndpoint::SUCCESS_CODE`], for endpoints
+    /// whose [`HttpEndpoint::created`] may return a path.
+    const MAY_CREATE: bool = false;
+    /// Only serve and document the endpoint if the flag is in
+    /// [`Config::enabled_features`], see [`feature_flags`]. Requests get a `404`
+    /// otherwise. Meant for gradual roll outs.
+    ///
+    /// Lives here and not on [`Endpoint`] as that one's implemented in bulk for
+    /// every [`AuthenticatedEndpoint`].
+    const FEATURE_FLAG: Option<&'static str> = None;
+    /// Request headers the response depends on besides the ones implied by the
+    /// other consts and the [`HttpEndpoint::HttpRequest`], e.g. `Accept` for
+    /// endpoints negotiating the content type. See [`HttpEndpoint::vary`].
+    const VARY: &'static [axum::http::header::HeaderName] = &[];
     // type HttpResponse: axum::response::IntoResponse;
 
     /// TODO: consider making this a `From` trait bound on `Self::Parameters`
     fn request(params: Self::HttpRequest) -> Result<Self::Request, Self::Error>;
     fn response(resp: Self::Response) -> axum::response::Response;
 
+    /// Id of the affected resource for the [`audit`] entry of a successful request.
+    fn audit_resource_id(_resp: &Self::Response) -> Option<String> {
+        None
+    }
+
+    /// Fallback for [`HttpEndpoint::audit_resource_id`] taken from the request, for
+    /// endpoints like deletes whose response doesn't say what was affected.
+    fn audit_target_id(_req: &Self::Request) -> Option<String> {
+        None
+    }
+
+    /// Sent as `Last-Modified` on success so that clients can revalidate with
+    /// `If-Modified-Since` and get an empty `304`. Meant for reads of resources with
+    /// an `updated_at`.
+    fn last_modified(_resp: &Self::Response) -> Option<time::OffsetDateTime> {
+        None
+    }
+
+    /// Path of the resource if the request created it rather than replaced it, e.g.
+    /// with a `PUT` upsert. Such responses are sent as a `201` instead of with the
+    /// [`HttpEndpoint::SUCCESS_CODE`] and carry the url of the resource in the
+    /// `Location` header.
+    fn created(_resp: &Self::Response) -> Option<String> {
+        None
+    }
+
+    /// Sent in the `Vary` of every response: the [`HttpEndpoint::VARY`], the auth
+    /// headers if the [`HttpEndpoint::HttpRequest`] takes any, and `Prefer` if
+    /// it's honored. The [`HttpEndpoint::VARY`] are also part of the
+    /// [`ResponseCache::key`].
+    fn vary() -> Vec<axum::http::header::HeaderName> {
+        let mut vary = Self::VARY.to_vec();
+        if Self::HttpRequest::HAS_BEARER {
+            vary.push(axum::http::header::AUTHORIZATION);
+        }
+        if Self::HttpRequest::HAS_API_KEY {
+            vary.push(axum::http::header::HeaderName::from_static(
+                auth::api_key::HEADER,
+            ));
+        }
+        if Self::RETURN_MINIMAL || Self::DRY_RUN {
+            vary.push(axum::http::header::HeaderName::from_static("prefer"));
+        }
+        vary
+    }
+
+    /// Lets clients pick the fields of the records they get through `?fields=`.
+    fn sparse_fields() -> Option<SparseFields> {
+        None
+    }
+
+    /// Put into successful json object responses under [`LINKS_FIELD`], e.g. for
+    /// `self`, `update` and `delete`. Opt in by returning any and setting
+    /// [`HttpEndpoint::HAS_LINKS`].
+    fn links(_resp: &Self::Response) -> Vec<ResourceLink> {
+        vec![]
+    }
+
+    /// Turns the endpoint into a plain [`tower::Service`]. See [`EndpointService`].
+    fn into_service(self) -> EndpointService
+    where
+        Self: Sized,
+    {
+        EndpointService::new(self)
+    }
+
     /// This actally need not be a method but I guess it allows for easy behavior
     /// modification. We ought to probably move these to the `Handler` impl
     /// when they stabilize specialization
     fn http(
         &self,
         req: hyper::Request<hyper::Body>,
+    ) -> std::pin::Pin<Box<dyn Future<Output = axum::response::Response> + Send>> {
+        let pretty = wants_pretty_json(&req);
+        let modified_since = if_modified_since(&req);
+        let range = Self::ACCEPT_RANGES.then(|| range_header(&req));
+        let fields = match Self::sparse_fields().map(|sparse| {
+            let fields = sparse.requested(&req)?;
+            Ok::<_, ApiError>(fields.map(|fields| (sparse, fields)))
+        }) {
+            Some(Ok(fields)) => fields,
+            Some(Err(err)) => return Box::pin(async move { err.into_response() }),
+            None => None,
+        };
+        let ctx = req
+            .extensions()
+            .get::<crate::SharedContext>()
+            .filter(|ctx| Self::TRANSFORM_RESPONSE && !ctx.response_transformers.is_empty())
+            .cloned();
+        let vary = Self::vary();
+        if !pretty
+            && modified_since.is_none()
+            && fields.is_none()
+            && ctx.is_none()
+            && range.is_none()
+            && vary.is_empty()
+        {
+            return self.http_cached(req);
+        }
+        // after the cache so that it only ever holds full, untransformed bodies
+        let fut = self.http_cached(req);
+        Box::pin(async move {
+            let mut resp = match modified_since {
+                Some(since) => not_modified_since(fut.await, since),
+                None => fut.await,
+            };
+            if let Some((sparse, fields)) = fields {
+                resp = sparse.prune(resp, &fields).await;
+            }
+            if let Some(ctx) = ctx {
+                resp = transform_response(&ctx, resp).await;
+            }
+            if pretty {
+                resp = pretty_json(resp).await;
+            }
+            let mut resp = match range {
+                Some(range) => byte_range(resp, range).await,
+                None => resp,
+            };
+            add_vary(resp.headers_mut(), &vary);
+            resp
+        })
+    }
+
+    /// [`HttpEndpoint::http`] minus the [`pretty_json`] printing, the
+    /// [`ResponseTransformer`]s, the [`SparseFields`] pruning, the
+    /// `If-Modified-Since` and the `Range` handling.
+    fn http_cached(
+        &self,
+        req: hyper::Request<hyper::Body>,
+    ) -> std::pin::Pin<Box<dyn Future<Output = axum::response::Response> + Send>> {
+        let safe = matches!(
+            *req.method(),
+            axum::http::Method::GET | axum::http::Method::HEAD
+        );
+        let coalesce = Self::SINGLEFLIGHT && safe;
+        let ttl = Self::CACHE_TTL.filter(|_| safe);
+        if ttl.is_none() && !coalesce {
+            return self.http_uncached(req);
+        }
+        let ctx = match req.extensions().get::<crate::SharedContext>() {
+            Some(ctx) => ctx.clone(),
+            None => return self.http_uncached(req),
+        };
+        // only the requests with the same credentials share the responses of the
+        // endpoints checking them, those are authorized by having been handled
+        let per_auth =
+            Self::CACHE_PER_AUTH || Self::HttpRequest::HAS_BEARER || Self::HttpRequest::HAS_API_KEY;
+        let key = ResponseCache::key(&req, per_auth, Self::VARY);
+        if ttl.is_some() {
+            if let Some(resp) = ctx.response_cache.get(&key) {
+                return Box::pin(async move { resp });
+            }
+        }
+        let fut = self.http_uncached(req);
+        let fut = if coalesce {
+            let (ctx, key) = (ctx.clone(), key.clone());
+            Box::pin(async move { ctx.singleflight.run(key, fut).await })
+        } else {
+            fut
+        };
+        match ttl {
+            Some(ttl) => {
+                Box::pin(async move { ctx.response_cache.store(key, ttl, fut.await).await })
+            }
+            None => fut,
+        }
+    }
+
+    /// [`HttpEndpoint::http_cached`] minus the [`ResponseCache`] and the
+    /// [`Singleflight`].
+    fn http_uncached(
+        &self,
+        req: hyper::Request<hyper::Body>,
     ) -> std::pin::Pin<Box<dyn Future<Output = axum::response::Response> + Send>> {
         let this = self.clone();
+        let return_minimal = Self::RETURN_MINIMAL && prefers_return_minimal(req.headers());
+        let dry_run = Self::DRY_RUN && dry_run::requests_dry_run(&req);
+        let urls = UrlBuilder::from_request_parts(
+            req.headers(),
+            req.uri(),
+            utils::from_trusted_proxy(req.extensions()),
+        );
+        let strict = Self::STRICT_BODY.unwrap_or_else(|| {
+            req.extensions()
+                .get::<crate::SharedContext>()
+                .map(|ctx| ctx.config.strict_request_bodies)
+                .unwrap_or_default()
+        });
+        let json_limits = req
+            .extensions()
+            .get::<crate::SharedContext>()
+            .map(|ctx| JsonLimits::from(&ctx.config))
+            .unwrap_or_default();
+        let body_limit = req
+            .extensions()
+            .get::<crate::SharedContext>()
+            .map(|ctx| ctx.config.body_limit);
+        let body_fields = req
+            .extensions()
+            .get::<BodyFields>()
+            .map(|BodyFields(fields)| fields.clone());
         Box::pin(async move {
+            let (req, json_body) = match buffer_json_body(req, body_limit).await {
+                Ok(val) => val,
+                Err(resp) => return resp,
+            };
+            if let Some(body) = &json_body {
+                if let Err(err) = json_limits.check(body) {
+                    return err.into_response();
+                }
+            }
+            if let (true, Some(body), Some(allowed)) = (strict, &json_body, &body_fields) {
+                if let Err(err) = reject_unknown_fields(body, allowed) {
+                    return err.into_response();
+                }
+            }
             let mut req_parts = axum::extract::RequestParts::new(req);
-            let req = match Self::HttpRequest::from_request(&mut req_parts).await {
+            // the rejection needn't be `Send` so it can't be held across the await below
+            let req = Self::HttpRequest::from_request(&mut req_parts)
+                .await
+                .map_err(IntoResponse::into_response);
+            let req = match req {
                 Ok(val) => val,
-                Err(err) => return err.into_response(),
+                Err(resp) => {
+                    let rejected_json = matches!(
+                        resp.status(),
+                        StatusCode::BAD_REQUEST | StatusCode::UNPROCESSABLE_ENTITY
+                    );
+                    let invalid = json_body.filter(|_| rejected_json).and_then(|body| {
+                        let err = Self::HttpRequest::json_body_error(&body)?;
+                        invalid_json(&err, &body)
+                    });
+                    return match invalid {
+                        Some(err) => err.into_response(),
+                        None => resp,
+                    };
+                }
             };
             let req = match Self::request(req) {
                 Ok(val) => val,
                 Err(err) => {
-                    return (Into::<StatusCode>::into(&err), response::Json(err)).into_response()
+                    return (
+                        Into::<StatusCode>::into(&err),
+                        response::Json(WithCode::new(&err)),
+                    )
+                        .into_response()
                 }
             };
             let Extension(ctx) =
@@ -221,19 +1119,121 @@ where
                     Ok(val) => val,
                     Err(err) => return err.into_response(),
                 };
-            // we have to clone it or the borrow checker biches that &T is
-            match this.handle(&ctx, req).await {
-                // Ok(ok) => Into::<Self::HttpResponse>::into(ok).into_response(),
-                Ok(ok) => {
-                    let mut resp = Self::response(ok);
-                    *resp.status_mut() = Self::SUCCESS_CODE;
-                    resp
+            let audit = Self::AUDIT_ACTION.filter(|_| !dry_run).map(|action| {
+                let req_ctx = req_parts.extensions().get::<RequestContext>();
+                audit::Entry {
+                    actor: req_ctx.and_then(|req_ctx| req_ctx.auth.as_ref().map(|auth| auth.id)),
+                    action,
+                    resource_id: None,
+                    path: req_parts
+                        .extensions()
+                        .get::<OriginalUri>()
+                        .map(|uri| uri.0.path().to_string())
+                        .unwrap_or_else(|| req_parts.uri().path().to_string()),
+                    request_id: req_ctx.map(|req_ctx| req_ctx.request_id.clone()),
+                    timestamp: ctx.clock.now(),
+                }
+            });
+            // settle the result in its own scope so the handler's non-Send types
+            // aren't held across the link injection await below
+            let (resp, links, last_modified, created) = {
+                // we have to clone it or the borrow checker biches that &T is
+                let target_id = audit.as_ref().and_then(|_| Self::audit_target_id(&req));
+                let result = if dry_run {
+                    dry_run::with_dry_run(this.handle(&ctx, req)).await
+                } else {
+                    this.handle(&ctx, req).await
+                };
+                if let (Some(mut entry), Ok(ok)) = (audit, &result) {
+                    entry.resource_id = Self::audit_resource_id(ok).or(target_id);
+                    entry.timestamp = ctx.clock.now();
+                    entry.record();
+                }
+                match result {
+                    // Ok(ok) => Into::<Self::HttpResponse>::into(ok).into_response(),
+                    Ok(_) if return_minimal => {
+                        return (
+                            StatusCode::NO_CONTENT,
+                            [(PREFERENCE_APPLIED, "return=minimal")],
+                        )
+                            .into_response()
+                    }
+                    Ok(ok) => {
+                        let last_modified = Self::last_modified(&ok);
+                        let links = Self::links(&ok);
+                        let created = Self::created(&ok);
+                        (Self::response(ok), links, last_modified, created)
+                    }
+                    Err(err) => {
+                        let status = Into::<StatusCode>::into(&err);
+                        let mut resp =
+                            (status, response::Json(WithCode::new(&err))).into_response();
+                        if status == StatusCode::SERVICE_UNAVAILABLE {
+                            set_retry_after(
+                                resp.headers_mut(),
+                                ctx.retry_after(Backoff::Overloaded),
+                            );
+                        }
+                        return resp;
+                    }
+                }
+            };
+            debug_assert!(
+                Self::HAS_LINKS || links.is_empty(),
+                "{} has links but not `HAS_LINKS`",
+                std::any::type_name::<Self>()
+            );
+            let mut resp = inject_links(resp, urls.as_ref(), &links).await;
+            *resp.status_mut() = Self::SUCCESS_CODE;
+            if let Some(path) = created {
+                *resp.status_mut() = StatusCode::CREATED;
+                let location = match &urls {
+                    Some(urls) => urls.absolute(&path),
+                    None => path,
+                };
+                if let Ok(val) = axum::http::HeaderValue::from_str(&location) {
+                    resp.headers_mut().insert(axum::http::header::LOCATION, val);
                 }
-                Err(err) => (Into::<StatusCode>::into(&err), response::Json(err)).into_response(),
+            }
+            if dry_run {
+                resp.headers_mut().insert(
+                    PREFERENCE_APPLIED,
+                    axum::http::HeaderValue::from_static("dry-run"),
+                );
+            }
+            match last_modified {
+                Some(at) => with_last_modified(resp, at),
+                None => resp,
             }
         })
     }
 }
+
+/// Sent as `Retry-After` with the `503`s of a [`HttpEndpoint::MAX_CONCURRENCY`]
+/// limit and of the db pool timing out while the [`CircuitBreaker`] isn't open.
+/// See [`utils::retry_after`].
+pub const RETRY_AFTER_SECS: u32 = 1;
+
+pub const PREFERENCE_APPLIED: &str = "preference-applied";
+
+/// Whether any of the `Prefer` headers asks for `return=minimal` (RFC 7240).
+pub fn prefers_return_minimal(headers: &axum::http::HeaderMap) -> bool {
+    headers
+        .get_all("prefer")
+        .iter()
+        .filter_map(|val| val.to_str().ok())
+        .flat_map(|val| val.split(','))
+        .filter_map(|pref| pref.split(';').next())
+        .any(|pref| {
+            let pref = pref.trim().to_ascii_lowercase();
+            matches!(
+                pref.split_once('=')
+                    .map(|(key, val)| (key.trim(), val.trim().trim_matches('"'))),
+                Some(("return", "minimal"))
+            )
+        })
+}
+
 pub struct Tag {
     name: &'static str,
     desc: &'static str,
@@ -253,17 +1253,31 @@ pub const DEFAULT_TAG: Tag = Tag {
     desc: "This is the catch all tag.",
 };
 
+/// The name of the parameter if the segment is a `:capture` or a `*wildcard`.
+fn axum_path_param(segment: &str) -> Option<&str> {
+    segment
+        .strip_prefix(':')
+        .or_else(|| segment.strip_prefix('*'))
+        .filter(|name| !name.is_empty())
+}
+
+/// Captures and wildcards become path parameters, the latter spanning the rest of
+/// the path despite OpenAPI having no way to say so. Other segments are passed
+/// through as they are, percent encoded or not.
 pub fn axum_path_str_to_openapi(path: &str) -> String {
-    path.split('/')
+    let path: String = path
+        .split('/')
         .filter(|s| !s.is_empty())
-        .map(|s| {
-            if &s[0..1] == ":" {
-                format!("/{{{}}}", &s[1..])
-            } else {
-                format!("/{s}")
-            }
+        .map(|s| match axum_path_param(s) {
+            Some(name) => format!("/{{{name}}}"),
+            None => format!("/{s}"),
         })
-        .collect()
+        .collect();
+    if path.is_empty() {
+        "/".into()
+    } else {
+        path
+    }
 }
 
 #[test]
@@ -271,6 +1285,12 @@ fn test_axum_path_str_to_openapi() {
     for (expected, path) in [
         ("/users/{id}", "/users/:id"),
         ("/users/{id}/resource/{resID}", "/users/:id/resource/:resID"),
+        ("/users", "/users/"),
+        ("/files/{rest}", "/files/*rest"),
+        ("/ünïcode/{id}", "/ünïcode/:id"),
+        ("/caf%C3%A9/{id}", "/caf%C3%A9/:id"),
+        ("/", ""),
+        ("/", "/"),
     ] {
         assert_eq!(
             expected,
@@ -282,9 +1302,8 @@ fn test_axum_path_str_to_openapi() {
 
 pub fn axum_path_parameter_list(path: &str) -> Vec<String> {
     path.split('/')
-        .filter(|s| !s.is_empty())
-        .filter(|s| &s[0..1] == ":")
-        .map(|s| s[1..].to_string())
+        .filter_map(axum_path_param)
+        .map(|name| name.to_string())
         .collect()
 }
 
@@ -296,6 +1315,9 @@ fn test_axum_path_paramter_list() {
             vec!["id".to_string(), "resID".to_string()],
             "/users/:id/resource/:resID",
         ),
+        (vec!["rest".to_string()], "/files/*rest"),
+        (vec![], "/ünïcode"),
+        (vec![], ""),
     ] {
         assert_eq!(
             expected,
@@ -308,6 +1330,10 @@ fn test_axum_path_paramter_list() {
 pub trait ToRefOrSchema {
     fn schema_name() -> &'static str;
     fn ref_or_schema() -> openapi::schema::RefOr<openapi::schema::Schema>;
+    /// Of the success response, see [`utils::Attachment`] for one that isn't json.
+    fn content_type() -> &'static str {
+        "application/json"
+    }
 }
 
 impl<T> ToRefOrSchema for T
@@ -323,8 +1349,18 @@ where
     }
 }
 
+/// Response for endpoints that have nothing to return. Renders as an empty `204`
+/// and is documented without a schema. Pair it with a [`HttpEndpoint::SUCCESS_CODE`]
+/// of [`StatusCode::NO_CONTENT`].
+#[derive(Debug, Clone, Copy)]
 pub struct NoContent;
 
+impl IntoResponse for NoContent {
+    fn into_response(self) -> response::Response {
+        StatusCode::NO_CONTENT.into_response()
+    }
+}
+
 impl From<()> for NoContent {
     fn from(_: ()) -> Self {
         Self
@@ -391,7 +1427,19 @@ impl From<openapi::request_body::RequestBody> for ParameterDoc {
 pub trait DocumentedParameter {
     // FIXME:: yikes
     const HAS_BEARER: bool = false;
+    /// Set by [`auth::api_key::ApiKey`].
+    const HAS_API_KEY: bool = false;
     fn to_openapi(op_id: &str, path: &str) -> Vec<ParameterDoc>;
+    /// Top level fields of the json body if this extracts one and its schema is
+    /// known. See [`HttpEndpoint::STRICT_BODY`].
+    fn body_fields() -> Option<Vec<String>> {
+        None
+    }
+    /// Deserializes `body` the way the json body extractor would so that the
+    /// [`serde_json::Error`] behind a rejection can be reported.
+    fn json_body_error(_body: &[u8]) -> Option<serde_json::Error> {
+        None
+    }
 }
 
 // impl<T> DocumentedParameter for axum::extract::Path<T> {
@@ -433,7 +1481,7 @@ impl DocumentedParameter for axum::extract::Path<uuid::Uuid> {
 
 impl<T> DocumentedParameter for axum::extract::Json<T>
 where
-    T: ToRefOrSchema,
+    T: ToRefOrSchema + serde::de::DeserializeOwned,
 {
     fn to_openapi(_op_id: &str, _path: &str) -> Vec<ParameterDoc> {
         vec![utoipa::openapi::request_body::RequestBodyBuilder::new()
@@ -459,6 +1507,93 @@ where
             .build()
             .into()]
     }
+
+    fn body_fields() -> Option<Vec<String>> {
+        object_fields(T::ref_or_schema(), T::schema_name())
+    }
+
+    fn json_body_error(body: &[u8]) -> Option<serde_json::Error> {
+        serde_json::from_slice::<T>(body).err()
+    }
+}
+
+/// The component schemas of the [`ApiDoc`], built once for [`object_fields`].
+static COMPONENT_SCHEMAS: once_cell::sync::Lazy<
+    std::collections::BTreeMap<String, openapi::schema::RefOr<openapi::Schema>>,
+> = once_cell::sync::Lazy::new(|| {
+    <ApiDoc as utoipa::OpenApi>::openapi()
+        .components
+        .map(|components| components.schemas)
+        .unwrap_or_default()
+});
+
+/// Top level properties of the object `schema` of `name`, following refs to the
+/// [`ApiDoc`] components. `None` with a warning if it isn't an object or refers
+/// to a schema the [`ApiDoc`] doesn't have, strict bodies are skipped for those.
+fn object_fields(
+    schema: openapi::schema::RefOr<openapi::Schema>,
+    name: &str,
+) -> Option<Vec<String>> {
+    match schema {
+        openapi::schema::RefOr::T(openapi::Schema::Object(obj)) => {
+            Some(obj.properties.into_keys().collect())
+        }
+        openapi::schema::RefOr::Ref(reference) => {
+            let component = reference
+                .ref_location
+                .rsplit('/')
+                .next()
+                .unwrap_or_default();
+            match COMPONENT_SCHEMAS.get(component) {
+                Some(schema) => object_fields(schema.clone(), name),
+                None => {
+                    tracing::warn!(
+                        name,
+                        component,
+                        "body refers to a schema missing from the ApiDoc, it won't be checked strictly"
+                    );
+                    None
+                }
+            }
+        }
+        _ => {
+            tracing::warn!(
+                name,
+                "body doesn't have an object schema, it won't be checked strictly"
+            );
+            None
+        }
+    }
+}
+
+/// For endpoints serving HTML form clients. Use `(Form<Request>,)` as the
+/// [`HttpEndpoint::HttpRequest`] and the body gets documented as
+/// `application/x-www-form-urlencoded` instead of json.
+impl<T> DocumentedParameter for axum::extract::Form<T>
+where
+    T: ToRefOrSchema,
+{
+    fn to_openapi(_op_id: &str, _path: &str) -> Vec<ParameterDoc> {
+        vec![utoipa::openapi::request_body::RequestBodyBuilder::new()
+            .content(
+                "application/x-www-form-urlencoded",
+                utoipa::openapi::ContentBuilder::new()
+                    .schema(match T::ref_or_schema() {
+                        utoipa::openapi::schema::RefOr::T(schema) => {
+                            if T::schema_name() == "Request" {
+                                schema.into()
+                            } else {
+                                utoipa::openapi::Ref::from_schema_name(T::schema_name().to_string())
+                                    .into()
+                            }
+                        }
+                        ref_or => ref_or,
+                    })
+                    .build(),
+            )
+            .build()
+            .into()]
+    }
 }
 
 // impl<T> DocumentedParameter for axum::extract::Query<T>
@@ -500,6 +1635,7 @@ where
     T: DocumentedParameter,
 {
     const HAS_BEARER: bool = T::HAS_BEARER;
+    const HAS_API_KEY: bool = T::HAS_API_KEY;
     fn to_openapi(op_id: &str, path: &str) -> Vec<ParameterDoc> {
         let mut vec = T::to_openapi(op_id, path);
         for param in &mut vec {
@@ -514,6 +1650,13 @@ where
         }
         vec
     }
+
+    fn body_fields() -> Option<Vec<String>> {
+        T::body_fields()
+    }
+    fn json_body_error(body: &[u8]) -> Option<serde_json::Error> {
+        T::json_body_error(body)
+    }
 }
 impl DocumentedParameter for () {
     fn to_openapi(_op_id: &str, _path: &str) -> Vec<ParameterDoc> {
@@ -526,9 +1669,16 @@ where
     T: DocumentedParameter,
 {
     const HAS_BEARER: bool = T::HAS_BEARER;
+    const HAS_API_KEY: bool = T::HAS_API_KEY;
     fn to_openapi(op_id: &str, path: &str) -> Vec<ParameterDoc> {
         T::to_openapi(op_id, path)
     }
+    fn body_fields() -> Option<Vec<String>> {
+        T::body_fields()
+    }
+    fn json_body_error(body: &[u8]) -> Option<serde_json::Error> {
+        T::json_body_error(body)
+    }
 }
 
 impl<T1, T2> DocumentedParameter for (T1, T2)
@@ -537,11 +1687,18 @@ where
     T2: DocumentedParameter,
 {
     const HAS_BEARER: bool = T1::HAS_BEARER | T2::HAS_BEARER;
+    const HAS_API_KEY: bool = T1::HAS_API_KEY | T2::HAS_API_KEY;
     fn to_openapi(op_id: &str, path: &str) -> Vec<ParameterDoc> {
         let mut vec = T1::to_openapi(op_id, path);
         vec.append(&mut T2::to_openapi(op_id, path));
         vec
     }
+    fn body_fields() -> Option<Vec<String>> {
+        T1::body_fields().or_else(T2::body_fields)
+    }
+    fn json_body_error(body: &[u8]) -> Option<serde_json::Error> {
+        T1::json_body_error(body).or_else(|| T2::json_body_error(body))
+    }
 }
 
 impl<T1, T2, T3> DocumentedParameter for (T1, T2, T3)
@@ -551,21 +1708,119 @@ where
     T3: DocumentedParameter,
 {
     const HAS_BEARER: bool = T1::HAS_BEARER | T2::HAS_BEARER | T3::HAS_BEARER;
+    const HAS_API_KEY: bool = T1::HAS_API_KEY | T2::HAS_API_KEY | T3::HAS_API_KEY;
+    fn to_openapi(op_id: &str, path: &str) -> Vec<ParameterDoc> {
+        let mut vec = T1::to_openapi(op_id, path);
+        vec.append(&mut T2::to_openapi(op_id, path));
+        vec.append(&mut T3::to_openapi(op_id, path));
+        vec
+    }
+    fn body_fields() -> Option<Vec<String>> {
+        T1::body_fields()
+            .or_else(T2::body_fields)
+            .or_else(T3::body_fields)
+    }
+    fn json_body_error(body: &[u8]) -> Option<serde_json::Error> {
+        T1::json_body_error(body)
+            .or_else(|| T2::json_body_error(body))
+            .or_else(|| T3::json_body_error(body))
+    }
+}
+
+impl<T1, T2, T3, T4> DocumentedParameter for (T1, T2, T3, T4)
+where
+    T1: DocumentedParameter,
+    T2: DocumentedParameter,
+    T3: DocumentedParameter,
+    T4: DocumentedParameter,
+{
+    const HAS_BEARER: bool = T1::HAS_BEARER | T2::HAS_BEARER | T3::HAS_BEARER | T4::HAS_BEARER;
+    const HAS_API_KEY: bool = T1::HAS_API_KEY | T2::HAS_API_KEY | T3::HAS_API_KEY | T4::HAS_API_KEY;
     fn to_openapi(op_id: &str, path: &str) -> Vec<ParameterDoc> {
         let mut vec = T1::to_openapi(op_id, path);
         vec.append(&mut T2::to_openapi(op_id, path));
         vec.append(&mut T3::to_openapi(op_id, path));
+        vec.append(&mut T4::to_openapi(op_id, path));
         vec
     }
+    fn body_fields() -> Option<Vec<String>> {
+        T1::body_fields()
+            .or_else(T2::body_fields)
+            .or_else(T3::body_fields)
+            .or_else(T4::body_fields)
+    }
+    fn json_body_error(body: &[u8]) -> Option<serde_json::Error> {
+        T1::json_body_error(body)
+            .or_else(|| T2::json_body_error(body))
+            .or_else(|| T3::json_body_error(body))
+            .or_else(|| T4::json_body_error(body))
+    }
 }
 
 /// (description, example)
 pub type ErrorResponse<Err> = (&'static str, Err);
 
-pub trait DocumentedEndpoint: HttpEndpoint + Sized
-where
-    Self::Response: ToRefOrSchema,
-    Self::Error: ToRefOrSchema + serde::Serialize,
+/// (name, summary, example). Swagger UI shows the examples sharing a response as a
+/// dropdown.
+pub type NamedExample<T> = (&'static str, &'static str, T);
+
+/// What an operation has that utoipa 2 has no field for, added to the serialized
+/// spec by [`openapi_json`].
+#[derive(Debug, Default, Clone)]
+pub struct OperationExtras {
+    /// The [`vendor_extensions`] of the operation object.
+    pub extensions: serde_json::Map<String, serde_json::Value>,
+    /// The [`named_examples`] of the request body.
+    pub request_examples: serde_json::Map<String, serde_json::Value>,
+    /// The [`named_examples`] of the responses by status code.
+    pub response_examples:
+        std::collections::BTreeMap<String, serde_json::Map<String, serde_json::Value>>,
+}
+
+/// The [`OperationExtras`] by operation id.
+pub type SpecExtras = std::collections::BTreeMap<&'static str, OperationExtras>;
+
+/// The [`openapi::PathsBuilder`] paired with the [`SpecExtras`] of the endpoints
+/// added to it.
+pub struct SpecPaths {
+    pub builder: openapi::PathsBuilder,
+    pub extras: SpecExtras,
+}
+
+impl SpecPaths {
+    pub fn new() -> Self {
+        Self {
+            builder: openapi::PathsBuilder::new(),
+            extras: Default::default(),
+        }
+    }
+
+    pub fn endpoint<T>(mut self) -> Self
+    where
+        T: DocumentedEndpoint,
+        T::Response: ToRefOrSchema,
+        T::Error: ToRefOrSchema + serde::Serialize + ErrorCode,
+        for<'a> &'a T::Error: Into<StatusCode>,
+        T::HttpRequest: DocumentedParameter,
+    {
+        self.extras.insert(T::id(), T::extras());
+        self.builder = self
+            .builder
+            .path(axum_path_str_to_openapi(T::PATH), T::path_item());
+        self
+    }
+}
+
+impl Default for SpecPaths {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub trait DocumentedEndpoint: HttpEndpoint + Sized
+where
+    Self::Response: ToRefOrSchema,
+    Self::Error: ToRefOrSchema + serde::Serialize + ErrorCode,
     for<'a> &'a Self::Error: Into<StatusCode>,
     Self::HttpRequest: DocumentedParameter,
 {
@@ -574,10 +1829,13 @@ where
     const DESCRIPTION: &'static str = "";
     const SUCCESS_DESCRIPTION: &'static str = "";
     const DEPRECATED: bool = false;
+    /// (description, url) of a runbook or guide for the operation.
+    const EXTERNAL_DOCS: Option<(&'static str, &'static str)> = None;
 
-    /// By default, this calls [`utils::type_name_raw`] on `Self`.
+    /// The [`operation_id`], kept the same as the one permissions are checked
+    /// against.
     fn id() -> &'static str {
-        type_name_raw::<Self>()
+        operation_id::<Self>()
     }
 
     /// Provide examples to be used for the error responses
@@ -594,11 +1852,76 @@ where
         vec![]
     }
 
+    /// Several examples for the success response, each under its own name. These
+    /// replace the [`success_examples`] if any.
+    fn named_success_examples() -> Vec<NamedExample<serde_json::Value>> {
+        vec![]
+    }
+
+    /// Named examples for the request body that Swagger UI offers to fill in on
+    /// "Try it out". Ignored if the endpoint takes no body.
+    fn request_examples() -> Vec<NamedExample<serde_json::Value>> {
+        vec![]
+    }
+
+    /// Like [`errors`] but the examples that share a status code are kept together
+    /// under a single response, described by the first of them.
+    fn named_errors() -> Vec<(&'static str, NamedExample<Self::Error>)> {
+        vec![]
+    }
+
+    /// `x-` prefixed fields added to the operation object for tooling that keys off
+    /// them, e.g. `("x-internal", json!(true))`. Keys lacking the prefix panic.
+    fn extensions() -> Vec<(&'static str, serde_json::Value)> {
+        vec![]
+    }
+
+    /// Gathers the [`extensions`] along with the ones for the [`HttpEndpoint::FEATURE_FLAG`]
+    /// and [`HttpEndpoint::HAS_LINKS`], and the named examples.
+    fn extras() -> OperationExtras {
+        let mut response_examples = std::collections::BTreeMap::new();
+        let named = Self::named_success_examples();
+        if !named.is_empty() {
+            let examples = named_examples::examples(named);
+            if Self::MAY_CREATE {
+                response_examples
+                    .insert(StatusCode::CREATED.as_u16().to_string(), examples.clone());
+            }
+            response_examples.insert(Self::SUCCESS_CODE.as_u16().to_string(), examples);
+        }
+        for (_, (name, summary, err)) in Self::named_errors() {
+            let code = Into::<StatusCode>::into(&err).as_u16().to_string();
+            response_examples
+                .entry(code)
+                .or_insert_with(serde_json::Map::new)
+                .extend(named_examples::examples([(
+                    name,
+                    summary,
+                    WithCode::new(&err),
+                )]));
+        }
+        OperationExtras {
+            request_examples: named_examples::examples(Self::request_examples()),
+            response_examples,
+            extensions: vendor_extensions::fields(
+                Self::id(),
+                Self::extensions()
+                    .into_iter()
+                    .chain(
+                        Self::FEATURE_FLAG
+                            .map(|flag| (feature_flags::EXTENSION, serde_json::json!(flag))),
+                    )
+                    .chain(Self::HAS_LINKS.then_some((LINKS_EXTENSION, serde_json::json!(true))))
+                    .collect(),
+            ),
+        }
+    }
+
     /// Read at `success_examples` for the default behavior.
     fn success_responses() -> Vec<(String, openapi::Response)> {
-        vec![(Self::SUCCESS_CODE.as_u16().to_string(), {
+        let success = {
             let builder = if Self::Response::schema_name() != type_name_raw::<NoContent>() {
-                openapi::ResponseBuilder::new().content("application/json", {
+                openapi::ResponseBuilder::new().content(Self::Response::content_type(), {
                     let mut schema = match Self::Response::ref_or_schema() {
                         // if it's a `Ref`, use the `schema_name`
                         openapi::schema::RefOr::Ref(_) => openapi::ContentBuilder::new()
@@ -627,7 +1950,30 @@ where
                 builder
             };
             builder.build()
-        })]
+        };
+        let created = Self::MAY_CREATE.then(|| {
+            let mut created = success.clone();
+            created.description = "Created.".into();
+            created.headers.insert(
+                "Location".into(),
+                openapi::header::HeaderBuilder::new()
+                    .description(Some("Url of the created resource."))
+                    .build(),
+            );
+            (StatusCode::CREATED.as_u16().to_string(), created)
+        });
+        vec![(Self::SUCCESS_CODE.as_u16().to_string(), success)]
+            .into_iter()
+            .chain(created)
+            .chain(Self::RETURN_MINIMAL.then(|| {
+                (
+                    StatusCode::NO_CONTENT.as_u16().to_string(),
+                    openapi::ResponseBuilder::new()
+                        .description("Success when requested with `Prefer: return=minimal`.")
+                        .build(),
+                )
+            }))
+            .collect()
     }
 
     /// Besides what's stated in the doc of [`errors`], the default impl assumes that
@@ -635,26 +1981,41 @@ where
     /// endpoint id coming from [`DocumentedEndpoint::id`]
     fn error_responses() -> Vec<(String, openapi::Response)> {
         let id = Self::id();
+        let response = |desc: &str, example: Option<serde_json::Value>| {
+            openapi::ResponseBuilder::new()
+                .description(desc)
+                .content(
+                    "application/json",
+                    openapi::ContentBuilder::new()
+                        .schema(utoipa::openapi::Ref::from_schema_name(format!("{id}Error")))
+                        // .schema(Self::Error::ref_or_schema())
+                        .example(example)
+                        .build(),
+                )
+                .build()
+        };
+        // the examples themselves are added from the `extras`
+        let mut named = std::collections::BTreeMap::new();
+        for (desc, (_, _, err)) in Self::named_errors() {
+            let code = Into::<StatusCode>::into(&err).as_u16().to_string();
+            named.entry(code).or_insert(desc);
+        }
         Self::errors()
             .into_iter()
             .map(|(desc, example)| {
                 (
                     Into::<StatusCode>::into(&example).as_u16().to_string(),
-                    openapi::ResponseBuilder::new()
-                        .description(desc)
-                        .content(
-                            "application/json",
-                            openapi::ContentBuilder::new()
-                                .schema(utoipa::openapi::Ref::from_schema_name(format!(
-                                    "{id}Error"
-                                )))
-                                // .schema(Self::Error::ref_or_schema())
-                                .example(Some(serde_json::to_value(example).unwrap()))
-                                .build(),
-                        )
-                        .build(),
+                    response(
+                        desc,
+                        Some(serde_json::to_value(WithCode::new(&example)).unwrap()),
+                    ),
                 )
             })
+            .chain(
+                named
+                    .into_iter()
+                    .map(|(code, desc)| (code, response(desc, None))),
+            )
             .collect()
     }
 
@@ -685,45 +2046,58 @@ where
                 (params, bodies)
             });
         assert!(bodies.len() < 2, "{id} has more than one Body ParameterDoc");
+        let params = params
+            .into_iter()
+            .chain(Self::sparse_fields().map(|sparse| sparse.parameter()))
+            .chain(Self::DRY_RUN.then(dry_run::parameter))
+            .collect();
         (bodies.into_iter().next(), params)
     }
 
     fn path_item() -> openapi::PathItem {
         let id = Self::id();
         let (body, params) = Self::paramters();
-        openapi::PathItem::new(
-            Self::METHOD,
-            openapi::path::OperationBuilder::new()
-                .operation_id(Some(id))
-                .deprecated(Some(if Self::DEPRECATED {
-                    openapi::Deprecated::True
-                } else {
-                    openapi::Deprecated::False
-                }))
-                .summary(if !Self::SUMMARY.is_empty() {
-                    Some(Self::SUMMARY)
-                } else {
-                    None
-                })
-                .description(if !Self::DESCRIPTION.is_empty() {
-                    Some(Self::DESCRIPTION)
-                } else {
-                    None
-                })
-                .tag(Self::TAG.name)
-                .securities(if Self::HttpRequest::HAS_BEARER {
-                    Some([openapi::security::SecurityRequirement::new::<
-                        &str,
-                        [&str; 1usize],
-                        &str,
-                    >("bearer", [""])])
-                } else {
-                    None
+        // utoipa 2 has no builder method for the operation's `externalDocs`
+        let mut operation = openapi::path::OperationBuilder::new()
+            .operation_id(Some(id))
+            .deprecated(Some(if Self::DEPRECATED {
+                openapi::Deprecated::True
+            } else {
+                openapi::Deprecated::False
+            }))
+            .summary(if !Self::SUMMARY.is_empty() {
+                Some(Self::SUMMARY)
+            } else {
+                None
+            })
+            .description(if !Self::DESCRIPTION.is_empty() {
+                Some(Self::DESCRIPTION)
+            } else {
+                None
+            })
+            .tag(Self::TAG.name)
+            .securities({
+                let schemes = [
+                    (Self::HttpRequest::HAS_BEARER, "bearer"),
+                    (Self::HttpRequest::HAS_API_KEY, "api_key"),
+                ]
+                .into_iter()
+                .filter_map(|(has, name)| has.then_some(name))
+                .map(|name| {
+                    openapi::security::SecurityRequirement::new::<&str, [&str; 1usize], &str>(
+                        name,
+                        [""],
+                    )
                 })
-                .request_body(body)
-                .parameters(Some(params.into_iter()))
-                .responses(Self::responses()),
-        )
+                .collect::<Vec<_>>();
+                (!schemes.is_empty()).then_some(schemes)
+            })
+            .request_body(body)
+            .parameters(Some(params.into_iter()))
+            .responses(Self::responses())
+            .build();
+        operation.external_docs = Self::EXTERNAL_DOCS.map(external_docs);
+        openapi::PathItem::new(Self::METHOD, operation)
     }
 
     /// Registers the [`Error`] type schema under `EndpointIdError` name using the
@@ -743,15 +2117,18 @@ where
         //         }
         //         (params, bodies)
         //     });
+        let codes = format!("{id}ErrorCode");
+        let error = match <Self::Error as ToRefOrSchema>::ref_or_schema() {
+            utoipa::openapi::schema::RefOr::T(schema) => add_code_property(schema, &codes).into(),
+            ref_or => ref_or,
+        };
         [
             // (
             //     format!("{id}Response"),
             //     <Self::Response as ToRefOrSchema>::ref_or_schema(),
             // ),
-            (
-                format!("{id}Error"),
-                <Self::Error as ToRefOrSchema>::ref_or_schema(),
-            ),
+            (format!("{id}Error"), error),
+            (codes, codes_schema(Self::error_codes()).into()),
         ]
         .into_iter()
         .fold(builder, |builder, (name, ref_or)| match ref_or {
@@ -761,6 +2138,19 @@ where
         })
     }
 
+    /// The [`ErrorCode`]s of the [`errors`] and [`named_errors`] examples.
+    fn error_codes() -> Vec<&'static str> {
+        Self::errors()
+            .iter()
+            .map(|(_, err)| err.code())
+            .chain(
+                Self::named_errors()
+                    .iter()
+                    .map(|(_, (_, _, err))| err.code()),
+            )
+            .collect()
+    }
+
     fn components(builder: openapi::ComponentsBuilder) -> openapi::ComponentsBuilder {
         Self::default_components(builder)
     }
@@ -768,6 +2158,585 @@ where
 
 pub type Method = openapi::PathItemType;
 
+#[cfg(test)]
+mod operation_id_tests {
+    use deps::*;
+
+    use super::*;
+
+    #[derive(Debug, serde::Serialize, utoipa::ToSchema)]
+    #[serde(crate = "serde", rename_all = "camelCase", tag = "error")]
+    pub enum Error {
+        Internal,
+    }
+
+    impl From<&Error> for StatusCode {
+        fn from(_: &Error) -> Self {
+            Self::INTERNAL_SERVER_ERROR
+        }
+    }
+
+    impl ErrorCode for Error {
+        fn code(&self) -> &'static str {
+            "service.internal"
+        }
+    }
+
+    macro_rules! dummy_endpoint {
+        ($name:ident, $path:literal, $op_id:expr) => {
+            dummy_endpoint!($name, $path, $op_id, None);
+        };
+        ($name:ident, $path:literal, $op_id:expr, $docs:expr) => {
+            dummy_endpoint!($name, $path, $op_id, $docs, vec![]);
+        };
+        ($name:ident, $path:literal, $op_id:expr, $docs:expr, $extensions:expr) => {
+            #[derive(Debug, Clone, Copy)]
+            pub struct $name;
+
+            #[async_trait::async_trait]
+            impl Endpoint for $name {
+                type Request = ();
+                type Response = NoContent;
+                type Error = Error;
+
+                const OPERATION_ID: Option<&'static str> = $op_id;
+
+                async fn handle(
+                    &self,
+                    _ctx: &crate::Context,
+                    _request: Self::Request,
+                ) -> Result<Self::Response, Self::Error> {
+                    Ok(NoContent)
+                }
+            }
+
+            impl HttpEndpoint for $name {
+                const METHOD: Method = Method::Get;
+                const PATH: &'static str = $path;
+
+                type HttpRequest = ();
+
+                fn request(_: Self::HttpRequest) -> Result<Self::Request, Self::Error> {
+                    Ok(())
+                }
+
+                fn response(_: Self::Response) -> axum::response::Response {
+                    StatusCode::NO_CONTENT.into_response()
+                }
+            }
+
+            impl DocumentedEndpoint for $name {
+                const EXTERNAL_DOCS: Option<(&'static str, &'static str)> = $docs;
+
+                fn errors() -> Vec<ErrorResponse<Self::Error>> {
+                    vec![("Internal server error", Error::Internal)]
+                }
+
+                fn extensions() -> Vec<(&'static str, serde_json::Value)> {
+                    $extensions
+                }
+            }
+        };
+    }
+
+    dummy_endpoint!(FetchThing, "/things", Some("fetchThing"));
+    dummy_endpoint!(FetchOtherThing, "/other-things", Some("fetchThing"));
+    dummy_endpoint!(Unnamed, "/unnamed", None);
+    dummy_endpoint!(
+        Runbooked,
+        "/runbooked",
+        None,
+        Some(("Runbook", "https://runbooks.example.com/runbooked"))
+    );
+    dummy_endpoint!(
+        Internal,
+        "/internal",
+        None,
+        None,
+        vec![("x-internal", serde_json::json!(true))]
+    );
+    dummy_endpoint!(
+        Misnamed,
+        "/misnamed",
+        None,
+        None,
+        vec![("internal", serde_json::json!(true))]
+    );
+
+    fn spec(paths: openapi::PathsBuilder) -> serde_json::Value {
+        serde_json::to_value(openapi::OpenApiBuilder::new().paths(paths.build()).build()).unwrap()
+    }
+
+    #[test]
+    fn custom_operation_id_is_used() {
+        let spec = spec(
+            openapi::PathsBuilder::new()
+                .path(FetchThing::PATH, FetchThing::path_item())
+                .path(Unnamed::PATH, Unnamed::path_item()),
+        );
+        assert_eq!(spec["paths"]["/things"]["get"]["operationId"], "fetchThing");
+        assert_eq!(spec["paths"]["/unnamed"]["get"]["operationId"], "Unnamed");
+        assert!(spec_lint::duplicate_operation_ids(&spec).is_empty());
+    }
+
+    #[test]
+    fn duplicate_operation_ids_are_detected() {
+        let spec = spec(
+            openapi::PathsBuilder::new()
+                .path(FetchThing::PATH, FetchThing::path_item())
+                .path(FetchOtherThing::PATH, FetchOtherThing::path_item()),
+        );
+        assert_eq!(spec_lint::duplicate_operation_ids(&spec), ["fetchThing"]);
+    }
+
+    #[test]
+    fn external_docs_are_linked() {
+        let spec = spec(
+            openapi::PathsBuilder::new()
+                .path(Runbooked::PATH, Runbooked::path_item())
+                .path(Unnamed::PATH, Unnamed::path_item()),
+        );
+        assert_eq!(
+            spec["paths"]["/runbooked"]["get"]["externalDocs"],
+            serde_json::json!({
+                "url": "https://runbooks.example.com/runbooked",
+                "description": "Runbook",
+            })
+        );
+        assert!(spec["paths"]["/unnamed"]["get"]
+            .get("externalDocs")
+            .is_none());
+    }
+
+    #[test]
+    fn vendor_extensions_are_added() {
+        let SpecPaths { builder, extras } = SpecPaths::new()
+            .endpoint::<Internal>()
+            .endpoint::<Unnamed>();
+        let mut spec = spec(builder);
+        vendor_extensions::expand(&mut spec, &extras);
+        let op = &spec["paths"]["/internal"]["get"];
+        assert_eq!(op["x-internal"], true);
+        assert!(op.get("callbacks").is_none(), "{op}");
+        let op = spec["paths"]["/unnamed"]["get"].as_object().unwrap();
+        assert!(!op.keys().any(|key| key.starts_with("x-")), "{op:?}");
+    }
+
+    #[test]
+    #[should_panic(expected = "not prefixed with `x-`")]
+    fn vendor_extensions_need_the_prefix() {
+        SpecPaths::new().endpoint::<Misnamed>();
+    }
+
+    #[derive(serde::Deserialize)]
+    #[serde(crate = "serde")]
+    struct RefedBody;
+
+    impl ToRefOrSchema for RefedBody {
+        fn schema_name() -> &'static str {
+            "ValidationError"
+        }
+
+        fn ref_or_schema() -> openapi::schema::RefOr<openapi::schema::Schema> {
+            openapi::Ref::from_schema_name(Self::schema_name()).into()
+        }
+    }
+
+    #[test]
+    fn body_fields_follow_refs() {
+        let mut fields = crate::utils::OptionalJson::<RefedBody>::body_fields().unwrap();
+        fields.sort();
+        assert_eq!(fields, ["code", "message", "params"]);
+    }
+
+    #[derive(serde::Deserialize)]
+    #[serde(crate = "serde")]
+    struct ArrayBody;
+
+    impl ToRefOrSchema for ArrayBody {
+        fn schema_name() -> &'static str {
+            "ArrayBody"
+        }
+
+        fn ref_or_schema() -> openapi::schema::RefOr<openapi::schema::Schema> {
+            openapi::Schema::Array(
+                openapi::schema::ArrayBuilder::new()
+                    .items(openapi::schema::ObjectBuilder::new())
+                    .build(),
+            )
+            .into()
+        }
+    }
+
+    #[test]
+    fn body_fields_of_non_objects_are_skipped() {
+        assert_eq!(axum::Json::<ArrayBody>::body_fields(), None);
+    }
+
+    #[test]
+    fn api_doc_has_unique_operation_ids() {
+        let spec = serde_json::to_value(<ApiDoc as utoipa::OpenApi>::openapi()).unwrap();
+        assert!(spec_lint::duplicate_operation_ids(&spec).is_empty());
+    }
+}
+
+#[cfg(test)]
+mod form_tests {
+    use deps::*;
+
+    use super::*;
+    use crate::utils::testing::*;
+
+    #[derive(Debug, Clone, Copy)]
+    pub struct Subscribe;
+
+    #[derive(Debug, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+    #[serde(crate = "serde", rename_all = "camelCase")]
+    pub struct Request {
+        email: String,
+        frequency_days: u32,
+    }
+
+    #[derive(Debug, serde::Serialize, utoipa::ToSchema)]
+    #[serde(crate = "serde", rename_all = "camelCase", tag = "error")]
+    pub enum Error {
+        Internal,
+    }
+
+    impl From<&Error> for StatusCode {
+        fn from(_: &Error) -> Self {
+            Self::INTERNAL_SERVER_ERROR
+        }
+    }
+
+    impl ErrorCode for Error {
+        fn code(&self) -> &'static str {
+            "service.internal"
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl Endpoint for Subscribe {
+        type Request = Request;
+        type Response = Request;
+        type Error = Error;
+
+        async fn handle(
+            &self,
+            _ctx: &crate::Context,
+            request: Self::Request,
+        ) -> Result<Self::Response, Self::Error> {
+            Ok(request)
+        }
+    }
+
+    impl HttpEndpoint for Subscribe {
+        const METHOD: Method = Method::Post;
+        const PATH: &'static str = "/subscriptions";
+
+        type HttpRequest = (Form<Request>,);
+
+        fn request((Form(req),): Self::HttpRequest) -> Result<Self::Request, Self::Error> {
+            Ok(req)
+        }
+
+        fn response(resp: Self::Response) -> axum::response::Response {
+            response::Json(resp).into_response()
+        }
+    }
+
+    impl DocumentedEndpoint for Subscribe {
+        fn errors() -> Vec<ErrorResponse<Self::Error>> {
+            vec![("Internal server error", Error::Internal)]
+        }
+    }
+
+    #[tokio::test]
+    async fn parses_form_bodies() {
+        setup_tracing_once();
+        let resp = endpoint_app(Subscribe)
+            .oneshot(
+                http::Request::builder()
+                    .method("POST")
+                    .uri("/subscriptions")
+                    .header(
+                        http::header::CONTENT_TYPE,
+                        "application/x-www-form-urlencoded",
+                    )
+                    .body("email=multis%40cream.mux&frequencyDays=7".into())
+                    .unwrap_or_log(),
+            )
+            .await
+            .unwrap_or_log();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(resp.into_body())
+            .await
+            .unwrap_or_log();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap_or_log();
+        assert_eq!(
+            body,
+            serde_json::json!({ "email": "multis@cream.mux", "frequencyDays": 7 })
+        );
+    }
+
+    #[test]
+    fn documents_form_content_type() {
+        let spec = serde_json::to_value(
+            openapi::OpenApiBuilder::new()
+                .paths(
+                    openapi::PathsBuilder::new()
+                        .path(Subscribe::PATH, Subscribe::path_item())
+                        .build(),
+                )
+                .build(),
+        )
+        .unwrap();
+        let content = spec["paths"]["/subscriptions"]["post"]["requestBody"]["content"]
+            .as_object()
+            .unwrap();
+        assert!(
+            content.contains_key("application/x-www-form-urlencoded"),
+            "{content:?}"
+        );
+        assert!(!content.contains_key("application/json"), "{content:?}");
+    }
+}
+
+#[cfg(test)]
+mod named_example_tests {
+    use deps::*;
+
+    use super::*;
+
+    #[derive(Debug, Clone, Copy)]
+    pub struct Resolve;
+
+    #[derive(Debug, serde::Serialize, utoipa::ToSchema)]
+    #[serde(crate = "serde", rename_all = "camelCase")]
+    pub struct Response {
+        user_id: Option<uuid::Uuid>,
+        email: Option<String>,
+    }
+
+    #[derive(Debug, serde::Serialize, utoipa::ToSchema)]
+    #[serde(crate = "serde", rename_all = "camelCase", tag = "error")]
+    pub enum Error {
+        NotFound,
+        Gone,
+        Internal,
+    }
+
+    impl From<&Error> for StatusCode {
+        fn from(err: &Error) -> Self {
+            match err {
+                Error::NotFound | Error::Gone => Self::NOT_FOUND,
+                Error::Internal => Self::INTERNAL_SERVER_ERROR,
+            }
+        }
+    }
+
+    impl ErrorCode for Error {
+        fn code(&self) -> &'static str {
+            match self {
+                Error::NotFound => "resolve.not_found",
+                Error::Gone => "resolve.gone",
+                Error::Internal => "service.internal",
+            }
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl Endpoint for Resolve {
+        type Request = ();
+        type Response = Response;
+        type Error = Error;
+
+        async fn handle(
+            &self,
+            _ctx: &crate::Context,
+            _request: Self::Request,
+        ) -> Result<Self::Response, Self::Error> {
+            Err(Error::Internal)
+        }
+    }
+
+    impl HttpEndpoint for Resolve {
+        const METHOD: Method = Method::Get;
+        const PATH: &'static str = "/resolve";
+
+        type HttpRequest = ();
+
+        fn request(_: Self::HttpRequest) -> Result<Self::Request, Self::Error> {
+            Ok(())
+        }
+
+        fn response(resp: Self::Response) -> axum::response::Response {
+            response::Json(resp).into_response()
+        }
+    }
+
+    impl DocumentedEndpoint for Resolve {
+        fn errors() -> Vec<ErrorResponse<Self::Error>> {
+            vec![("Internal server error", Error::Internal)]
+        }
+
+        fn named_success_examples() -> Vec<NamedExample<serde_json::Value>> {
+            vec![
+                (
+                    "byId",
+                    "Resolved to a user",
+                    serde_json::json!({ "userId": "add83cdf-2ab3-443f-84dd-476d7984cf75" }),
+                ),
+                (
+                    "byEmail",
+                    "Resolved to an invite",
+                    serde_json::json!({ "email": "multis@cream.mux" }),
+                ),
+            ]
+        }
+
+        fn named_errors() -> Vec<(&'static str, NamedExample<Self::Error>)> {
+            vec![
+                (
+                    "Nothing to resolve",
+                    ("notFound", "Never existed", Error::NotFound),
+                ),
+                ("Nothing to resolve", ("gone", "Since deleted", Error::Gone)),
+            ]
+        }
+    }
+
+    fn spec() -> serde_json::Value {
+        let SpecPaths { builder, extras } = SpecPaths::new().endpoint::<Resolve>();
+        let mut spec = serde_json::to_value(
+            openapi::OpenApiBuilder::new()
+                .paths(builder.build())
+                .build(),
+        )
+        .unwrap();
+        named_examples::expand(&mut spec, &extras);
+        spec
+    }
+
+    #[test]
+    fn named_examples_share_the_status() {
+        let responses = &spec()["paths"]["/resolve"]["get"]["responses"];
+        let content = &responses["200"]["content"]["application/json"];
+        assert!(content.get("example").is_none(), "{content}");
+        assert_eq!(
+            content["examples"],
+            serde_json::json!({
+                "byId": {
+                    "summary": "Resolved to a user",
+                    "value": { "userId": "add83cdf-2ab3-443f-84dd-476d7984cf75" },
+                },
+                "byEmail": {
+                    "summary": "Resolved to an invite",
+                    "value": { "email": "multis@cream.mux" },
+                },
+            })
+        );
+        let content = &responses["404"]["content"]["application/json"];
+        assert_eq!(responses["404"]["description"], "Nothing to resolve");
+        assert_eq!(
+            content["examples"],
+            serde_json::json!({
+                "notFound": {
+                    "summary": "Never existed",
+                    "value": { "code": "resolve.not_found", "error": "notFound" },
+                },
+                "gone": {
+                    "summary": "Since deleted",
+                    "value": { "code": "resolve.gone", "error": "gone" },
+                },
+            })
+        );
+        assert_eq!(
+            responses["500"]["content"]["application/json"]["example"],
+            serde_json::json!({ "code": "service.internal", "error": "internal" })
+        );
+    }
+}
+
+#[cfg(test)]
+mod concurrency_tests {
+    use deps::*;
+
+    use super::*;
+    use crate::utils::testing::*;
+
+    #[derive(Debug, Clone, Copy)]
+    pub struct Expensive;
+
+    #[async_trait::async_trait]
+    impl Endpoint for Expensive {
+        type Request = ();
+        type Response = NoContent;
+        type Error = Infallible;
+
+        async fn handle(
+            &self,
+            _ctx: &crate::Context,
+            _request: Self::Request,
+        ) -> Result<Self::Response, Self::Error> {
+            tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+            Ok(NoContent)
+        }
+    }
+
+    impl HttpEndpoint for Expensive {
+        const METHOD: Method = Method::Post;
+        const PATH: &'static str = "/expensive";
+        const SUCCESS_CODE: StatusCode = StatusCode::NO_CONTENT;
+        const MAX_CONCURRENCY: Option<usize> = Some(2);
+
+        type HttpRequest = ();
+
+        fn request(_: Self::HttpRequest) -> Result<Self::Request, Self::Error> {
+            Ok(())
+        }
+
+        fn response(resp: Self::Response) -> axum::response::Response {
+            resp.into_response()
+        }
+    }
+
+    #[tokio::test]
+    async fn sheds_requests_past_the_limit() {
+        setup_tracing_once();
+        let app = endpoint_app(Expensive);
+        let statuses = futures::future::join_all((0..5).map(|_| {
+            app.clone().oneshot(
+                http::Request::builder()
+                    .method("POST")
+                    .uri("/expensive")
+                    .body(Default::default())
+                    .unwrap_or_log(),
+            )
+        }))
+        .await
+        .into_iter()
+        .map(|resp| resp.unwrap_or_log().status())
+        .collect::<Vec<_>>();
+        let count = |status| statuses.iter().filter(|val| **val == status).count();
+        assert_eq!(count(StatusCode::NO_CONTENT), 2, "{statuses:?}");
+        assert_eq!(count(StatusCode::SERVICE_UNAVAILABLE), 3, "{statuses:?}");
+
+        // permits are released once the requests finish
+        let resp = app
+            .oneshot(
+                http::Request::builder()
+                    .method("POST")
+                    .uri("/expensive")
+                    .body(Default::default())
+                    .unwrap_or_log(),
+            )
+            .await
+            .unwrap_or_log();
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+    }
+}
+
 // pub struct DocParameterBuilder {
 //     inner: utoipa::openapi::path::ParameterBuilder,
 // }
@@ -794,17 +2763,30 @@ pub type Method = openapi::PathItemType;
 #[derive(educe::Educe)]
 #[educe(Deref, DerefMut)]
 pub struct EndpointWrapper<T> {
+    #[educe(Deref, DerefMut)]
     inner: T,
+    /// The [`DocumentedParameter::body_fields`], worked out once when the
+    /// route is built rather than on every request.
+    body_fields: Option<BodyFields>,
 }
 
+/// Put in the request extensions for [`HttpEndpoint::STRICT_BODY`] checks.
+#[derive(Debug, Clone)]
+struct BodyFields(std::sync::Arc<[String]>);
+
 impl<T> EndpointWrapper<T>
 where
     T: HttpEndpoint + Clone + Send + Sized + 'static,
-    T::Error: serde::Serialize,
+    T::Error: serde::Serialize + ErrorCode,
     for<'a> &'a T::Error: Into<StatusCode>,
 {
     pub fn new(inner: T) -> Self {
-        Self { inner }
+        let body_fields = if T::STRICT_BODY == Some(false) {
+            None
+        } else {
+            T::HttpRequest::body_fields().map(|fields| BodyFields(fields.into()))
+        };
+        Self { inner, body_fields }
     }
 }
 
@@ -815,6 +2797,7 @@ where
     fn clone(&self) -> Self {
         Self {
             inner: self.inner.clone(),
+            body_fields: self.body_fields.clone(),
         }
     }
 }
@@ -822,12 +2805,15 @@ where
 impl<T> axum::handler::Handler<T::Request> for EndpointWrapper<T>
 where
     T: HttpEndpoint + Clone,
-    T::Error: serde::Serialize,
+    T::Error: serde::Serialize + ErrorCode,
     for<'a> &'a T::Error: Into<StatusCode>,
 {
     type Future = std::pin::Pin<Box<dyn Future<Output = axum::response::Response> + Send>>;
 
-    fn call(self, req: hyper::Request<hyper::Body>) -> Self::Future {
+    fn call(self, mut req: hyper::Request<hyper::Body>) -> Self::Future {
+        if let Some(fields) = &self.body_fields {
+            req.extensions_mut().insert(fields.clone());
+        }
         self.http(req)
     }
 }
@@ -835,7 +2821,7 @@ where
 impl<T> From<EndpointWrapper<T>> for axum::Router
 where
     T: HttpEndpoint + Clone,
-    T::Error: serde::Serialize,
+    T::Error: serde::Serialize + ErrorCode,
     for<'a> &'a T::Error: Into<StatusCode>,
 {
     fn from(wrapper: EndpointWrapper<T>) -> Self {
@@ -851,7 +2837,94 @@ where
             PathItemType::Trace => axum::routing::trace(wrapper),
             PathItemType::Connect => todo!(),
         };
-        axum::Router::new().route(T::PATH, method)
+        let router = axum::Router::new().route(T::PATH, method);
+        let router = match T::MAX_CONCURRENCY {
+            Some(max) => {
+                let permits = std::sync::Arc::new(tokio::sync::Semaphore::new(max));
+                router.route_layer(axum::middleware::from_fn(
+                    move |req: axum::http::Request<axum::body::Body>,
+                          next: axum::middleware::Next<axum::body::Body>| {
+                        concurrency_limit(permits.clone(), req, next)
+                    },
+                ))
+            }
+            None => router,
+        };
+        // outermost so that disabled endpoints don't take up permits
+        match T::FEATURE_FLAG {
+            Some(flag) => router.route_layer(axum::middleware::from_fn(
+                move |req: axum::http::Request<axum::body::Body>,
+                      next: axum::middleware::Next<axum::body::Body>| {
+                    feature_flags::require_feature(flag, req, next)
+                },
+            )),
+            None => router,
+        }
+    }
+}
+
+/// Sheds requests with a `503` once all the `permits` are taken. See
+/// [`HttpEndpoint::MAX_CONCURRENCY`].
+async fn concurrency_limit(
+    permits: std::sync::Arc<tokio::sync::Semaphore>,
+    req: axum::http::Request<axum::body::Body>,
+    next: axum::middleware::Next<axum::body::Body>,
+) -> axum::response::Response {
+    let _permit = match permits.try_acquire_owned() {
+        Ok(permit) => permit,
+        Err(_) => {
+            let mut resp = (
+                StatusCode::SERVICE_UNAVAILABLE,
+                response::Json(RoutingError::Overloaded),
+            )
+                .into_response();
+            set_retry_after(
+                resp.headers_mut(),
+                time::Duration::seconds(RETRY_AFTER_SECS.into()),
+            );
+            return resp;
+        }
+    };
+    next.run(req).await
+}
+
+/// An [`HttpEndpoint`] as a [`tower::Service`] for composing with arbitrary middleware
+/// stacks. Requests whose method and path don't match the endpoint get the usual
+/// `405` and `404` responses. The [`SharedContext`] is expected in the request
+/// extensions unless provided through [`EndpointService::with_context`].
+#[derive(Clone)]
+pub struct EndpointService(axum::Router);
+
+impl EndpointService {
+    pub fn new<T>(endpoint: T) -> Self
+    where
+        T: HttpEndpoint + Clone + Send + Sized + 'static,
+        T::Error: serde::Serialize + ErrorCode,
+        for<'a> &'a T::Error: Into<StatusCode>,
+    {
+        // routing is still needed so that `Path` parameters can be extracted
+        Self(EndpointWrapper::new(endpoint).into())
+    }
+
+    pub fn with_context(self, ctx: SharedContext) -> Self {
+        Self(self.0.layer(Extension(ctx)))
+    }
+}
+
+impl tower::Service<hyper::Request<hyper::Body>> for EndpointService {
+    type Response = axum::response::Response;
+    type Error = std::convert::Infallible;
+    type Future = axum::routing::future::RouteFuture<hyper::Body, std::convert::Infallible>;
+
+    fn poll_ready(
+        &mut self,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        tower::Service::<hyper::Request<hyper::Body>>::poll_ready(&mut self.0, cx)
+    }
+
+    fn call(&mut self, req: hyper::Request<hyper::Body>) -> Self::Future {
+        tower::Service::call(&mut self.0, req)
     }
 }
 
@@ -860,7 +2933,7 @@ where
     T: DocumentedEndpoint,
     T::Request: axum::extract::FromRequest<axum::body::Body>,
     T::Response: utoipa::ToSchema,
-    T::Error: utoipa::ToSchema + serde::Serialize,
+    T::Error: utoipa::ToSchema + serde::Serialize + ErrorCode,
     for<'a> &'a T::Error: Into<StatusCode>,
     T::HttpRequest: DocumentedParameter,
 {
@@ -873,6 +2946,272 @@ where
     }
 }
 
+/// Errors generated by the router itself before reaching any [`Endpoint`].
+#[derive(Debug, serde::Serialize, thiserror::Error, utoipa::ToSchema)]
+#[serde(crate = "serde", rename_all = "camelCase", tag = "error")]
+pub enum RoutingError {
+    #[error("method not allowed, allowed: {allowed:?}")]
+    MethodNotAllowed { allowed: Vec<String> },
+    #[error("no route found for path: {path}")]
+    NotFound { path: String },
+    #[error("too many requests in flight")]
+    Overloaded,
+    #[error("uri too long: {length} > {max}")]
+    UriTooLong { length: usize, max: usize },
+    #[error("rate limited")]
+    RateLimited,
+    #[error("request deadline exceeded")]
+    DeadlineExceeded,
+    #[error("request body over {limit} bytes")]
+    PayloadTooLarge { limit: usize },
+    #[error("no tenant given")]
+    MissingTenant,
+    #[error("invalid tenant: {tenant:?}")]
+    InvalidTenant { tenant: String },
+    #[error("tenant header {header:?} doesn't match subdomain {subdomain:?}")]
+    TenantMismatch { header: String, subdomain: String },
+    #[error("unknown tenant: {tenant:?}")]
+    UnknownTenant { tenant: String },
+    #[error("under maintenance")]
+    UnderMaintenance,
+}
+
+/// Fallback for requests that don't match any route. Register it on the final,
+/// fully merged router since axum panics when merging two routers that both
+/// have a custom fallback.
+pub async fn not_found(uri: axum::http::Uri) -> axum::response::Response {
+    (
+        StatusCode::NOT_FOUND,
+        response::Json(RoutingError::NotFound {
+            path: uri.path().to_string(),
+        }),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_not_found() {
+    use crate::utils::testing::*;
+    use axum::handler::Handler;
+    let resp = crate::user::router()
+        .fallback(not_found.into_service())
+        .oneshot(
+            http::Request::builder()
+                .method("GET")
+                .uri("/nowhere/to/be/found?q=1")
+                .body(Default::default())
+                .unwrap_or_log(),
+        )
+        .await
+        .unwrap_or_log();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    let body = hyper::body::to_bytes(resp.into_body())
+        .await
+        .unwrap_or_log();
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap_or_log();
+    assert_eq!(
+        body,
+        serde_json::json!({
+            "error": "notFound",
+            "path": "/nowhere/to/be/found",
+        })
+    );
+}
+
+/// Middleware that adds a [`RoutingError`] json body to the `405 Method Not Allowed`
+/// responses axum generates for known paths. Axum takes care of the `Allow` header.
+pub async fn method_not_allowed_body(
+    req: axum::http::Request<axum::body::Body>,
+    next: axum::middleware::Next<axum::body::Body>,
+) -> axum::response::Response {
+    let resp = next.run(req).await;
+    if resp.status() != StatusCode::METHOD_NOT_ALLOWED {
+        return resp;
+    }
+    let allow = resp.headers().get(axum::http::header::ALLOW).cloned();
+    let allowed = allow
+        .as_ref()
+        .and_then(|val| val.to_str().ok())
+        .map(|val| {
+            val.split(',')
+                .map(str::trim)
+                .filter(|method| !method.is_empty())
+                .map(String::from)
+                .collect()
+        })
+        .unwrap_or_default();
+    let mut resp = (
+        StatusCode::METHOD_NOT_ALLOWED,
+        response::Json(RoutingError::MethodNotAllowed { allowed }),
+    )
+        .into_response();
+    if let Some(allow) = allow {
+        resp.headers_mut().insert(axum::http::header::ALLOW, allow);
+    }
+    resp
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_method_not_allowed_body() {
+    use crate::utils::testing::*;
+    let resp = crate::user::router()
+        .oneshot(
+            http::Request::builder()
+                .method("POST")
+                .uri(format!("/users/{}", uuid::Uuid::new_v4()))
+                .body(Default::default())
+                .unwrap_or_log(),
+        )
+        .await
+        .unwrap_or_log();
+    assert_eq!(resp.status(), StatusCode::METHOD_NOT_ALLOWED);
+    let mut allow = resp
+        .headers()
+        .get(http::header::ALLOW)
+        .expect("Allow header not set")
+        .to_str()
+        .unwrap()
+        .split(',')
+        .map(String::from)
+        .collect::<Vec<_>>();
+    allow.sort();
+    assert_eq!(allow, ["DELETE", "GET", "HEAD", "PATCH", "PUT"]);
+    let body = hyper::body::to_bytes(resp.into_body())
+        .await
+        .unwrap_or_log();
+    let body = serde_json::from_slice(&body).unwrap_or_log();
+    check_json(
+        (
+            "expected",
+            &serde_json::json!({
+                "error": "methodNotAllowed",
+            }),
+        ),
+        ("response", &body),
+    );
+}
+
+/// Answers requests whose path and query are longer than [`Config::max_uri_len`]
+/// with a `414` instead of routing them. Needs the [`SharedContext`] extension.
+pub async fn reject_long_uri(
+    req: axum::http::Request<axum::body::Body>,
+    next: axum::middleware::Next<axum::body::Body>,
+) -> axum::response::Response {
+    let max = req
+        .extensions()
+        .get::<SharedContext>()
+        .map(|ctx| ctx.config.max_uri_len);
+    let length = req
+        .uri()
+        .path_and_query()
+        .map(|target| target.as_str().len())
+        .unwrap_or_default();
+    match max {
+        Some(max) if length > max => (
+            StatusCode::URI_TOO_LONG,
+            response::Json(RoutingError::UriTooLong { length, max }),
+        )
+            .into_response(),
+        _ => next.run(req).await,
+    }
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_reject_long_uri() {
+    use crate::user::testing::*;
+    use crate::utils::testing::*;
+    let ctx = std::sync::Arc::new(crate::Context::mock(InMemoryDb::fixture()));
+    let max = ctx.config.max_uri_len;
+    let app = crate::user::router()
+        .layer(axum::middleware::from_fn(reject_long_uri))
+        .layer(axum::Extension(ctx));
+    let get_user = |query: String| {
+        app.clone().oneshot(
+            http::Request::builder()
+                .method("GET")
+                .uri(format!("/users/{USER_01_ID}?{query}"))
+                .header(
+                    http::header::AUTHORIZATION,
+                    format!("Bearer {USER_01_SESSION}"),
+                )
+                .body(Default::default())
+                .unwrap_or_log(),
+        )
+    };
+
+    let resp = get_user("q=1".into()).await.unwrap_or_log();
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let resp = get_user(format!("q={}", "1".repeat(max)))
+        .await
+        .unwrap_or_log();
+    assert_eq!(resp.status(), StatusCode::URI_TOO_LONG);
+    let body = hyper::body::to_bytes(resp.into_body())
+        .await
+        .unwrap_or_log();
+    let body = serde_json::from_slice(&body).unwrap_or_log();
+    check_json(
+        (
+            "expected",
+            &serde_json::json!({
+                "error": "uriTooLong",
+                "max": max,
+            }),
+        ),
+        ("response", &body),
+    );
+}
+
+/// Wraps the assembled router so that paths with trailing slashes are routed to the
+/// same handlers as their canonical form if `enabled`. This has to sit outside the
+/// [`axum::Router`] since layers added to the router only run after routing.
+pub fn trim_trailing_slash(
+    router: axum::Router,
+    enabled: bool,
+) -> tower::util::Either<tower_http::normalize_path::NormalizePath<axum::Router>, axum::Router> {
+    tower::ServiceBuilder::new()
+        .option_layer(
+            enabled.then(tower_http::normalize_path::NormalizePathLayer::trim_trailing_slash),
+        )
+        .service(router)
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_trim_trailing_slash() {
+    use crate::utils::testing::*;
+    let ctx = TestContext::new(crate::function!()).await;
+    {
+        let app = trim_trailing_slash(
+            crate::user::router().layer(axum::Extension(ctx.ctx())),
+            true,
+        );
+        for uri in ["/users", "/users/"] {
+            let resp = app
+                .clone()
+                .oneshot(
+                    http::Request::builder()
+                        .method("GET")
+                        .uri(uri)
+                        .header(
+                            http::header::AUTHORIZATION,
+                            format!("Bearer {USER_01_SESSION}"),
+                        )
+                        .header(http::header::CONTENT_TYPE, "application/json")
+                        .body("{}".into())
+                        .unwrap_or_log(),
+                )
+                .await
+                .unwrap_or_log();
+            assert_eq!(resp.status(), StatusCode::OK, "failed on {uri}");
+        }
+    }
+    ctx.close().await;
+}
+
 pub struct BearerToken(pub std::sync::Arc<str>);
 
 #[async_trait::async_trait]
@@ -87,8 +87,8 @@ macro_rules! alias_and_ref {
 }
 
 /// Implement [`From`] [`crate::auth::authorize::Error`] for the provided type
-/// This expects the standard unit `AccessDenied` and the struct `Internal`
-/// variant on the `Error` enum
+/// This expects the standard unit `AccessDenied`, unit `Forbidden` and the
+/// struct `Internal` variant on the `Error` enum
 #[macro_export]
 macro_rules! impl_from_auth_err {
     ($errty:ident) => {
@@ -97,6 +97,8 @@ macro_rules! impl_from_auth_err {
                 use $crate::auth::authorize::Error;
                 match err {
                     Error::Unauthorized | Error::InvalidToken => Self::AccessDenied,
+                    Error::Forbidden => Self::Forbidden,
+                    Error::Overloaded => Self::Overloaded,
                     Error::Internal { message } => Self::Internal { message },
                 }
             }
@@ -104,6 +106,25 @@ macro_rules! impl_from_auth_err {
     };
 }
 
+/// Implement [`From`] [`sqlx::Error`] for the provided type so that handlers can
+/// `?` the errors they have no special handling for. Pool timeouts become the unit
+/// `Overloaded` variant and everything else the struct `Internal` one.
+#[macro_export]
+macro_rules! impl_from_db_err {
+    ($errty:ident) => {
+        impl From<sqlx::Error> for $errty {
+            fn from(err: sqlx::Error) -> Self {
+                match err {
+                    sqlx::Error::PoolTimedOut => Self::Overloaded,
+                    _ => Self::Internal {
+                        message: format!("db error: {err}"),
+                    },
+                }
+            }
+        }
+    };
+}
+
 /// Name of currently execution function
 /// Resolves to first found in current function path that isn't a closure.
 #[macro_export]
@@ -356,6 +377,7 @@ macro_rules! integration_table_tests {
             $(body: $json_body:expr,)?
             $(check_json: $check_json:expr,)?
             $(auth_token: $auth_token:expr,)?
+            $(setup: $setup_fn:expr,)?
             $(extra_assertions: $extra_fn:expr,)?
             $(print_response: $print_res:expr,)?
         },
@@ -366,6 +388,11 @@ macro_rules! integration_table_tests {
             async fn $name() {
                 let mut ctx = $crate::utils::testing::TestContext::new($crate::function_full!()).await;
                 {
+                    let setup: Option<&$crate::utils::testing::TestSetup> = $crate::optional_expr!($($setup_fn)?);
+                    if let Some(setup) = setup {
+                        setup(&ctx).await;
+                    }
+
                     let mut request = axum::http::Request::builder()
                                         .method($method)
                                         .uri($uri);
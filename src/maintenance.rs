@@ -0,0 +1,357 @@
+//! Planned maintenance. While it's on, [`reject_during_maintenance`] answers all
+//! but the [`health::INFRA_PATHS`] with a `503` so that clients back off instead of
+//! running into half migrated tables. Starts out as
+//! [`Config::maintenance_mode`] and is toggled at runtime through [`SetMaintenance`].
+
+use deps::*;
+
+use crate::*;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use serde::{Deserialize, Serialize};
+
+/// Sent in the `Retry-After` header of the rejections when there's no
+/// [`MaintenanceStatus::ends_at`] or it has passed. See [`utils::retry_after`].
+pub const RETRY_AFTER_SECS: u64 = 120;
+
+#[derive(Debug, Default)]
+pub struct MaintenanceMode {
+    on: AtomicBool,
+    ends_at: parking_lot::Mutex<Option<time::OffsetDateTime>>,
+}
+
+impl MaintenanceMode {
+    pub fn new(on: bool) -> Self {
+        Self {
+            on: AtomicBool::new(on),
+            ends_at: Default::default(),
+        }
+    }
+
+    pub fn is_on(&self) -> bool {
+        self.on.load(Ordering::Relaxed)
+    }
+
+    /// Returns whether it was on before.
+    pub fn set(&self, on: bool) -> bool {
+        self.on.swap(on, Ordering::Relaxed)
+    }
+
+    /// When it's expected to be over, if anyone said.
+    pub fn ends_at(&self) -> Option<time::OffsetDateTime> {
+        *self.ends_at.lock()
+    }
+
+    pub fn set_ends_at(&self, ends_at: Option<time::OffsetDateTime>) {
+        *self.ends_at.lock() = ends_at;
+    }
+}
+
+/// Middleware answering with a `503` while [`Context::maintenance`] is on.
+/// Expects the [`SharedContext`] in the request extensions.
+pub async fn reject_during_maintenance(
+    req: axum::http::Request<axum::body::Body>,
+    next: axum::middleware::Next<axum::body::Body>,
+) -> axum::response::Response {
+    let ctx = match req.extensions().get::<SharedContext>() {
+        Some(ctx) if ctx.maintenance.is_on() => ctx.clone(),
+        _ => return next.run(req).await,
+    };
+    // the probe so the orchestrator doesn't start restarting instances and the
+    // admin endpoints so it can be turned off again
+    if health::is_infra_path(req.uri().path()) {
+        return next.run(req).await;
+    }
+    let mut resp = (
+        StatusCode::SERVICE_UNAVAILABLE,
+        Json(RoutingError::UnderMaintenance),
+    )
+        .into_response();
+    set_retry_after(
+        resp.headers_mut(),
+        ctx.retry_after(utils::Backoff::Maintenance),
+    );
+    resp
+}
+
+pub const TAG: Tag = Tag {
+    name: "admin",
+    desc: "Operating the service.",
+};
+
+pub fn router() -> axum::Router {
+    axum::Router::new()
+        .merge(EndpointWrapper::new(SetMaintenance))
+        .layer(axum::middleware::from_fn(crate::method_not_allowed_body))
+}
+
+pub fn components(builder: openapi::ComponentsBuilder) -> openapi::ComponentsBuilder {
+    let builder = SetMaintenance::components(builder);
+    builder.schema(
+        type_name_raw::<MaintenanceStatus>(),
+        <MaintenanceStatus as utoipa::ToSchema>::schema(),
+    )
+}
+
+pub fn paths(paths: crate::SpecPaths) -> crate::SpecPaths {
+    paths.endpoint::<SetMaintenance>()
+}
+
+/// Turns maintenance mode on or off. Only for [`auth::Role::SuperAdmin`]s.
+#[derive(Debug, Clone, Copy)]
+pub struct SetMaintenance;
+
+#[derive(Debug)]
+pub struct Request {
+    pub auth_token: std::sync::Arc<str>,
+    pub body: MaintenanceStatus,
+}
+
+#[derive(Debug, Serialize, Deserialize, utoipa::ToSchema)]
+#[serde(crate = "serde", rename_all = "camelCase")]
+pub struct MaintenanceStatus {
+    pub enabled: bool,
+    /// When it's expected to be over, sets the `Retry-After` of the rejections.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "time::serde::rfc3339::option"
+    )]
+    pub ends_at: Option<time::OffsetDateTime>,
+}
+
+#[derive(Debug, thiserror::Error, Serialize, utoipa::ToSchema)]
+#[serde(crate = "serde", tag = "error", rename_all = "camelCase")]
+pub enum Error {
+    #[error("acess denied")]
+    AccessDenied,
+    #[error("forbidden")]
+    Forbidden,
+    #[error("database overloaded")]
+    Overloaded,
+    #[error("internal server error: {message:?}")]
+    Internal { message: String },
+}
+
+crate::impl_from_auth_err!(Error);
+crate::impl_from_db_err!(Error);
+
+#[async_trait::async_trait]
+impl AuthenticatedEndpoint for SetMaintenance {
+    type Request = Request;
+    type Response = Ref<MaintenanceStatus>;
+    type Error = Error;
+
+    fn authorize_request(&self, request: &Self::Request) -> auth::authorize::Request {
+        auth::authorize::Request {
+            auth_token: request.auth_token.clone(),
+            resource: auth::Resource::Maintenance,
+            action: auth::Action::Write,
+        }
+    }
+
+    #[tracing::instrument(skip(ctx))]
+    async fn handle(
+        &self,
+        ctx: &crate::Context,
+        accessing_user: uuid::Uuid,
+        request: Self::Request,
+    ) -> Result<Self::Response, Self::Error> {
+        auth::require_role(ctx, accessing_user, auth::Role::SuperAdmin).await?;
+        let enabled = request.body.enabled;
+        let ends_at = request.body.ends_at.filter(|_| enabled);
+        ctx.maintenance.set_ends_at(ends_at);
+        let was_enabled = ctx.maintenance.set(enabled);
+        tracing::warn!(%accessing_user, enabled, was_enabled, ?ends_at, "maintenance mode set");
+        Ok(MaintenanceStatus { enabled, ends_at }.into())
+    }
+}
+
+impl From<&Error> for StatusCode {
+    fn from(err: &Error) -> Self {
+        use Error::*;
+        match err {
+            AccessDenied => Self::UNAUTHORIZED,
+            Forbidden => Self::FORBIDDEN,
+            Overloaded => Self::SERVICE_UNAVAILABLE,
+            Internal { .. } => Self::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl crate::utils::ErrorCode for Error {
+    fn code(&self) -> &'static str {
+        use Error::*;
+        match self {
+            AccessDenied => "auth.access_denied",
+            Forbidden => "auth.forbidden",
+            Overloaded => "service.overloaded",
+            Internal { .. } => "service.internal",
+        }
+    }
+}
+
+impl HttpEndpoint for SetMaintenance {
+    const METHOD: Method = Method::Put;
+    const PATH: &'static str = "/admin/maintenance";
+    const AUDIT_ACTION: Option<&'static str> = Some("maintenance.set");
+
+    type HttpRequest = (BearerToken, Json<MaintenanceStatus>);
+
+    fn request(
+        (BearerToken(token), Json(body)): Self::HttpRequest,
+    ) -> Result<Self::Request, Self::Error> {
+        Ok(self::Request {
+            auth_token: token,
+            body,
+        })
+    }
+
+    fn response(Ref(resp): Self::Response) -> axum::response::Response {
+        Json(resp).into_response()
+    }
+}
+
+impl DocumentedEndpoint for SetMaintenance {
+    const TAG: &'static Tag = &TAG;
+    const SUMMARY: &'static str = "Turn maintenance mode on or off.";
+
+    fn success_examples() -> Vec<serde_json::Value> {
+        vec![serde_json::json!({ "enabled": true, "endsAt": "2022-10-16T09:30:00Z" })]
+    }
+
+    fn errors() -> Vec<ErrorResponse<Self::Error>> {
+        vec![
+            ("Access denied", Error::AccessDenied),
+            ("Forbidden", Error::Forbidden),
+            ("Overloaded", Error::Overloaded),
+            (
+                "Internal server error",
+                Error::Internal {
+                    message: "internal server error".to_string(),
+                },
+            ),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use deps::*;
+
+    use crate::user::testing::*;
+    use crate::utils::testing::*;
+
+    fn app(ctx: crate::SharedContext) -> axum::Router {
+        crate::RouterBuilder::new()
+            .build()
+            .layer(axum::middleware::from_fn(super::reject_during_maintenance))
+            .layer(axum::Extension(ctx))
+    }
+
+    fn set_maintenance(enabled: bool) -> http::Request<axum::body::Body> {
+        http::Request::builder()
+            .method("PUT")
+            .uri("/admin/maintenance")
+            .header(
+                http::header::AUTHORIZATION,
+                format!("Bearer {USER_01_SESSION}"),
+            )
+            .header(http::header::CONTENT_TYPE, "application/json")
+            .body(
+                serde_json::to_vec(&serde_json::json!({ "enabled": enabled }))
+                    .unwrap()
+                    .into(),
+            )
+            .unwrap_or_log()
+    }
+
+    fn get(uri: String) -> http::Request<axum::body::Body> {
+        http::Request::builder()
+            .method("GET")
+            .uri(uri)
+            .header(
+                http::header::AUTHORIZATION,
+                format!("Bearer {USER_01_SESSION}"),
+            )
+            .body(Default::default())
+            .unwrap_or_log()
+    }
+
+    #[tokio::test]
+    async fn serves_only_health_during_maintenance() {
+        let ctx = TestContext::new(crate::function!()).await;
+        {
+            sqlx::query("INSERT INTO user_roles (user_id, role) VALUES ($1, 'superAdmin')")
+                .bind(USER_01_ID)
+                .execute(&ctx.ctx().db_pool)
+                .await
+                .unwrap_or_log();
+            let app = app(ctx.ctx());
+
+            let resp = app
+                .clone()
+                .oneshot(set_maintenance(true))
+                .await
+                .unwrap_or_log();
+            assert_eq!(resp.status(), StatusCode::OK);
+
+            let resp = app
+                .clone()
+                .oneshot(get(format!("/users/{USER_01_ID}")))
+                .await
+                .unwrap_or_log();
+            assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+            assert_eq!(
+                resp.headers()[http::header::RETRY_AFTER],
+                super::RETRY_AFTER_SECS.to_string()
+            );
+            let body = hyper::body::to_bytes(resp.into_body())
+                .await
+                .unwrap_or_log();
+            let body = serde_json::from_slice(&body).unwrap_or_log();
+            check_json(
+                (
+                    "expected",
+                    &serde_json::json!({ "error": "underMaintenance" }),
+                ),
+                ("response", &body),
+            );
+
+            let resp = app
+                .clone()
+                .oneshot(get("/ready".into()))
+                .await
+                .unwrap_or_log();
+            assert_eq!(resp.status(), StatusCode::OK);
+
+            let resp = app
+                .clone()
+                .oneshot(set_maintenance(false))
+                .await
+                .unwrap_or_log();
+            assert_eq!(resp.status(), StatusCode::OK);
+            let resp = app
+                .oneshot(get(format!("/users/{USER_01_ID}")))
+                .await
+                .unwrap_or_log();
+            assert_eq!(resp.status(), StatusCode::OK);
+        }
+        ctx.close().await;
+    }
+
+    #[tokio::test]
+    async fn only_super_admins_toggle() {
+        setup_tracing_once();
+        let ctx = std::sync::Arc::new(crate::Context::mock(
+            InMemoryDb::fixture().with_role(USER_01_ID, crate::auth::Role::Basic),
+        ));
+        let resp = app(ctx.clone())
+            .oneshot(set_maintenance(true))
+            .await
+            .unwrap_or_log();
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        assert!(!ctx.maintenance.is_on());
+    }
+}
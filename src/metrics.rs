@@ -0,0 +1,338 @@
+//! Bare bones gauges exposed in the Prometheus text format at `/metrics`.
+
+use deps::*;
+
+use crate::*;
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// An `f64` that can be updated from any thread.
+#[derive(Debug, Default)]
+pub struct Gauge(AtomicU64);
+
+impl Gauge {
+    pub fn set(&self, val: f64) {
+        self.0.store(val.to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> f64 {
+        f64::from_bits(self.0.load(Ordering::Relaxed))
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct PoolMetrics {
+    pub size: Gauge,
+    pub idle: Gauge,
+    pub max_connections: Gauge,
+    pub acquire_wait_seconds: Gauge,
+    /// Pools open for [`tenant::Tenant`]s, on top of the `db_pool`.
+    pub tenant_pools: Gauge,
+    /// Connections open across the tenant pools, idle or not.
+    pub tenant_size: Gauge,
+}
+
+#[derive(Debug, Default)]
+pub struct Metrics {
+    pub db_pool: PoolMetrics,
+}
+
+impl Metrics {
+    /// Renders all the gauges in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        use std::fmt::Write;
+        let mut out = String::new();
+        for (name, help, gauge) in [
+            (
+                "db_pool_size",
+                "Connections currently open, idle or not.",
+                &self.db_pool.size,
+            ),
+            (
+                "db_pool_idle",
+                "Connections open and not in use.",
+                &self.db_pool.idle,
+            ),
+            (
+                "db_pool_max_connections",
+                "Most connections the pool will open.",
+                &self.db_pool.max_connections,
+            ),
+            (
+                "db_pool_acquire_wait_seconds",
+                "How long the last sample waited to acquire a connection.",
+                &self.db_pool.acquire_wait_seconds,
+            ),
+            (
+                "db_tenant_pools",
+                "Pools open for tenants on top of the main one.",
+                &self.db_pool.tenant_pools,
+            ),
+            (
+                "db_tenant_pool_size",
+                "Connections currently open across the tenant pools, idle or not.",
+                &self.db_pool.tenant_size,
+            ),
+        ] {
+            writeln!(out, "# HELP {name} {help}").unwrap();
+            writeln!(out, "# TYPE {name} gauge").unwrap();
+            writeln!(out, "{name} {}", gauge.get()).unwrap();
+        }
+        out
+    }
+}
+
+impl Context {
+    /// Updates the [`PoolMetrics`] gauges from the current state of `db_pool` and
+    /// the [`db::DbBackend::tenant_pools`].
+    pub async fn sample_pool_metrics(&self) {
+        let gauges = &self.metrics.db_pool;
+        gauges
+            .max_connections
+            .set(self.config.db_max_connections as f64);
+
+        let start = std::time::Instant::now();
+        let conn =
+            tokio::time::timeout(self.config.pool_metrics_interval, self.db_pool.acquire()).await;
+        gauges
+            .acquire_wait_seconds
+            .set(start.elapsed().as_secs_f64());
+        match conn {
+            Ok(Ok(_)) => {}
+            Ok(Err(err)) => tracing::warn!(?err, "unable to acquire connection for metrics"),
+            Err(_) => tracing::warn!("timed out acquiring connection for metrics"),
+        }
+
+        gauges.size.set(self.db_pool.size() as f64);
+        gauges.idle.set(self.db_pool.num_idle() as f64);
+        let tenant_pools = self.db.tenant_pools();
+        gauges.tenant_pools.set(tenant_pools.len() as f64);
+        gauges.tenant_size.set(
+            tenant_pools
+                .iter()
+                .map(|pool| pool.size() as f64)
+                .sum::<f64>(),
+        );
+    }
+}
+
+/// Serves the [`router`] on its own at [`Config::metrics_bind_addr`] until `handle`
+/// shuts down. Failing to bind, say with the port taken, only costs the metrics
+/// unless [`Config::metrics_required`] is set in which case the error is returned
+/// for startup to abort on.
+pub fn spawn_exporter(
+    ctx: SharedContext,
+    handle: axum_server::Handle,
+) -> std::io::Result<Option<tokio::task::JoinHandle<()>>> {
+    let addr = match ctx.config.metrics_bind_addr {
+        Some(addr) => addr,
+        None => return Ok(None),
+    };
+    let listener = match std::net::TcpListener::bind(addr) {
+        Ok(listener) => listener,
+        Err(err) if ctx.config.metrics_required => return Err(err),
+        Err(err) => {
+            tracing::warn!(?err, %addr, "unable to bind metrics exporter, serving without it");
+            return Ok(None);
+        }
+    };
+    tracing::info!("Metrics exporter listening at {addr:?}");
+    let app = router().layer(axum::Extension(ctx));
+    Ok(Some(tokio::spawn(async move {
+        if let Err(err) = axum_server::from_tcp(listener)
+            .handle(handle)
+            .serve(app.into_make_service())
+            .await
+        {
+            tracing::error!(?err, "metrics exporter failed");
+        }
+    })))
+}
+
+/// For the exporter, see [`public_router`] for the main listener.
+pub fn router() -> axum::Router {
+    axum::Router::new()
+        .route("/metrics", axum::routing::get(metrics))
+        .route_layer(axum::middleware::from_fn(require_token))
+        .layer(axum::middleware::from_fn(crate::method_not_allowed_body))
+}
+
+/// The [`router`], answering like an unknown route when the metrics are exported
+/// at [`Config::metrics_bind_addr`] instead.
+pub fn public_router() -> axum::Router {
+    router().route_layer(axum::middleware::from_fn(hide_if_exported))
+}
+
+async fn hide_if_exported(
+    req: axum::http::Request<axum::body::Body>,
+    next: axum::middleware::Next<axum::body::Body>,
+) -> axum::response::Response {
+    let exported = req
+        .extensions()
+        .get::<SharedContext>()
+        .map(|ctx| ctx.config.metrics_bind_addr.is_some())
+        .unwrap_or_default();
+    if !exported {
+        return next.run(req).await;
+    }
+    (
+        StatusCode::NOT_FOUND,
+        axum::response::Json(RoutingError::NotFound {
+            path: req.uri().path().to_string(),
+        }),
+    )
+        .into_response()
+}
+
+/// Checks the bearer token against [`Config::metrics_token`] if set.
+async fn require_token(
+    req: axum::http::Request<axum::body::Body>,
+    next: axum::middleware::Next<axum::body::Body>,
+) -> axum::response::Response {
+    let token = match req
+        .extensions()
+        .get::<SharedContext>()
+        .and_then(|ctx| ctx.config.metrics_token.clone())
+    {
+        Some(token) => token,
+        None => return next.run(req).await,
+    };
+    let authorized = req
+        .headers()
+        .get(axum::http::header::AUTHORIZATION)
+        .and_then(|val| val.to_str().ok())
+        .and_then(|val| val.strip_prefix("Bearer "))
+        // constant time
+        .map(|val| subtle::ConstantTimeEq::ct_eq(val.as_bytes(), token.as_bytes()).into())
+        .unwrap_or_default();
+    if authorized {
+        return next.run(req).await;
+    }
+    (
+        StatusCode::UNAUTHORIZED,
+        [(axum::http::header::WWW_AUTHENTICATE, "Bearer")],
+        "Bearer token missing or invalid",
+    )
+        .into_response()
+}
+
+async fn metrics(Extension(ctx): Extension<SharedContext>) -> axum::response::Response {
+    (
+        [(
+            axum::http::header::CONTENT_TYPE,
+            "text/plain; version=0.0.4",
+        )],
+        ctx.metrics.render(),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use deps::*;
+
+    use crate::utils::testing::*;
+
+    #[tokio::test]
+    async fn pool_gauges_reflect_the_pool() {
+        let ctx = TestContext::new(crate::function!()).await;
+        {
+            let shared = ctx.ctx();
+            let max = shared.config.db_max_connections;
+
+            let held = futures::future::try_join_all((0..2).map(|_| shared.db_pool.acquire()))
+                .await
+                .unwrap_or_log();
+            shared.sample_pool_metrics().await;
+            let gauges = &shared.metrics.db_pool;
+            assert_eq!(gauges.max_connections.get(), max as f64);
+            let size = gauges.size.get();
+            // the held ones and the one the sampler times its acquire with
+            let in_use = size - gauges.idle.get();
+            assert!(in_use >= 3., "size {size}, in use {in_use}");
+            drop(held);
+
+            let resp = crate::metrics::router()
+                .layer(axum::Extension(shared))
+                .oneshot(
+                    http::Request::builder()
+                        .method("GET")
+                        .uri("/metrics")
+                        .body(Default::default())
+                        .unwrap_or_log(),
+                )
+                .await
+                .unwrap_or_log();
+            assert_eq!(resp.status(), StatusCode::OK);
+            let body = hyper::body::to_bytes(resp.into_body())
+                .await
+                .unwrap_or_log();
+            let body = String::from_utf8(body.to_vec()).unwrap_or_log();
+            for expected in [
+                format!("db_pool_max_connections {max}"),
+                format!("db_pool_size {size}"),
+            ] {
+                assert!(body.lines().any(|line| line == expected), "{body}");
+            }
+        }
+        ctx.close().await;
+    }
+
+    crate::table_tests! {
+        metrics_guard tokio,
+        (public, metrics_bind_addr, metrics_token, authorization, status),
+        {
+            let mut ctx = crate::Context::mock(InMemoryDb::default());
+            ctx.config.metrics_bind_addr = metrics_bind_addr;
+            let metrics_token: Option<&str> = metrics_token;
+            ctx.config.metrics_token = metrics_token.map(String::from);
+            let router = if public {
+                crate::metrics::public_router()
+            } else {
+                crate::metrics::router()
+            };
+            let mut req = http::Request::builder().method("GET").uri("/metrics");
+            let authorization: Option<&str> = authorization;
+            if let Some(authorization) = authorization {
+                req = req.header(http::header::AUTHORIZATION, authorization);
+            }
+            let resp = router
+                .layer(axum::Extension(std::sync::Arc::new(ctx)))
+                .oneshot(req.body(Default::default()).unwrap_or_log())
+                .await
+                .unwrap_or_log();
+            assert_eq!(resp.status(), status);
+        },
+    }
+
+    const INTERNAL: Option<std::net::SocketAddr> = Some(std::net::SocketAddr::V4(
+        std::net::SocketAddrV4::new(std::net::Ipv4Addr::LOCALHOST, 9090),
+    ));
+
+    metrics_guard! {
+        public_without_exporter: (true, None, None, None, StatusCode::OK),
+        public_hidden_with_exporter: (true, INTERNAL, None, None, StatusCode::NOT_FOUND),
+        internal_with_exporter: (false, INTERNAL, None, None, StatusCode::OK),
+        token_missing: (true, None, Some("s3cret"), None, StatusCode::UNAUTHORIZED),
+        token_wrong: (false, INTERNAL, Some("s3cret"), Some("Bearer nope"), StatusCode::UNAUTHORIZED),
+        token_valid: (false, INTERNAL, Some("s3cret"), Some("Bearer s3cret"), StatusCode::OK),
+    }
+
+    #[tokio::test]
+    async fn exporter_bind_failure() {
+        setup_tracing_once();
+        let taken = std::net::TcpListener::bind("127.0.0.1:0").unwrap_or_log();
+        let exporter = |required| {
+            let mut ctx = crate::Context::mock(InMemoryDb::default());
+            ctx.config.metrics_bind_addr = Some(taken.local_addr().unwrap());
+            ctx.config.metrics_required = required;
+            crate::metrics::spawn_exporter(std::sync::Arc::new(ctx), axum_server::Handle::new())
+        };
+        // startup carries on without the exporter
+        assert!(exporter(false).unwrap_or_log().is_none());
+        assert_eq!(
+            exporter(true).unwrap_err().kind(),
+            std::io::ErrorKind::AddrInUse
+        );
+    }
+}
@@ -0,0 +1,501 @@
+//! Runtime config reloads. [`Reload`] re-reads the [`Reloadable`] part of the
+//! [`Config`], the log level, the feature flags and the rate limit, from the env
+//! and the `.env` file and swaps it in whole. The [`Config`] itself stays as it
+//! was at startup, the readers go through [`Context::enabled_features`] and
+//! [`Context::rate_limit`] which prefer the last reload.
+
+use deps::*;
+
+use crate::*;
+
+use serde::{Deserialize, Serialize};
+
+/// Handle on the filter installed by [`setup_tracing`].
+pub type LogFilter =
+    tracing_subscriber::reload::Handle<tracing_subscriber::EnvFilter, tracing_subscriber::Registry>;
+
+static LOG_FILTER: once_cell::sync::OnceCell<LogFilter> = once_cell::sync::OnceCell::new();
+
+/// Called by [`setup_tracing`], the [`LiveConfig`]s built after pick it up.
+pub fn set_log_filter(filter: LogFilter) {
+    LOG_FILTER.set(filter).ok();
+}
+
+/// Sent along every reload so nobody expects their other changes to have applied.
+pub const NOT_RELOADED_NOTE: &str = "only the log level, feature flags and rate limit are \
+    reloaded, the rest of the config, e.g. the bind address and the database url, \
+    takes a restart";
+
+/// The part of the [`Config`] that's safe to change while serving.
+#[derive(Debug, Clone)]
+pub struct Reloadable {
+    /// `RUST_LOG` directives.
+    pub log_level: String,
+    pub enabled_features: Vec<String>,
+    pub rate_limit: Option<RateLimit>,
+}
+
+impl Reloadable {
+    /// From `RUST_LOG`, `ENABLED_FEATURES`, `RATE_LIMIT` and
+    /// `RATE_LIMIT_WINDOW_SECS` as looked up by `var`.
+    pub fn from_env(var: impl Fn(&str) -> Option<String>) -> Result<Self, String> {
+        let rate_limit = match var("RATE_LIMIT") {
+            Some(limit) => Some(RateLimit {
+                limit: limit
+                    .parse()
+                    .map_err(|err| format!("invalid RATE_LIMIT: {err}"))?,
+                window: std::time::Duration::from_secs(
+                    var("RATE_LIMIT_WINDOW_SECS")
+                        .map(|val| val.parse())
+                        .unwrap_or(Ok(60))
+                        .map_err(|err| format!("invalid RATE_LIMIT_WINDOW_SECS: {err}"))?,
+                ),
+            }),
+            None => None,
+        };
+        Ok(Self {
+            log_level: var("RUST_LOG").unwrap_or_else(|| "info".into()),
+            enabled_features: var("ENABLED_FEATURES")
+                .map(|val| {
+                    val.split(',')
+                        .map(|flag| flag.trim().to_string())
+                        .filter(|flag| !flag.is_empty())
+                        .collect()
+                })
+                .unwrap_or_default(),
+            rate_limit,
+        })
+    }
+
+    /// [`Reloadable::from_env`] with the `.env` file, if there's one, over the
+    /// env. The process env itself is left alone, it's not safe to change with
+    /// other threads reading it.
+    pub fn from_dotenv() -> Result<Self, String> {
+        match dotenvy::dotenv_iter() {
+            Ok(vars) => Self::over_env(vars),
+            Err(_) => Self::from_env(|key| std::env::var(key).ok()),
+        }
+    }
+
+    /// [`Reloadable::from_dotenv`] with the env file at `path`.
+    pub fn from_env_file(path: impl AsRef<std::path::Path>) -> Result<Self, String> {
+        let vars = dotenvy::from_path_iter(path)
+            .map_err(|err| format!("unable to read the env file: {err}"))?;
+        Self::over_env(vars)
+    }
+
+    fn over_env(
+        vars: impl Iterator<Item = dotenvy::Result<(String, String)>>,
+    ) -> Result<Self, String> {
+        let vars = vars
+            .collect::<Result<std::collections::HashMap<_, _>, _>>()
+            .map_err(|err| format!("invalid .env file: {err}"))?;
+        Self::from_env(|key| vars.get(key).cloned().or_else(|| std::env::var(key).ok()))
+    }
+}
+
+type Source = Box<dyn Fn() -> Result<Reloadable, String> + Send + Sync>;
+
+/// What the [`Context`] was last [`Reload`]ed to, nothing until the first one.
+pub struct LiveConfig {
+    current: arc_swap::ArcSwapOption<Reloadable>,
+    source: Source,
+    log_filter: Option<LogFilter>,
+}
+
+impl std::fmt::Debug for LiveConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LiveConfig")
+            .field("current", &self.current)
+            .field("log_filter", &self.log_filter.is_some())
+            .finish()
+    }
+}
+
+impl Default for LiveConfig {
+    /// Reloads [`Reloadable::from_dotenv`] and the filter of [`setup_tracing`] if
+    /// it was called.
+    fn default() -> Self {
+        Self::new(Box::new(Reloadable::from_dotenv), LOG_FILTER.get().cloned())
+    }
+}
+
+impl LiveConfig {
+    pub fn new(source: Source, log_filter: Option<LogFilter>) -> Self {
+        Self {
+            current: Default::default(),
+            source,
+            log_filter,
+        }
+    }
+
+    pub fn current(&self) -> Option<std::sync::Arc<Reloadable>> {
+        self.current.load_full()
+    }
+
+    /// Reads the source and applies it, all or nothing.
+    pub fn reload(&self) -> Result<std::sync::Arc<Reloadable>, String> {
+        let reloaded = std::sync::Arc::new((self.source)()?);
+        if let Some(log_filter) = &self.log_filter {
+            let filter = tracing_subscriber::EnvFilter::try_new(&reloaded.log_level)
+                .map_err(|err| format!("invalid log level: {err}"))?;
+            log_filter
+                .reload(filter)
+                .map_err(|err| format!("unable to swap the log filter: {err}"))?;
+        }
+        self.current.store(Some(reloaded.clone()));
+        Ok(reloaded)
+    }
+}
+
+impl Context {
+    /// [`Config::enabled_features`] as last reloaded.
+    pub fn enabled_features(&self) -> Vec<String> {
+        match self.live_config.current() {
+            Some(live) => live.enabled_features.clone(),
+            None => self.config.enabled_features.clone(),
+        }
+    }
+
+    /// [`Config::rate_limit`] as last reloaded.
+    pub fn rate_limit(&self) -> Option<RateLimit> {
+        match self.live_config.current() {
+            Some(live) => live.rate_limit,
+            None => self.config.rate_limit,
+        }
+    }
+}
+
+pub fn router() -> axum::Router {
+    axum::Router::new()
+        .merge(EndpointWrapper::new(Reload))
+        .layer(axum::middleware::from_fn(crate::method_not_allowed_body))
+}
+
+pub fn components(builder: openapi::ComponentsBuilder) -> openapi::ComponentsBuilder {
+    let builder = Reload::components(builder);
+    builder
+        .schema(
+            type_name_raw::<ReloadRequest>(),
+            <ReloadRequest as utoipa::ToSchema>::schema(),
+        )
+        .schema(
+            type_name_raw::<ReloadedConfig>(),
+            <ReloadedConfig as utoipa::ToSchema>::schema(),
+        )
+        .schema(
+            type_name_raw::<ReloadedRateLimit>(),
+            <ReloadedRateLimit as utoipa::ToSchema>::schema(),
+        )
+}
+
+pub fn paths(paths: crate::SpecPaths) -> crate::SpecPaths {
+    paths.endpoint::<Reload>()
+}
+
+/// Reloads the [`Reloadable`] config. Only for [`auth::Role::SuperAdmin`]s.
+#[derive(Debug, Clone, Copy)]
+pub struct Reload;
+
+#[derive(Debug)]
+pub struct Request {
+    pub auth_token: std::sync::Arc<str>,
+    pub body: ReloadRequest,
+}
+
+#[derive(Debug, Serialize, Deserialize, utoipa::ToSchema)]
+#[serde(crate = "serde", rename_all = "camelCase")]
+pub struct ReloadRequest {
+    /// Logged along the reload for whoever wonders about it later.
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, utoipa::ToSchema)]
+#[serde(crate = "serde", rename_all = "camelCase")]
+pub struct ReloadedConfig {
+    pub log_level: String,
+    pub enabled_features: Vec<String>,
+    pub rate_limit: Option<ReloadedRateLimit>,
+    /// What wasn't reloaded.
+    pub note: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, utoipa::ToSchema)]
+#[serde(crate = "serde", rename_all = "camelCase")]
+pub struct ReloadedRateLimit {
+    pub limit: u32,
+    pub window_secs: u64,
+}
+
+impl From<&Reloadable> for ReloadedConfig {
+    fn from(reloaded: &Reloadable) -> Self {
+        Self {
+            log_level: reloaded.log_level.clone(),
+            enabled_features: reloaded.enabled_features.clone(),
+            rate_limit: reloaded.rate_limit.map(|limit| ReloadedRateLimit {
+                limit: limit.limit,
+                window_secs: limit.window.as_secs(),
+            }),
+            note: NOT_RELOADED_NOTE.into(),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error, Serialize, utoipa::ToSchema)]
+#[serde(crate = "serde", tag = "error", rename_all = "camelCase")]
+pub enum Error {
+    #[error("acess denied")]
+    AccessDenied,
+    #[error("forbidden")]
+    Forbidden,
+    #[error("invalid config: {message:?}")]
+    InvalidConfig { message: String },
+    #[error("database overloaded")]
+    Overloaded,
+    #[error("internal server error: {message:?}")]
+    Internal { message: String },
+}
+
+crate::impl_from_auth_err!(Error);
+crate::impl_from_db_err!(Error);
+
+#[async_trait::async_trait]
+impl AuthenticatedEndpoint for Reload {
+    type Request = Request;
+    type Response = Ref<ReloadedConfig>;
+    type Error = Error;
+
+    fn authorize_request(&self, request: &Self::Request) -> auth::authorize::Request {
+        auth::authorize::Request {
+            auth_token: request.auth_token.clone(),
+            resource: auth::Resource::Config,
+            action: auth::Action::Write,
+        }
+    }
+
+    #[tracing::instrument(skip(ctx))]
+    async fn handle(
+        &self,
+        ctx: &crate::Context,
+        accessing_user: uuid::Uuid,
+        request: Self::Request,
+    ) -> Result<Self::Response, Self::Error> {
+        auth::require_role(ctx, accessing_user, auth::Role::SuperAdmin).await?;
+        let reloaded = ctx
+            .live_config
+            .reload()
+            .map_err(|message| Error::InvalidConfig { message })?;
+        tracing::warn!(
+            %accessing_user,
+            reason = ?request.body.reason,
+            ?reloaded,
+            note = NOT_RELOADED_NOTE,
+            "config reloaded"
+        );
+        Ok(ReloadedConfig::from(reloaded.as_ref()).into())
+    }
+}
+
+impl From<&Error> for StatusCode {
+    fn from(err: &Error) -> Self {
+        use Error::*;
+        match err {
+            AccessDenied => Self::UNAUTHORIZED,
+            Forbidden => Self::FORBIDDEN,
+            InvalidConfig { .. } => Self::UNPROCESSABLE_ENTITY,
+            Overloaded => Self::SERVICE_UNAVAILABLE,
+            Internal { .. } => Self::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl crate::utils::ErrorCode for Error {
+    fn code(&self) -> &'static str {
+        use Error::*;
+        match self {
+            AccessDenied => "auth.access_denied",
+            Forbidden => "auth.forbidden",
+            InvalidConfig { .. } => "config.invalid",
+            Overloaded => "service.overloaded",
+            Internal { .. } => "service.internal",
+        }
+    }
+}
+
+impl HttpEndpoint for Reload {
+    const METHOD: Method = Method::Post;
+    const PATH: &'static str = "/admin/reload";
+    const AUDIT_ACTION: Option<&'static str> = Some("config.reload");
+
+    type HttpRequest = (BearerToken, Json<ReloadRequest>);
+
+    fn request(
+        (BearerToken(token), Json(body)): Self::HttpRequest,
+    ) -> Result<Self::Request, Self::Error> {
+        Ok(self::Request {
+            auth_token: token,
+            body,
+        })
+    }
+
+    fn response(Ref(resp): Self::Response) -> axum::response::Response {
+        Json(resp).into_response()
+    }
+}
+
+impl DocumentedEndpoint for Reload {
+    const TAG: &'static Tag = &maintenance::TAG;
+    const SUMMARY: &'static str = "Reload the log level, feature flags and rate limit.";
+
+    fn success_examples() -> Vec<serde_json::Value> {
+        vec![serde_json::json!({
+            "logLevel": "info,template_rust_web_api=debug",
+            "enabledFeatures": ["bulkExport"],
+            "rateLimit": { "limit": 100, "windowSecs": 60 },
+            "note": NOT_RELOADED_NOTE,
+        })]
+    }
+
+    fn errors() -> Vec<ErrorResponse<Self::Error>> {
+        vec![
+            ("Access denied", Error::AccessDenied),
+            ("Forbidden", Error::Forbidden),
+            (
+                "Invalid config",
+                Error::InvalidConfig {
+                    message: "invalid RATE_LIMIT: invalid digit found in string".to_string(),
+                },
+            ),
+            ("Overloaded", Error::Overloaded),
+            (
+                "Internal server error",
+                Error::Internal {
+                    message: "internal server error".to_string(),
+                },
+            ),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use deps::*;
+
+    use super::*;
+    use crate::user::testing::*;
+    use crate::utils::testing::*;
+
+    const TARGET: &str = "reload_test";
+
+    fn reload() -> http::Request<axum::body::Body> {
+        http::Request::builder()
+            .method("POST")
+            .uri("/admin/reload")
+            .header(
+                http::header::AUTHORIZATION,
+                format!("Bearer {USER_01_SESSION}"),
+            )
+            .header(http::header::CONTENT_TYPE, "application/json")
+            .body(r#"{ "reason": "debugging" }"#.into())
+            .unwrap_or_log()
+    }
+
+    #[tokio::test]
+    async fn log_level_changes_without_restart() {
+        use tracing_subscriber::layer::SubscriberExt;
+        let (filter, log_filter) =
+            tracing_subscriber::reload::Layer::new(tracing_subscriber::EnvFilter::new("info"));
+        let events = CapturedEvents::new(TARGET);
+        let _guard = tracing::subscriber::set_default(
+            tracing_subscriber::registry()
+                .with(filter)
+                .with(events.clone()),
+        );
+        let mut ctx = crate::Context::mock(
+            InMemoryDb::fixture().with_role(USER_01_ID, crate::auth::Role::SuperAdmin),
+        );
+        ctx.live_config = LiveConfig::new(
+            Box::new(|| {
+                Ok(Reloadable {
+                    log_level: format!("info,{TARGET}=debug"),
+                    enabled_features: vec!["bulkExport".into()],
+                    rate_limit: None,
+                })
+            }),
+            Some(log_filter),
+        );
+        let ctx = std::sync::Arc::new(ctx);
+        let app = router().layer(axum::Extension(ctx.clone()));
+
+        tracing::debug!(target: TARGET, "before");
+        assert!(events.events().is_empty());
+
+        let resp = app.oneshot(reload()).await.unwrap_or_log();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(resp.into_body())
+            .await
+            .unwrap_or_log();
+        let body = serde_json::from_slice(&body).unwrap_or_log();
+        check_json(
+            (
+                "expected",
+                &serde_json::json!({
+                    "logLevel": format!("info,{TARGET}=debug"),
+                    "enabledFeatures": ["bulkExport"],
+                    "note": NOT_RELOADED_NOTE,
+                }),
+            ),
+            ("response", &body),
+        );
+
+        tracing::debug!(target: TARGET, "after");
+        assert_eq!(events.events().len(), 1);
+        assert_eq!(ctx.enabled_features(), vec!["bulkExport".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn only_super_admins_reload() {
+        setup_tracing_once();
+        let mut ctx = crate::Context::mock(
+            InMemoryDb::fixture().with_role(USER_01_ID, crate::auth::Role::Basic),
+        );
+        ctx.live_config = LiveConfig::new(
+            Box::new(|| {
+                Ok(Reloadable {
+                    log_level: "info".into(),
+                    enabled_features: vec![],
+                    rate_limit: None,
+                })
+            }),
+            None,
+        );
+        let ctx = std::sync::Arc::new(ctx);
+        let resp = router()
+            .layer(axum::Extension(ctx.clone()))
+            .oneshot(reload())
+            .await
+            .unwrap_or_log();
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        assert!(ctx.live_config.current().is_none());
+    }
+
+    #[test]
+    fn env_file_goes_over_the_env_without_changing_it() {
+        let path = std::env::temp_dir().join(format!("reload-{}.env", uuid::Uuid::new_v4()));
+        std::fs::write(&path, "ENABLED_FEATURES=bulkExport,dryRun\nRATE_LIMIT=10\n")
+            .unwrap_or_log();
+        let reloaded = Reloadable::from_env_file(&path).unwrap_or_log();
+        assert_eq!(
+            reloaded.enabled_features,
+            vec!["bulkExport".to_string(), "dryRun".to_string()]
+        );
+        assert_eq!(reloaded.rate_limit.map(|limit| limit.limit), Some(10));
+        assert!(std::env::var("RATE_LIMIT").is_err(), "env changed");
+
+        // nothing of a file that fails halfway through is applied
+        std::fs::write(&path, "RATE_LIMIT=10\nnot a var\n").unwrap_or_log();
+        assert!(Reloadable::from_env_file(&path).is_err());
+        assert!(std::env::var("RATE_LIMIT").is_err(), "env changed");
+        std::fs::remove_file(&path).ok();
+    }
+}
@@ -0,0 +1,109 @@
+//! Periodic background work like sampling metrics. Tasks are registered at startup,
+//! share the [`SharedContext`] with the handlers and are stopped as part of the
+//! graceful shutdown.
+
+use deps::*;
+
+use crate::SharedContext;
+
+use std::future::Future;
+
+#[derive(Debug)]
+pub struct Scheduler {
+    ctx: SharedContext,
+    shutdown: tokio::sync::watch::Sender<bool>,
+    tasks: Vec<(&'static str, tokio::task::JoinHandle<()>)>,
+}
+
+impl Scheduler {
+    pub fn new(ctx: SharedContext) -> Self {
+        Self {
+            ctx,
+            shutdown: tokio::sync::watch::channel(false).0,
+            tasks: vec![],
+        }
+    }
+
+    /// Spawns `task` to run every `interval`, starting right away. Runs that take
+    /// longer than the `interval` delay the next one instead of piling up.
+    /// Panics if `interval` is zero, configs are expected to rule that out.
+    pub fn every<F, Fut>(
+        &mut self,
+        name: &'static str,
+        interval: std::time::Duration,
+        task: F,
+    ) -> &mut Self
+    where
+        F: Fn(SharedContext) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let ctx = self.ctx.clone();
+        let mut shutdown = self.shutdown.subscribe();
+        let handle = tokio::spawn(async move {
+            let mut interval = tokio::time::interval(interval);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => task(ctx.clone()).await,
+                    _ = shutdown.changed() => break,
+                }
+            }
+            tracing::debug!(task = name, "background task stopped");
+        });
+        self.tasks.push((name, handle));
+        self
+    }
+
+    /// Stops all the tasks, letting runs that are underway finish first.
+    pub async fn shutdown(self) {
+        self.shutdown.send(true).ok();
+        for (name, handle) in self.tasks {
+            if let Err(err) = handle.await {
+                tracing::error!(task = name, ?err, "background task failed");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use deps::*;
+
+    use super::*;
+    use crate::utils::testing::*;
+
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    #[tokio::test]
+    async fn runs_until_shutdown() {
+        setup_tracing_once();
+        let ctx = Arc::new(crate::Context::mock(InMemoryDb::default()));
+        let runs = Arc::new(AtomicUsize::new(0));
+        let mut scheduler = Scheduler::new(ctx);
+        scheduler.every("count", std::time::Duration::from_millis(10), {
+            let runs = runs.clone();
+            move |_ctx| {
+                let runs = runs.clone();
+                async move {
+                    runs.fetch_add(1, Ordering::SeqCst);
+                }
+            }
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        assert!(runs.load(Ordering::SeqCst) >= 2, "didn't run");
+
+        tokio::time::timeout(std::time::Duration::from_secs(5), scheduler.shutdown())
+            .await
+            .expect("didn't stop");
+        let stopped_at = runs.load(Ordering::SeqCst);
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        assert_eq!(
+            runs.load(Ordering::SeqCst),
+            stopped_at,
+            "ran after shutdown"
+        );
+    }
+}
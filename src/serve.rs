@@ -0,0 +1,266 @@
+//! Binds the assembled app over plain HTTP or, if [`crate::Config::tls`] is set,
+//! over TLS with rustls.
+
+use deps::*;
+
+use std::path::PathBuf;
+
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    /// PEM encoded certificate chain.
+    pub cert_path: PathBuf,
+    /// PEM encoded PKCS#8 or RSA private key.
+    pub key_path: PathBuf,
+}
+
+/// Connection level settings, see the [`crate::Config`] fields of the same names.
+/// Left as hyper's defaults if not set.
+#[derive(Debug, Clone, Default)]
+pub struct ConnectionConfig {
+    pub tcp_keepalive: Option<std::time::Duration>,
+    pub http2_keepalive_interval: Option<std::time::Duration>,
+    pub max_connections: Option<usize>,
+}
+
+impl From<&crate::Config> for ConnectionConfig {
+    fn from(config: &crate::Config) -> Self {
+        Self {
+            tcp_keepalive: config.tcp_keepalive,
+            http2_keepalive_interval: config.http2_keepalive_interval,
+            max_connections: config.max_connections,
+        }
+    }
+}
+
+/// Applies the socket and protocol settings of `conn` to `server`.
+pub fn configure<A>(
+    server: axum_server::Server<A>,
+    conn: &ConnectionConfig,
+) -> axum_server::Server<A> {
+    server
+        .addr_incoming_config(
+            axum_server::AddrIncomingConfig::new()
+                .tcp_keepalive(conn.tcp_keepalive)
+                .build(),
+        )
+        .http_config(
+            axum_server::HttpConfig::new()
+                .http2_keep_alive_interval(conn.http2_keepalive_interval)
+                .build(),
+        )
+}
+
+/// Serves `app` at `addr` until `handle` is told to shut down. With `tls` set, the
+/// certificate and key are reloaded from disk on `SIGHUP`. Requests carry the
+/// peer's address as a [`axum::extract::ConnectInfo<std::net::SocketAddr>`].
+/// Connections past the [`ConnectionConfig::max_connections`] are closed as soon
+/// as they're accepted.
+pub async fn serve<S>(
+    app: S,
+    addr: std::net::SocketAddr,
+    tls: Option<&TlsConfig>,
+    conn: &ConnectionConfig,
+    handle: axum_server::Handle,
+) -> std::io::Result<()>
+where
+    S: tower::Service<axum::http::Request<hyper::Body>, Response = axum::response::Response>
+        + Clone
+        + Send
+        + 'static,
+    S::Error: Into<axum::BoxError>,
+    S::Future: Send + 'static,
+{
+    let slots = conn
+        .max_connections
+        .map(|max| std::sync::Arc::new(tokio::sync::Semaphore::new(max)));
+    // hands each connection's peer to the app, see `utils::ClientIp`
+    let make_service = tower::service_fn(move |stream: &hyper::server::conn::AddrStream| {
+        use tower::Layer;
+        // held by the connection's service and so released when it closes
+        let slot = match &slots {
+            Some(slots) => match slots.clone().try_acquire_owned() {
+                Ok(permit) => Some(std::sync::Arc::new(permit)),
+                Err(_) => {
+                    tracing::warn!(peer = %stream.remote_addr(), "connection limit reached");
+                    return std::future::ready(Err(TooManyConnections));
+                }
+            },
+            None => None,
+        };
+        let app = axum::Extension(ConnectionSlot { _permit: slot }).layer(
+            axum::Extension(axum::extract::ConnectInfo(stream.remote_addr())).layer(app.clone()),
+        );
+        std::future::ready(Ok(app))
+    });
+    let tls = match tls {
+        Some(tls) => tls,
+        None => {
+            return configure(axum_server::bind(addr), conn)
+                .handle(handle)
+                .serve(make_service)
+                .await
+        }
+    };
+    let rustls =
+        axum_server::tls_rustls::RustlsConfig::from_pem_file(&tls.cert_path, &tls.key_path).await?;
+    #[cfg(unix)]
+    let reloader = spawn_reload_on_sighup(rustls.clone(), tls.clone())?;
+    let result = configure(axum_server::bind_rustls(addr, rustls), conn)
+        .handle(handle)
+        .serve(make_service)
+        .await;
+    #[cfg(unix)]
+    reloader.abort();
+    result
+}
+
+/// A spot under the [`ConnectionConfig::max_connections`]. Only held to give the
+/// permit back once the connection's last clone is dropped.
+#[derive(Debug, Clone)]
+struct ConnectionSlot {
+    _permit: Option<std::sync::Arc<tokio::sync::OwnedSemaphorePermit>>,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("too many connections")]
+struct TooManyConnections;
+
+#[cfg(unix)]
+fn spawn_reload_on_sighup(
+    rustls: axum_server::tls_rustls::RustlsConfig,
+    tls: TlsConfig,
+) -> std::io::Result<tokio::task::JoinHandle<()>> {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut hangups = signal(SignalKind::hangup())?;
+    Ok(tokio::spawn(async move {
+        while hangups.recv().await.is_some() {
+            match rustls
+                .reload_from_pem_file(&tls.cert_path, &tls.key_path)
+                .await
+            {
+                Ok(()) => tracing::info!("reloaded tls certificate"),
+                Err(err) => tracing::error!(?err, "unable to reload tls certificate"),
+            }
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use deps::*;
+
+    use super::*;
+    use crate::utils::testing::*;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio_rustls::rustls;
+
+    fn test_tls_config() -> TlsConfig {
+        let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("certs");
+        TlsConfig {
+            cert_path: dir.join("localhost.crt"),
+            key_path: dir.join("localhost.key"),
+        }
+    }
+
+    fn client_config(tls: &TlsConfig) -> rustls::ClientConfig {
+        let pem = std::fs::read(&tls.cert_path).unwrap_or_log();
+        let mut roots = rustls::RootCertStore::empty();
+        for cert in rustls_pemfile::certs(&mut &pem[..]).unwrap_or_log() {
+            roots.add(&rustls::Certificate(cert)).unwrap_or_log();
+        }
+        rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots)
+            .with_no_client_auth()
+    }
+
+    #[tokio::test]
+    async fn serves_over_tls() {
+        setup_tracing_once();
+        let tls = test_tls_config();
+        let app = axum::Router::new().route("/custom", axum::routing::get(|| async { "custom" }));
+        let handle = axum_server::Handle::new();
+        let server = tokio::spawn({
+            let handle = handle.clone();
+            let tls = tls.clone();
+            async move {
+                serve(
+                    app,
+                    std::net::SocketAddr::from((std::net::Ipv4Addr::LOCALHOST, 0)),
+                    Some(&tls),
+                    &Default::default(),
+                    handle,
+                )
+                .await
+            }
+        });
+        let addr = handle.listening().await.expect("server failed to bind");
+
+        let connector = tokio_rustls::TlsConnector::from(std::sync::Arc::new(client_config(&tls)));
+        let stream = tokio::net::TcpStream::connect(addr).await.unwrap_or_log();
+        let mut stream = connector
+            .connect(rustls::ServerName::try_from("localhost").unwrap(), stream)
+            .await
+            .unwrap_or_log();
+        stream
+            .write_all(b"GET /custom HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\r\n")
+            .await
+            .unwrap_or_log();
+        let mut resp = vec![];
+        let mut buf = [0; 1024];
+        while !String::from_utf8_lossy(&resp).ends_with("custom") {
+            let read = stream.read(&mut buf).await.unwrap_or_log();
+            assert_ne!(read, 0, "{}", String::from_utf8_lossy(&resp));
+            resp.extend_from_slice(&buf[..read]);
+        }
+        let resp = String::from_utf8(resp).unwrap_or_log();
+        assert!(resp.starts_with("HTTP/1.1 200 OK"), "{resp}");
+
+        handle.graceful_shutdown(None);
+        server.await.unwrap_or_log().unwrap_or_log();
+    }
+
+    /// Whether `SO_KEEPALIVE` is set on the accepted socket.
+    async fn accepted_keepalive(conn: ConnectionConfig) -> bool {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let make_service = tower::service_fn(move |stream: &hyper::server::conn::AddrStream| {
+            tx.send(socket2::SockRef::from(stream).keepalive().unwrap_or_log())
+                .unwrap_or_log();
+            std::future::ready(Ok::<_, std::convert::Infallible>(
+                axum::Router::new().route("/", axum::routing::get(|| async { "" })),
+            ))
+        });
+        let handle = axum_server::Handle::new();
+        let server = tokio::spawn(
+            configure(
+                axum_server::bind(std::net::SocketAddr::from((
+                    std::net::Ipv4Addr::LOCALHOST,
+                    0,
+                ))),
+                &conn,
+            )
+            .handle(handle.clone())
+            .serve(make_service),
+        );
+        let addr = handle.listening().await.expect("server failed to bind");
+        let _stream = tokio::net::TcpStream::connect(addr).await.unwrap_or_log();
+        let keepalive = rx.recv().await.unwrap();
+        handle.shutdown();
+        server.await.unwrap_or_log().unwrap_or_log();
+        keepalive
+    }
+
+    #[tokio::test]
+    async fn applies_tcp_keepalive() {
+        setup_tracing_once();
+        assert!(!accepted_keepalive(Default::default()).await);
+        assert!(
+            accepted_keepalive(ConnectionConfig {
+                tcp_keepalive: Some(std::time::Duration::from_secs(60)),
+                ..Default::default()
+            })
+            .await
+        );
+    }
+}
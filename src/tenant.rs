@@ -0,0 +1,718 @@
+//! Multi-tenancy, enabled through [`crate::Config::multi_tenant`]. The
+//! [`resolve_tenant`] middleware finds the [`Tenant`] of each request and makes
+//! it current for the handler's task so that the [`TenantDb`] behind
+//! [`crate::Context::db`] answers from that tenant's data. With Postgres each
+//! tenant gets its own schema through the connections' `search_path`, sessions
+//! included, so a token only authenticates within the tenant it was issued by.
+//! The schema holds its own copy of the [`crate::db::MIGRATOR`] migrations, see
+//! [`migrate`], and `public` stays on the path for the extensions.
+//!
+//! Queries the [`DbBackend`] doesn't cover go through [`crate::Context::pool`]
+//! to be scoped too, those made straight on [`crate::Context::db_pool`] aren't.
+
+use deps::*;
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+
+use axum::{
+    http::{header, StatusCode},
+    response::{IntoResponse, Json, Response},
+};
+
+use crate::{
+    auth::{ApiKeyRecord, Role, Session},
+    db::DbBackend,
+    user::User,
+    RoutingError, SharedContext,
+};
+
+pub const HEADER: &str = "x-tenant-id";
+
+/// A validated tenant slug: lowercase ascii letters, digits and `_`, at most
+/// [`Tenant::MAX_LEN`] long so that it always makes for a valid schema name.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Tenant(Arc<str>);
+
+impl Tenant {
+    pub const MAX_LEN: usize = 48;
+
+    pub fn parse(slug: &str) -> Option<Self> {
+        let valid = !slug.is_empty()
+            && slug.len() <= Self::MAX_LEN
+            && slug
+                .bytes()
+                .all(|byte| byte.is_ascii_lowercase() || byte.is_ascii_digit() || byte == b'_');
+        valid.then(|| Self(slug.into()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// The Postgres schema holding the tenant's tables.
+    pub fn schema(&self) -> String {
+        format!("tenant_{}", self.0)
+    }
+}
+
+impl std::fmt::Display for Tenant {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+tokio::task_local! {
+    static TENANT: Tenant;
+}
+
+/// The tenant of the request being handled on this task if any.
+pub fn current_tenant() -> Option<Tenant> {
+    TENANT.try_with(|tenant| tenant.clone()).ok()
+}
+
+/// Runs `fut` within `tenant`, see [`current_tenant`].
+pub async fn with_tenant<F: Future>(tenant: Tenant, fut: F) -> F::Output {
+    TENANT.scope(tenant, fut).await
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum TenantError {
+    #[error("unknown tenant")]
+    Unknown,
+    #[error("too many tenants in use")]
+    TooMany,
+    #[error("{pending} pending migrations")]
+    Unmigrated { pending: usize },
+    #[error("db error: {0}")]
+    Db(#[from] sqlx::Error),
+    #[error("migration error: {0}")]
+    Migrate(#[from] sqlx::migrate::MigrateError),
+}
+
+/// The `search_path` of the connections made for `tenant`. `public` comes last
+/// for the extensions, the tables and functions are all found in the tenant's
+/// schema first once it's [`migrate`]d.
+fn search_path(tenant: &Tenant) -> String {
+    format!(r#"SET search_path TO "{}", public"#, tenant.schema())
+}
+
+/// Versions of the [`crate::db::MIGRATOR`] migrations yet to be applied to the
+/// schema of `tenant`.
+pub async fn pending_migrations(
+    pool: &sqlx::postgres::PgPool,
+    tenant: &Tenant,
+) -> Result<Vec<i64>, sqlx::Error> {
+    // qualified so that the one in `public` isn't picked up
+    let table = format!(r#""{}"._sqlx_migrations"#, tenant.schema());
+    let has_table: bool = sqlx::query_scalar("SELECT to_regclass($1) IS NOT NULL")
+        .bind(&table)
+        .fetch_one(pool)
+        .await?;
+    let applied: Vec<i64> = if has_table {
+        sqlx::query_scalar(&format!("SELECT version FROM {table} WHERE success"))
+            .fetch_all(pool)
+            .await?
+    } else {
+        vec![]
+    };
+    Ok(crate::db::MIGRATOR
+        .iter()
+        .filter(|migration| !migration.migration_type.is_down_migration())
+        .map(|migration| migration.version)
+        .filter(|version| !applied.contains(version))
+        .collect())
+}
+
+/// Applies the [`crate::db::MIGRATOR`] migrations to the schema of `tenant`,
+/// which has to exist, on connections of their own so the `search_path` doesn't
+/// leak into `pool`.
+pub async fn migrate(pool: &sqlx::postgres::PgPool, tenant: &Tenant) -> Result<(), TenantError> {
+    let scoped = tenant_pool(pool.connect_options().clone(), tenant, 1);
+    let result = crate::db::MIGRATOR.run(&scoped).await;
+    scoped.close().await;
+    Ok(result?)
+}
+
+/// A lazily connected pool on `opts` with the [`search_path`] of `tenant`.
+fn tenant_pool(
+    opts: sqlx::postgres::PgConnectOptions,
+    tenant: &Tenant,
+    max_connections: u32,
+) -> sqlx::postgres::PgPool {
+    let set_path = search_path(tenant);
+    sqlx::postgres::PgPoolOptions::new()
+        .max_connections(max_connections)
+        .after_connect(move |conn, _| {
+            let set_path = set_path.clone();
+            Box::pin(async move {
+                sqlx::query(&set_path).execute(conn).await?;
+                Ok(())
+            })
+        })
+        .connect_lazy_with(opts)
+}
+
+/// The subdomain of [`crate::Config::tenant_base_domain`] the request was sent
+/// to.
+fn subdomain<B>(req: &axum::http::Request<B>, base_domain: Option<&str>) -> Option<String> {
+    let base_domain = base_domain?;
+    let host = req
+        .headers()
+        .get(header::HOST)
+        .and_then(|val| val.to_str().ok())
+        .or_else(|| req.uri().host())?;
+    let host = host.split(':').next().unwrap_or(host);
+    host.strip_suffix(base_domain)?
+        .strip_suffix('.')
+        .map(str::to_string)
+}
+
+/// The [`HEADER`] or the [`subdomain`], `Err` if both are set but disagree.
+fn find_tenant<B>(
+    req: &axum::http::Request<B>,
+    base_domain: Option<&str>,
+) -> Result<Option<String>, RoutingError> {
+    let header = req
+        .headers()
+        .get(HEADER)
+        .map(|val| String::from_utf8_lossy(val.as_bytes()).into_owned());
+    match (header, subdomain(req, base_domain)) {
+        (Some(header), Some(subdomain)) if header != subdomain => {
+            Err(RoutingError::TenantMismatch { header, subdomain })
+        }
+        (header, subdomain) => Ok(header.or(subdomain)),
+    }
+}
+
+/// Rejects requests whose tenant can't be resolved with a `400`, or a `404` if
+/// [`DbBackend::admit`] doesn't know it, and runs the rest within it. Also stores
+/// the [`Tenant`] in the request extensions. The [`crate::health::INFRA_PATHS`] are let through
+/// as they are. Expects the [`SharedContext`] in the request extensions.
+pub async fn resolve_tenant(
+    mut req: axum::http::Request<axum::body::Body>,
+    next: axum::middleware::Next<axum::body::Body>,
+) -> Response {
+    let ctx = match req.extensions().get::<SharedContext>() {
+        Some(ctx) if ctx.config.multi_tenant && !crate::health::is_infra_path(req.uri().path()) => {
+            ctx.clone()
+        }
+        _ => return next.run(req).await,
+    };
+    let slug = match find_tenant(&req, ctx.config.tenant_base_domain.as_deref()) {
+        Ok(slug) => slug,
+        Err(err) => return (StatusCode::BAD_REQUEST, Json(err)).into_response(),
+    };
+    let tenant = match slug {
+        Some(slug) => match Tenant::parse(&slug) {
+            Some(tenant) => tenant,
+            None => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    Json(RoutingError::InvalidTenant { tenant: slug }),
+                )
+                    .into_response()
+            }
+        },
+        None => {
+            return (StatusCode::BAD_REQUEST, Json(RoutingError::MissingTenant)).into_response()
+        }
+    };
+    match ctx.db.admit(&tenant).await {
+        Ok(()) => {}
+        Err(TenantError::Unknown) => {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(RoutingError::UnknownTenant {
+                    tenant: tenant.to_string(),
+                }),
+            )
+                .into_response()
+        }
+        Err(err) => {
+            tracing::error!(%tenant, ?err, "unable to admit tenant");
+            return (
+                StatusCode::SERVICE_UNAVAILABLE,
+                Json(RoutingError::Overloaded),
+            )
+                .into_response();
+        }
+    }
+    req.extensions_mut().insert(tenant.clone());
+    with_tenant(tenant, next.run(req)).await
+}
+
+type MakeDb = dyn Fn(&Tenant) -> Arc<dyn DbBackend> + Send + Sync;
+
+/// Dispatches to the backend of the [`current_tenant`], made when the tenant is
+/// first admitted, or to the default one outside of any tenant. At most
+/// `max_tenants` are kept, past that new ones are refused rather than evicting
+/// the pools of those in use.
+pub struct TenantDb {
+    default: Arc<dyn DbBackend>,
+    make: Box<MakeDb>,
+    max_tenants: usize,
+    /// Checks that the tenant exists before a backend is made for it.
+    schemas: Option<sqlx::postgres::PgPool>,
+    /// [`migrate`] tenants on admission instead of refusing them.
+    auto_migrate: bool,
+    tenants: parking_lot::RwLock<HashMap<Tenant, Arc<dyn DbBackend>>>,
+}
+
+impl std::fmt::Debug for TenantDb {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TenantDb")
+            .field("default", &self.default)
+            .field("max_tenants", &self.max_tenants)
+            .field("tenants", &self.tenants)
+            .finish_non_exhaustive()
+    }
+}
+
+impl TenantDb {
+    /// Admits any tenant.
+    pub fn new(
+        default: Arc<dyn DbBackend>,
+        max_tenants: usize,
+        make: impl Fn(&Tenant) -> Arc<dyn DbBackend> + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            default,
+            make: Box::new(make),
+            max_tenants,
+            schemas: None,
+            auto_migrate: false,
+            tenants: Default::default(),
+        }
+    }
+
+    /// A lazily connected pool per tenant, like `pool` but with the
+    /// `search_path` of its connections starting at [`Tenant::schema`]. Tenants
+    /// without a schema aren't admitted, nor are those with
+    /// [`pending_migrations`] unless `auto_migrate` in which case they're
+    /// [`migrate`]d first. The pools of all the tenants together open at most
+    /// `max_connections`, each gets an even share of them but at least one.
+    pub fn postgres(
+        pool: sqlx::postgres::PgPool,
+        max_connections: u32,
+        max_tenants: usize,
+        auto_migrate: bool,
+    ) -> Self {
+        let opts = pool.connect_options().clone();
+        let per_tenant = (max_connections / max_tenants.max(1) as u32).max(1);
+        let mut db = Self::new(Arc::new(pool.clone()), max_tenants, move |tenant| {
+            Arc::new(tenant_pool(opts.clone(), tenant, per_tenant))
+        });
+        db.schemas = Some(pool);
+        db.auto_migrate = auto_migrate;
+        db
+    }
+
+    fn admitted(&self, tenant: &Tenant) -> Option<Arc<dyn DbBackend>> {
+        self.tenants.read().get(tenant).cloned()
+    }
+
+    async fn backend(&self) -> Result<Arc<dyn DbBackend>, sqlx::Error> {
+        let tenant = match current_tenant() {
+            Some(tenant) => tenant,
+            None => return Ok(self.default.clone()),
+        };
+        if let Some(db) = self.admitted(&tenant) {
+            return Ok(db);
+        }
+        match self.admit(&tenant).await {
+            Ok(()) => Ok(self.admitted(&tenant).expect("tenant was just admitted")),
+            Err(TenantError::Db(err)) => Err(err),
+            Err(err) => Err(sqlx::Error::Configuration(err.into())),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl DbBackend for TenantDb {
+    async fn session(&self, token: &str) -> Result<Session, sqlx::Error> {
+        self.backend().await?.session(token).await
+    }
+
+    async fn user(&self, id: uuid::Uuid) -> Result<User, sqlx::Error> {
+        self.backend().await?.user(id).await
+    }
+
+    async fn user_roles(&self, user_id: uuid::Uuid) -> Result<Vec<Role>, sqlx::Error> {
+        self.backend().await?.user_roles(user_id).await
+    }
+
+    async fn api_key(&self, key_hash: &str) -> Result<ApiKeyRecord, sqlx::Error> {
+        self.backend().await?.api_key(key_hash).await
+    }
+
+    async fn admit(&self, tenant: &Tenant) -> Result<(), TenantError> {
+        if self.admitted(tenant).is_some() {
+            return Ok(());
+        }
+        if let Some(pool) = &self.schemas {
+            let exists: bool =
+                sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM pg_namespace WHERE nspname = $1)")
+                    .bind(tenant.schema())
+                    .fetch_one(pool)
+                    .await?;
+            if !exists {
+                return Err(TenantError::Unknown);
+            }
+            let pending = pending_migrations(pool, tenant).await?;
+            if !pending.is_empty() {
+                if !self.auto_migrate {
+                    return Err(TenantError::Unmigrated {
+                        pending: pending.len(),
+                    });
+                }
+                migrate(pool, tenant).await?;
+            }
+        }
+        let mut tenants = self.tenants.write();
+        if !tenants.contains_key(tenant) {
+            if tenants.len() >= self.max_tenants {
+                return Err(TenantError::TooMany);
+            }
+            tenants.insert(tenant.clone(), (self.make)(tenant));
+        }
+        Ok(())
+    }
+
+    /// `None` within a tenant that wasn't admitted rather than the default pool.
+    fn pg_pool(&self) -> Option<sqlx::postgres::PgPool> {
+        match current_tenant() {
+            Some(tenant) => self.admitted(&tenant)?.pg_pool(),
+            None => self.default.pg_pool(),
+        }
+    }
+
+    fn tenant_pools(&self) -> Vec<sqlx::postgres::PgPool> {
+        self.tenants
+            .read()
+            .values()
+            .filter_map(|db| db.pg_pool())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use deps::*;
+
+    use super::*;
+    use crate::user::testing::*;
+    use crate::utils::testing::*;
+
+    fn app() -> axum::Router {
+        let db = TenantDb::new(Arc::new(InMemoryDb::default()), 8, |tenant| {
+            let db = InMemoryDb::fixture();
+            db.users.write().get_mut(&USER_01_ID).unwrap().username =
+                format!("{}_{USER_01_USERNAME}", tenant);
+            // has the user but didn't issue the session
+            if tenant.as_str() == "initech" {
+                db.sessions.write().clear();
+            }
+            Arc::new(db)
+        });
+        let mut ctx = crate::Context::mock(db);
+        ctx.config.multi_tenant = true;
+        ctx.config.tenant_base_domain = Some("todo.example.com".into());
+        crate::user::router()
+            .layer(axum::middleware::from_fn(resolve_tenant))
+            .layer(axum::Extension(Arc::new(ctx)))
+    }
+
+    crate::table_tests! {
+        tenant_resolution tokio,
+        (headers, status, expected),
+        {
+            setup_tracing_once();
+            let headers: Vec<(&str, &str)> = headers;
+            let mut req = http::Request::builder()
+                .method("GET")
+                .uri(format!("/users/{USER_01_ID}"))
+                .header(
+                    http::header::AUTHORIZATION,
+                    format!("Bearer {USER_01_SESSION}"),
+                );
+            for (name, val) in headers {
+                req = req.header(name, val);
+            }
+            let resp = app()
+                .oneshot(req.body(Default::default()).unwrap_or_log())
+                .await
+                .unwrap_or_log();
+            assert_eq!(resp.status(), status);
+            let body = hyper::body::to_bytes(resp.into_body())
+                .await
+                .unwrap_or_log();
+            let body = serde_json::from_slice(&body).unwrap_or_log();
+            check_json(("expected", &expected), ("response", &body));
+        },
+    }
+
+    tenant_resolution! {
+        scopes_by_header: (
+            vec![(HEADER, "acme")],
+            StatusCode::OK,
+            serde_json::json!({ "username": format!("acme_{USER_01_USERNAME}") }),
+        ),
+        scopes_by_subdomain: (
+            vec![("host", "globex.todo.example.com:8080")],
+            StatusCode::OK,
+            serde_json::json!({ "username": format!("globex_{USER_01_USERNAME}") }),
+        ),
+        rejects_missing_tenant: (
+            vec![("host", "todo.example.com")],
+            StatusCode::BAD_REQUEST,
+            serde_json::json!({ "error": "missingTenant" }),
+        ),
+        rejects_invalid_tenant: (
+            vec![(HEADER, "Acme; DROP")],
+            StatusCode::BAD_REQUEST,
+            serde_json::json!({ "error": "invalidTenant", "tenant": "Acme; DROP" }),
+        ),
+        header_matching_subdomain: (
+            vec![(HEADER, "acme"), ("host", "acme.todo.example.com")],
+            StatusCode::OK,
+            serde_json::json!({ "username": format!("acme_{USER_01_USERNAME}") }),
+        ),
+        rejects_header_not_matching_subdomain: (
+            vec![(HEADER, "acme"), ("host", "globex.todo.example.com")],
+            StatusCode::BAD_REQUEST,
+            serde_json::json!({
+                "error": "tenantMismatch",
+                "header": "acme",
+                "subdomain": "globex",
+            }),
+        ),
+        sessions_stay_in_their_tenant: (
+            vec![(HEADER, "initech")],
+            StatusCode::UNAUTHORIZED,
+            serde_json::json!({ "error": "accessDenied" }),
+        ),
+    }
+
+    #[tokio::test]
+    async fn caps_the_tenants() {
+        let db = TenantDb::new(Arc::new(InMemoryDb::default()), 1, |_| {
+            Arc::new(InMemoryDb::fixture())
+        });
+        let acme = Tenant::parse("acme").unwrap();
+        let globex = Tenant::parse("globex").unwrap();
+        db.admit(&acme).await.unwrap();
+        assert!(matches!(db.admit(&globex).await, Err(TenantError::TooMany)));
+        db.admit(&acme).await.unwrap();
+        assert!(with_tenant(acme, db.user(USER_01_ID)).await.is_ok());
+        assert!(matches!(
+            with_tenant(globex, db.user(USER_01_ID)).await,
+            Err(sqlx::Error::Configuration(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn postgres_tenants_need_their_schema_migrated() {
+        let ctx = TestContext::new(crate::function!()).await;
+        {
+            let pool = ctx.ctx().db_pool.clone();
+            let db = TenantDb::postgres(pool.clone(), 1, 8, false);
+            let acme = Tenant::parse("acme").unwrap();
+            assert!(matches!(db.admit(&acme).await, Err(TenantError::Unknown)));
+            assert!(db.tenants.read().is_empty());
+
+            sqlx::query(&format!(r#"CREATE SCHEMA "{}""#, acme.schema()))
+                .execute(&pool)
+                .await
+                .unwrap_or_log();
+            assert!(matches!(
+                db.admit(&acme).await,
+                Err(TenantError::Unmigrated { .. })
+            ));
+            assert!(db.tenants.read().is_empty());
+
+            migrate(&pool, &acme).await.unwrap_or_log();
+            assert!(pending_migrations(&pool, &acme)
+                .await
+                .unwrap_or_log()
+                .is_empty());
+            db.admit(&acme).await.unwrap();
+            let scoped = with_tenant(acme.clone(), async { db.pg_pool() })
+                .await
+                .unwrap();
+            // the tenant's own tables rather than those in `public`
+            let users: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM users")
+                .fetch_one(&scoped)
+                .await
+                .unwrap_or_log();
+            assert_eq!(users, 0);
+            // nor did the migration change the path of the shared pool
+            let users: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM users")
+                .fetch_one(&pool)
+                .await
+                .unwrap_or_log();
+            assert!(users > 0);
+            assert_eq!(db.tenant_pools().len(), 1);
+            scoped.close().await;
+        }
+        ctx.close().await;
+    }
+
+    #[tokio::test]
+    async fn postgres_tenants_are_isolated() {
+        let ctx = TestContext::new(crate::function!()).await;
+        {
+            let pool = ctx.ctx().db_pool.clone();
+            let mut config = test_config();
+            config.multi_tenant = true;
+            config.auto_migrate = true;
+            let shared = Arc::new(crate::Context::new(pool.clone(), config));
+            let mut users = vec![];
+            for slug in ["acme", "globex"] {
+                let tenant = Tenant::parse(slug).unwrap();
+                sqlx::query(&format!(r#"CREATE SCHEMA "{}""#, tenant.schema()))
+                    .execute(&pool)
+                    .await
+                    .unwrap_or_log();
+                shared.db.admit(&tenant).await.unwrap();
+                let scoped = with_tenant(tenant, async { shared.pool() })
+                    .await
+                    .unwrap_or_log();
+                // through the function and the `CITEXT` casts the endpoints use
+                let id: uuid::Uuid = sqlx::query_scalar(
+                    "SELECT id FROM create_user($1::TEXT::CITEXT, $2::TEXT::CITEXT, 'hash')",
+                )
+                .bind(format!("{slug}_{USER_01_USERNAME}"))
+                .bind(format!("{slug}@example.com"))
+                .fetch_one(&scoped)
+                .await
+                .unwrap_or_log();
+                sqlx::query(
+                    "INSERT INTO sessions (token, user_id, expires_at) \
+                     VALUES ($1, $2, NOW() + INTERVAL '1 hour')",
+                )
+                .bind(format!("{slug}-token"))
+                .bind(id)
+                .execute(&scoped)
+                .await
+                .unwrap_or_log();
+                users.push(id);
+            }
+            let app = crate::user::router()
+                .layer(axum::middleware::from_fn(resolve_tenant))
+                .layer(axum::Extension(shared));
+            let get_user = |tenant: &str, token: &str, id: uuid::Uuid| {
+                let req = http::Request::builder()
+                    .method("GET")
+                    .uri(format!("/users/{id}"))
+                    .header(HEADER, tenant)
+                    .header(http::header::AUTHORIZATION, format!("Bearer {token}"))
+                    .body(Default::default())
+                    .unwrap_or_log();
+                let app = app.clone();
+                async move {
+                    let resp = app.oneshot(req).await.unwrap_or_log();
+                    let status = resp.status();
+                    let body = hyper::body::to_bytes(resp.into_body())
+                        .await
+                        .unwrap_or_log();
+                    (status, serde_json::from_slice(&body).unwrap_or_log())
+                }
+            };
+            let (acme, globex) = (users[0], users[1]);
+            for (tenant, token, id, status, expected) in [
+                (
+                    "acme",
+                    "acme-token",
+                    acme,
+                    StatusCode::OK,
+                    serde_json::json!({ "username": format!("acme_{USER_01_USERNAME}") }),
+                ),
+                (
+                    "globex",
+                    "globex-token",
+                    globex,
+                    StatusCode::OK,
+                    serde_json::json!({ "username": format!("globex_{USER_01_USERNAME}") }),
+                ),
+                (
+                    "globex",
+                    "globex-token",
+                    acme,
+                    StatusCode::NOT_FOUND,
+                    serde_json::json!({ "error": "notFound" }),
+                ),
+                (
+                    "globex",
+                    "acme-token",
+                    globex,
+                    StatusCode::UNAUTHORIZED,
+                    serde_json::json!({ "error": "accessDenied" }),
+                ),
+            ] {
+                let (got_status, body) = get_user(tenant, token, id).await;
+                assert_eq!(got_status, status, "{tenant} {token} {body}");
+                check_json(("expected", &expected), ("response", &body));
+            }
+        }
+        ctx.close().await;
+    }
+
+    #[tokio::test]
+    async fn tenants_are_isolated() {
+        let db = TenantDb::new(Arc::new(InMemoryDb::default()), 8, |tenant| {
+            match tenant.as_str() {
+                "acme" => Arc::new(InMemoryDb::fixture()),
+                _ => Arc::new(InMemoryDb::default()),
+            }
+        });
+        let acme = Tenant::parse("acme").unwrap();
+        let globex = Tenant::parse("globex").unwrap();
+        assert!(with_tenant(acme, db.user(USER_01_ID)).await.is_ok());
+        assert!(matches!(
+            with_tenant(globex, db.user(USER_01_ID)).await,
+            Err(sqlx::Error::RowNotFound)
+        ));
+        assert!(matches!(
+            db.user(USER_01_ID).await,
+            Err(sqlx::Error::RowNotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn infra_paths_need_no_tenant() {
+        let ctx = TestContext::new(crate::function!()).await;
+        {
+            let mut config = test_config();
+            config.multi_tenant = true;
+            let ctx = Arc::new(crate::Context::new(ctx.ctx().db_pool.clone(), config));
+            let app = crate::health::router()
+                .merge(crate::docs_router())
+                .merge(crate::maintenance::router())
+                .layer(axum::middleware::from_fn(resolve_tenant))
+                .layer(axum::Extension(ctx));
+            for (method, uri, status) in [
+                ("GET", "/ready", StatusCode::OK),
+                ("GET", "/api-doc/openapi.json", StatusCode::OK),
+                ("GET", "/swagger-ui/index.html", StatusCode::OK),
+                // past the tenant and on to wanting a superadmin
+                ("PUT", "/admin/maintenance", StatusCode::UNAUTHORIZED),
+            ] {
+                let resp = app
+                    .clone()
+                    .oneshot(
+                        http::Request::builder()
+                            .method(method)
+                            .uri(uri)
+                            .body(Default::default())
+                            .unwrap_or_log(),
+                    )
+                    .await
+                    .unwrap_or_log();
+                assert_eq!(resp.status(), status, "failed on {uri}");
+            }
+        }
+        ctx.close().await;
+    }
+}
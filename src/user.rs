@@ -1,20 +1,18 @@
 use deps::*;
 
-use crate::{DocumentedEndpoint, EndpointWrapper, HttpEndpoint};
+use crate::{DocumentedEndpoint, EndpointWrapper};
 
 use once_cell::sync::Lazy;
 
-#[derive(serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
 #[serde(crate = "serde", rename_all = "camelCase")]
 pub struct User {
     pub id: uuid::Uuid,
-    /// In seconds since unix epoch in UTC.
-    #[schema(example = 1234567)]
-    #[serde(with = "time::serde::timestamp")]
+    #[schema(example = "2022-09-19T14:55:58Z")]
+    #[serde(with = "crate::utils::rfc3339")]
     pub created_at: time::OffsetDateTime,
-    /// In seconds since unix epoch in UTC.
-    #[schema(example = 1234567)]
-    #[serde(with = "time::serde::timestamp")]
+    #[schema(example = "2022-09-19T14:55:58Z")]
+    #[serde(with = "crate::utils::rfc3339")]
     pub updated_at: time::OffsetDateTime,
     #[schema(example = "alice@example.com")]
     pub email: String,
@@ -33,19 +31,26 @@ pub const TAG: crate::Tag = crate::Tag {
     desc: "Manipulate User objects.",
 };
 
+mod bulk_delete;
 mod create;
 mod delete;
 mod get;
+mod ingest;
 mod list;
 mod update;
+mod upsert;
 
 pub fn router() -> axum::Router {
     axum::Router::new()
         .merge(EndpointWrapper::new(get::GetUser))
         .merge(EndpointWrapper::new(create::CreateUser))
         .merge(EndpointWrapper::new(update::UpdateUser))
+        .merge(EndpointWrapper::new(upsert::UpsertUser))
         .merge(EndpointWrapper::new(list::ListUsers))
         .merge(EndpointWrapper::new(delete::DeleteUser))
+        .merge(EndpointWrapper::new(ingest::IngestUsers))
+        .merge(EndpointWrapper::new(bulk_delete::BulkDeleteUsers))
+        .layer(axum::middleware::from_fn(crate::method_not_allowed_body))
 }
 
 pub fn components(
@@ -54,40 +59,64 @@ pub fn components(
     let builder = get::GetUser::components(builder);
     let builder = create::CreateUser::components(builder);
     let builder = update::UpdateUser::components(builder);
+    let builder = upsert::UpsertUser::components(builder);
     let builder = list::ListUsers::components(builder);
     let builder = delete::DeleteUser::components(builder);
+    let builder = ingest::IngestUsers::components(builder);
+    let builder = bulk_delete::BulkDeleteUsers::components(builder);
     builder
         .schema("User", <User as utoipa::ToSchema>::schema())
+        .schema(
+            crate::utils::type_name_raw::<ingest::IngestReport>(),
+            <ingest::IngestReport as utoipa::ToSchema>::schema(),
+        )
+        .schema(
+            crate::utils::type_name_raw::<ingest::RecordResult>(),
+            <ingest::RecordResult as utoipa::ToSchema>::schema(),
+        )
+        .schema(
+            crate::utils::type_name_raw::<ingest::RecordStatus>(),
+            <ingest::RecordStatus as utoipa::ToSchema>::schema(),
+        )
+        .schema(
+            crate::utils::type_name_raw::<bulk_delete::BulkDeleteRequest>(),
+            <bulk_delete::BulkDeleteRequest as utoipa::ToSchema>::schema(),
+        )
+        .schema(
+            crate::utils::type_name_raw::<bulk_delete::BulkDeleteReport>(),
+            <bulk_delete::BulkDeleteReport as utoipa::ToSchema>::schema(),
+        )
+        .schema(
+            crate::utils::type_name_raw::<bulk_delete::DeleteResult>(),
+            <bulk_delete::DeleteResult as utoipa::ToSchema>::schema(),
+        )
+        .schema(
+            crate::utils::type_name_raw::<bulk_delete::DeleteStatus>(),
+            <bulk_delete::DeleteStatus as utoipa::ToSchema>::schema(),
+        )
         .schema(
             crate::utils::type_name_raw::<UserSortingField>(),
             <UserSortingField as utoipa::ToSchema>::schema(),
         )
         .schemas_from_iter(<list::ListUsersRequest as utoipa::ToSchema>::aliases())
         .schemas_from_iter(<list::ListUsersResponse as utoipa::ToSchema>::aliases())
+        .schemas_from_iter(<crate::utils::ListUsersEnvelope as utoipa::ToSchema>::aliases())
+        .schema(
+            crate::utils::type_name_raw::<crate::utils::ListMeta>(),
+            <crate::utils::ListMeta as utoipa::ToSchema>::schema(),
+        )
 }
 
-pub fn paths(builder: utoipa::openapi::PathsBuilder) -> utoipa::openapi::PathsBuilder {
-    builder
-        .path(
-            crate::axum_path_str_to_openapi(get::GetUser::PATH),
-            get::GetUser::path_item(),
-        )
-        .path(
-            crate::axum_path_str_to_openapi(update::UpdateUser::PATH),
-            update::UpdateUser::path_item(),
-        )
-        .path(
-            crate::axum_path_str_to_openapi(delete::DeleteUser::PATH),
-            delete::DeleteUser::path_item(),
-        )
-        .path(
-            crate::axum_path_str_to_openapi(create::CreateUser::PATH),
-            create::CreateUser::path_item(),
-        )
-        .path(
-            crate::axum_path_str_to_openapi(list::ListUsers::PATH),
-            list::ListUsers::path_item(),
-        )
+pub fn paths(paths: crate::SpecPaths) -> crate::SpecPaths {
+    paths
+        .endpoint::<get::GetUser>()
+        .endpoint::<update::UpdateUser>()
+        .endpoint::<upsert::UpsertUser>()
+        .endpoint::<delete::DeleteUser>()
+        .endpoint::<create::CreateUser>()
+        .endpoint::<list::ListUsers>()
+        .endpoint::<ingest::IngestUsers>()
+        .endpoint::<bulk_delete::BulkDeleteUsers>()
 }
 
 // #[cfg(test)]
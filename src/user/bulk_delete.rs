@@ -0,0 +1,379 @@
+use deps::*;
+
+use crate::*;
+
+use serde::{Deserialize, Serialize};
+
+/// Deletes each of the users one by one so that one failing doesn't roll back the
+/// rest. What happened to each is reported in the order they were requested.
+/// Only for [`crate::auth::Role::SuperAdmin`]s.
+#[derive(Debug, Clone, Copy)]
+pub struct BulkDeleteUsers;
+
+#[derive(Debug)]
+pub struct Request {
+    pub auth_token: std::sync::Arc<str>,
+    pub body: BulkDeleteRequest,
+}
+
+#[derive(Debug, Deserialize, utoipa::ToSchema)]
+#[serde(crate = "serde", rename_all = "camelCase")]
+pub struct BulkDeleteRequest {
+    pub ids: Vec<uuid::Uuid>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, utoipa::ToSchema)]
+#[serde(crate = "serde", rename_all = "camelCase")]
+pub enum DeleteStatus {
+    Deleted,
+    NotFound,
+    Error,
+}
+
+#[derive(Debug, Serialize, utoipa::ToSchema)]
+#[serde(crate = "serde", rename_all = "camelCase")]
+pub struct DeleteResult {
+    pub id: uuid::Uuid,
+    pub status: DeleteStatus,
+    /// Error code of a failed delete, e.g. `service.overloaded`, details are logged.
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize, utoipa::ToSchema)]
+#[serde(crate = "serde", rename_all = "camelCase")]
+pub struct BulkDeleteReport {
+    pub deleted: usize,
+    pub not_found: usize,
+    pub failed: usize,
+    pub results: Vec<DeleteResult>,
+}
+
+#[derive(Debug, thiserror::Error, serde::Serialize, utoipa::ToSchema)]
+#[serde(crate = "serde", tag = "error", rename_all = "camelCase")]
+pub enum Error {
+    #[error("acess denied")]
+    AccessDenied,
+    #[error("forbidden")]
+    Forbidden,
+    #[error("too many ids: {count} > {max}")]
+    TooManyIds { count: usize, max: usize },
+    #[error("database overloaded")]
+    Overloaded,
+    #[error("internal server error: {message:?}")]
+    Internal { message: String },
+}
+
+crate::impl_from_auth_err!(Error);
+crate::impl_from_db_err!(Error);
+
+pub type Response = Ref<BulkDeleteReport>;
+
+#[async_trait::async_trait]
+impl crate::AuthenticatedEndpoint for BulkDeleteUsers {
+    type Request = Request;
+    type Response = Response;
+    type Error = Error;
+
+    fn authorize_request(&self, request: &Self::Request) -> crate::auth::authorize::Request {
+        crate::auth::authorize::Request {
+            auth_token: request.auth_token.clone(),
+            resource: crate::auth::Resource::Users,
+            action: crate::auth::Action::Delete,
+        }
+    }
+
+    #[tracing::instrument(skip(ctx))]
+    async fn handle(
+        &self,
+        ctx: &crate::Context,
+        accessing_user: uuid::Uuid,
+        request: Self::Request,
+    ) -> Result<Self::Response, Self::Error> {
+        crate::auth::require_role(ctx, accessing_user, crate::auth::Role::SuperAdmin).await?;
+        let pool = ctx.pool()?;
+        let ids = request.body.ids;
+        let max = ctx.config.bulk_delete_max;
+        if ids.len() > max {
+            return Err(Error::TooManyIds {
+                count: ids.len(),
+                max,
+            });
+        }
+        let mut report = BulkDeleteReport {
+            deleted: 0,
+            not_found: 0,
+            failed: 0,
+            results: Vec::with_capacity(ids.len()),
+        };
+        for id in ids {
+            let was_deleted = ctx
+                .guard_db(
+                    sqlx::query_scalar!(
+                        r#"
+SELECT delete_user($1)
+                "#,
+                        &id
+                    )
+                    .fetch_one(&pool),
+                )
+                .await;
+            report.results.push(match was_deleted {
+                Ok(Some(true)) => {
+                    report.deleted += 1;
+                    DeleteResult {
+                        id,
+                        status: DeleteStatus::Deleted,
+                        error: None,
+                    }
+                }
+                Ok(_) => {
+                    report.not_found += 1;
+                    DeleteResult {
+                        id,
+                        status: DeleteStatus::NotFound,
+                        error: None,
+                    }
+                }
+                Err(err) => {
+                    tracing::error!(%id, ?err, "error deleting user");
+                    report.failed += 1;
+                    DeleteResult {
+                        id,
+                        status: DeleteStatus::Error,
+                        error: Some(Error::from(err).code().to_string()),
+                    }
+                }
+            });
+        }
+        Ok(report.into())
+    }
+}
+
+impl From<&Error> for axum::http::StatusCode {
+    fn from(err: &Error) -> Self {
+        use Error::*;
+        match err {
+            AccessDenied => Self::UNAUTHORIZED,
+            Forbidden => Self::FORBIDDEN,
+            TooManyIds { .. } => Self::BAD_REQUEST,
+            Overloaded => Self::SERVICE_UNAVAILABLE,
+            Internal { .. } => Self::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl crate::utils::ErrorCode for Error {
+    fn code(&self) -> &'static str {
+        use Error::*;
+        match self {
+            AccessDenied => "auth.access_denied",
+            Forbidden => "auth.forbidden",
+            TooManyIds { .. } => "user.too_many_ids",
+            Overloaded => "service.overloaded",
+            Internal { .. } => "service.internal",
+        }
+    }
+}
+
+impl HttpEndpoint for BulkDeleteUsers {
+    const METHOD: Method = Method::Post;
+    const PATH: &'static str = "/users/bulk-delete";
+    const AUDIT_ACTION: Option<&'static str> = Some("user.bulkDelete");
+
+    type HttpRequest = (BearerToken, Json<BulkDeleteRequest>);
+
+    fn request(
+        (BearerToken(token), Json(body)): Self::HttpRequest,
+    ) -> Result<Self::Request, Self::Error> {
+        Ok(self::Request {
+            auth_token: token,
+            body,
+        })
+    }
+
+    fn response(Ref(resp): Self::Response) -> axum::response::Response {
+        Json(resp).into_response()
+    }
+
+    /// The ids that were actually deleted, comma separated.
+    fn audit_resource_id(Ref(report): &Self::Response) -> Option<String> {
+        let deleted = report
+            .results
+            .iter()
+            .filter(|result| result.status == DeleteStatus::Deleted)
+            .map(|result| result.id.to_string())
+            .collect::<Vec<_>>();
+        (!deleted.is_empty()).then(|| deleted.join(","))
+    }
+}
+
+impl DocumentedEndpoint for BulkDeleteUsers {
+    const TAG: &'static Tag = &super::TAG;
+    const SUMMARY: &'static str = "Delete many users at once.";
+
+    fn success_examples() -> Vec<serde_json::Value> {
+        use crate::user::testing::*;
+        [BulkDeleteReport {
+            deleted: 1,
+            not_found: 1,
+            failed: 0,
+            results: vec![
+                DeleteResult {
+                    id: USER_01_ID,
+                    status: DeleteStatus::Deleted,
+                    error: None,
+                },
+                DeleteResult {
+                    id: Default::default(),
+                    status: DeleteStatus::NotFound,
+                    error: None,
+                },
+            ],
+        }]
+        .into_iter()
+        .map(serde_json::to_value)
+        .collect::<Result<_, _>>()
+        .unwrap()
+    }
+
+    fn errors() -> Vec<ErrorResponse<Self::Error>> {
+        vec![
+            ("Access denied", Error::AccessDenied),
+            ("Forbidden", Error::Forbidden),
+            (
+                "Too many ids",
+                Error::TooManyIds {
+                    count: 1000,
+                    max: 100,
+                },
+            ),
+            ("Overloaded", Error::Overloaded),
+            (
+                "Internal server error",
+                Error::Internal {
+                    message: "internal server error".to_string(),
+                },
+            ),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use deps::*;
+
+    use crate::user::testing::*;
+    use crate::utils::testing::*;
+
+    fn grant_super_admin(ctx: &TestContext) -> LocalBoxFuture<'_, ()> {
+        Box::pin(async move {
+            sqlx::query("INSERT INTO user_roles (user_id, role) VALUES ($1, 'superAdmin')")
+                .bind(USER_01_ID)
+                .execute(&ctx.ctx().db_pool)
+                .await
+                .unwrap_or_log();
+        })
+    }
+
+    macro_rules! bulk_delete_integ {
+        ($(
+            $name:ident: {
+                body: $json_body:expr,
+                status: $status:expr,
+                $(check_json: $check_json:expr,)?
+                $(setup: $setup_fn:expr,)?
+                $(extra_assertions: $extra_fn:expr,)?
+            },
+        )*) => {
+            mod integ {
+                use super::*;
+                crate::integration_table_tests! {
+                    $(
+                        $name: {
+                            uri: "/users/bulk-delete",
+                            method: "POST",
+                            status: $status,
+                            router: crate::user::router(),
+                            body: $json_body,
+                            $(check_json: $check_json,)?
+                            auth_token: USER_01_SESSION.into(),
+                            $(setup: $setup_fn,)?
+                            $(extra_assertions: $extra_fn,)?
+                        },
+                    )*
+                }
+            }
+        };
+    }
+
+    bulk_delete_integ! {
+        deletes_all: {
+            body: serde_json::json!({ "ids": [USER_02_ID, USER_03_ID] }),
+            status: StatusCode::OK,
+            check_json: serde_json::json!({
+                "deleted": 2,
+                "notFound": 0,
+                "failed": 0,
+                "results": [
+                    { "id": USER_02_ID, "status": "deleted" },
+                    { "id": USER_03_ID, "status": "deleted" },
+                ],
+            }),
+            setup: &grant_super_admin,
+            extra_assertions: &|EAArgs { ctx, .. }| {
+                Box::pin(async move {
+                    let count: i64 =
+                        sqlx::query_scalar("SELECT COUNT(*) FROM users WHERE id = ANY($1)")
+                            .bind(&[USER_02_ID, USER_03_ID][..])
+                            .fetch_one(&ctx.ctx().db_pool)
+                            .await
+                            .unwrap_or_log();
+                    assert_eq!(count, 0);
+                })
+            },
+        },
+        reports_not_found: {
+            body: serde_json::json!({ "ids": [USER_02_ID, uuid::Uuid::nil(), USER_02_ID] }),
+            status: StatusCode::OK,
+            check_json: serde_json::json!({
+                "deleted": 1,
+                "notFound": 2,
+                "failed": 0,
+                "results": [
+                    { "id": USER_02_ID, "status": "deleted" },
+                    { "id": uuid::Uuid::nil(), "status": "notFound" },
+                    { "id": USER_02_ID, "status": "notFound" },
+                ],
+            }),
+            setup: &grant_super_admin,
+        },
+        rejects_too_many_ids: {
+            body: serde_json::json!({
+                "ids": (0..=crate::utils::testing::test_config().bulk_delete_max)
+                    .map(|_| uuid::Uuid::new_v4())
+                    .collect::<Vec<_>>(),
+            }),
+            status: StatusCode::BAD_REQUEST,
+            check_json: serde_json::json!({
+                "error": "tooManyIds",
+                "max": crate::utils::testing::test_config().bulk_delete_max,
+            }),
+            setup: &grant_super_admin,
+        },
+        only_super_admins_delete: {
+            body: serde_json::json!({ "ids": [USER_02_ID] }),
+            status: StatusCode::FORBIDDEN,
+            check_json: serde_json::json!({ "error": "forbidden" }),
+            extra_assertions: &|EAArgs { ctx, .. }| {
+                Box::pin(async move {
+                    let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM users WHERE id = $1")
+                        .bind(USER_02_ID)
+                        .fetch_one(&ctx.ctx().db_pool)
+                        .await
+                        .unwrap_or_log();
+                    assert_eq!(count, 1);
+                })
+            },
+        },
+    }
+}
@@ -32,10 +32,14 @@ pub enum Error {
         #[from]
         issues: ValidationErrors,
     },
+    #[error("database overloaded")]
+    Overloaded,
     #[error("internal server error: {message:?}")]
     Internal { message: String },
 }
 
+crate::impl_from_db_err!(Error);
+
 pub type Response = Ref<super::User>;
 
 #[async_trait::async_trait]
@@ -57,9 +61,12 @@ impl Endpoint for CreateUser {
             &ctx.config.argon2_conf,
         )
         .unwrap_or_log();
-        let user = sqlx::query_as!(
-            super::User,
-            r#"
+        let mut tx = ctx.begin().await?;
+        let user = ctx
+            .guard_db(async {
+                let user = sqlx::query_as!(
+                    super::User,
+                    r#"
 SELECT
     id as "id!",
     created_at as "created_at!",
@@ -69,30 +76,32 @@ SELECT
     pic_url
 FROM create_user($1::TEXT::CITEXT, $2::TEXT::CITEXT, $3)
                 "#,
-            &request.username,
-            &request.email,
-            &pass_hash
-        )
-        .fetch_one(&ctx.db_pool)
-        .await
-        .map_err(|err| match &err {
-            sqlx::Error::Database(boxed) if boxed.constraint().is_some() => {
-                match boxed.constraint().unwrap() {
-                    "unique_users_username" => Error::UsernameOccupied {
-                        username: request.username,
-                    },
-                    "unique_users_email" => Error::EmailOccupied {
-                        email: request.email,
-                    },
-                    _ => Error::Internal {
-                        message: format!("db error: {err}"),
-                    },
+                    &request.username,
+                    &request.email,
+                    &pass_hash
+                )
+                .fetch_one(&mut *tx)
+                .await?;
+                tx.commit().await?;
+                Ok::<_, sqlx::Error>(user)
+            })
+            .await
+            .map_err(|err| match &err {
+                sqlx::Error::Database(boxed) if boxed.constraint().is_some() => {
+                    match boxed.constraint().unwrap() {
+                        "unique_users_username" => Error::UsernameOccupied {
+                            username: request.username,
+                        },
+                        "unique_users_email" => Error::EmailOccupied {
+                            email: request.email,
+                        },
+                        _ => Error::Internal {
+                            message: format!("db error: {err}"),
+                        },
+                    }
                 }
-            }
-            _ => Error::Internal {
-                message: format!("db error: {err}"),
-            },
-        })?;
+                _ => err.into(),
+            })?;
         // TODO: email notification, account activation
         Ok(user.into())
     }
@@ -105,15 +114,32 @@ impl From<&Error> for axum::http::StatusCode {
             UsernameOccupied { .. } | EmailOccupied { .. } | InvalidInput { .. } => {
                 Self::BAD_REQUEST
             }
+            Overloaded => Self::SERVICE_UNAVAILABLE,
             Internal { .. } => Self::INTERNAL_SERVER_ERROR,
         }
     }
 }
 
+impl crate::utils::ErrorCode for Error {
+    fn code(&self) -> &'static str {
+        use Error::*;
+        match self {
+            UsernameOccupied { .. } => "user.username_occupied",
+            EmailOccupied { .. } => "user.email_occupied",
+            InvalidInput { .. } => "request.invalid_input",
+            Overloaded => "service.overloaded",
+            Internal { .. } => "service.internal",
+        }
+    }
+}
+
 impl HttpEndpoint for CreateUser {
     const METHOD: Method = Method::Post;
     const PATH: &'static str = "/users";
     const SUCCESS_CODE: StatusCode = StatusCode::CREATED;
+    const RETURN_MINIMAL: bool = true;
+    const AUDIT_ACTION: Option<&'static str> = Some("user.create");
+    const DRY_RUN: bool = true;
 
     type HttpRequest = (Json<Request>,);
 
@@ -124,6 +150,10 @@ impl HttpEndpoint for CreateUser {
     fn response(Ref(resp): Self::Response) -> axum::response::Response {
         Json(resp).into_response()
     }
+
+    fn audit_resource_id(Ref(user): &Self::Response) -> Option<String> {
+        Some(user.id.to_string())
+    }
 }
 
 impl DocumentedEndpoint for CreateUser {
@@ -145,6 +175,19 @@ impl DocumentedEndpoint for CreateUser {
         .unwrap()
     }
 
+    fn request_examples() -> Vec<NamedExample<serde_json::Value>> {
+        use crate::user::testing::*;
+        vec![(
+            "newUser",
+            "Sign up",
+            serde_json::json!({
+                "username": USER_01_USERNAME,
+                "email": USER_01_EMAIL,
+                "password": "lovebite",
+            }),
+        )]
+    }
+
     fn errors() -> Vec<ErrorResponse<Self::Error>> {
         use crate::user::testing::*;
         vec![
@@ -182,6 +225,7 @@ impl DocumentedEndpoint for CreateUser {
                     },
                 },
             ),
+            ("Overloaded", Error::Overloaded),
             (
                 "Internal server error",
                 Error::Internal {
@@ -388,4 +432,198 @@ mod tests {
             }),
         },
     }
+
+    #[tokio::test]
+    async fn dry_run_leaves_table_unchanged() {
+        let ctx = TestContext::new(crate::function!()).await;
+        {
+            let count_users = || async {
+                sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM users")
+                    .fetch_one(&ctx.ctx().db_pool)
+                    .await
+                    .unwrap_or_log()
+            };
+            let before = count_users().await;
+            let resp = crate::user::router()
+                .layer(axum::Extension(ctx.ctx()))
+                .oneshot(
+                    http::Request::builder()
+                        .method("POST")
+                        .uri("/users?dry_run=true")
+                        .header(http::header::CONTENT_TYPE, "application/json")
+                        .body(serde_json::to_vec(&fixture_request_json()).unwrap().into())
+                        .unwrap_or_log(),
+                )
+                .await
+                .unwrap_or_log();
+            assert_eq!(resp.status(), http::StatusCode::CREATED);
+            assert_eq!(resp.headers()[crate::PREFERENCE_APPLIED], "dry-run");
+            let body = hyper::body::to_bytes(resp.into_body())
+                .await
+                .unwrap_or_log();
+            let body = serde_json::from_slice(&body).unwrap_or_log();
+            check_json(
+                (
+                    "expected",
+                    &fixture_request_json().remove_keys_from_obj(&["password"]),
+                ),
+                ("response", &body),
+            );
+            assert_eq!(count_users().await, before);
+
+            // still validated
+            let resp = crate::user::router()
+                .layer(axum::Extension(ctx.ctx()))
+                .oneshot(
+                    http::Request::builder()
+                        .method("POST")
+                        .uri("/users")
+                        .header(http::header::CONTENT_TYPE, "application/json")
+                        .header("prefer", "dry-run")
+                        .body(
+                            serde_json::to_vec(&fixture_request_json().destructure_into_self(
+                                serde_json::json!({ "username": USER_01_USERNAME }),
+                            ))
+                            .unwrap()
+                            .into(),
+                        )
+                        .unwrap_or_log(),
+                )
+                .await
+                .unwrap_or_log();
+            assert_eq!(resp.status(), http::StatusCode::BAD_REQUEST);
+        }
+        ctx.close().await;
+    }
+
+    crate::table_tests! {
+        create_user_prefer tokio,
+        (prefer, status, applied, has_body),
+        {
+            let ctx = TestContext::new(crate::function!()).await;
+            {
+                let mut req = http::Request::builder()
+                    .method("POST")
+                    .uri("/users")
+                    .header(http::header::CONTENT_TYPE, "application/json");
+                if let Some(prefer) = prefer {
+                    req = req.header("prefer", prefer);
+                }
+                let resp = crate::user::router()
+                    .layer(axum::Extension(ctx.ctx()))
+                    .oneshot(
+                        req.body(serde_json::to_vec(&fixture_request_json()).unwrap().into())
+                            .unwrap_or_log(),
+                    )
+                    .await
+                    .unwrap_or_log();
+                assert_eq!(resp.status(), status);
+                assert_eq!(
+                    resp.headers()
+                        .get(crate::PREFERENCE_APPLIED)
+                        .map(|val| val.to_str().unwrap()),
+                    applied
+                );
+                let body = hyper::body::to_bytes(resp.into_body())
+                    .await
+                    .unwrap_or_log();
+                assert_eq!(!body.is_empty(), has_body);
+            }
+            ctx.close().await;
+        },
+    }
+
+    create_user_prefer! {
+        prefer_minimal_returns_no_content: (
+            Some("return=minimal"),
+            StatusCode::NO_CONTENT,
+            Some("return=minimal"),
+            false,
+        ),
+        prefer_representation_returns_body: (
+            Some("return=representation"),
+            StatusCode::CREATED,
+            None,
+            true,
+        ),
+        no_preference_returns_body: (None::<&str>, StatusCode::CREATED, None, true),
+    }
+
+    #[tokio::test]
+    async fn records_an_audit_entry() {
+        use tracing_subscriber::layer::SubscriberExt;
+        let ctx = TestContext::new(crate::function!()).await;
+        {
+            let audits = CapturedEvents::new(crate::utils::audit::TARGET);
+            let _guard = tracing::subscriber::set_default(
+                tracing_subscriber::registry().with(audits.clone()),
+            );
+            let resp = crate::user::router()
+                .layer(axum::middleware::from_fn(
+                    crate::utils::populate_request_context,
+                ))
+                .layer(axum::Extension(ctx.ctx()))
+                .oneshot(
+                    http::Request::builder()
+                        .method("POST")
+                        .uri("/users")
+                        .header(http::header::CONTENT_TYPE, "application/json")
+                        .header(
+                            http::header::AUTHORIZATION,
+                            format!("Bearer {USER_01_SESSION}"),
+                        )
+                        .header(crate::utils::REQUEST_ID, "req-audit")
+                        .body(serde_json::to_vec(&fixture_request_json()).unwrap().into())
+                        .unwrap_or_log(),
+                )
+                .await
+                .unwrap_or_log();
+            assert_eq!(resp.status(), StatusCode::CREATED);
+            let body = hyper::body::to_bytes(resp.into_body())
+                .await
+                .unwrap_or_log();
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap_or_log();
+
+            let entries = audits.events();
+            assert_eq!(entries.len(), 1, "{entries:?}");
+            check_json(
+                (
+                    "expected",
+                    &serde_json::json!({
+                        "actor": USER_01_ID.to_string(),
+                        "action": "user.create",
+                        "resource_id": body["id"],
+                        "path": "/users",
+                        "request_id": "req-audit",
+                    }),
+                ),
+                ("entry", &serde_json::Value::Object(entries[0].clone())),
+            );
+            assert!(entries[0].contains_key("timestamp"));
+        }
+        ctx.close().await;
+    }
+
+    #[test]
+    fn documents_request_examples() {
+        let spec = crate::openapi_json();
+        let content =
+            &spec["paths"]["/users"]["post"]["requestBody"]["content"]["application/json"];
+        assert!(content.get("example").is_none(), "{content}");
+        check_json(
+            (
+                "expected",
+                &serde_json::json!({
+                    "newUser": {
+                        "summary": "Sign up",
+                        "value": {
+                            "username": USER_01_USERNAME,
+                            "email": USER_01_EMAIL,
+                        },
+                    },
+                }),
+            ),
+            ("examples", &content["examples"]),
+        );
+    }
 }
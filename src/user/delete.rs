@@ -1,7 +1,5 @@
 use deps::*;
 
-use axum::extract::*;
-
 use crate::*;
 
 #[derive(Clone, Copy, Debug)]
@@ -18,11 +16,16 @@ pub struct Request {
 pub enum Error {
     #[error("acess denied")]
     AccessDenied,
+    #[error("forbidden")]
+    Forbidden,
+    #[error("database overloaded")]
+    Overloaded,
     #[error("internal server error: {message:?}")]
     Internal { message: String },
 }
 
 crate::impl_from_auth_err!(Error);
+crate::impl_from_db_err!(Error);
 
 pub type Response = NoContent;
 
@@ -47,19 +50,20 @@ impl crate::AuthenticatedEndpoint for DeleteUser {
         _accessing_user: uuid::Uuid,
         request: Self::Request,
     ) -> Result<Self::Response, Self::Error> {
+        let pool = ctx.pool()?;
         let id = request.id;
 
-        let was_deleted = sqlx::query!(
-            r#"
+        let was_deleted = ctx
+            .guard_db(
+                sqlx::query!(
+                    r#"
 SELECT delete_user($1)
             "#,
-            &id
-        )
-        .fetch_one(&ctx.db_pool)
-        .await
-        .map_err(|err| Error::Internal {
-            message: format!("db error: {err}"),
-        })?;
+                    &id
+                )
+                .fetch_one(&pool),
+            )
+            .await?;
         tracing::trace!(?was_deleted);
         Ok(NoContent)
     }
@@ -70,20 +74,35 @@ impl From<&Error> for axum::http::StatusCode {
         use Error::*;
         match err {
             AccessDenied => Self::UNAUTHORIZED,
+            Forbidden => Self::FORBIDDEN,
+            Overloaded => Self::SERVICE_UNAVAILABLE,
             Internal { .. } => Self::INTERNAL_SERVER_ERROR,
         }
     }
 }
 
+impl crate::utils::ErrorCode for Error {
+    fn code(&self) -> &'static str {
+        use Error::*;
+        match self {
+            AccessDenied => "auth.access_denied",
+            Forbidden => "auth.forbidden",
+            Overloaded => "service.overloaded",
+            Internal { .. } => "service.internal",
+        }
+    }
+}
+
 impl HttpEndpoint for DeleteUser {
     const METHOD: Method = Method::Delete;
     const PATH: &'static str = "/users/:id";
     const SUCCESS_CODE: StatusCode = StatusCode::NO_CONTENT;
+    const AUDIT_ACTION: Option<&'static str> = Some("user.delete");
 
-    type HttpRequest = (BearerToken, Path<uuid::Uuid>);
+    type HttpRequest = (BearerToken, crate::utils::UuidPath);
 
     fn request(
-        (BearerToken(token), Path(id)): Self::HttpRequest,
+        (BearerToken(token), crate::utils::UuidPath(id)): Self::HttpRequest,
     ) -> Result<Self::Request, Self::Error> {
         Ok(self::Request {
             auth_token: token,
@@ -91,8 +110,13 @@ impl HttpEndpoint for DeleteUser {
         })
     }
 
-    fn response(_: Self::Response) -> axum::response::Response {
-        Default::default()
+    fn response(resp: Self::Response) -> axum::response::Response {
+        resp.into_response()
+    }
+
+    // the id is only in the path
+    fn audit_target_id(req: &Self::Request) -> Option<String> {
+        Some(req.id.to_string())
     }
 }
 
@@ -102,6 +126,8 @@ impl DocumentedEndpoint for DeleteUser {
     fn errors() -> Vec<ErrorResponse<Error>> {
         vec![
             ("Access denied", Error::AccessDenied),
+            ("Forbidden", Error::Forbidden),
+            ("Overloaded", Error::Overloaded),
             (
                 "Internal server error",
                 Error::Internal {
@@ -151,8 +177,10 @@ mod tests {
             uri: format!("/users/{USER_01_ID}"),
             auth_token: USER_01_SESSION.into(),
             status: StatusCode::NO_CONTENT,
-            extra_assertions: &|EAArgs { ctx, response_json, .. }| {
+            extra_assertions: &|EAArgs { ctx, response_json, response_head, .. }| {
                 Box::pin(async move {
+                    assert!(response_json.is_none());
+                    assert!(response_head.headers.get(http::header::CONTENT_TYPE).is_none());
                     let app = crate::user::router().layer(axum::Extension(ctx.ctx()));
                     let resp = app
                         .oneshot(
@@ -178,4 +206,54 @@ mod tests {
             status: StatusCode::NO_CONTENT,
         },
     }
+
+    #[tokio::test]
+    async fn audits_the_deleted_id() {
+        use tracing_subscriber::layer::SubscriberExt;
+        let ctx = TestContext::new(crate::function!()).await;
+        {
+            let audits = CapturedEvents::new(crate::utils::audit::TARGET);
+            let _guard = tracing::subscriber::set_default(
+                tracing_subscriber::registry().with(audits.clone()),
+            );
+            let resp = crate::user::router()
+                .layer(axum::Extension(ctx.ctx()))
+                .oneshot(
+                    http::Request::builder()
+                        .method("DELETE")
+                        .uri(format!("/users/{USER_01_ID}"))
+                        .header(
+                            http::header::AUTHORIZATION,
+                            format!("Bearer {USER_01_SESSION}"),
+                        )
+                        .body(Default::default())
+                        .unwrap_or_log(),
+                )
+                .await
+                .unwrap_or_log();
+            assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+
+            let entries = audits.events();
+            assert_eq!(entries.len(), 1, "{entries:?}");
+            check_json(
+                (
+                    "expected",
+                    &serde_json::json!({
+                        "action": "user.delete",
+                        "resource_id": USER_01_ID.to_string(),
+                    }),
+                ),
+                ("entry", &serde_json::Value::Object(entries[0].clone())),
+            );
+        }
+        ctx.close().await;
+    }
+
+    #[test]
+    fn documents_204_without_content() {
+        let spec = serde_json::to_value(<crate::ApiDoc as utoipa::OpenApi>::openapi()).unwrap();
+        let resp = &spec["paths"]["/users/{id}"]["delete"]["responses"]["204"];
+        assert!(resp.is_object(), "{spec:#}");
+        assert!(resp.get("content").is_none(), "{resp:#}");
+    }
 }
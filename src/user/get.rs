@@ -22,11 +22,16 @@ pub enum Error {
     NotFound { id: uuid::Uuid },
     #[error("acess denied")]
     AccessDenied,
+    #[error("forbidden")]
+    Forbidden,
+    #[error("database overloaded")]
+    Overloaded,
     #[error("internal server error: {message:?}")]
     Internal { message: String },
 }
 
 crate::impl_from_auth_err!(Error);
+crate::impl_from_db_err!(Error);
 
 pub type Response = Ref<super::User>;
 
@@ -53,30 +58,13 @@ impl crate::AuthenticatedEndpoint for GetUser {
     ) -> Result<Self::Response, Self::Error> {
         let id = request.id;
 
-        sqlx::query_as!(
-            User,
-            r#"
-SELECT 
-    id,
-    created_at,
-    updated_at,
-    email::TEXT as "email!",
-    username::TEXT as "username!",
-    pic_url
-FROM users
-WHERE id = $1::uuid
-            "#,
-            &id
-        )
-        .fetch_one(&ctx.db_pool)
-        .await
-        .map(|val| val.into())
-        .map_err(|err| match err {
-            sqlx::Error::RowNotFound => Error::NotFound { id },
-            _ => Error::Internal {
-                message: format!("db error: {err}"),
-            },
-        })
+        ctx.guard_db(ctx.db.user(id))
+            .await
+            .map(|val| val.into())
+            .map_err(|err| match err {
+                sqlx::Error::RowNotFound => Error::NotFound { id },
+                _ => err.into(),
+            })
     }
 }
 
@@ -86,19 +74,35 @@ impl From<&Error> for axum::http::StatusCode {
         match err {
             NotFound { .. } => Self::NOT_FOUND,
             AccessDenied => Self::UNAUTHORIZED,
+            Forbidden => Self::FORBIDDEN,
+            Overloaded => Self::SERVICE_UNAVAILABLE,
             Internal { .. } => Self::INTERNAL_SERVER_ERROR,
         }
     }
 }
 
+impl crate::utils::ErrorCode for Error {
+    fn code(&self) -> &'static str {
+        use Error::*;
+        match self {
+            NotFound { .. } => "user.not_found",
+            AccessDenied => "auth.access_denied",
+            Forbidden => "auth.forbidden",
+            Overloaded => "service.overloaded",
+            Internal { .. } => "service.internal",
+        }
+    }
+}
+
 impl HttpEndpoint for GetUser {
     const METHOD: Method = Method::Get;
     const PATH: &'static str = "/users/:id";
+    const HAS_LINKS: bool = true;
 
-    type HttpRequest = (BearerToken, Path<uuid::Uuid>);
+    type HttpRequest = (BearerToken, crate::utils::UuidPath);
 
     fn request(
-        (BearerToken(token), Path(id)): Self::HttpRequest,
+        (BearerToken(token), crate::utils::UuidPath(id)): Self::HttpRequest,
     ) -> Result<Self::Request, Self::Error> {
         Ok(self::Request {
             auth_token: token,
@@ -109,6 +113,32 @@ impl HttpEndpoint for GetUser {
     fn response(Ref(resp): Self::Response) -> axum::response::Response {
         Json(resp).into_response()
     }
+
+    fn last_modified(Ref(user): &Self::Response) -> Option<time::OffsetDateTime> {
+        Some(user.updated_at)
+    }
+
+    fn sparse_fields() -> Option<SparseFields> {
+        Some(SparseFields::of::<User>(""))
+    }
+
+    fn links(Ref(user): &Self::Response) -> Vec<ResourceLink> {
+        vec![
+            ResourceLink::new("self", Self::METHOD, Self::PATH).param("id", user.id),
+            ResourceLink::new(
+                "update",
+                super::update::UpdateUser::METHOD,
+                super::update::UpdateUser::PATH,
+            )
+            .param("id", user.id),
+            ResourceLink::new(
+                "delete",
+                super::delete::DeleteUser::METHOD,
+                super::delete::DeleteUser::PATH,
+            )
+            .param("id", user.id),
+        ]
+    }
 }
 
 impl DocumentedEndpoint for GetUser {
@@ -133,12 +163,14 @@ impl DocumentedEndpoint for GetUser {
     fn errors() -> Vec<ErrorResponse<Error>> {
         vec![
             ("Access denied", Error::AccessDenied),
+            ("Forbidden", Error::Forbidden),
             (
                 "Not found",
                 Error::NotFound {
                     id: Default::default(),
                 },
             ),
+            ("Overloaded", Error::Overloaded),
             (
                 "Internal server error",
                 Error::Internal {
@@ -202,7 +234,233 @@ mod tests {
             status: StatusCode::NOT_FOUND,
             check_json: serde_json::json!({
                 "error": "notFound",
+                "code": "user.not_found",
             }),
         },
     }
+
+    #[tokio::test]
+    async fn works_against_mock_db() {
+        setup_tracing_once();
+        let ctx = std::sync::Arc::new(crate::Context::mock(InMemoryDb::fixture()));
+        let app = crate::user::router().layer(axum::Extension(ctx));
+        let resp = app
+            .oneshot(
+                http::Request::builder()
+                    .method("GET")
+                    .uri(format!("/users/{USER_01_ID}"))
+                    .header(
+                        http::header::AUTHORIZATION,
+                        format!("Bearer {USER_01_SESSION}"),
+                    )
+                    .body(Default::default())
+                    .unwrap_or_log(),
+            )
+            .await
+            .unwrap_or_log();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(resp.into_body())
+            .await
+            .unwrap_or_log();
+        let body = serde_json::from_slice(&body).unwrap_or_log();
+        check_json(
+            (
+                "expected",
+                &serde_json::json!({
+                    "id": USER_01_ID,
+                    "username": USER_01_USERNAME,
+                    "email": USER_01_EMAIL,
+                }),
+            ),
+            ("response", &body),
+        );
+    }
+
+    #[tokio::test]
+    async fn links_to_itself() {
+        setup_tracing_once();
+        let mut ctx = crate::Context::mock(InMemoryDb::fixture());
+        ctx.config.trusted_proxies = vec!["10.0.0.0/8".parse().unwrap()];
+        let app = crate::user::router().layer(axum::Extension(std::sync::Arc::new(ctx)));
+        let mut req = http::Request::builder()
+            .method("GET")
+            .uri(format!("/users/{USER_01_ID}"))
+            .header(http::header::HOST, "api.example.com")
+            .header("x-forwarded-proto", "https")
+            .header(
+                http::header::AUTHORIZATION,
+                format!("Bearer {USER_01_SESSION}"),
+            )
+            .body(Default::default())
+            .unwrap_or_log();
+        req.extensions_mut()
+            .insert(axum::extract::ConnectInfo(std::net::SocketAddr::from((
+                [10, 0, 0, 2],
+                41_000,
+            ))));
+        let resp = app.oneshot(req).await.unwrap_or_log();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(resp.into_body())
+            .await
+            .unwrap_or_log();
+        let body = serde_json::from_slice(&body).unwrap_or_log();
+        check_json(
+            (
+                "expected",
+                &serde_json::json!({
+                    "id": USER_01_ID,
+                    "_links": {
+                        "self": {
+                            "href": format!("https://api.example.com/users/{USER_01_ID}"),
+                            "method": "GET",
+                        },
+                        "delete": {
+                            "href": format!("https://api.example.com/users/{USER_01_ID}"),
+                            "method": "DELETE",
+                        },
+                    },
+                }),
+            ),
+            ("response", &body),
+        );
+    }
+
+    #[test]
+    fn documents_links() {
+        let spec = crate::openapi_json();
+        let op = &spec["paths"]["/users/{id}"]["get"];
+        assert!(op.get(crate::utils::LINKS_EXTENSION).is_none(), "{op}");
+        let schema = &op["responses"]["200"]["content"]["application/json"]["schema"];
+        assert_eq!(schema["allOf"][0]["$ref"], "#/components/schemas/User");
+        assert_eq!(
+            schema["allOf"][1]["required"],
+            serde_json::json!(["_links"])
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_malformed_id() {
+        setup_tracing_once();
+        let ctx = std::sync::Arc::new(crate::Context::mock(InMemoryDb::fixture()));
+        let app = crate::user::router().layer(axum::Extension(ctx));
+        let resp = app
+            .oneshot(
+                http::Request::builder()
+                    .method("GET")
+                    .uri("/users/not-a-uuid")
+                    .header(
+                        http::header::AUTHORIZATION,
+                        format!("Bearer {USER_01_SESSION}"),
+                    )
+                    .body(Default::default())
+                    .unwrap_or_log(),
+            )
+            .await
+            .unwrap_or_log();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let body = hyper::body::to_bytes(resp.into_body())
+            .await
+            .unwrap_or_log();
+        let body = serde_json::from_slice(&body).unwrap_or_log();
+        check_json(
+            (
+                "expected",
+                &serde_json::json!({
+                    "error": "invalidPathParameter",
+                    "code": "request.invalid_path_parameter",
+                    "name": "id",
+                    "value": "not-a-uuid",
+                    "expected": "uuid",
+                }),
+            ),
+            ("response", &body),
+        );
+    }
+
+    #[tokio::test]
+    async fn works_as_tower_service() {
+        use crate::HttpEndpoint;
+        setup_tracing_once();
+        let ctx = std::sync::Arc::new(crate::Context::mock(InMemoryDb::fixture()));
+        let service = super::GetUser.into_service().with_context(ctx);
+        let resp = service
+            .oneshot(
+                http::Request::builder()
+                    .method("GET")
+                    .uri(format!("/users/{USER_01_ID}"))
+                    .header(
+                        http::header::AUTHORIZATION,
+                        format!("Bearer {USER_01_SESSION}"),
+                    )
+                    .body(Default::default())
+                    .unwrap_or_log(),
+            )
+            .await
+            .unwrap_or_log();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(resp.into_body())
+            .await
+            .unwrap_or_log();
+        let body = serde_json::from_slice(&body).unwrap_or_log();
+        check_json(
+            ("expected", &serde_json::json!({ "id": USER_01_ID })),
+            ("response", &body),
+        );
+    }
+
+    #[tokio::test]
+    async fn answers_if_modified_since() {
+        setup_tracing_once();
+        let ctx = std::sync::Arc::new(crate::Context::mock(InMemoryDb::fixture()));
+        let app = crate::user::router().layer(axum::Extension(ctx));
+        let get_user = |if_modified_since: Option<String>| {
+            let mut req = http::Request::builder()
+                .method("GET")
+                .uri(format!("/users/{USER_01_ID}"))
+                .header(
+                    http::header::AUTHORIZATION,
+                    format!("Bearer {USER_01_SESSION}"),
+                );
+            if let Some(val) = if_modified_since {
+                req = req.header(http::header::IF_MODIFIED_SINCE, val);
+            }
+            app.clone()
+                .oneshot(req.body(Default::default()).unwrap_or_log())
+        };
+
+        let resp = get_user(None).await.unwrap_or_log();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let last_modified = resp.headers()[http::header::LAST_MODIFIED]
+            .to_str()
+            .unwrap_or_log()
+            .to_string();
+        let at = crate::utils::parse_http_date(&last_modified).unwrap();
+
+        let resp = get_user(Some(last_modified.clone())).await.unwrap_or_log();
+        assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(resp.headers()[http::header::LAST_MODIFIED], last_modified);
+        let body = hyper::body::to_bytes(resp.into_body())
+            .await
+            .unwrap_or_log();
+        assert!(body.is_empty());
+
+        let earlier = crate::utils::format_http_date(at - time::Duration::seconds(1));
+        let resp = get_user(Some(earlier)).await.unwrap_or_log();
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn documents_error_codes() {
+        let spec = crate::openapi_json();
+        let schemas = &spec["components"]["schemas"];
+        let codes = schemas["GetUserErrorCode"]["enum"].as_array().unwrap();
+        assert!(codes.contains(&"user.not_found".into()), "{codes:?}");
+        assert!(codes.contains(&"auth.access_denied".into()), "{codes:?}");
+        for variant in schemas["GetUserError"]["oneOf"].as_array().unwrap() {
+            assert_eq!(
+                variant["properties"]["code"]["$ref"],
+                "#/components/schemas/GetUserErrorCode"
+            );
+        }
+    }
 }
@@ -0,0 +1,367 @@
+use deps::*;
+
+use crate::utils::*;
+use crate::*;
+
+use futures::StreamExt;
+use serde::Serialize;
+
+/// Creates users from an `application/x-ndjson` stream of [`super::create::Request`]s.
+/// Each record is processed as soon as it arrives and failures don't abort the
+/// rest of the ingest. Only for [`auth::Role::SuperAdmin`]s.
+#[derive(Debug, Clone)]
+pub struct IngestUsers;
+
+#[derive(Debug)]
+pub struct Request {
+    pub auth_token: std::sync::Arc<str>,
+    pub records: NdJson<super::create::Request>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, utoipa::ToSchema)]
+#[serde(crate = "serde", rename_all = "camelCase")]
+pub enum RecordStatus {
+    Created,
+    Failed,
+}
+
+#[derive(Debug, Serialize, utoipa::ToSchema)]
+#[serde(crate = "serde", rename_all = "camelCase")]
+pub struct RecordResult {
+    /// 1-based line number of the record in the request body.
+    pub line: usize,
+    pub status: RecordStatus,
+    /// Id of the created user.
+    pub id: Option<uuid::Uuid>,
+    /// The [`ErrorCode`] of what went wrong, the details are only logged.
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize, utoipa::ToSchema)]
+#[serde(crate = "serde", rename_all = "camelCase")]
+pub struct IngestReport {
+    pub created: usize,
+    pub failed: usize,
+    pub records: Vec<RecordResult>,
+}
+
+#[derive(Debug, Serialize, thiserror::Error, utoipa::ToSchema)]
+#[serde(crate = "serde", rename_all = "camelCase", tag = "error")]
+pub enum Error {
+    #[error("acess denied")]
+    AccessDenied,
+    #[error("forbidden")]
+    Forbidden,
+    #[error("error reading request body: {message:?}")]
+    BodyRead { message: String },
+    #[error("database overloaded")]
+    Overloaded,
+    #[error("internal server error: {message:?}")]
+    Internal { message: String },
+}
+
+crate::impl_from_auth_err!(Error);
+crate::impl_from_db_err!(Error);
+
+pub type Response = Ref<IngestReport>;
+
+/// What's reported of a record that wasn't created.
+const INVALID_RECORD: &str = "request.invalid_json";
+
+#[async_trait::async_trait]
+impl AuthenticatedEndpoint for IngestUsers {
+    type Request = Request;
+    type Response = Response;
+    type Error = Error;
+
+    fn authorize_request(&self, request: &Self::Request) -> auth::authorize::Request {
+        auth::authorize::Request {
+            auth_token: request.auth_token.clone(),
+            resource: auth::Resource::Users,
+            action: auth::Action::Write,
+        }
+    }
+
+    #[tracing::instrument(skip(ctx))]
+    async fn handle(
+        &self,
+        ctx: &crate::Context,
+        accessing_user: uuid::Uuid,
+        request: Self::Request,
+    ) -> Result<Self::Response, Self::Error> {
+        auth::require_role(ctx, accessing_user, auth::Role::SuperAdmin).await?;
+        let mut records = Box::pin(request.records.records());
+        let mut report = IngestReport {
+            created: 0,
+            failed: 0,
+            records: vec![],
+        };
+        while let Some(item) = records.next().await {
+            let (line, record) = item.map_err(|err| Error::BodyRead {
+                message: err.to_string(),
+            })?;
+            let result = match record {
+                Ok(record) => super::create::CreateUser
+                    .handle(ctx, record)
+                    .await
+                    .map(|Ref(user)| user.id)
+                    .map_err(|err| {
+                        tracing::warn!(line, ?err, "error ingesting user");
+                        err.code().to_string()
+                    }),
+                Err(err) => {
+                    tracing::debug!(line, ?err, "invalid record");
+                    Err(INVALID_RECORD.to_string())
+                }
+            };
+            report.records.push(match result {
+                Ok(id) => {
+                    report.created += 1;
+                    RecordResult {
+                        line,
+                        status: RecordStatus::Created,
+                        id: Some(id),
+                        error: None,
+                    }
+                }
+                Err(error) => {
+                    report.failed += 1;
+                    RecordResult {
+                        line,
+                        status: RecordStatus::Failed,
+                        id: None,
+                        error: Some(error),
+                    }
+                }
+            });
+        }
+        Ok(report.into())
+    }
+}
+
+impl From<&Error> for axum::http::StatusCode {
+    fn from(err: &Error) -> Self {
+        use Error::*;
+        match err {
+            AccessDenied => Self::UNAUTHORIZED,
+            Forbidden => Self::FORBIDDEN,
+            BodyRead { .. } => Self::BAD_REQUEST,
+            Overloaded => Self::SERVICE_UNAVAILABLE,
+            Internal { .. } => Self::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl crate::utils::ErrorCode for Error {
+    fn code(&self) -> &'static str {
+        use Error::*;
+        match self {
+            AccessDenied => "auth.access_denied",
+            Forbidden => "auth.forbidden",
+            BodyRead { .. } => "request.body_read",
+            Overloaded => "service.overloaded",
+            Internal { .. } => "service.internal",
+        }
+    }
+}
+
+impl HttpEndpoint for IngestUsers {
+    const METHOD: Method = Method::Post;
+    const PATH: &'static str = "/users/bulk";
+
+    type HttpRequest = (BearerToken, NdJson<super::create::Request>);
+
+    fn request(
+        (BearerToken(token), records): Self::HttpRequest,
+    ) -> Result<Self::Request, Self::Error> {
+        Ok(self::Request {
+            auth_token: token,
+            records,
+        })
+    }
+
+    fn response(Ref(resp): Self::Response) -> axum::response::Response {
+        Json(resp).into_response()
+    }
+}
+
+impl DocumentedEndpoint for IngestUsers {
+    const TAG: &'static Tag = &super::TAG;
+    const SUMMARY: &'static str = "Bulk create users from newline delimited JSON.";
+
+    fn success_examples() -> Vec<serde_json::Value> {
+        use crate::user::testing::*;
+        [IngestReport {
+            created: 1,
+            failed: 1,
+            records: vec![
+                RecordResult {
+                    line: 1,
+                    status: RecordStatus::Created,
+                    id: Some(USER_01_ID),
+                    error: None,
+                },
+                RecordResult {
+                    line: 2,
+                    status: RecordStatus::Failed,
+                    id: None,
+                    error: Some("user.username_occupied".into()),
+                },
+            ],
+        }]
+        .into_iter()
+        .map(serde_json::to_value)
+        .collect::<Result<_, _>>()
+        .unwrap()
+    }
+
+    fn errors() -> Vec<ErrorResponse<Self::Error>> {
+        vec![
+            ("Access denied", Error::AccessDenied),
+            ("Forbidden", Error::Forbidden),
+            (
+                "Error reading request body",
+                Error::BodyRead {
+                    message: "connection reset".to_string(),
+                },
+            ),
+            ("Overloaded", Error::Overloaded),
+            (
+                "Internal server error",
+                Error::Internal {
+                    message: "internal server error".to_string(),
+                },
+            ),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use deps::*;
+
+    use crate::user::testing::*;
+    use crate::utils::testing::*;
+
+    fn ingest(body: String) -> http::Request<axum::body::Body> {
+        http::Request::builder()
+            .method("POST")
+            .uri("/users/bulk")
+            .header(
+                http::header::CONTENT_TYPE,
+                crate::utils::NDJSON_CONTENT_TYPE,
+            )
+            .header(
+                http::header::AUTHORIZATION,
+                format!("Bearer {USER_01_SESSION}"),
+            )
+            .body(body.into())
+            .unwrap_or_log()
+    }
+
+    #[tokio::test]
+    async fn ingests_records_with_per_record_status() {
+        let ctx = TestContext::new(crate::function!()).await;
+        {
+            sqlx::query("INSERT INTO user_roles (user_id, role) VALUES ($1, 'superAdmin')")
+                .bind(USER_01_ID)
+                .execute(&ctx.ctx().db_pool)
+                .await
+                .unwrap_or_log();
+            let body = [
+                serde_json::json!({
+                    "username": "whish_box12",
+                    "email": "multis@cream.mux",
+                    "password": "lovebite",
+                })
+                .to_string(),
+                serde_json::json!({
+                    "username": USER_01_USERNAME,
+                    "email": "not.taken@cream.mux",
+                    "password": "lovebite",
+                })
+                .to_string(),
+                "{ not json".to_string(),
+                serde_json::json!({
+                    "username": "sherry_bert",
+                    "email": "tender@loving.care",
+                    "password": "lovebite",
+                })
+                .to_string(),
+            ]
+            .join("\n");
+            let app = crate::user::router().layer(axum::Extension(ctx.ctx()));
+            let resp = app.oneshot(ingest(body)).await.unwrap_or_log();
+            assert_eq!(resp.status(), StatusCode::OK);
+            let body = hyper::body::to_bytes(resp.into_body())
+                .await
+                .unwrap_or_log();
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap_or_log();
+            check_json(
+                (
+                    "expected",
+                    &serde_json::json!({
+                        "created": 2,
+                        "failed": 2,
+                        "records": [
+                            { "line": 1, "status": "created" },
+                            {
+                                "line": 2,
+                                "status": "failed",
+                                "id": null,
+                                "error": "user.username_occupied",
+                            },
+                            {
+                                "line": 3,
+                                "status": "failed",
+                                "id": null,
+                                "error": "request.invalid_json",
+                            },
+                            { "line": 4, "status": "created" },
+                        ],
+                    }),
+                ),
+                ("response", &body),
+            );
+
+            let ids = body["records"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .filter_map(|record| record["id"].as_str())
+                .map(|id| id.parse().unwrap())
+                .collect::<Vec<uuid::Uuid>>();
+            let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM users WHERE id = ANY($1)")
+                .bind(&ids)
+                .fetch_one(&ctx.ctx().db_pool)
+                .await
+                .unwrap_or_log();
+            assert_eq!(count, 2);
+        }
+        ctx.close().await;
+    }
+
+    #[tokio::test]
+    async fn only_super_admins_ingest() {
+        let ctx = TestContext::new(crate::function!()).await;
+        {
+            let body = serde_json::json!({
+                "username": "whish_box12",
+                "email": "multis@cream.mux",
+                "password": "lovebite",
+            })
+            .to_string();
+            let app = crate::user::router().layer(axum::Extension(ctx.ctx()));
+            let resp = app.oneshot(ingest(body)).await.unwrap_or_log();
+            assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+            let count: i64 =
+                sqlx::query_scalar("SELECT COUNT(*) FROM users WHERE username = 'whish_box12'")
+                    .fetch_one(&ctx.ctx().db_pool)
+                    .await
+                    .unwrap_or_log();
+            assert_eq!(count, 0);
+        }
+        ctx.close().await;
+    }
+}
@@ -1,7 +1,5 @@
 use deps::*;
 
-use axum::extract::*;
-
 use crate::utils::*;
 use crate::*;
 
@@ -39,18 +37,45 @@ crate::alias_and_ref!(ListRequest<UserSortingField>, ListUsersRequest, Request,
 pub enum Error {
     #[error("acess denied")]
     AccessDenied,
+    #[error("forbidden")]
+    Forbidden,
     #[error("invalid input: {issues:?}")]
     InvalidInput {
         #[from]
         issues: ValidationErrors,
     },
+    #[error("database overloaded")]
+    Overloaded,
     #[error("internal server error: {message:?}")]
     Internal { message: String },
 }
 
 crate::impl_from_auth_err!(Error);
+crate::impl_from_db_err!(Error);
+
+pub type ListUsersResponse = ListResponse<super::User>;
 
-crate::alias_and_ref!(ListResponse<super::User>, ListUsersResponse, Response, ser);
+#[derive(educe::Educe, serde::Serialize)]
+#[serde(crate = "serde")]
+#[educe(Deref)]
+pub struct Response(ListUsersResponse);
+
+impl From<ListUsersResponse> for Response {
+    fn from(inner: ListUsersResponse) -> Self {
+        Self(inner)
+    }
+}
+
+impl ToRefOrSchema for Response {
+    fn schema_name() -> &'static str {
+        "ListUsersResponse"
+    }
+
+    /// Either shape, see [`PageQuery::envelope`].
+    fn ref_or_schema() -> openapi::schema::RefOr<openapi::schema::Schema> {
+        list_schema(Self::schema_name(), "ListUsersEnvelope")
+    }
+}
 
 #[async_trait::async_trait]
 impl crate::AuthenticatedEndpoint for ListUsers {
@@ -73,7 +98,11 @@ impl crate::AuthenticatedEndpoint for ListUsers {
         _accessing_user: uuid::Uuid,
         Request(request): Self::Request,
     ) -> Result<Self::Response, Self::Error> {
+        let pool = ctx.pool()?;
         validator::Validate::validate(&request).map_err(utils::ValidationErrors::from)?;
+        let paging = request.after_cursor.is_some() || request.before_cursor.is_some();
+        // pages before a cursor are fetched nearest first and put back in order
+        let backwards = request.after_cursor.is_none() && request.before_cursor.is_some();
         let (cursor_clause, sorting_field, sorting_order, filter) = request
             .after_cursor
             .map(|cursor| (true, cursor))
@@ -140,26 +169,47 @@ impl crate::AuthenticatedEndpoint for ListUsers {
                     request.filter,
                 ))
             })?;
-        let (sorting_field_str, sorting_order_str) =
-            (sorting_field.sql_field_name(), sorting_order.sql_key_word());
+        let (sorting_field_str, sorting_order_str) = if backwards {
+            (
+                format!("{} desc", sorting_field.sql_field_name()),
+                sorting_order.reversed().sql_key_word(),
+            )
+        } else {
+            (sorting_field.sql_field_name(), sorting_order.sql_key_word())
+        };
         let limit = request.limit.unwrap_or(DEFAULT_LIST_LIMIT);
-        let results = sqlx::query(
-            format!(
-                r#"
+        let count_total = request.envelope && request.include_total;
+        // the window is evaluated before the cursor and the limit apply so it sees
+        // every match, which is also why it's only paid for when asked
+        let created_clause = TimeRange::sql_condition("created_at", 3, 4);
+        let (total_column, total_select) = if count_total {
+            (", COUNT(*) OVER () AS total", ",\n    total")
+        } else {
+            ("", "")
+        };
+        // the ordering and cursor clause are spliced in so this can't be checked by
+        // `sqlx::query!` and isn't part of `sqlx-data.json`
+        let results = ctx
+            .guard_db(
+                sqlx::query(
+                    format!(
+                        r#"
 SELECT 
     id,
     created_at,
     updated_at,
     email::TEXT as "email!",
     username::TEXT as "username!",
-    pic_url
+    pic_url{total_select}
 FROM (
-    SELECT *
+    SELECT *{total_column}
     FROM users
-    WHERE cast($1 as text) IS NULL OR (
-        username ILIKE '%%' || $1 || '%%'
-        OR email ILIKE '%%' || $1 || '%%'
-    )
+    WHERE (
+        cast($1 as text) IS NULL OR (
+            username ILIKE '%%' || $1 || '%%'
+            OR email ILIKE '%%' || $1 || '%%'
+        )
+    ) AND {created_clause}
     ORDER BY {sorting_field_str}, id {sorting_order_str}
     NULLS LAST
 ) as f
@@ -168,17 +218,35 @@ FROM (
 -- (counts are expensive or something)
 LIMIT $2 + 1 
         "#
+                    )
+                    .as_str(),
+                )
+                .bind(filter.as_ref())
+                .bind(limit as i64)
+                .bind(request.created.since)
+                .bind(request.created.until)
+                .fetch_all(&pool),
             )
-            .as_str(),
-        )
-        .bind(filter.as_ref())
-        .bind(limit as i64)
-        .fetch_all(&ctx.db_pool)
-        .await;
+            .await;
         match results {
             Ok(results) => {
                 let more_rows_pending = results.len() == limit + 1;
-                let items = results
+                let total = match results.first() {
+                    Some(row) if count_total => {
+                        use sqlx::Row;
+                        Some(
+                            row.try_get::<i64, _>("total")
+                                .map_err(|err| Error::Internal {
+                                    message: format!("row mapping err: {err}"),
+                                })? as u64,
+                        )
+                    }
+                    // nothing matched unless a cursor skipped the matches
+                    None if count_total && cursor_clause.is_empty() => Some(0),
+                    _ => None,
+                };
+                let envelope = request.envelope.then_some(Envelope { limit, total });
+                let mut items = results
                     .into_iter()
                     .take(limit as _)
                     .map(|row| {
@@ -196,41 +264,58 @@ LIMIT $2 + 1
                     .map_err(|err| Error::Internal {
                         message: format!("row mapping err: {err}"),
                     })?;
-                let cursor = if more_rows_pending {
-                    Some(
-                        Cursor {
-                            value: {
-                                let last = items.last().unwrap();
-                                match sorting_field {
-                                    UserSortingField::Username => serde_json::json!(last.username),
-                                    UserSortingField::Email => serde_json::json!(last.email),
-                                    UserSortingField::CreatedAt => {
-                                        serde_json::json!(last.created_at.unix_timestamp())
-                                    }
-                                    UserSortingField::UpdatedAt => {
-                                        serde_json::json!(last.updated_at.unix_timestamp())
-                                    }
-                                }
-                            },
-                            field: sorting_field,
-                            order: sorting_order,
-                            filter,
-                        }
-                        .to_encoded_str(),
-                    )
+                if backwards {
+                    items.reverse();
+                }
+                let cursor_at = |user: &User| {
+                    Cursor {
+                        value: match sorting_field {
+                            UserSortingField::Username => serde_json::json!(user.username),
+                            UserSortingField::Email => serde_json::json!(user.email),
+                            UserSortingField::CreatedAt => {
+                                serde_json::json!(user.created_at.unix_timestamp())
+                            }
+                            UserSortingField::UpdatedAt => {
+                                serde_json::json!(user.updated_at.unix_timestamp())
+                            }
+                        },
+                        field: sorting_field,
+                        order: sorting_order,
+                        filter: filter.clone(),
+                    }
+                    .to_encoded_str()
+                };
+                // going backwards, the rows pending are before the page and the
+                // page it was reached from is after it
+                let (has_next, has_prev) = if backwards {
+                    (true, more_rows_pending)
                 } else {
-                    None
+                    // the first page has nothing before it
+                    (more_rows_pending, paging)
                 };
-                Ok(ListUsersResponse { cursor, items }.into())
+                let cursor = items.last().filter(|_| has_next).map(&cursor_at);
+                let prev_cursor = items.first().filter(|_| has_prev).map(&cursor_at);
+                Ok(ListUsersResponse {
+                    cursor,
+                    items,
+                    prev_cursor,
+                    base_url: request.base_url,
+                    envelope,
+                }
+                .into())
             }
             Err(sqlx::Error::RowNotFound) => Ok(ListUsersResponse {
                 cursor: None,
                 items: vec![],
+                prev_cursor: None,
+                base_url: request.base_url,
+                envelope: request.envelope.then_some(Envelope {
+                    limit,
+                    total: count_total.then_some(0),
+                }),
             }
             .into()),
-            Err(err) => Err(Error::Internal {
-                message: format!("db err: {err}"),
-            }),
+            Err(err) => Err(err.into()),
         }
     }
 }
@@ -241,29 +326,63 @@ impl From<&Error> for axum::http::StatusCode {
         match err {
             InvalidInput { .. } => Self::BAD_REQUEST,
             AccessDenied => Self::UNAUTHORIZED,
+            Forbidden => Self::FORBIDDEN,
+            Overloaded => Self::SERVICE_UNAVAILABLE,
             Internal { .. } => Self::INTERNAL_SERVER_ERROR,
         }
     }
 }
 
+impl crate::utils::ErrorCode for Error {
+    fn code(&self) -> &'static str {
+        use Error::*;
+        match self {
+            InvalidInput { .. } => "request.invalid_input",
+            AccessDenied => "auth.access_denied",
+            Forbidden => "auth.forbidden",
+            Overloaded => "service.overloaded",
+            Internal { .. } => "service.internal",
+        }
+    }
+}
+
 impl HttpEndpoint for ListUsers {
     const METHOD: Method = Method::Get;
     const PATH: &'static str = "/users";
 
-    type HttpRequest = (BearerToken, Json<Request>);
+    type HttpRequest = (BearerToken, PageQuery, TimeRange, OptionalJson<Request>);
 
+    /// Cursors and the limit from the query, as found in the `Link` headers, take
+    /// precedence over the ones in the body.
     fn request(
-        (BearerToken(token), Json(Request(request))): Self::HttpRequest,
+        (BearerToken(token), page, created, OptionalJson(request)): Self::HttpRequest,
     ) -> Result<Self::Request, Self::Error> {
+        let request = request.map(|Request(request)| request).unwrap_or_default();
+        // the links can't carry the body so the limit moves to their query
+        let base_url = match (page.limit, request.limit) {
+            (None, Some(limit)) => with_query_param(&page.base_url, "limit", limit),
+            _ => page.base_url,
+        };
         Ok(ListUsersRequest {
             auth_token: Some(token),
+            limit: page.limit.or(request.limit),
+            after_cursor: page.after_cursor.or(request.after_cursor),
+            before_cursor: page.before_cursor.or(request.before_cursor),
+            base_url: Some(base_url),
+            envelope: page.envelope,
+            include_total: page.include_total,
+            created,
             ..request
         }
         .into())
     }
 
     fn response(Response(resp): Self::Response) -> axum::response::Response {
-        Json(resp).into_response()
+        resp.into_response()
+    }
+
+    fn sparse_fields() -> Option<SparseFields> {
+        Some(SparseFields::of::<User>("/items"))
     }
 }
 
@@ -292,6 +411,9 @@ impl DocumentedEndpoint for ListUsers {
                     pic_url: None,
                 },
             ],
+            prev_cursor: None,
+            base_url: None,
+            envelope: None,
         }]
         .into_iter()
         .map(serde_json::to_value)
@@ -302,6 +424,7 @@ impl DocumentedEndpoint for ListUsers {
     fn errors() -> Vec<ErrorResponse<Error>> {
         vec![
             ("Access denied", Error::AccessDenied),
+            ("Forbidden", Error::Forbidden),
             (
                 "Invalid input",
                 Error::InvalidInput {
@@ -321,6 +444,7 @@ impl DocumentedEndpoint for ListUsers {
                     },
                 },
             ),
+            ("Overloaded", Error::Overloaded),
             (
                 "Internal server error",
                 Error::Internal {
@@ -391,6 +515,10 @@ mod tests {
                 filter: None,
                 sorting_field: None,
                 sorting_order: None,
+                base_url: None,
+                envelope: false,
+                include_total: false,
+                created: Default::default(),
             },
             Some("__all__"),
         ),
@@ -487,4 +615,273 @@ mod tests {
             },
         },
     }
+
+    /// `(rel, url)` pairs of a `Link` header.
+    fn parse_links(header: &str) -> Vec<(String, String)> {
+        header
+            .split(", ")
+            .map(|link| {
+                let (url, rel) = link.split_once(">; rel=").unwrap();
+                (
+                    rel.trim_matches('"').to_string(),
+                    url.trim_start_matches('<').to_string(),
+                )
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn link_header_round_trips_to_next_page() {
+        let ctx = TestContext::new(crate::function!()).await;
+        {
+            let app = crate::user::router().layer(axum::Extension(ctx.ctx()));
+            let send = |uri: String, body: Option<serde_json::Value>| {
+                let app = app.clone();
+                async move {
+                    let mut req = http::Request::builder()
+                        .method("GET")
+                        .uri(uri)
+                        .header(http::header::HOST, "api.example.com")
+                        .header(
+                            http::header::AUTHORIZATION,
+                            format!("Bearer {USER_01_SESSION}"),
+                        );
+                    if body.is_some() {
+                        req = req.header(http::header::CONTENT_TYPE, "application/json");
+                    }
+                    let resp = app
+                        .oneshot(
+                            req.body(
+                                body.map(|body| serde_json::to_vec(&body).unwrap())
+                                    .unwrap_or_default()
+                                    .into(),
+                            )
+                            .unwrap_or_log(),
+                        )
+                        .await
+                        .unwrap_or_log();
+                    assert_eq!(resp.status(), StatusCode::OK);
+                    let links = resp
+                        .headers()
+                        .get(http::header::LINK)
+                        .map(|val| parse_links(val.to_str().unwrap()))
+                        .unwrap_or_default();
+                    let body = hyper::body::to_bytes(resp.into_body())
+                        .await
+                        .unwrap_or_log();
+                    let body: serde_json::Value = serde_json::from_slice(&body).unwrap_or_log();
+                    (links, body)
+                }
+            };
+
+            let (links, first) = send(
+                "/users".into(),
+                // users are created in the same second so page by something unique
+                Some(serde_json::json!({ "limit": 1, "sortingField": "username" })),
+            )
+            .await;
+            assert_eq!(links.len(), 1, "{links:?}");
+            let (rel, next_url) = &links[0];
+            assert_eq!(rel, "next");
+            let next_path = next_url
+                .strip_prefix("http://api.example.com")
+                .expect("link not absolute");
+            assert!(
+                next_path.starts_with("/users?limit=1&afterCursor="),
+                "{next_path}"
+            );
+
+            let (links, followed) = send(next_path.into(), None).await;
+            let (_, expected) = send(
+                "/users".into(),
+                Some(serde_json::json!({ "afterCursor": first["cursor"], "limit": 1 })),
+            )
+            .await;
+            assert_ne!(followed["items"][0]["id"], first["items"][0]["id"]);
+            assert_eq!(followed["items"], expected["items"]);
+
+            let link = |links: &[(String, String)], rel: &str| {
+                links
+                    .iter()
+                    .find(|(found, _)| found == rel)
+                    .map(|(_, url)| {
+                        url.strip_prefix("http://api.example.com")
+                            .expect("link not absolute")
+                            .to_string()
+                    })
+                    .unwrap_or_else(|| panic!("no {rel} link in {links:?}"))
+            };
+            let (links, third) = send(link(&links, "next"), None).await;
+            assert_ne!(third["items"][0]["id"], followed["items"][0]["id"]);
+            let (links, back) = send(link(&links, "prev"), None).await;
+            assert_eq!(back["items"], followed["items"]);
+            let (links, back) = send(link(&links, "prev"), None).await;
+            assert_eq!(back["items"], first["items"]);
+            assert!(links.iter().all(|(rel, _)| rel != "prev"), "{links:?}");
+            let (_, forth) = send(link(&links, "next"), None).await;
+            assert_eq!(forth["items"], followed["items"]);
+
+            let (links, _) = send(
+                "/users?envelope=true&includeTotal=true&limit=1".into(),
+                Some(serde_json::json!({ "sortingField": "username" })),
+            )
+            .await;
+            let (_, next_url) = &links[0];
+            assert!(
+                next_url.starts_with(
+                    "http://api.example.com/users?envelope=true&includeTotal=true&limit=1&afterCursor="
+                ),
+                "{next_url}"
+            );
+        }
+        ctx.close().await;
+    }
+
+    #[tokio::test]
+    async fn envelope_carries_page_meta() {
+        let ctx = TestContext::new(crate::function!()).await;
+        {
+            let app = crate::user::router().layer(axum::Extension(ctx.ctx()));
+            let send = |query: &'static str| {
+                let app = app.clone();
+                async move {
+                    let resp = app
+                        .oneshot(
+                            http::Request::builder()
+                                .method("GET")
+                                .uri(format!("/users?{query}"))
+                                .header(
+                                    http::header::AUTHORIZATION,
+                                    format!("Bearer {USER_01_SESSION}"),
+                                )
+                                .header(axum::http::header::CONTENT_TYPE, "application/json")
+                                .body(
+                                    serde_json::to_vec(&serde_json::json!({ "limit": 1 }))
+                                        .unwrap()
+                                        .into(),
+                                )
+                                .unwrap_or_log(),
+                        )
+                        .await
+                        .unwrap_or_log();
+                    assert_eq!(resp.status(), StatusCode::OK);
+                    let body = hyper::body::to_bytes(resp.into_body())
+                        .await
+                        .unwrap_or_log();
+                    serde_json::from_slice::<serde_json::Value>(&body).unwrap_or_log()
+                }
+            };
+
+            let body = send("envelope=true&includeTotal=true").await;
+            assert_eq!(body["data"].as_array().unwrap().len(), 1, "{body}");
+            assert!(body.get("items").is_none(), "{body}");
+            assert_eq!(body["meta"]["limit"], 1, "{body}");
+            assert!(body["meta"]["nextCursor"].is_string(), "{body}");
+            assert!(body["meta"]["total"].as_u64().unwrap() >= 4, "{body}");
+
+            let body = send("envelope=true").await;
+            assert!(body["meta"].get("total").is_none(), "{body}");
+            assert!(body["meta"]["nextCursor"].is_string(), "{body}");
+
+            // totals are only part of the envelope
+            let body = send("includeTotal=true").await;
+            assert_eq!(body["items"].as_array().unwrap().len(), 1, "{body}");
+            assert!(body.get("meta").is_none(), "{body}");
+        }
+        ctx.close().await;
+    }
+
+    #[tokio::test]
+    async fn lists_users_seeded_from_fixtures() {
+        let ctx = TestContext::with_fixtures(
+            crate::function!(),
+            &[Fixture::Json {
+                table: "users",
+                rows: serde_json::json!([
+                    { "username": "jughead", "email": "burger@pops.chock" },
+                    { "username": "ethel", "email": "muggs@pops.chock" },
+                ]),
+            }],
+        )
+        .await;
+        {
+            let app = crate::user::router().layer(axum::Extension(ctx.ctx()));
+            let resp = app
+                .oneshot(
+                    http::Request::builder()
+                        .method("GET")
+                        .uri("/users")
+                        .header(
+                            http::header::AUTHORIZATION,
+                            format!("Bearer {USER_01_SESSION}"),
+                        )
+                        .header(axum::http::header::CONTENT_TYPE, "application/json")
+                        .body(
+                            serde_json::to_vec(&serde_json::json!({ "limit": 25 }))
+                                .unwrap()
+                                .into(),
+                        )
+                        .unwrap_or_log(),
+                )
+                .await
+                .unwrap_or_log();
+            assert_eq!(resp.status(), StatusCode::OK);
+            let body = hyper::body::to_bytes(resp.into_body())
+                .await
+                .unwrap_or_log();
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap_or_log();
+            let usernames = body["items"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|user| user["username"].as_str().unwrap())
+                .collect::<Vec<_>>();
+            assert!(usernames.contains(&"jughead"), "{body:?}");
+            assert!(usernames.contains(&"ethel"), "{body:?}");
+        }
+        ctx.close().await;
+    }
+
+    #[tokio::test]
+    async fn filters_by_created_range() {
+        let ctx = TestContext::new(crate::function!()).await;
+        {
+            let app = crate::user::router().layer(axum::Extension(ctx.ctx()));
+            for (query, any) in [
+                ("?since=2000-01-01T00:00:00Z", true),
+                ("?until=2000-01-01T00:00:00Z", false),
+                (
+                    "?since=2000-01-01T00:00:00Z&until=2100-01-01T00:00:00Z",
+                    true,
+                ),
+            ] {
+                let resp = app
+                    .clone()
+                    .oneshot(
+                        http::Request::builder()
+                            .method("GET")
+                            .uri(format!("/users{query}"))
+                            .header(
+                                http::header::AUTHORIZATION,
+                                format!("Bearer {USER_01_SESSION}"),
+                            )
+                            .body(Default::default())
+                            .unwrap_or_log(),
+                    )
+                    .await
+                    .unwrap_or_log();
+                assert_eq!(resp.status(), StatusCode::OK, "{query}");
+                let body = hyper::body::to_bytes(resp.into_body())
+                    .await
+                    .unwrap_or_log();
+                let body: serde_json::Value = serde_json::from_slice(&body).unwrap_or_log();
+                assert_eq!(
+                    !body["items"].as_array().unwrap().is_empty(),
+                    any,
+                    "{query}: {body:?}"
+                );
+            }
+        }
+        ctx.close().await;
+    }
 }
@@ -41,6 +41,8 @@ pub enum Error {
     NotFound { id: uuid::Uuid },
     #[error("acess denied")]
     AccessDenied,
+    #[error("forbidden")]
+    Forbidden,
     #[error("username occupied: {username:?}")]
     UsernameOccupied { username: String },
     #[error("email occupied: {email:?}")]
@@ -50,11 +52,14 @@ pub enum Error {
         #[from]
         issues: ValidationErrors,
     },
+    #[error("database overloaded")]
+    Overloaded,
     #[error("internal server error: {message:?}")]
     Internal { message: String },
 }
 
 crate::impl_from_auth_err!(Error);
+crate::impl_from_db_err!(Error);
 
 pub type Response = Ref<super::User>;
 
@@ -81,6 +86,7 @@ impl AuthenticatedEndpoint for UpdateUser {
         accessing_user: uuid::Uuid,
         request: Self::Request,
     ) -> Result<Self::Response, Self::Error> {
+        let pool = ctx.pool()?;
         validator::Validate::validate(&request).map_err(utils::ValidationErrors::from)?;
         if request.is_empty() {
             return AuthenticatedEndpoint::handle(
@@ -96,6 +102,8 @@ impl AuthenticatedEndpoint for UpdateUser {
             .map_err(|err| match err {
                 user::get::Error::NotFound { id } => Error::NotFound { id },
                 user::get::Error::AccessDenied => Error::AccessDenied,
+                user::get::Error::Forbidden => Error::Forbidden,
+                user::get::Error::Overloaded => Error::Overloaded,
                 user::get::Error::Internal { message } => Error::Internal { message },
             });
         }
@@ -108,9 +116,11 @@ impl AuthenticatedEndpoint for UpdateUser {
             .unwrap_or_log()
         });
         let null_str = "NULL".into();
-        let user = sqlx::query_as!(
-            super::User,
-            r#"
+        let user = ctx
+            .guard_db(
+                sqlx::query_as!(
+                    super::User,
+                    r#"
 SELECT
     id as "id!",
     created_at as "created_at!",
@@ -126,35 +136,34 @@ FROM update_user(
     $5
 )
                 "#,
-            &request.user_id.unwrap(),
-            &request.username.as_ref().unwrap_or(&null_str),
-            &request.email.as_ref().unwrap_or(&null_str),
-            &request.pic_url.as_ref().unwrap_or(&null_str),
-            &pass_hash.as_ref().unwrap_or(&null_str)
-        )
-        .fetch_one(&ctx.db_pool)
-        .await
-        .map_err(|err| match &err {
-            sqlx::Error::RowNotFound => Error::NotFound {
-                id: request.user_id.unwrap(),
-            },
-            sqlx::Error::Database(boxed) if boxed.constraint().is_some() => {
-                match boxed.constraint().unwrap() {
-                    "unique_users_username" => Error::UsernameOccupied {
-                        username: request.username.unwrap(),
-                    },
-                    "unique_users_email" => Error::EmailOccupied {
-                        email: request.email.unwrap(),
-                    },
-                    _ => Error::Internal {
-                        message: format!("db error: {err}"),
-                    },
+                    &request.user_id.unwrap(),
+                    &request.username.as_ref().unwrap_or(&null_str),
+                    &request.email.as_ref().unwrap_or(&null_str),
+                    &request.pic_url.as_ref().unwrap_or(&null_str),
+                    &pass_hash.as_ref().unwrap_or(&null_str)
+                )
+                .fetch_one(&pool),
+            )
+            .await
+            .map_err(|err| match &err {
+                sqlx::Error::RowNotFound => Error::NotFound {
+                    id: request.user_id.unwrap(),
+                },
+                sqlx::Error::Database(boxed) if boxed.constraint().is_some() => {
+                    match boxed.constraint().unwrap() {
+                        "unique_users_username" => Error::UsernameOccupied {
+                            username: request.username.unwrap(),
+                        },
+                        "unique_users_email" => Error::EmailOccupied {
+                            email: request.email.unwrap(),
+                        },
+                        _ => Error::Internal {
+                            message: format!("db error: {err}"),
+                        },
+                    }
                 }
-            }
-            _ => Error::Internal {
-                message: format!("db error: {err}"),
-            },
-        })?;
+                _ => err.into(),
+            })?;
         // TODO: email notification, account activation
         Ok(user.into())
     }
@@ -166,33 +175,60 @@ impl From<&Error> for axum::http::StatusCode {
         match err {
             NotFound { .. } => Self::NOT_FOUND,
             AccessDenied => Self::UNAUTHORIZED,
+            Forbidden => Self::FORBIDDEN,
             UsernameOccupied { .. } | EmailOccupied { .. } | InvalidInput { .. } => {
                 Self::BAD_REQUEST
             }
+            Overloaded => Self::SERVICE_UNAVAILABLE,
             Internal { .. } => Self::INTERNAL_SERVER_ERROR,
         }
     }
 }
 
+impl crate::utils::ErrorCode for Error {
+    fn code(&self) -> &'static str {
+        use Error::*;
+        match self {
+            NotFound { .. } => "user.not_found",
+            AccessDenied => "auth.access_denied",
+            Forbidden => "auth.forbidden",
+            UsernameOccupied { .. } => "user.username_occupied",
+            EmailOccupied { .. } => "user.email_occupied",
+            InvalidInput { .. } => "request.invalid_input",
+            Overloaded => "service.overloaded",
+            Internal { .. } => "service.internal",
+        }
+    }
+}
+
 impl HttpEndpoint for UpdateUser {
     const METHOD: Method = Method::Patch;
     const PATH: &'static str = "/users/:id";
+    const AUDIT_ACTION: Option<&'static str> = Some("user.update");
 
-    type HttpRequest = (BearerToken, Path<uuid::Uuid>, Json<Request>);
+    type HttpRequest = ApiRequest<Request, uuid::Uuid>;
 
     fn request(
-        (BearerToken(token), Path(user_id), Json(req)): Self::HttpRequest,
+        ApiRequest {
+            body,
+            path: user_id,
+            auth: BearerToken(token),
+        }: Self::HttpRequest,
     ) -> Result<Self::Request, Self::Error> {
         Ok(Request {
             auth_token: Some(token),
             user_id: Some(user_id),
-            ..req
+            ..body
         })
     }
 
     fn response(Ref(resp): Self::Response) -> axum::response::Response {
         Json(resp).into_response()
     }
+
+    fn audit_resource_id(Ref(user): &Self::Response) -> Option<String> {
+        Some(user.id.to_string())
+    }
 }
 
 impl DocumentedEndpoint for UpdateUser {
@@ -218,6 +254,7 @@ impl DocumentedEndpoint for UpdateUser {
         use crate::user::testing::*;
         vec![
             ("Access denied", Error::AccessDenied),
+            ("Forbidden", Error::Forbidden),
             (
                 "Not found",
                 Error::NotFound {
@@ -258,6 +295,7 @@ impl DocumentedEndpoint for UpdateUser {
                     },
                 },
             ),
+            ("Overloaded", Error::Overloaded),
             (
                 "Internal server error",
                 Error::Internal {
@@ -404,9 +442,15 @@ mod tests {
                     let req_body_json = fixture_request_json();
                     let resp_body_json = response_json.unwrap();
                     tracing::info!(?resp_body_json);
+                    let parse_time = |json: &serde_json::Value| {
+                        time::OffsetDateTime::parse(
+                            json.as_str().unwrap(),
+                            &time::format_description::well_known::Rfc3339,
+                        ).unwrap()
+                    };
                     assert!(
-                        resp_body_json["updatedAt"].as_i64().unwrap() >
-                        resp_body_json["createdAt"].as_i64().unwrap()
+                        parse_time(&resp_body_json["updatedAt"]) >
+                        parse_time(&resp_body_json["createdAt"])
                     );
                     let app = crate::user::router().layer(axum::Extension(ctx.ctx()));
                     let resp = app
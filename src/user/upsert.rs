@@ -0,0 +1,428 @@
+use deps::*;
+
+use crate::utils::*;
+use crate::*;
+
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+/// `PUT /users/:id`, creating the user at the id if there's none and replacing
+/// it otherwise.
+#[derive(Debug, Clone)]
+pub struct UpsertUser;
+
+/// The whole user, fields left out are cleared on replace.
+#[derive(Debug, Deserialize, Validate, utoipa::ToSchema)]
+#[serde(crate = "serde", rename_all = "camelCase")]
+pub struct Request {
+    #[serde(skip)]
+    auth_token: std::sync::Arc<str>,
+    #[serde(skip)]
+    user_id: uuid::Uuid,
+    #[validate(length(min = 5, max = 25), regex(path = "crate::user::USERNAME_REGEX"))]
+    pub username: String,
+    #[validate(email)]
+    pub email: String,
+    #[validate(url)]
+    pub pic_url: Option<String>,
+    #[validate(length(min = 8))]
+    pub password: String,
+}
+
+#[derive(Debug, Serialize, thiserror::Error, utoipa::ToSchema)]
+#[serde(crate = "serde", rename_all = "camelCase", tag = "error")]
+pub enum Error {
+    #[error("acess denied")]
+    AccessDenied,
+    #[error("forbidden")]
+    Forbidden,
+    #[error("username occupied: {username:?}")]
+    UsernameOccupied { username: String },
+    #[error("email occupied: {email:?}")]
+    EmailOccupied { email: String },
+    #[error("invalid input: {issues:?}")]
+    InvalidInput {
+        #[from]
+        issues: ValidationErrors,
+    },
+    #[error("database overloaded")]
+    Overloaded,
+    #[error("internal server error: {message:?}")]
+    Internal { message: String },
+}
+
+crate::impl_from_auth_err!(Error);
+crate::impl_from_db_err!(Error);
+
+#[derive(Debug)]
+pub struct Response {
+    pub user: super::User,
+    /// As opposed to replaced.
+    pub created: bool,
+}
+
+/// A row of `upsert_user`.
+struct Upserted {
+    id: uuid::Uuid,
+    created_at: time::OffsetDateTime,
+    updated_at: time::OffsetDateTime,
+    email: String,
+    username: String,
+    pic_url: Option<String>,
+    created: bool,
+}
+
+impl ToRefOrSchema for Response {
+    fn schema_name() -> &'static str {
+        type_name_raw::<super::User>()
+    }
+
+    fn ref_or_schema() -> openapi::schema::RefOr<openapi::schema::Schema> {
+        openapi::Ref::from_schema_name(type_name_raw::<super::User>()).into()
+    }
+}
+
+#[async_trait::async_trait]
+impl AuthenticatedEndpoint for UpsertUser {
+    type Request = Request;
+    type Response = Response;
+    type Error = Error;
+
+    fn authorize_request(&self, request: &Self::Request) -> crate::auth::authorize::Request {
+        crate::auth::authorize::Request {
+            auth_token: request.auth_token.clone(),
+            resource: crate::auth::Resource::User {
+                id: request.user_id,
+            },
+            action: crate::auth::Action::Write,
+        }
+    }
+
+    #[tracing::instrument(skip(ctx))]
+    async fn handle(
+        &self,
+        ctx: &crate::Context,
+        _accessing_user: uuid::Uuid,
+        request: Self::Request,
+    ) -> Result<Self::Response, Self::Error> {
+        validator::Validate::validate(&request).map_err(utils::ValidationErrors::from)?;
+        let pass_hash = argon2::hash_encoded(
+            request.password.as_bytes(),
+            &ctx.config.pass_salt_hash,
+            &ctx.config.argon2_conf,
+        )
+        .unwrap_or_log();
+        let mut tx = ctx.begin().await?;
+        let upserted = ctx
+            .guard_db(async {
+                let upserted = sqlx::query_as!(
+                    Upserted,
+                    r#"
+SELECT
+    (le_user).id as "id!",
+    (le_user).created_at as "created_at!",
+    (le_user).updated_at as "updated_at!",
+    (le_user).email::TEXT as "email!",
+    (le_user).username::TEXT as "username!",
+    (le_user).pic_url,
+    created as "created!"
+FROM upsert_user($1, $2::TEXT::CITEXT, $3::TEXT::CITEXT, $4, $5)
+                "#,
+                    &request.user_id,
+                    &request.username,
+                    &request.email,
+                    request.pic_url.as_ref(),
+                    &pass_hash
+                )
+                .fetch_one(&mut *tx)
+                .await?;
+                tx.commit().await?;
+                Ok::<_, sqlx::Error>(upserted)
+            })
+            .await
+            .map_err(|err| match &err {
+                sqlx::Error::Database(boxed) => match boxed.constraint() {
+                    Some("unique_users_username") => Error::UsernameOccupied {
+                        username: request.username,
+                    },
+                    Some("unique_users_email") => Error::EmailOccupied {
+                        email: request.email,
+                    },
+                    Some(_) => Error::Internal {
+                        message: format!("db error: {err}"),
+                    },
+                    None => err.into(),
+                },
+                _ => err.into(),
+            })?;
+        Ok(Response {
+            user: super::User {
+                id: upserted.id,
+                created_at: upserted.created_at,
+                updated_at: upserted.updated_at,
+                email: upserted.email,
+                username: upserted.username,
+                pic_url: upserted.pic_url,
+            },
+            created: upserted.created,
+        })
+    }
+}
+
+impl From<&Error> for axum::http::StatusCode {
+    fn from(err: &Error) -> Self {
+        use Error::*;
+        match err {
+            AccessDenied => Self::UNAUTHORIZED,
+            Forbidden => Self::FORBIDDEN,
+            UsernameOccupied { .. } | EmailOccupied { .. } | InvalidInput { .. } => {
+                Self::BAD_REQUEST
+            }
+            Overloaded => Self::SERVICE_UNAVAILABLE,
+            Internal { .. } => Self::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl crate::utils::ErrorCode for Error {
+    fn code(&self) -> &'static str {
+        use Error::*;
+        match self {
+            AccessDenied => "auth.access_denied",
+            Forbidden => "auth.forbidden",
+            UsernameOccupied { .. } => "user.username_occupied",
+            EmailOccupied { .. } => "user.email_occupied",
+            InvalidInput { .. } => "request.invalid_input",
+            Overloaded => "service.overloaded",
+            Internal { .. } => "service.internal",
+        }
+    }
+}
+
+impl HttpEndpoint for UpsertUser {
+    const METHOD: Method = Method::Put;
+    const PATH: &'static str = "/users/:id";
+    const AUDIT_ACTION: Option<&'static str> = Some("user.upsert");
+    const MAY_CREATE: bool = true;
+
+    type HttpRequest = ApiRequest<Request, uuid::Uuid>;
+
+    fn request(
+        ApiRequest {
+            body,
+            path: user_id,
+            auth: BearerToken(token),
+        }: Self::HttpRequest,
+    ) -> Result<Self::Request, Self::Error> {
+        Ok(Request {
+            auth_token: token,
+            user_id,
+            ..body
+        })
+    }
+
+    fn response(resp: Self::Response) -> axum::response::Response {
+        Json(resp.user).into_response()
+    }
+
+    fn audit_resource_id(resp: &Self::Response) -> Option<String> {
+        Some(resp.user.id.to_string())
+    }
+
+    fn created(resp: &Self::Response) -> Option<String> {
+        resp.created
+            .then(|| UrlBuilder::path(Self::PATH, &[("id", &resp.user.id.to_string())]))
+    }
+}
+
+impl DocumentedEndpoint for UpsertUser {
+    const TAG: &'static Tag = &super::TAG;
+
+    fn success_examples() -> Vec<serde_json::Value> {
+        use crate::user::testing::*;
+        [super::User {
+            id: Default::default(),
+            created_at: time::OffsetDateTime::now_utc(),
+            updated_at: time::OffsetDateTime::now_utc(),
+            email: USER_01_EMAIL.into(),
+            username: USER_01_USERNAME.into(),
+            pic_url: Some("https:://example.com/picture.jpg".into()),
+        }]
+        .into_iter()
+        .map(serde_json::to_value)
+        .collect::<Result<_, _>>()
+        .unwrap()
+    }
+
+    fn errors() -> Vec<ErrorResponse<Self::Error>> {
+        use crate::user::testing::*;
+        vec![
+            ("Access denied", Error::AccessDenied),
+            ("Forbidden", Error::Forbidden),
+            (
+                "Username occupied",
+                Error::UsernameOccupied {
+                    username: USER_01_USERNAME.into(),
+                },
+            ),
+            (
+                "Email occupied",
+                Error::EmailOccupied {
+                    email: USER_01_EMAIL.into(),
+                },
+            ),
+            (
+                "Invalid input",
+                Error::InvalidInput {
+                    issues: {
+                        let mut issues = validator::ValidationErrors::new();
+                        issues.add(
+                            "email",
+                            validator::ValidationError {
+                                code: std::borrow::Cow::from("email"),
+                                message: None,
+                                params: [(
+                                    std::borrow::Cow::from("value"),
+                                    serde_json::json!("bad.email.com"),
+                                )]
+                                .into_iter()
+                                .collect(),
+                            },
+                        );
+                        issues.into()
+                    },
+                },
+            ),
+            ("Overloaded", Error::Overloaded),
+            (
+                "Internal server error",
+                Error::Internal {
+                    message: "internal server error".to_string(),
+                },
+            ),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use deps::*;
+
+    use crate::user::testing::*;
+    use crate::utils::testing::*;
+
+    const NEW_ID: uuid::Uuid = uuid::uuid!("0b5b0a8e-8c8f-4f53-9a37-2a5a4c0f3e11");
+
+    fn fixture_request_json() -> serde_json::Value {
+        serde_json::json!({
+            "username": "whish_box",
+            "email": "multis@cream.mux",
+            "password": "lovebite",
+            "picUrl": "http://i.will.neve.eva/eva.leave.im.80ies.soul.babe",
+        })
+    }
+
+    macro_rules! upsert_user_integ {
+        ($(
+            $name:ident: {
+                uri: $uri:expr,
+                auth_token: $auth_token:expr,
+                status: $status:expr,
+                body: $json_body:expr,
+                $(check_json: $check_json:expr,)?
+                $(extra_assertions: $extra_fn:expr,)?
+            },
+        )*) => {
+            mod integ {
+                use super::*;
+                crate::integration_table_tests! {
+                    $(
+                        $name: {
+                            uri: $uri,
+                            method: "PUT",
+                            status: $status,
+                            router: crate::user::router(),
+                            body: $json_body,
+                            $(check_json: $check_json,)?
+                            auth_token: $auth_token,
+                            $(extra_assertions: $extra_fn,)?
+                        },
+                    )*
+                }
+            }
+        };
+    }
+
+    upsert_user_integ! {
+        creates_if_absent: {
+            uri: format!("/users/{NEW_ID}"),
+            auth_token: USER_01_SESSION.into(),
+            status: http::StatusCode::CREATED,
+            body: fixture_request_json(),
+            check_json: fixture_request_json()
+                .remove_keys_from_obj(&["password"])
+                .destructure_into_self(serde_json::json!({ "id": NEW_ID })),
+            extra_assertions: &|EAArgs { response_head, .. }| {
+                Box::pin(async move {
+                    assert_eq!(
+                        response_head.headers[http::header::LOCATION],
+                        format!("/users/{NEW_ID}")
+                    );
+                })
+            },
+        },
+        replaces_if_present: {
+            uri: format!("/users/{USER_01_ID}"),
+            auth_token: USER_01_SESSION.into(),
+            status: http::StatusCode::OK,
+            body: fixture_request_json(),
+            check_json: fixture_request_json()
+                .remove_keys_from_obj(&["password"])
+                .destructure_into_self(serde_json::json!({ "id": USER_01_ID })),
+            extra_assertions: &|EAArgs { response_head, .. }| {
+                Box::pin(async move {
+                    assert!(response_head.headers.get(http::header::LOCATION).is_none());
+                })
+            },
+        },
+        replace_overwrites_every_field: {
+            uri: format!("/users/{USER_01_ID}"),
+            auth_token: USER_01_SESSION.into(),
+            status: http::StatusCode::OK,
+            // no `picUrl`
+            body: fixture_request_json().remove_keys_from_obj(&["picUrl"]),
+            extra_assertions: &|EAArgs { ctx, response_json, .. }| {
+                Box::pin(async move {
+                    let resp_body_json = response_json.unwrap();
+                    assert_eq!(resp_body_json["picUrl"], serde_json::Value::Null);
+                    let user = ctx.ctx().db.user(USER_01_ID).await.unwrap_or_log();
+                    assert_eq!(user.username, "whish_box");
+                    assert_eq!(user.email, "multis@cream.mux");
+                    assert_eq!(user.pic_url, None);
+                    // the password was replaced too
+                    let token = crate::Endpoint::handle(
+                            &crate::auth::authenticate::Authenticate,
+                            &ctx.ctx(),
+                            crate::auth::authenticate::Request {
+                                identifier: "whish_box".into(),
+                                password: "lovebite".into(),
+                            },
+                        )
+                        .await;
+                    assert!(token.is_ok(), "{token:?}");
+                })
+            },
+        },
+        fails_if_username_occupied: {
+            uri: format!("/users/{NEW_ID}"),
+            auth_token: USER_01_SESSION.into(),
+            status: http::StatusCode::BAD_REQUEST,
+            body: fixture_request_json().destructure_into_self(
+                serde_json::json!({ "username": USER_02_USERNAME })
+            ),
+            check_json: serde_json::json!({
+                "error": "usernameOccupied"
+            }),
+        },
+    }
+}
@@ -1,14 +1,161 @@
+pub use api_request::*;
+mod api_request;
+
+pub use body_limit::*;
+mod body_limit;
+
+pub use byte_range::*;
+mod byte_range;
+
+pub use cache::*;
+mod cache;
+
+pub use circuit_breaker::*;
+mod circuit_breaker;
+
+pub use client_ip::*;
+mod client_ip;
+
+pub use clock::*;
+mod clock;
+
+pub use content_disposition::*;
+mod content_disposition;
+
+pub use cors::*;
+mod cors;
+
+pub use deadline::*;
+mod deadline;
+
+pub use decimal::*;
+mod decimal;
+
+pub mod dry_run;
+
+pub use enum_param::*;
+mod enum_param;
+
+pub use error_code::*;
+mod error_code;
+
+pub use header_param::*;
+mod header_param;
+
+pub use internal_error::*;
+mod internal_error;
+
+pub use json_error::*;
+mod json_error;
+
+pub use json_limits::*;
+mod json_limits;
+
+pub use last_modified::*;
+mod last_modified;
+
+pub use links::*;
+mod links;
+
 pub use list_request::*;
 mod list_request;
 
+pub use validated_query::*;
+mod validated_query;
+
 pub use validation_errs::*;
 mod validation_errs;
 
+pub use merge_patch::*;
+mod merge_patch;
+
+pub use one_or_many::*;
+mod one_or_many;
+
+pub use optional_json::*;
+mod optional_json;
+
+pub use pretty_json::*;
+mod pretty_json;
+
+pub use multipart_mixed::*;
+mod multipart_mixed;
+
+pub use ndjson::*;
+mod ndjson;
+
+pub use response_cache::*;
+mod response_cache;
+
+pub use response_transformer::*;
+mod response_transformer;
+
+pub use query_param::*;
+mod query_param;
+
+pub use rate_limit::*;
+mod rate_limit;
+
+pub use request_context::*;
+mod request_context;
+
+pub use retry_after::*;
+mod retry_after;
+
+pub use rewrite_body::*;
+mod rewrite_body;
+
+pub use sensitive::*;
+mod sensitive;
+
+pub use singleflight::*;
+mod singleflight;
+
+pub use sparse_fields::*;
+mod sparse_fields;
+
+pub use time_range::*;
+mod time_range;
+
+pub use url_builder::*;
+mod url_builder;
+
+pub use uuid_path::*;
+mod uuid_path;
+
+pub use vary::*;
+mod vary;
+
+pub mod asyncapi;
+
+pub mod audit;
+
+pub mod compression;
+
+pub mod feature_flags;
+
+pub mod named_examples;
+
+pub mod recording;
+
+pub mod rfc3339;
+
+pub mod security_headers;
+
+pub mod slow_requests;
+
+pub mod spec_lint;
+
+pub mod vendor_extensions;
+
+#[cfg(debug_assertions)]
+pub mod schema_check;
+
 #[cfg(test)]
 pub mod testing;
 
 /// This baby doesn't work on generic types
-pub fn type_name_raw<T>() -> &'static str {
+pub fn type_name_raw<T: ?Sized>() -> &'static str {
     let name = std::any::type_name::<T>();
     match &name.rfind(':') {
         Some(pos) => &name[pos + 1..name.len()],
@@ -0,0 +1,217 @@
+use deps::*;
+
+use axum::{
+    extract::{FromRequest, Json, Path, RequestParts},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+
+use crate::{BearerToken, DocumentedParameter, ParameterDoc};
+
+/// Extracts the auth, path parameters and json body of the typical authenticated
+/// CRUD request in one go. Extraction happens in that order and the first failure
+/// is reported as an [`ApiRequestRejection`].
+#[derive(Debug)]
+pub struct ApiRequest<B, P, A = BearerToken> {
+    pub body: B,
+    pub path: P,
+    pub auth: A,
+}
+
+#[derive(Debug, Serialize, thiserror::Error, utoipa::ToSchema)]
+#[serde(crate = "serde", rename_all = "camelCase", tag = "error")]
+pub enum ApiRequestRejection {
+    #[error("unauthorized: {message:?}")]
+    Unauthorized { message: String },
+    #[error("invalid path: {message:?}")]
+    InvalidPath { message: String },
+    #[error("unsupported media type: {message:?}")]
+    UnsupportedMediaType { message: String },
+    #[error("invalid body: {message:?}")]
+    InvalidBody { message: String },
+}
+
+impl From<&ApiRequestRejection> for StatusCode {
+    fn from(err: &ApiRequestRejection) -> Self {
+        use ApiRequestRejection::*;
+        match err {
+            Unauthorized { .. } => Self::UNAUTHORIZED,
+            InvalidPath { .. } | InvalidBody { .. } => Self::BAD_REQUEST,
+            UnsupportedMediaType { .. } => Self::UNSUPPORTED_MEDIA_TYPE,
+        }
+    }
+}
+
+impl IntoResponse for ApiRequestRejection {
+    fn into_response(self) -> Response {
+        (Into::<StatusCode>::into(&self), Json(self)).into_response()
+    }
+}
+
+#[async_trait::async_trait]
+impl<B, P, A> FromRequest<axum::body::Body> for ApiRequest<B, P, A>
+where
+    B: serde::de::DeserializeOwned + Send,
+    P: serde::de::DeserializeOwned + Send,
+    A: FromRequest<axum::body::Body> + Send,
+    A::Rejection: IntoResponse,
+{
+    type Rejection = ApiRequestRejection;
+
+    async fn from_request(
+        req: &mut RequestParts<axum::body::Body>,
+    ) -> Result<Self, Self::Rejection> {
+        // turn the rejection into a response first since it needn't be `Send`
+        let auth = A::from_request(req)
+            .await
+            .map_err(IntoResponse::into_response);
+        let auth = match auth {
+            Ok(auth) => auth,
+            Err(response) => {
+                // auth rejections are opaque responses so we pull out the message
+                let body = response.into_body();
+                let message = hyper::body::to_bytes(body)
+                    .await
+                    .map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+                    .unwrap_or_default();
+                return Err(ApiRequestRejection::Unauthorized { message });
+            }
+        };
+        let Path(path) = Path::<P>::from_request(req).await.map_err(|rejection| {
+            ApiRequestRejection::InvalidPath {
+                message: rejection.to_string(),
+            }
+        })?;
+        let Json(body) =
+            Json::<B>::from_request(req)
+                .await
+                .map_err(|rejection| match rejection {
+                    axum::extract::rejection::JsonRejection::MissingJsonContentType(_) => {
+                        ApiRequestRejection::UnsupportedMediaType {
+                            message: rejection.to_string(),
+                        }
+                    }
+                    _ => ApiRequestRejection::InvalidBody {
+                        message: rejection.to_string(),
+                    },
+                })?;
+        Ok(Self { body, path, auth })
+    }
+}
+
+impl<B, P, A> DocumentedParameter for ApiRequest<B, P, A>
+where
+    A: DocumentedParameter,
+    Path<P>: DocumentedParameter,
+    Json<B>: DocumentedParameter,
+{
+    const HAS_BEARER: bool = A::HAS_BEARER;
+    const HAS_API_KEY: bool = A::HAS_API_KEY;
+    fn to_openapi(op_id: &str, path: &str) -> Vec<ParameterDoc> {
+        <(A, Path<P>, Json<B>)>::to_openapi(op_id, path)
+    }
+
+    fn body_fields() -> Option<Vec<String>> {
+        Json::<B>::body_fields()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use deps::*;
+
+    use super::ApiRequest;
+    use crate::utils::testing::*;
+
+    #[derive(Debug, serde::Deserialize)]
+    #[serde(crate = "serde")]
+    struct Body {
+        name: String,
+    }
+
+    fn echo_router() -> axum::Router {
+        axum::Router::new().route(
+            "/things/:id",
+            axum::routing::patch(|req: ApiRequest<Body, uuid::Uuid>| async move {
+                axum::Json(serde_json::json!({
+                    "name": req.body.name,
+                    "id": req.path,
+                    "token": &*req.auth.0,
+                }))
+            }),
+        )
+    }
+
+    crate::table_tests! {
+        api_request tokio,
+        (uri, auth, content_type, body, status, expected),
+        {
+            let mut req = http::Request::builder().method("PATCH").uri(uri);
+            if let Some(auth) = auth {
+                req = req.header(http::header::AUTHORIZATION, auth);
+            }
+            if let Some(content_type) = content_type {
+                req = req.header(http::header::CONTENT_TYPE, content_type);
+            }
+            let resp = echo_router()
+                .oneshot(req.body(body.into()).unwrap_or_log())
+                .await
+                .unwrap_or_log();
+            assert_eq!(resp.status(), status);
+            let body = hyper::body::to_bytes(resp.into_body())
+                .await
+                .unwrap_or_log();
+            let body = serde_json::from_slice(&body).unwrap_or_log();
+            check_json(("expected", &expected), ("response", &body));
+        },
+    }
+
+    const THING_ID: &str = "add83cdf-2ab3-443f-84dd-476d7984cf75";
+
+    api_request! {
+        extracts_all_parts: (
+            format!("/things/{THING_ID}"),
+            Some("Bearer t0k3n"),
+            Some("application/json"),
+            r#"{ "name": "widget" }"#,
+            StatusCode::OK,
+            serde_json::json!({ "name": "widget", "id": THING_ID, "token": "t0k3n" }),
+        ),
+        rejects_missing_auth: (
+            format!("/things/{THING_ID}"),
+            None::<&str>,
+            Some("application/json"),
+            r#"{ "name": "widget" }"#,
+            StatusCode::UNAUTHORIZED,
+            serde_json::json!({
+                "error": "unauthorized",
+                "message": "Authorization header not set",
+            }),
+        ),
+        rejects_invalid_path: (
+            "/things/not-a-uuid".to_string(),
+            Some("Bearer t0k3n"),
+            Some("application/json"),
+            r#"{ "name": "widget" }"#,
+            StatusCode::BAD_REQUEST,
+            serde_json::json!({ "error": "invalidPath" }),
+        ),
+        rejects_missing_content_type: (
+            format!("/things/{THING_ID}"),
+            Some("Bearer t0k3n"),
+            None::<&str>,
+            r#"{ "name": "widget" }"#,
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            serde_json::json!({ "error": "unsupportedMediaType" }),
+        ),
+        rejects_invalid_body: (
+            format!("/things/{THING_ID}"),
+            Some("Bearer t0k3n"),
+            Some("application/json"),
+            r#"{ "nom": "widget" }"#,
+            StatusCode::BAD_REQUEST,
+            serde_json::json!({ "error": "invalidBody" }),
+        ),
+    }
+}
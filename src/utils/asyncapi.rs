@@ -0,0 +1,154 @@
+//! An [AsyncAPI](https://www.asyncapi.com/docs/reference/specification/v2.5.0) document
+//! for the streaming endpoints that the OpenAPI spec can't describe. The message
+//! payloads come from the same [`utoipa::ToSchema`] types so the two stay in sync.
+
+use deps::*;
+
+use super::type_name_raw;
+
+pub const VERSION: &str = "2.5.0";
+
+#[derive(Debug, Clone, Copy)]
+pub enum Protocol {
+    /// `text/event-stream` over plain http.
+    Sse,
+    WebSocket,
+}
+
+impl Protocol {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Sse => "sse",
+            Self::WebSocket => "ws",
+        }
+    }
+}
+
+/// A channel the server publishes [`DocumentedChannel::Message`]s on.
+pub trait DocumentedChannel: Sized {
+    const PATH: &'static str;
+    const PROTOCOL: Protocol;
+    const DESCRIPTION: &'static str = "";
+
+    type Message: utoipa::ToSchema;
+
+    /// Defaults to the type name of `Self`.
+    fn id() -> &'static str {
+        type_name_raw::<Self>()
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct DocumentBuilder {
+    channels: serde_json::Map<String, serde_json::Value>,
+    schemas: serde_json::Map<String, serde_json::Value>,
+}
+
+impl DocumentBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn channel<C: DocumentedChannel>(mut self) -> Self {
+        let message = type_name_raw::<C::Message>();
+        self.schemas.insert(
+            message.into(),
+            serde_json::to_value(<C::Message as utoipa::ToSchema>::schema())
+                .expect("unable to serialize message schema"),
+        );
+        let mut channel = serde_json::json!({
+            "bindings": { (C::PROTOCOL.as_str()): {} },
+            "subscribe": {
+                "operationId": C::id(),
+                "message": {
+                    "name": message,
+                    "payload": { "$ref": format!("#/components/schemas/{message}") },
+                },
+            },
+        });
+        if !C::DESCRIPTION.is_empty() {
+            channel["description"] = C::DESCRIPTION.into();
+        }
+        assert!(
+            self.channels.insert(C::PATH.into(), channel).is_none(),
+            "channel {} documented twice",
+            C::PATH
+        );
+        self
+    }
+
+    pub fn build(self, title: &str, version: &str) -> serde_json::Value {
+        serde_json::json!({
+            "asyncapi": VERSION,
+            "info": { "title": title, "version": version },
+            "channels": self.channels,
+            "components": { "schemas": self.schemas },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use deps::*;
+
+    use super::*;
+
+    #[derive(Debug, serde::Serialize, utoipa::ToSchema)]
+    #[serde(crate = "serde", rename_all = "camelCase")]
+    pub struct UserChanged {
+        id: uuid::Uuid,
+        username: String,
+    }
+
+    pub struct UserEvents;
+
+    impl DocumentedChannel for UserEvents {
+        const PATH: &'static str = "/users/events";
+        const PROTOCOL: Protocol = Protocol::Sse;
+        const DESCRIPTION: &'static str = "Changes to users as they happen.";
+
+        type Message = UserChanged;
+    }
+
+    #[test]
+    fn documents_sse_channel() {
+        let doc = DocumentBuilder::new()
+            .channel::<UserEvents>()
+            .build("test", "0.1.0");
+        assert_eq!(doc["asyncapi"], VERSION);
+        assert_eq!(
+            doc["channels"]["/users/events"],
+            serde_json::json!({
+                "description": "Changes to users as they happen.",
+                "bindings": { "sse": {} },
+                "subscribe": {
+                    "operationId": "UserEvents",
+                    "message": {
+                        "name": "UserChanged",
+                        "payload": { "$ref": "#/components/schemas/UserChanged" },
+                    },
+                },
+            })
+        );
+        assert_eq!(
+            doc["components"]["schemas"]["UserChanged"],
+            serde_json::to_value(<UserChanged as utoipa::ToSchema>::schema()).unwrap()
+        );
+        let message = serde_json::to_value(UserChanged {
+            id: uuid::Uuid::nil(),
+            username: "sabrina".into(),
+        })
+        .unwrap();
+        let props = doc["components"]["schemas"]["UserChanged"]["properties"]
+            .as_object()
+            .unwrap();
+        assert!(
+            message
+                .as_object()
+                .unwrap()
+                .keys()
+                .all(|key| props.contains_key(key)),
+            "{message} not in {props:?}"
+        );
+    }
+}
@@ -0,0 +1,38 @@
+//! Audit trail of successful writes. Entries are emitted as structured events under
+//! the [`TARGET`] tracing target so that they can be filtered into their own sink.
+//! Endpoints opt in through [`crate::HttpEndpoint::AUDIT_ACTION`].
+
+use deps::*;
+
+pub const TARGET: &str = "audit";
+
+#[derive(Debug, Clone)]
+pub struct Entry {
+    /// From [`super::RequestContext::auth`]. `None` for anonymous requests.
+    pub actor: Option<uuid::Uuid>,
+    pub action: &'static str,
+    /// See [`crate::HttpEndpoint::audit_resource_id`].
+    pub resource_id: Option<String>,
+    pub path: String,
+    pub request_id: Option<std::sync::Arc<str>>,
+    pub timestamp: time::OffsetDateTime,
+}
+
+impl Entry {
+    pub fn record(&self) {
+        let timestamp = self
+            .timestamp
+            .format(&time::format_description::well_known::Rfc3339)
+            .unwrap_or_log();
+        tracing::info!(
+            target: TARGET,
+            actor = self.actor.map(tracing::field::display),
+            action = self.action,
+            resource_id = self.resource_id.as_deref(),
+            path = %self.path,
+            request_id = self.request_id.as_deref(),
+            %timestamp,
+            "audit"
+        );
+    }
+}
@@ -0,0 +1,156 @@
+//! Request body caps by content type: [`crate::Config::upload_body_limit`] for
+//! `multipart/form-data` and [`crate::Config::body_limit`] for everything else.
+//! Bodies are counted as they stream in so an oversized one is cut off at the
+//! limit instead of being read to the end first.
+
+use deps::*;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use axum::{
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Json, Response},
+};
+use futures::StreamExt;
+
+use crate::{RoutingError, SharedContext};
+
+#[derive(Debug, thiserror::Error)]
+#[error("request body over {limit} bytes")]
+struct TooLarge {
+    limit: usize,
+}
+
+fn limit_for(config: &crate::Config, headers: &HeaderMap) -> usize {
+    let is_multipart = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|val| val.to_str().ok())
+        .and_then(|val| val.split(';').next())
+        .map(|mime| mime.trim().eq_ignore_ascii_case("multipart/form-data"))
+        .unwrap_or(false);
+    if is_multipart {
+        config.upload_body_limit
+    } else {
+        config.body_limit
+    }
+}
+
+/// Answers with a `413` if the body is over its limit, right away if the
+/// `Content-Length` says so or else once that many bytes have been read.
+/// Needs the [`SharedContext`] extension.
+pub async fn limit_body(
+    req: axum::http::Request<axum::body::Body>,
+    next: axum::middleware::Next<axum::body::Body>,
+) -> Response {
+    let limit = match req.extensions().get::<SharedContext>() {
+        Some(ctx) => limit_for(&ctx.config, req.headers()),
+        None => return next.run(req).await,
+    };
+    let too_large = || {
+        (
+            StatusCode::PAYLOAD_TOO_LARGE,
+            Json(RoutingError::PayloadTooLarge { limit }),
+        )
+            .into_response()
+    };
+    let length = req
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|val| val.to_str().ok())
+        .and_then(|val| val.parse::<usize>().ok());
+    if matches!(length, Some(length) if length > limit) {
+        return too_large();
+    }
+    let tripped = Arc::new(AtomicBool::new(false));
+    let (parts, body) = req.into_parts();
+    let body = {
+        let tripped = tripped.clone();
+        let mut read = 0;
+        axum::body::Body::wrap_stream(body.map(move |chunk| {
+            let chunk = chunk?;
+            read += chunk.len();
+            if read > limit {
+                tripped.store(true, Ordering::SeqCst);
+                return Err(Box::new(TooLarge { limit }) as axum::BoxError);
+            }
+            Ok(chunk)
+        }))
+    };
+    let resp = next.run(axum::http::Request::from_parts(parts, body)).await;
+    // whatever the extractor made of the cut off body
+    if tripped.load(Ordering::SeqCst) {
+        too_large()
+    } else {
+        resp
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use deps::*;
+
+    use super::*;
+    use crate::utils::testing::*;
+
+    const BODY_LEN: usize = 4 * 1024;
+
+    fn app() -> axum::Router {
+        let mut ctx = crate::Context::mock(InMemoryDb::fixture());
+        ctx.config.body_limit = 1024;
+        ctx.config.upload_body_limit = 64 * 1024;
+        axum::Router::new()
+            .route(
+                "/upload",
+                axum::routing::post(
+                    |body: axum::body::Bytes| async move { body.len().to_string() },
+                ),
+            )
+            .layer(axum::middleware::from_fn(limit_body))
+            .layer(axum::Extension(Arc::new(ctx)))
+    }
+
+    crate::table_tests! {
+        body_limit tokio,
+        (content_type, with_length, status),
+        {
+            let mut req = http::Request::builder()
+                .method("POST")
+                .uri("/upload")
+                .header(header::CONTENT_TYPE, content_type);
+            if with_length {
+                req = req.header(header::CONTENT_LENGTH, BODY_LEN);
+            }
+            let resp = app()
+                .oneshot(req.body(vec![b'a'; BODY_LEN].into()).unwrap_or_log())
+                .await
+                .unwrap_or_log();
+            assert_eq!(resp.status(), status);
+            let body = hyper::body::to_bytes(resp.into_body())
+                .await
+                .unwrap_or_log();
+            if status == StatusCode::PAYLOAD_TOO_LARGE {
+                let body = serde_json::from_slice(&body).unwrap_or_log();
+                check_json(
+                    (
+                        "expected",
+                        &serde_json::json!({ "error": "payloadTooLarge", "limit": 1024 }),
+                    ),
+                    ("response", &body),
+                );
+            } else {
+                assert_eq!(&body[..], BODY_LEN.to_string().as_bytes());
+            }
+        },
+    }
+
+    body_limit! {
+        rejects_large_json: ("application/json", false, StatusCode::PAYLOAD_TOO_LARGE),
+        rejects_large_json_by_length: ("application/json", true, StatusCode::PAYLOAD_TOO_LARGE),
+        accepts_upload_under_cap: (
+            "multipart/form-data; boundary=xyz",
+            false,
+            StatusCode::OK,
+        ),
+    }
+}
@@ -0,0 +1,206 @@
+//! Single byte range requests (RFC 7233) for the endpoints that set
+//! [`crate::HttpEndpoint::ACCEPT_RANGES`] so that downloads can be resumed.
+//! Multiple ranges aren't supported and get the full body as the RFC allows.
+
+use deps::*;
+
+use axum::{
+    http::{self, header, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteRange {
+    /// Both ends inclusive.
+    Satisfiable {
+        start: u64,
+        end: u64,
+    },
+    Unsatisfiable,
+}
+
+/// `None` if the header is to be ignored for not being a single, well formed
+/// `bytes` range.
+pub fn parse_byte_range(val: &str, len: u64) -> Option<ByteRange> {
+    let spec = val.trim().strip_prefix("bytes=")?;
+    if spec.contains(',') {
+        return None;
+    }
+    let (start, end) = spec.split_once('-')?;
+    let (start, end) = (start.trim(), end.trim());
+    if start.is_empty() {
+        let suffix: u64 = end.parse().ok()?;
+        if suffix == 0 || len == 0 {
+            return Some(ByteRange::Unsatisfiable);
+        }
+        return Some(ByteRange::Satisfiable {
+            start: len.saturating_sub(suffix),
+            end: len - 1,
+        });
+    }
+    let start: u64 = start.parse().ok()?;
+    let end = match end {
+        "" => u64::MAX,
+        end => end.parse().ok()?,
+    };
+    if end < start {
+        return None;
+    }
+    if start >= len {
+        return Some(ByteRange::Unsatisfiable);
+    }
+    Some(ByteRange::Satisfiable {
+        start,
+        end: end.min(len - 1),
+    })
+}
+
+/// The `Range` of a `GET`.
+pub fn range_header<B>(req: &http::Request<B>) -> Option<String> {
+    if req.method() != http::Method::GET {
+        return None;
+    }
+    req.headers()
+        .get(header::RANGE)
+        .and_then(|val| val.to_str().ok())
+        .map(String::from)
+}
+
+/// Advertises `Accept-Ranges` on a `200` and cuts it down to the `range` if there's
+/// one, answering with a `206` or a `416` if it's out of bounds.
+pub async fn byte_range(resp: Response, range: Option<String>) -> Response {
+    if resp.status() != StatusCode::OK {
+        return resp;
+    }
+    let (mut parts, body) = resp.into_parts();
+    parts
+        .headers
+        .insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+    let range = match range {
+        Some(range) => range,
+        None => return Response::from_parts(parts, body),
+    };
+    let body = match hyper::body::to_bytes(body).await {
+        Ok(body) => body,
+        Err(err) => {
+            tracing::error!(?err, "unable to buffer response for range");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    let len = body.len() as u64;
+    let (content_range, body) = match parse_byte_range(&range, len) {
+        None => {
+            return Response::from_parts(parts, axum::body::boxed(axum::body::Full::from(body)))
+        }
+        Some(ByteRange::Satisfiable { start, end }) => {
+            parts.status = StatusCode::PARTIAL_CONTENT;
+            (
+                format!("bytes {start}-{end}/{len}"),
+                body.slice(start as usize..=end as usize),
+            )
+        }
+        Some(ByteRange::Unsatisfiable) => {
+            parts.status = StatusCode::RANGE_NOT_SATISFIABLE;
+            parts.headers.remove(header::CONTENT_TYPE);
+            (format!("bytes */{len}"), Default::default())
+        }
+    };
+    parts.headers.remove(header::CONTENT_LENGTH);
+    parts.headers.insert(
+        header::CONTENT_RANGE,
+        HeaderValue::from_str(&content_range).expect("content ranges are valid header values"),
+    );
+    Response::from_parts(parts, axum::body::boxed(axum::body::Full::from(body)))
+}
+
+#[cfg(test)]
+mod tests {
+    use deps::*;
+
+    use crate::utils::testing::*;
+    use crate::*;
+
+    const CONTENT: &[u8] = b"0123456789";
+
+    #[derive(Debug, Clone, Copy)]
+    struct Export;
+
+    #[async_trait::async_trait]
+    impl Endpoint for Export {
+        type Request = ();
+        type Response = &'static [u8];
+        type Error = Infallible;
+
+        async fn handle(
+            &self,
+            _ctx: &crate::Context,
+            _request: Self::Request,
+        ) -> Result<Self::Response, Self::Error> {
+            Ok(CONTENT)
+        }
+    }
+
+    impl HttpEndpoint for Export {
+        const METHOD: Method = Method::Get;
+        const PATH: &'static str = "/export";
+        const ACCEPT_RANGES: bool = true;
+
+        type HttpRequest = ();
+
+        fn request(_: Self::HttpRequest) -> Result<Self::Request, Self::Error> {
+            Ok(())
+        }
+
+        fn response(resp: Self::Response) -> axum::response::Response {
+            (
+                [(http::header::CONTENT_TYPE, "application/octet-stream")],
+                resp,
+            )
+                .into_response()
+        }
+    }
+
+    crate::table_tests! {
+        byte_range tokio,
+        (range, status, content_range, body),
+        {
+            setup_tracing_once();
+            let range: Option<&str> = range;
+            let app = endpoint_app(Export);
+            let mut req = http::Request::builder().method("GET").uri("/export");
+            if let Some(range) = range {
+                req = req.header(http::header::RANGE, range);
+            }
+            let resp = app
+                .oneshot(req.body(Default::default()).unwrap_or_log())
+                .await
+                .unwrap_or_log();
+            assert_eq!(resp.status(), status);
+            let header = |name: http::header::HeaderName| {
+                resp.headers()
+                    .get(name)
+                    .map(|val: &http::HeaderValue| val.to_str().unwrap().to_string())
+            };
+            if status == StatusCode::OK {
+                assert_eq!(header(http::header::ACCEPT_RANGES).as_deref(), Some("bytes"));
+            }
+            let content_range: Option<&str> = content_range;
+            assert_eq!(header(http::header::CONTENT_RANGE).as_deref(), content_range);
+            let expected: &[u8] = body;
+            let body = hyper::body::to_bytes(resp.into_body())
+                .await
+                .unwrap_or_log();
+            assert_eq!(&body[..], expected);
+        },
+    }
+
+    byte_range! {
+        full_without_range: (None, StatusCode::OK, None, CONTENT),
+        partial_range: (Some("bytes=2-5"), StatusCode::PARTIAL_CONTENT, Some("bytes 2-5/10"), b"2345"),
+        open_ended_range: (Some("bytes=7-"), StatusCode::PARTIAL_CONTENT, Some("bytes 7-9/10"), b"789"),
+        suffix_range: (Some("bytes=-3"), StatusCode::PARTIAL_CONTENT, Some("bytes 7-9/10"), b"789"),
+        unsatisfiable_range: (Some("bytes=20-30"), StatusCode::RANGE_NOT_SATISFIABLE, Some("bytes */10"), b""),
+        ignores_malformed_range: (Some("bytes=5-2"), StatusCode::OK, None, CONTENT),
+        ignores_multiple_ranges: (Some("bytes=0-1,4-5"), StatusCode::OK, None, CONTENT),
+    }
+}
@@ -0,0 +1,404 @@
+//! Shared cache for values handlers compute, see [`crate::Context::cache`]. It's
+//! in memory unless [`crate::Config::redis_url`] is set and the crate was built
+//! with the `redis-cache` feature, in which case it's shared between instances.
+
+use deps::*;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::utils::Clock;
+
+#[derive(Debug, thiserror::Error)]
+pub enum CacheError {
+    #[error("cached value doesn't match the type: {0}")]
+    Serde(#[from] serde_json::Error),
+    #[error("cache backend error: {message:?}")]
+    Backend { message: String },
+}
+
+/// Json values by key, each with its own time to live. Go through the typed
+/// [`get`](#method.get) and [`set`](#method.set) on `dyn Cache`.
+#[async_trait::async_trait]
+pub trait Cache: std::fmt::Debug + Send + Sync + 'static {
+    async fn get_json(&self, key: &str) -> Result<Option<serde_json::Value>, CacheError>;
+    async fn set_json(
+        &self,
+        key: &str,
+        value: serde_json::Value,
+        ttl: std::time::Duration,
+    ) -> Result<(), CacheError>;
+    /// Sets `key` only if it isn't already, in one step so that concurrent
+    /// callers can't both succeed. Whether it was set.
+    async fn set_json_if_absent(
+        &self,
+        key: &str,
+        value: serde_json::Value,
+        ttl: std::time::Duration,
+    ) -> Result<bool, CacheError>;
+    /// Whether there was anything to delete.
+    async fn delete(&self, key: &str) -> Result<bool, CacheError>;
+}
+
+impl dyn Cache {
+    pub async fn get<T: serde::de::DeserializeOwned>(
+        &self,
+        key: &str,
+    ) -> Result<Option<T>, CacheError> {
+        Ok(self
+            .get_json(key)
+            .await?
+            .map(serde_json::from_value)
+            .transpose()?)
+    }
+
+    pub async fn set<T: serde::Serialize>(
+        &self,
+        key: &str,
+        value: &T,
+        ttl: std::time::Duration,
+    ) -> Result<(), CacheError> {
+        self.set_json(key, serde_json::to_value(value)?, ttl).await
+    }
+
+    pub async fn set_if_absent<T: serde::Serialize>(
+        &self,
+        key: &str,
+        value: &T,
+        ttl: std::time::Duration,
+    ) -> Result<bool, CacheError> {
+        self.set_json_if_absent(key, serde_json::to_value(value)?, ttl)
+            .await
+    }
+}
+
+/// The [`Cache`] for [`crate::Config::redis_url`], falling back to an
+/// [`InMemoryCache`] on `clock` if not set.
+pub fn cache_from_config(config: &crate::Config, clock: Arc<dyn Clock>) -> Arc<dyn Cache> {
+    match &config.redis_url {
+        #[cfg(feature = "redis-cache")]
+        Some(url) => Arc::new(RedisCache::new(url).unwrap_or_else(|err| {
+            panic!("invalid redis url: {err}");
+        })),
+        #[cfg(not(feature = "redis-cache"))]
+        Some(_) => {
+            tracing::warn!(
+                "redis url set but built without the `redis-cache` feature, caching in memory"
+            );
+            Arc::new(InMemoryCache::new(clock, config.cache_capacity))
+        }
+        None => Arc::new(InMemoryCache::new(clock, config.cache_capacity)),
+    }
+}
+
+#[derive(Debug)]
+struct Entry {
+    value: serde_json::Value,
+    expires_at: time::OffsetDateTime,
+}
+
+/// Local to the process and holding at most `capacity` entries. Once full, a
+/// new key first sweeps the expired entries and then evicts the one closest
+/// to expiring. A zero capacity caches nothing.
+#[derive(Debug)]
+pub struct InMemoryCache {
+    entries: parking_lot::Mutex<HashMap<String, Entry>>,
+    clock: Arc<dyn Clock>,
+    capacity: usize,
+}
+
+impl InMemoryCache {
+    /// Pass the [`crate::Context::clock`] so that expiry follows it.
+    pub fn new(clock: Arc<dyn Clock>, capacity: usize) -> Self {
+        Self {
+            entries: Default::default(),
+            clock,
+            capacity,
+        }
+    }
+
+    fn insert(
+        &self,
+        entries: &mut HashMap<String, Entry>,
+        now: time::OffsetDateTime,
+        key: &str,
+        value: serde_json::Value,
+        ttl: std::time::Duration,
+    ) {
+        let ttl: time::Duration = ttl.try_into().unwrap_or(time::Duration::MAX);
+        if entries.len() >= self.capacity && !entries.contains_key(key) {
+            entries.retain(|_, entry| entry.expires_at > now);
+            if entries.len() >= self.capacity {
+                let soonest = entries
+                    .iter()
+                    .min_by_key(|(_, entry)| entry.expires_at)
+                    .map(|(key, _)| key.clone());
+                if let Some(soonest) = soonest {
+                    entries.remove(&soonest);
+                }
+            }
+        }
+        entries.insert(
+            key.into(),
+            Entry {
+                value,
+                expires_at: now.saturating_add(ttl),
+            },
+        );
+    }
+}
+
+#[async_trait::async_trait]
+impl Cache for InMemoryCache {
+    async fn get_json(&self, key: &str) -> Result<Option<serde_json::Value>, CacheError> {
+        let now = self.clock.now();
+        let mut entries = self.entries.lock();
+        match entries.get(key) {
+            Some(entry) if entry.expires_at > now => Ok(Some(entry.value.clone())),
+            Some(_) => {
+                entries.remove(key);
+                Ok(None)
+            }
+            None => Ok(None),
+        }
+    }
+
+    async fn set_json(
+        &self,
+        key: &str,
+        value: serde_json::Value,
+        ttl: std::time::Duration,
+    ) -> Result<(), CacheError> {
+        if self.capacity == 0 {
+            return Ok(());
+        }
+        let now = self.clock.now();
+        self.insert(&mut self.entries.lock(), now, key, value, ttl);
+        Ok(())
+    }
+
+    /// Errors with a zero capacity as there's nothing to check against.
+    async fn set_json_if_absent(
+        &self,
+        key: &str,
+        value: serde_json::Value,
+        ttl: std::time::Duration,
+    ) -> Result<bool, CacheError> {
+        if self.capacity == 0 {
+            return Err(CacheError::Backend {
+                message: "in memory cache disabled by a zero capacity".into(),
+            });
+        }
+        let now = self.clock.now();
+        let mut entries = self.entries.lock();
+        if entries.get(key).is_some_and(|entry| entry.expires_at > now) {
+            return Ok(false);
+        }
+        self.insert(&mut entries, now, key, value, ttl);
+        Ok(true)
+    }
+
+    async fn delete(&self, key: &str) -> Result<bool, CacheError> {
+        Ok(self.entries.lock().remove(key).is_some())
+    }
+}
+
+#[cfg(feature = "redis-cache")]
+pub use redis_backend::RedisCache;
+
+#[cfg(feature = "redis-cache")]
+mod redis_backend {
+    use deps::*;
+
+    use super::{Cache, CacheError};
+
+    use bb8_redis::{bb8, RedisConnectionManager};
+
+    impl From<redis::RedisError> for CacheError {
+        fn from(err: redis::RedisError) -> Self {
+            Self::Backend {
+                message: err.to_string(),
+            }
+        }
+    }
+
+    impl From<bb8::RunError<redis::RedisError>> for CacheError {
+        fn from(err: bb8::RunError<redis::RedisError>) -> Self {
+            Self::Backend {
+                message: err.to_string(),
+            }
+        }
+    }
+
+    /// Values are stored as json strings and expire through redis' own `PX`.
+    #[derive(Debug, Clone)]
+    pub struct RedisCache {
+        pool: bb8::Pool<RedisConnectionManager>,
+    }
+
+    impl RedisCache {
+        /// Connects lazily so that an unreachable redis only fails the requests
+        /// that use it.
+        pub fn new(url: &str) -> Result<Self, redis::RedisError> {
+            Ok(Self {
+                pool: bb8::Pool::builder().build_unchecked(RedisConnectionManager::new(url)?),
+            })
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl Cache for RedisCache {
+        async fn get_json(&self, key: &str) -> Result<Option<serde_json::Value>, CacheError> {
+            let mut conn = self.pool.get().await?;
+            let value: Option<String> = redis::cmd("GET").arg(key).query_async(&mut *conn).await?;
+            Ok(value
+                .map(|value| serde_json::from_str(&value))
+                .transpose()?)
+        }
+
+        async fn set_json(
+            &self,
+            key: &str,
+            value: serde_json::Value,
+            ttl: std::time::Duration,
+        ) -> Result<(), CacheError> {
+            let mut conn = self.pool.get().await?;
+            redis::cmd("SET")
+                .arg(key)
+                .arg(value.to_string())
+                .arg("PX")
+                // redis rejects a zero expiry
+                .arg(ttl.as_millis().clamp(1, u64::MAX as u128) as u64)
+                .query_async::<_, ()>(&mut *conn)
+                .await?;
+            Ok(())
+        }
+
+        async fn set_json_if_absent(
+            &self,
+            key: &str,
+            value: serde_json::Value,
+            ttl: std::time::Duration,
+        ) -> Result<bool, CacheError> {
+            let mut conn = self.pool.get().await?;
+            // `NX` replies nil rather than `OK` if the key exists
+            let set: Option<String> = redis::cmd("SET")
+                .arg(key)
+                .arg(value.to_string())
+                .arg("NX")
+                .arg("PX")
+                .arg(ttl.as_millis().clamp(1, u64::MAX as u128) as u64)
+                .query_async(&mut *conn)
+                .await?;
+            Ok(set.is_some())
+        }
+
+        async fn delete(&self, key: &str) -> Result<bool, CacheError> {
+            let mut conn = self.pool.get().await?;
+            let deleted: u64 = redis::cmd("DEL").arg(key).query_async(&mut *conn).await?;
+            Ok(deleted > 0)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use deps::*;
+
+    use super::*;
+    use crate::utils::testing::*;
+
+    fn cache() -> (Arc<dyn Cache>, Arc<FixedClock>) {
+        let clock = Arc::new(FixedClock::new(
+            time::OffsetDateTime::from_unix_timestamp(1_665_997_920).unwrap(),
+        ));
+        (Arc::new(InMemoryCache::new(clock.clone(), 3)), clock)
+    }
+
+    #[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+    #[serde(crate = "serde")]
+    struct Stats {
+        users: u64,
+        active: u64,
+    }
+
+    const TTL: std::time::Duration = std::time::Duration::from_secs(60);
+
+    #[tokio::test]
+    async fn set_then_get() {
+        let (cache, _) = cache();
+        let stats = Stats {
+            users: 12,
+            active: 3,
+        };
+        cache.set("stats", &stats, TTL).await.unwrap();
+        assert_eq!(cache.get::<Stats>("stats").await.unwrap(), Some(stats));
+        assert_eq!(cache.get::<Stats>("other").await.unwrap(), None);
+        assert!(matches!(
+            cache.get::<String>("stats").await,
+            Err(CacheError::Serde(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn expires_after_ttl() {
+        let (cache, clock) = cache();
+        cache.set("answer", &42, TTL).await.unwrap();
+        clock.advance(time::Duration::seconds(59));
+        assert_eq!(cache.get::<u64>("answer").await.unwrap(), Some(42));
+        clock.advance(time::Duration::seconds(1));
+        assert_eq!(cache.get::<u64>("answer").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn deletes() {
+        let (cache, _) = cache();
+        cache.set("answer", &42, TTL).await.unwrap();
+        assert!(cache.delete("answer").await.unwrap());
+        assert_eq!(cache.get::<u64>("answer").await.unwrap(), None);
+        assert!(!cache.delete("answer").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn evicts_the_soonest_to_expire_when_full() {
+        let (cache, clock) = cache();
+        cache.set("a", &1, TTL).await.unwrap();
+        cache.set("b", &2, TTL * 2).await.unwrap();
+        cache
+            .set("c", &3, std::time::Duration::from_secs(1))
+            .await
+            .unwrap();
+        // overwriting a key doesn't evict
+        cache.set("a", &10, TTL).await.unwrap();
+        assert_eq!(cache.get::<u64>("c").await.unwrap(), Some(3));
+        cache.set("d", &4, TTL).await.unwrap();
+        assert_eq!(cache.get::<u64>("c").await.unwrap(), None);
+        clock.advance(time::Duration::seconds(60));
+        // "a" and "d" expired so both get swept, not just one evicted
+        cache.set("e", &5, TTL).await.unwrap();
+        cache.set("f", &6, TTL).await.unwrap();
+        assert_eq!(cache.get::<u64>("b").await.unwrap(), Some(2));
+        assert_eq!(cache.get::<u64>("e").await.unwrap(), Some(5));
+        assert_eq!(cache.get::<u64>("f").await.unwrap(), Some(6));
+    }
+
+    #[tokio::test]
+    async fn zero_capacity_caches_nothing() {
+        let clock = Arc::new(crate::utils::SystemClock);
+        let cache: Arc<dyn Cache> = Arc::new(InMemoryCache::new(clock, 0));
+        cache.set("answer", &42, TTL).await.unwrap();
+        assert_eq!(cache.get::<u64>("answer").await.unwrap(), None);
+        assert!(cache.set_if_absent("answer", &42, TTL).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn sets_only_if_absent() {
+        let (cache, clock) = cache();
+        assert!(cache.set_if_absent("nonce", &1, TTL).await.unwrap());
+        assert!(!cache.set_if_absent("nonce", &2, TTL).await.unwrap());
+        assert_eq!(cache.get::<u64>("nonce").await.unwrap(), Some(1));
+        clock.advance(time::Duration::seconds(60));
+        assert!(cache.set_if_absent("nonce", &3, TTL).await.unwrap());
+        assert_eq!(cache.get::<u64>("nonce").await.unwrap(), Some(3));
+    }
+}
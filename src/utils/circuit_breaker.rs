@@ -0,0 +1,390 @@
+use deps::*;
+
+use std::future::Future;
+
+use super::Clock;
+
+/// Stops sending queries to a database that keeps failing to answer. After
+/// `threshold` consecutive outage errors it opens and fails every operation with
+/// [`sqlx::Error::PoolTimedOut`] right away, which the endpoints already answer
+/// with a `503`. Once the `cooldown` is over a single probe is let through and its
+/// outcome decides whether to close again. Go through [`crate::Context::guard_db`].
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    threshold: u32,
+    cooldown: time::Duration,
+    state: parking_lot::Mutex<BreakerState>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakerState {
+    Closed {
+        failures: u32,
+    },
+    Open {
+        since: time::OffsetDateTime,
+    },
+    /// The probe is in flight.
+    HalfOpen,
+}
+
+impl CircuitBreaker {
+    /// A `threshold` of zero never opens.
+    pub fn new(threshold: u32, cooldown: std::time::Duration) -> Self {
+        Self {
+            threshold,
+            cooldown: cooldown.try_into().unwrap_or(time::Duration::MAX),
+            state: parking_lot::Mutex::new(BreakerState::Closed { failures: 0 }),
+        }
+    }
+
+    pub fn state(&self) -> BreakerState {
+        *self.state.lock()
+    }
+
+    /// How long until an open breaker lets a probe through, `None` if it's not
+    /// open.
+    pub fn cooldown_left(&self, now: time::OffsetDateTime) -> Option<time::Duration> {
+        match self.state() {
+            BreakerState::Open { since } => {
+                Some((self.cooldown - (now - since)).max(time::Duration::ZERO))
+            }
+            BreakerState::Closed { .. } | BreakerState::HalfOpen => None,
+        }
+    }
+
+    /// Runs `op` unless the breaker is open and counts its outcome. The `pool`
+    /// the `op` runs on tells pool timeouts from a busy pool apart from ones of a
+    /// pool that can't connect at all.
+    pub async fn guard<T, F>(
+        &self,
+        clock: &dyn Clock,
+        pool: Option<&sqlx::postgres::PgPool>,
+        op: F,
+    ) -> Result<T, sqlx::Error>
+    where
+        F: Future<Output = Result<T, sqlx::Error>>,
+    {
+        if !self.admit(clock.now()) {
+            return Err(sqlx::Error::PoolTimedOut);
+        }
+        let mut pending = Pending {
+            breaker: self,
+            clock,
+            done: false,
+        };
+        let result = op.await;
+        pending.done = true;
+        match &result {
+            Err(err) if is_outage(err) => self.record_failure(clock.now()),
+            // sqlx retries failed connects until the acquire timeout, so a pool
+            // without a single connection timing out is the database being gone
+            Err(sqlx::Error::PoolTimedOut) if pool.is_some_and(|pool| pool.size() == 0) => {
+                self.record_failure(clock.now())
+            }
+            // says nothing about the database either way
+            Err(sqlx::Error::PoolTimedOut) => self.record_inconclusive(clock.now()),
+            _ => self.record_success(),
+        }
+        result
+    }
+
+    fn admit(&self, now: time::OffsetDateTime) -> bool {
+        let mut state = self.state.lock();
+        match *state {
+            BreakerState::Closed { .. } => true,
+            BreakerState::Open { since } if now - since >= self.cooldown => {
+                tracing::info!("db circuit breaker half-open, probing");
+                *state = BreakerState::HalfOpen;
+                true
+            }
+            BreakerState::Open { .. } | BreakerState::HalfOpen => false,
+        }
+    }
+
+    pub fn record_failure(&self, now: time::OffsetDateTime) {
+        if self.threshold == 0 {
+            return;
+        }
+        let mut state = self.state.lock();
+        *state = match *state {
+            BreakerState::Closed { failures } if failures + 1 < self.threshold => {
+                BreakerState::Closed {
+                    failures: failures + 1,
+                }
+            }
+            BreakerState::Closed { .. } | BreakerState::HalfOpen => {
+                tracing::warn!(cooldown = %self.cooldown, "db circuit breaker open");
+                BreakerState::Open { since: now }
+            }
+            open @ BreakerState::Open { .. } => open,
+        };
+    }
+
+    /// Leaves the count alone. A probe that couldn't tell is retried after another
+    /// cooldown.
+    pub fn record_inconclusive(&self, now: time::OffsetDateTime) {
+        let mut state = self.state.lock();
+        if *state == BreakerState::HalfOpen {
+            *state = BreakerState::Open { since: now };
+        }
+    }
+
+    pub fn record_success(&self) {
+        let mut state = self.state.lock();
+        if *state == BreakerState::HalfOpen {
+            tracing::info!("db circuit breaker closed");
+        }
+        *state = BreakerState::Closed { failures: 0 };
+    }
+}
+
+/// Reopens the breaker if the probe is dropped before it's done, as happens when
+/// the request's deadline passes or its client goes away. It'd stay half-open for
+/// good otherwise.
+struct Pending<'a> {
+    breaker: &'a CircuitBreaker,
+    clock: &'a dyn Clock,
+    done: bool,
+}
+
+impl Drop for Pending<'_> {
+    fn drop(&mut self) {
+        if self.done {
+            return;
+        }
+        let mut state = self.breaker.state.lock();
+        if *state == BreakerState::HalfOpen {
+            *state = BreakerState::Open {
+                since: self.clock.now(),
+            };
+        }
+    }
+}
+
+/// Errors that say the database can't be reached as opposed to it refusing a
+/// particular query. Pool timeouts are left out, those usually mean the pool is
+/// busy, not that the database is gone, and they are what an open breaker
+/// answers with. [`CircuitBreaker::guard`] counts the ones of an empty pool.
+pub fn is_outage(err: &sqlx::Error) -> bool {
+    matches!(
+        err,
+        sqlx::Error::Io(_)
+            | sqlx::Error::Tls(_)
+            | sqlx::Error::Protocol(_)
+            | sqlx::Error::PoolClosed
+            | sqlx::Error::WorkerCrashed
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use deps::*;
+
+    use super::*;
+    use crate::user::testing::*;
+    use crate::utils::testing::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn clock() -> FixedClock {
+        FixedClock::new(time::OffsetDateTime::from_unix_timestamp(1_663_599_358).unwrap())
+    }
+
+    async fn run(
+        breaker: &CircuitBreaker,
+        clock: &FixedClock,
+        calls: &AtomicUsize,
+        fail: bool,
+    ) -> Result<(), sqlx::Error> {
+        breaker
+            .guard(clock, None, async {
+                calls.fetch_add(1, Ordering::SeqCst);
+                if fail {
+                    Err(sqlx::Error::Io(
+                        std::io::ErrorKind::ConnectionRefused.into(),
+                    ))
+                } else {
+                    Ok(())
+                }
+            })
+            .await
+    }
+
+    #[tokio::test]
+    async fn trips_and_fails_fast() {
+        let (breaker, clock, calls) = (
+            CircuitBreaker::new(3, std::time::Duration::from_secs(5)),
+            clock(),
+            AtomicUsize::new(0),
+        );
+        for _ in 0..3 {
+            assert!(matches!(
+                run(&breaker, &clock, &calls, true).await,
+                Err(sqlx::Error::Io(_))
+            ));
+        }
+        assert!(matches!(breaker.state(), BreakerState::Open { .. }));
+        assert!(matches!(
+            run(&breaker, &clock, &calls, false).await,
+            Err(sqlx::Error::PoolTimedOut)
+        ));
+        assert_eq!(calls.load(Ordering::SeqCst), 3, "ran while open");
+    }
+
+    #[tokio::test]
+    async fn pool_timeouts_keep_the_count() {
+        let (breaker, clock, calls) = (
+            CircuitBreaker::new(2, std::time::Duration::from_secs(5)),
+            clock(),
+            AtomicUsize::new(0),
+        );
+        run(&breaker, &clock, &calls, true).await.unwrap_err();
+        breaker
+            .guard(&clock, None, async {
+                Err::<(), _>(sqlx::Error::PoolTimedOut)
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(breaker.state(), BreakerState::Closed { failures: 1 });
+    }
+
+    #[tokio::test]
+    async fn unreachable_db_trips() {
+        let (breaker, clock) = (
+            CircuitBreaker::new(2, std::time::Duration::from_secs(5)),
+            clock(),
+        );
+        // nothing listens on the port a just closed listener had
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap_or_log()
+            .local_addr()
+            .unwrap_or_log()
+            .port();
+        let pool = sqlx::postgres::PgPoolOptions::new()
+            .acquire_timeout(std::time::Duration::from_millis(200))
+            .connect_lazy_with(
+                sqlx::postgres::PgConnectOptions::new()
+                    .host("127.0.0.1")
+                    .port(port),
+            );
+        for _ in 0..2 {
+            let result = breaker
+                .guard(&clock, Some(&pool), async {
+                    sqlx::query("SELECT 1").execute(&pool).await
+                })
+                .await;
+            assert!(
+                matches!(result, Err(sqlx::Error::PoolTimedOut)),
+                "{result:?}"
+            );
+        }
+        assert!(matches!(breaker.state(), BreakerState::Open { .. }));
+    }
+
+    #[tokio::test]
+    async fn only_outages_count() {
+        let (breaker, clock) = (
+            CircuitBreaker::new(2, std::time::Duration::from_secs(5)),
+            clock(),
+        );
+        for _ in 0..3 {
+            breaker
+                .guard(&clock, None, async {
+                    Err::<(), _>(sqlx::Error::RowNotFound)
+                })
+                .await
+                .unwrap_err();
+            breaker
+                .guard(&clock, None, async {
+                    Err::<(), _>(sqlx::Error::PoolTimedOut)
+                })
+                .await
+                .unwrap_err();
+        }
+        assert_eq!(breaker.state(), BreakerState::Closed { failures: 0 });
+    }
+
+    #[tokio::test]
+    async fn recovers_after_cooldown() {
+        let (breaker, clock, calls) = (
+            CircuitBreaker::new(1, std::time::Duration::from_secs(5)),
+            clock(),
+            AtomicUsize::new(0),
+        );
+        run(&breaker, &clock, &calls, true).await.unwrap_err();
+        clock.advance(time::Duration::seconds(4));
+        run(&breaker, &clock, &calls, false).await.unwrap_err();
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // a failed probe opens it for another cooldown
+        clock.advance(time::Duration::seconds(1));
+        run(&breaker, &clock, &calls, true).await.unwrap_err();
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        assert!(matches!(breaker.state(), BreakerState::Open { .. }));
+        run(&breaker, &clock, &calls, false).await.unwrap_err();
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        clock.advance(time::Duration::seconds(5));
+        run(&breaker, &clock, &calls, false).await.unwrap_or_log();
+        assert_eq!(breaker.state(), BreakerState::Closed { failures: 0 });
+        run(&breaker, &clock, &calls, false).await.unwrap_or_log();
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+    }
+
+    #[tokio::test]
+    async fn dropped_probe_reopens() {
+        let (breaker, clock) = (
+            CircuitBreaker::new(1, std::time::Duration::from_secs(5)),
+            clock(),
+        );
+        breaker.record_failure(clock.now());
+        clock.advance(time::Duration::seconds(5));
+        let probe = breaker.guard(&clock, None, futures::future::pending::<Result<(), _>>());
+        assert!(
+            futures::FutureExt::now_or_never(probe).is_none(),
+            "probe finished"
+        );
+        assert!(matches!(breaker.state(), BreakerState::Open { .. }));
+    }
+
+    #[tokio::test]
+    async fn open_breaker_is_503() {
+        setup_tracing_once();
+        let clock = std::sync::Arc::new(clock());
+        let mut ctx = crate::Context::mock(InMemoryDb::fixture());
+        ctx.clock = clock.clone();
+        for _ in 0..ctx.config.db_breaker_threshold {
+            ctx.db_breaker.record_failure(clock.now());
+        }
+        let cooldown = ctx.config.db_breaker_cooldown;
+        let app = crate::user::router().layer(axum::Extension(std::sync::Arc::new(ctx)));
+        let get_user = || {
+            app.clone().oneshot(
+                http::Request::builder()
+                    .method("GET")
+                    .uri(format!("/users/{USER_01_ID}"))
+                    .header(
+                        http::header::AUTHORIZATION,
+                        format!("Bearer {USER_01_SESSION}"),
+                    )
+                    .body(Default::default())
+                    .unwrap_or_log(),
+            )
+        };
+        let resp = get_user().await.unwrap_or_log();
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body = hyper::body::to_bytes(resp.into_body())
+            .await
+            .unwrap_or_log();
+        let body = serde_json::from_slice(&body).unwrap_or_log();
+        check_json(
+            ("expected", &serde_json::json!({ "error": "overloaded" })),
+            ("response", &body),
+        );
+
+        clock.advance(cooldown.try_into().unwrap());
+        let resp = get_user().await.unwrap_or_log();
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+}
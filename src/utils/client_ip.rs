@@ -0,0 +1,294 @@
+//! Behind a proxy the peer is the proxy, not the client. [`ClientIp`] looks past
+//! the [`crate::Config::trusted_proxies`] using the `Forwarded` or
+//! `X-Forwarded-For` headers they append to. Headers sent by anyone else are
+//! ignored since clients can put whatever they like in them.
+
+use deps::*;
+
+use std::net::{IpAddr, SocketAddr};
+
+use axum::{
+    extract::{rejection::ExtensionRejection, ConnectInfo, FromRequest, RequestParts},
+    http::{header, HeaderMap},
+};
+
+use crate::{DocumentedParameter, ParameterDoc, SharedContext};
+
+pub const X_FORWARDED_FOR: &str = "x-forwarded-for";
+
+/// A block of addresses in CIDR notation, e.g. `10.0.0.0/8`. A bare address is a
+/// block of one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    pub addr: IpAddr,
+    pub prefix: u8,
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            // v4 clients connecting to a dual stack socket
+            (IpAddr::V4(_), IpAddr::V6(ip)) => ip
+                .to_ipv4_mapped()
+                .map(|ip| self.contains(ip.into()))
+                .unwrap_or_default(),
+            (IpAddr::V6(_), IpAddr::V4(_)) => false,
+        }
+    }
+}
+
+impl std::str::FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr
+            .trim()
+            .parse()
+            .map_err(|err| format!("invalid address in {s:?}: {err}"))?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .trim()
+                .parse::<u8>()
+                .ok()
+                .filter(|prefix| *prefix <= max)
+                .ok_or_else(|| format!("invalid prefix length in {s:?}"))?,
+            None => max,
+        };
+        Ok(Self { addr, prefix })
+    }
+}
+
+/// Parses a `for=` node of the `Forwarded` header, e.g. `"[2001:db8::17]:4711"`.
+/// Obfuscated and `unknown` nodes come out as `None`.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+    if let Ok(ip) = node.parse() {
+        return Some(ip);
+    }
+    if let Some(rest) = node.strip_prefix('[') {
+        return rest.split_once(']')?.0.parse().ok();
+    }
+    node.parse::<SocketAddr>().ok().map(|addr| addr.ip())
+}
+
+/// The hops recorded by the proxies, closest to the client first. `Forwarded` is
+/// preferred over `X-Forwarded-For` if both are set.
+fn forwarded_hops(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    let values = |name| {
+        headers
+            .get_all(name)
+            .iter()
+            .filter_map(|val| val.to_str().ok())
+            .flat_map(|val| val.split(','))
+            .collect::<Vec<_>>()
+    };
+    let forwarded = values(header::FORWARDED);
+    if !forwarded.is_empty() {
+        return forwarded
+            .into_iter()
+            .map(|element| {
+                element.split(';').find_map(|pair| {
+                    let (key, val) = pair.split_once('=')?;
+                    key.trim().eq_ignore_ascii_case("for").then_some(val)
+                })
+            })
+            .map(|node| node.and_then(parse_node))
+            .collect();
+    }
+    values(header::HeaderName::from_static(X_FORWARDED_FOR))
+        .into_iter()
+        .map(parse_node)
+        .collect()
+}
+
+/// Resolves the client's address given the `peer` the request came from. Walks
+/// the forwarded hops from the peer back towards the client and stops at the first
+/// one that isn't one of the `trusted` proxies.
+pub fn resolve_client_ip(peer: IpAddr, headers: &HeaderMap, trusted: &[Cidr]) -> IpAddr {
+    let is_trusted = |ip: IpAddr| trusted.iter().any(|cidr| cidr.contains(ip));
+    let mut client = peer;
+    if !is_trusted(client) {
+        return client;
+    }
+    for hop in forwarded_hops(headers).into_iter().rev() {
+        match hop {
+            Some(hop) => {
+                client = hop;
+                if !is_trusted(hop) {
+                    break;
+                }
+            }
+            // can't see past a hop the proxy didn't disclose
+            None => break,
+        }
+    }
+    client
+}
+
+/// The address of the client as resolved by [`resolve_client_ip`]. Needs the app
+/// to be served with the peer's [`ConnectInfo`], as [`crate::serve::serve`] does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientIp(pub IpAddr);
+
+impl ClientIp {
+    /// `None` if the request lacks the peer's [`ConnectInfo`].
+    pub fn from_parts<B>(req: &axum::http::Request<B>) -> Option<Self> {
+        let ConnectInfo(peer) = req.extensions().get::<ConnectInfo<SocketAddr>>()?;
+        Some(Self(resolve_client_ip(
+            peer.ip(),
+            req.headers(),
+            trusted_proxies(req.extensions()),
+        )))
+    }
+}
+
+/// Whether the request came straight from one of the
+/// [`crate::Config::trusted_proxies`] and so gets to speak for the client through
+/// the forwarding headers. `false` without the peer's [`ConnectInfo`].
+pub fn from_trusted_proxy(extensions: &axum::http::Extensions) -> bool {
+    let peer = match extensions.get::<ConnectInfo<SocketAddr>>() {
+        Some(ConnectInfo(peer)) => peer.ip(),
+        None => return false,
+    };
+    trusted_proxies(extensions)
+        .iter()
+        .any(|cidr| cidr.contains(peer))
+}
+
+fn trusted_proxies(extensions: &axum::http::Extensions) -> &[Cidr] {
+    extensions
+        .get::<SharedContext>()
+        .map(|ctx| &ctx.config.trusted_proxies[..])
+        .unwrap_or_default()
+}
+
+#[async_trait::async_trait]
+impl<B> FromRequest<B> for ClientIp
+where
+    B: Send,
+{
+    type Rejection = ExtensionRejection;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let ConnectInfo(peer) = ConnectInfo::<SocketAddr>::from_request(req).await?;
+        Ok(Self(resolve_client_ip(
+            peer.ip(),
+            req.headers(),
+            trusted_proxies(req.extensions()),
+        )))
+    }
+}
+
+impl DocumentedParameter for ClientIp {
+    fn to_openapi(_op_id: &str, _path: &str) -> Vec<ParameterDoc> {
+        vec![]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use deps::*;
+
+    use super::*;
+    use crate::utils::testing::*;
+
+    fn app(trusted_proxies: &[&str]) -> axum::Router {
+        let mut ctx = crate::Context::mock(InMemoryDb::fixture());
+        ctx.config.trusted_proxies = trusted_proxies
+            .iter()
+            .map(|cidr| cidr.parse().unwrap())
+            .collect();
+        axum::Router::new()
+            .route(
+                "/ip",
+                axum::routing::get(|ClientIp(ip): ClientIp| async move { ip.to_string() }),
+            )
+            .layer(axum::Extension(std::sync::Arc::new(ctx)))
+    }
+
+    crate::table_tests! {
+        client_ip tokio,
+        (trusted_proxies, peer, headers, expected),
+        {
+            let trusted_proxies: &[&str] = trusted_proxies;
+            let headers: &[(&str, &str)] = headers;
+            let peer: SocketAddr = peer.parse().unwrap();
+            let mut req = http::Request::builder().method("GET").uri("/ip");
+            for (name, val) in headers {
+                req = req.header(*name, *val);
+            }
+            let mut req = req.body(Default::default()).unwrap_or_log();
+            req.extensions_mut().insert(ConnectInfo(peer));
+            let resp = app(trusted_proxies).oneshot(req).await.unwrap_or_log();
+            assert_eq!(resp.status(), StatusCode::OK);
+            let body = hyper::body::to_bytes(resp.into_body())
+                .await
+                .unwrap_or_log();
+            assert_eq!(std::str::from_utf8(&body).unwrap(), expected);
+        },
+    }
+
+    client_ip! {
+        trusted_proxy_forwards_client: (
+            &["10.0.0.0/8"],
+            "10.1.2.3:4711",
+            &[(X_FORWARDED_FOR, "203.0.113.7")],
+            "203.0.113.7",
+        ),
+        skips_trusted_hops: (
+            &["10.0.0.0/8"],
+            "10.1.2.3:4711",
+            &[(X_FORWARDED_FOR, "198.51.100.1, 203.0.113.7, 10.9.9.9")],
+            "203.0.113.7",
+        ),
+        untrusted_peer_is_the_client: (
+            &["10.0.0.0/8"],
+            "192.0.2.10:4711",
+            &[(X_FORWARDED_FOR, "203.0.113.7")],
+            "192.0.2.10",
+        ),
+        nothing_trusted_by_default: (
+            &[],
+            "10.1.2.3:4711",
+            &[(X_FORWARDED_FOR, "203.0.113.7")],
+            "10.1.2.3",
+        ),
+        reads_forwarded: (
+            &["10.0.0.1"],
+            "10.0.0.1:4711",
+            &[("forwarded", r#"for="[2001:db8:cafe::17]:4711";proto=https"#)],
+            "2001:db8:cafe::17",
+        ),
+        stops_at_obfuscated_hops: (
+            &["10.0.0.0/8"],
+            "10.1.2.3:4711",
+            &[("forwarded", "for=203.0.113.7, for=_hidden")],
+            "10.1.2.3",
+        ),
+    }
+
+    #[test]
+    fn parses_cidrs() {
+        let cidr: Cidr = "192.168.0.0/16".parse().unwrap();
+        assert!(cidr.contains("192.168.4.2".parse().unwrap()));
+        assert!(!cidr.contains("192.169.0.1".parse().unwrap()));
+        assert!(cidr.contains("::ffff:192.168.4.2".parse().unwrap()));
+        let cidr: Cidr = "2001:db8::/32".parse().unwrap();
+        assert!(cidr.contains("2001:db8:cafe::17".parse().unwrap()));
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert_eq!("0.0.0.0/0".parse::<Cidr>().unwrap().prefix, 0);
+    }
+}
@@ -0,0 +1,32 @@
+//! Sources of the current time and fresh ids that handlers go through so that
+//! tests can swap in deterministic ones. See [`crate::Context::clock`].
+
+use deps::*;
+
+pub trait Clock: std::fmt::Debug + Send + Sync + 'static {
+    fn now(&self) -> time::OffsetDateTime;
+}
+
+/// The real time in UTC.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> time::OffsetDateTime {
+        time::OffsetDateTime::now_utc()
+    }
+}
+
+pub trait IdGen: std::fmt::Debug + Send + Sync + 'static {
+    fn new_id(&self) -> uuid::Uuid;
+}
+
+/// Random v4 UUIDs.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RandomIds;
+
+impl IdGen for RandomIds {
+    fn new_id(&self) -> uuid::Uuid {
+        uuid::Uuid::new_v4()
+    }
+}
@@ -0,0 +1,131 @@
+//! Compresses responses according to `Accept-Encoding`. All of them get it added
+//! to their `Vary`, even the ones that went out as they were since the same
+//! request with another `Accept-Encoding` could have had them compressed.
+//!
+//! Small bodies, images, gRPC, `text/event-stream` and the
+//! [`crate::Config::uncompressed_content_types`] are never compressed.
+
+use deps::*;
+
+use axum::http::{header, HeaderMap};
+use tower_http::compression::predicate::{DefaultPredicate, NotForContentType, Predicate};
+
+/// Defaults for [`crate::Config::uncompressed_content_types`], already compressed
+/// formats that would only cost cpu.
+pub const DEFAULT_UNCOMPRESSED_CONTENT_TYPES: &[&str] = &[
+    "application/gzip",
+    "application/zip",
+    "application/zstd",
+    "audio/",
+    "video/",
+];
+
+/// Responses whose content type starts with any of the `uncompressed` ones are
+/// left as they are.
+pub fn layer(router: axum::Router, uncompressed: &[String]) -> axum::Router {
+    let uncompressed: std::sync::Arc<[String]> = uncompressed.into();
+    router
+        .layer(
+            tower_http::compression::CompressionLayer::new().compress_when(
+                DefaultPredicate::new()
+                    // compressors hold on to the events until they have enough
+                    .and(NotForContentType::const_new("text/event-stream"))
+                    .and(
+                        move |_: axum::http::StatusCode,
+                              _: axum::http::Version,
+                              headers: &HeaderMap,
+                              _: &axum::http::Extensions| {
+                            let content_type = headers
+                                .get(header::CONTENT_TYPE)
+                                .and_then(|val| val.to_str().ok())
+                                .unwrap_or_default();
+                            !uncompressed
+                                .iter()
+                                .any(|prefix| content_type.starts_with(prefix.as_str()))
+                        },
+                    ),
+            ),
+        )
+        .layer(axum::middleware::from_fn(
+            |req: axum::http::Request<axum::body::Body>,
+             next: axum::middleware::Next<axum::body::Body>| async move {
+                let mut resp = next.run(req).await;
+                super::add_vary(resp.headers_mut(), &[header::ACCEPT_ENCODING]);
+                resp
+            },
+        ))
+}
+
+#[cfg(test)]
+mod tests {
+    use deps::*;
+
+    use super::*;
+    use crate::utils::testing::*;
+
+    fn app() -> axum::Router {
+        let body = "hello there, ".repeat(64);
+        let events = {
+            let body = body.clone();
+            move || {
+                let events = (0..3)
+                    .map(|_| {
+                        Ok::<_, std::convert::Infallible>(
+                            axum::response::sse::Event::default().data(&body),
+                        )
+                    })
+                    .collect::<Vec<_>>();
+                async move { axum::response::sse::Sse::new(futures::stream::iter(events)) }
+            }
+        };
+        let typed = |content_type: &'static str| {
+            let body = body.clone();
+            move || {
+                let body = body.clone();
+                async move { ([(header::CONTENT_TYPE, content_type)], body) }
+            }
+        };
+        layer(
+            axum::Router::new()
+                .route("/events", axum::routing::get(events))
+                .route("/text", axum::routing::get(typed("text/plain")))
+                .route("/csv", axum::routing::get(typed("text/csv")))
+                .route("/zip", axum::routing::get(typed("application/zip"))),
+            &["text/csv".to_string(), "application/zip".to_string()],
+        )
+    }
+
+    crate::table_tests! {
+        compression tokio,
+        (path, expected_encoding),
+        {
+            let resp = app()
+                .oneshot(
+                    http::Request::builder()
+                        .method("GET")
+                        .uri(path)
+                        .header(header::ACCEPT_ENCODING, "gzip")
+                        .body(Default::default())
+                        .unwrap_or_log(),
+                )
+                .await
+                .unwrap_or_log();
+            assert_eq!(resp.status(), StatusCode::OK);
+            let expected_encoding: Option<&str> = expected_encoding;
+            assert_eq!(
+                resp.headers()
+                    .get(header::CONTENT_ENCODING)
+                    .map(|val| val.to_str().unwrap()),
+                expected_encoding
+            );
+            assert_eq!(resp.headers()[header::VARY], "accept-encoding");
+        },
+    }
+
+    compression! {
+        compresses_text: ("/text", Some("gzip")),
+        skips_event_streams: ("/events", None),
+        skips_excluded_types: ("/csv", None),
+        skips_compressed_types: ("/zip", None),
+    }
+}
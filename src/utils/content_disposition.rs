@@ -0,0 +1,213 @@
+//! Downloads that the browser should save under a suggested name rather than
+//! display, as described in [RFC 6266](https://www.rfc-editor.org/rfc/rfc6266).
+
+use deps::*;
+
+use axum::{
+    body::Bytes,
+    http::{header, HeaderValue},
+    response::{IntoResponse, Response},
+};
+use utoipa::openapi;
+
+/// `attachment` with the `filename` as a quoted ASCII fallback and, if that
+/// lost anything, a UTF-8 `filename*` encoded per
+/// [RFC 5987](https://www.rfc-editor.org/rfc/rfc5987).
+pub fn content_disposition(filename: &str) -> HeaderValue {
+    let fallback = filename
+        .chars()
+        .map(|ch| match ch {
+            ' '..='~' => ch,
+            _ => '_',
+        })
+        .collect::<String>()
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"");
+    let mut val = format!(r#"attachment; filename="{fallback}""#);
+    if !filename.chars().all(|ch| matches!(ch, ' '..='~')) {
+        val.push_str("; filename*=UTF-8''");
+        for byte in filename.bytes() {
+            match byte {
+                b'A'..=b'Z'
+                | b'a'..=b'z'
+                | b'0'..=b'9'
+                | b'!'
+                | b'#'
+                | b'$'
+                | b'&'
+                | b'+'
+                | b'-'
+                | b'.'
+                | b'^'
+                | b'_'
+                | b'`'
+                | b'|'
+                | b'~' => val.push(byte as char),
+                _ => val.push_str(&format!("%{byte:02X}")),
+            }
+        }
+    }
+    HeaderValue::from_str(&val).expect("only visible ASCII is used")
+}
+
+/// Response for endpoints that return a file. Documented as binary data of the
+/// [`Attachment::CONTENT_TYPE`] instead of json.
+#[derive(Debug, Clone)]
+pub struct Attachment {
+    pub filename: String,
+    /// Defaults to [`Attachment::CONTENT_TYPE`] if not set.
+    pub content_type: Option<HeaderValue>,
+    pub body: Bytes,
+}
+
+impl Attachment {
+    pub const CONTENT_TYPE: &'static str = "application/octet-stream";
+}
+
+impl IntoResponse for Attachment {
+    fn into_response(self) -> Response {
+        (
+            [
+                (
+                    header::CONTENT_TYPE,
+                    self.content_type
+                        .unwrap_or_else(|| HeaderValue::from_static(Self::CONTENT_TYPE)),
+                ),
+                (
+                    header::CONTENT_DISPOSITION,
+                    content_disposition(&self.filename),
+                ),
+            ],
+            self.body,
+        )
+            .into_response()
+    }
+}
+
+impl crate::ToRefOrSchema for Attachment {
+    fn schema_name() -> &'static str {
+        super::type_name_raw::<Attachment>()
+    }
+
+    fn ref_or_schema() -> openapi::schema::RefOr<openapi::schema::Schema> {
+        openapi::schema::ObjectBuilder::new()
+            .schema_type(openapi::SchemaType::String)
+            .format(Some(openapi::SchemaFormat::Binary))
+            .into()
+    }
+
+    fn content_type() -> &'static str {
+        Self::CONTENT_TYPE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use deps::*;
+
+    use super::*;
+    use crate::utils::testing::*;
+    use crate::*;
+
+    crate::table_tests! {
+        disposition_header,
+        (filename, expected),
+        {
+            assert_eq!(content_disposition(filename), expected);
+        }
+    }
+
+    disposition_header! {
+        ascii: ("report-2022.csv", r#"attachment; filename="report-2022.csv""#),
+        escapes_quotes: (r#"say "hi".txt"#, r#"attachment; filename="say \"hi\".txt""#),
+        utf8: (
+            "résumé 📄.pdf",
+            r#"attachment; filename="r_sum_ _.pdf"; filename*=UTF-8''r%C3%A9sum%C3%A9%20%F0%9F%93%84.pdf"#,
+        ),
+    }
+
+    #[derive(Debug, Clone, Copy)]
+    struct Download;
+
+    #[async_trait::async_trait]
+    impl Endpoint for Download {
+        type Request = ();
+        type Response = Attachment;
+        type Error = Infallible;
+
+        async fn handle(
+            &self,
+            _ctx: &crate::Context,
+            _request: Self::Request,
+        ) -> Result<Self::Response, Self::Error> {
+            Ok(Attachment {
+                filename: "naïve.txt".into(),
+                content_type: Some(HeaderValue::from_static("text/plain")),
+                body: "contents".into(),
+            })
+        }
+    }
+
+    impl HttpEndpoint for Download {
+        const METHOD: Method = Method::Get;
+        const PATH: &'static str = "/download";
+
+        type HttpRequest = ();
+
+        fn request(_: Self::HttpRequest) -> Result<Self::Request, Self::Error> {
+            Ok(())
+        }
+
+        fn response(resp: Self::Response) -> axum::response::Response {
+            resp.into_response()
+        }
+    }
+
+    impl DocumentedEndpoint for Download {
+        fn errors() -> Vec<ErrorResponse<Self::Error>> {
+            vec![]
+        }
+    }
+
+    #[tokio::test]
+    async fn serves_attachment() {
+        setup_tracing_once();
+        let app = endpoint_app(Download);
+        let resp = app
+            .oneshot(
+                http::Request::builder()
+                    .method("GET")
+                    .uri("/download")
+                    .body(Default::default())
+                    .unwrap_or_log(),
+            )
+            .await
+            .unwrap_or_log();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers()[header::CONTENT_TYPE], "text/plain");
+        assert_eq!(
+            resp.headers()[header::CONTENT_DISPOSITION],
+            r#"attachment; filename="na_ve.txt"; filename*=UTF-8''na%C3%AFve.txt"#
+        );
+        let body = hyper::body::to_bytes(resp.into_body())
+            .await
+            .unwrap_or_log();
+        assert_eq!(&body[..], b"contents");
+    }
+
+    #[test]
+    fn documented_as_binary() {
+        let op = serde_json::to_value(Download::path_item()).unwrap();
+        let content = op["get"]["responses"]["200"]["content"]
+            .as_object()
+            .unwrap();
+        assert_eq!(
+            content.keys().collect::<Vec<_>>(),
+            ["application/octet-stream"]
+        );
+        assert_eq!(
+            content["application/octet-stream"]["schema"],
+            serde_json::json!({ "type": "string", "format": "binary" })
+        );
+    }
+}
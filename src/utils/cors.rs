@@ -0,0 +1,154 @@
+//! CORS for the origins in [`crate::Config::cors_origins`]. Preflights are
+//! answered with the methods the router actually has for the path, taken from
+//! the `Allow` header axum puts on its `405`s, so nothing has to be kept in sync
+//! by hand.
+
+use deps::*;
+
+use axum::{
+    http::{header, HeaderValue, Method, StatusCode},
+    response::{IntoResponse, Response},
+};
+
+use crate::SharedContext;
+
+/// How long browsers may cache a preflight.
+pub const PREFLIGHT_MAX_AGE_SECS: u64 = 600;
+
+fn origin_allowed(origins: &[String], origin: &HeaderValue) -> bool {
+    origins
+        .iter()
+        .any(|allowed| allowed == "*" || origin.as_bytes() == allowed.as_bytes())
+}
+
+/// Applies [`cors`] around the whole of `router`. Axum's `Router::layer` wraps
+/// each method's handler on its own so the middleware would see the `405`s
+/// before the method router adds their `Allow` header.
+pub fn with_cors(router: axum::Router) -> axum::Router {
+    axum::Router::new()
+        .fallback(router)
+        .layer(axum::middleware::from_fn(cors))
+}
+
+/// Expects the [`SharedContext`] in the request extensions. Does nothing for
+/// requests from origins that aren't allowed, leaving it to the browser to
+/// block them.
+pub async fn cors(
+    req: axum::http::Request<axum::body::Body>,
+    next: axum::middleware::Next<axum::body::Body>,
+) -> Response {
+    let origin = match (
+        req.extensions().get::<SharedContext>(),
+        req.headers().get(header::ORIGIN),
+    ) {
+        (Some(ctx), Some(origin)) if origin_allowed(&ctx.config.cors_origins, origin) => {
+            origin.clone()
+        }
+        _ => return next.run(req).await,
+    };
+    let is_preflight = req.method() == Method::OPTIONS
+        && req
+            .headers()
+            .contains_key(header::ACCESS_CONTROL_REQUEST_METHOD);
+    if !is_preflight {
+        let mut resp = next.run(req).await;
+        let headers = resp.headers_mut();
+        headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+        headers.append(header::VARY, HeaderValue::from_static("origin"));
+        return resp;
+    }
+    let request_headers = req
+        .headers()
+        .get(header::ACCESS_CONTROL_REQUEST_HEADERS)
+        .cloned();
+    let resp = next.run(req).await;
+    // unknown paths and routes with their own `OPTIONS` handler are left be
+    let methods = match (resp.status(), resp.headers().get(header::ALLOW)) {
+        (StatusCode::METHOD_NOT_ALLOWED, Some(allow)) => allow.clone(),
+        _ => return resp,
+    };
+    let mut resp = StatusCode::NO_CONTENT.into_response();
+    let headers = resp.headers_mut();
+    headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+    headers.insert(header::ACCESS_CONTROL_ALLOW_METHODS, methods);
+    if let Some(request_headers) = request_headers {
+        headers.insert(header::ACCESS_CONTROL_ALLOW_HEADERS, request_headers);
+    }
+    headers.insert(
+        header::ACCESS_CONTROL_MAX_AGE,
+        HeaderValue::from(PREFLIGHT_MAX_AGE_SECS),
+    );
+    headers.insert(header::VARY, HeaderValue::from_static("origin"));
+    resp
+}
+
+#[cfg(test)]
+mod tests {
+    use deps::*;
+
+    use super::*;
+    use crate::utils::testing::*;
+
+    const ORIGIN: &str = "https://app.example.com";
+
+    fn app() -> axum::Router {
+        let mut ctx = crate::Context::mock(InMemoryDb::fixture());
+        ctx.config.cors_origins = vec![ORIGIN.into()];
+        with_cors(axum::Router::new().route("/things", axum::routing::post(|| async { "created" })))
+            .layer(axum::Extension(std::sync::Arc::new(ctx)))
+    }
+
+    fn preflight(origin: &str) -> http::Request<axum::body::Body> {
+        http::Request::builder()
+            .method("OPTIONS")
+            .uri("/things")
+            .header(header::ORIGIN, origin)
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+            .header(header::ACCESS_CONTROL_REQUEST_HEADERS, "content-type")
+            .body(Default::default())
+            .unwrap_or_log()
+    }
+
+    #[tokio::test]
+    async fn preflight_lists_registered_methods() {
+        let resp = app().oneshot(preflight(ORIGIN)).await.unwrap_or_log();
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        let headers = resp.headers();
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_ORIGIN], ORIGIN);
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_METHODS], "POST");
+        assert_eq!(
+            headers[header::ACCESS_CONTROL_ALLOW_HEADERS],
+            "content-type"
+        );
+    }
+
+    #[tokio::test]
+    async fn ignores_other_origins() {
+        let resp = app()
+            .oneshot(preflight("https://evil.example.com"))
+            .await
+            .unwrap_or_log();
+        assert_eq!(resp.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert!(!resp
+            .headers()
+            .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+    }
+
+    #[tokio::test]
+    async fn allows_origin_on_actual_requests() {
+        let resp = app()
+            .oneshot(
+                http::Request::builder()
+                    .method("POST")
+                    .uri("/things")
+                    .header(header::ORIGIN, ORIGIN)
+                    .body(Default::default())
+                    .unwrap_or_log(),
+            )
+            .await
+            .unwrap_or_log();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN], ORIGIN);
+        assert_eq!(resp.headers()[header::VARY], "origin");
+    }
+}
@@ -0,0 +1,190 @@
+//! Per request deadlines. The [`enforce_deadline`] middleware sets one for the
+//! handler's task and [`crate::Context::guard_db`] picks it up so queries are
+//! dropped, and with them their connection, instead of running on after nobody's
+//! waiting for the answer anymore.
+
+use deps::*;
+
+use std::future::Future;
+
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Json, Response},
+};
+
+use crate::{RoutingError, SharedContext};
+
+tokio::task_local! {
+    static DEADLINE: tokio::time::Instant;
+}
+
+/// The deadline of the request being handled on this task if any.
+pub fn current_deadline() -> Option<tokio::time::Instant> {
+    DEADLINE.try_with(|deadline| *deadline).ok()
+}
+
+/// Runs `fut` with `deadline` visible to [`current_deadline`]. Doesn't cut it
+/// short by itself.
+pub async fn with_deadline<F: Future>(deadline: tokio::time::Instant, fut: F) -> F::Output {
+    DEADLINE.scope(deadline, fut).await
+}
+
+/// Answers with a `504` once [`crate::Config::request_timeout`] elapses, dropping
+/// whatever the handler was still doing. Expects the [`SharedContext`] in the
+/// request extensions.
+pub async fn enforce_deadline(
+    req: axum::http::Request<axum::body::Body>,
+    next: axum::middleware::Next<axum::body::Body>,
+) -> Response {
+    let timeout = req
+        .extensions()
+        .get::<SharedContext>()
+        .and_then(|ctx| ctx.config.request_timeout);
+    let timeout = match timeout {
+        Some(timeout) => timeout,
+        None => return next.run(req).await,
+    };
+    let deadline = tokio::time::Instant::now() + timeout;
+    match tokio::time::timeout_at(deadline, with_deadline(deadline, next.run(req))).await {
+        // `guard_db` gives up at the same instant and the handler answers with
+        // whatever it makes of that, usually a 503, before this timer fires
+        Ok(resp) if tokio::time::Instant::now() < deadline => resp,
+        _ => {
+            tracing::warn!(?timeout, "request deadline exceeded");
+            (
+                StatusCode::GATEWAY_TIMEOUT,
+                Json(RoutingError::DeadlineExceeded),
+            )
+                .into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use deps::*;
+
+    use super::*;
+    use crate::auth::{ApiKeyRecord, Role, Session};
+    use crate::user::{testing::*, User};
+    use crate::utils::testing::*;
+
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    /// Takes its time answering [`crate::db::DbBackend::user`] and records whether
+    /// the query ran to completion or got dropped.
+    #[derive(Debug, Default)]
+    struct SlowDb {
+        inner: InMemoryDb,
+        started: tokio::sync::Notify,
+        finished: AtomicBool,
+        cancelled: AtomicBool,
+    }
+
+    struct OnDrop<'a>(&'a SlowDb);
+
+    impl Drop for OnDrop<'_> {
+        fn drop(&mut self) {
+            if !self.0.finished.load(Ordering::SeqCst) {
+                self.0.cancelled.store(true, Ordering::SeqCst);
+            }
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl crate::db::DbBackend for Arc<SlowDb> {
+        async fn session(&self, token: &str) -> Result<Session, sqlx::Error> {
+            self.inner.session(token).await
+        }
+
+        async fn user(&self, id: uuid::Uuid) -> Result<User, sqlx::Error> {
+            let _guard = OnDrop(self);
+            self.started.notify_one();
+            tokio::time::sleep(std::time::Duration::from_secs(60)).await;
+            self.finished.store(true, Ordering::SeqCst);
+            self.inner.user(id).await
+        }
+
+        async fn user_roles(&self, user_id: uuid::Uuid) -> Result<Vec<Role>, sqlx::Error> {
+            self.inner.user_roles(user_id).await
+        }
+
+        async fn api_key(&self, key_hash: &str) -> Result<ApiKeyRecord, sqlx::Error> {
+            self.inner.api_key(key_hash).await
+        }
+    }
+
+    fn app(request_timeout: Option<std::time::Duration>) -> (axum::Router, Arc<SlowDb>) {
+        let db = Arc::new(SlowDb {
+            inner: InMemoryDb::fixture(),
+            ..Default::default()
+        });
+        let mut ctx = crate::Context::mock(db.clone());
+        ctx.config.request_timeout = request_timeout;
+        let app = crate::user::router()
+            .layer(axum::middleware::from_fn(enforce_deadline))
+            .layer(axum::Extension(Arc::new(ctx)));
+        (app, db)
+    }
+
+    fn get_user() -> http::Request<axum::body::Body> {
+        http::Request::builder()
+            .method("GET")
+            .uri(format!("/users/{USER_01_ID}"))
+            .header(
+                http::header::AUTHORIZATION,
+                format!("Bearer {USER_01_SESSION}"),
+            )
+            .body(Default::default())
+            .unwrap_or_log()
+    }
+
+    #[tokio::test]
+    async fn client_disconnect_cancels_query() {
+        setup_tracing_once();
+        let (app, db) = app(None);
+        // hyper drops the response future when the client goes away
+        tokio::select! {
+            _ = app.oneshot(get_user()) => panic!("query ran to completion"),
+            _ = db.started.notified() => {}
+        }
+        assert!(db.cancelled.load(Ordering::SeqCst));
+        assert!(!db.finished.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn deadline_cancels_query() {
+        setup_tracing_once();
+        let (app, db) = app(Some(std::time::Duration::from_millis(50)));
+        let resp = app.oneshot(get_user()).await.unwrap_or_log();
+        assert_eq!(resp.status(), StatusCode::GATEWAY_TIMEOUT);
+        let body = hyper::body::to_bytes(resp.into_body())
+            .await
+            .unwrap_or_log();
+        let body = serde_json::from_slice(&body).unwrap_or_log();
+        check_json(
+            (
+                "expected",
+                &serde_json::json!({ "error": "deadlineExceeded" }),
+            ),
+            ("response", &body),
+        );
+        assert!(db.cancelled.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn guard_db_honors_deadline() {
+        let (_, db) = app(None);
+        let ctx = crate::Context::mock(db.clone());
+        let deadline = tokio::time::Instant::now() + std::time::Duration::from_millis(50);
+        let result = with_deadline(deadline, ctx.guard_db(ctx.db.user(USER_01_ID))).await;
+        assert!(matches!(result, Err(sqlx::Error::PoolTimedOut)));
+        assert!(db.cancelled.load(Ordering::SeqCst));
+        // running out of time says nothing about the db's health
+        assert_eq!(
+            ctx.db_breaker.state(),
+            crate::utils::BreakerState::Closed { failures: 0 }
+        );
+    }
+}
@@ -0,0 +1,191 @@
+use deps::*;
+
+use utoipa::openapi;
+
+pub const DECIMAL_FORMAT: &str = "decimal";
+
+/// Exact decimal for Postgres `NUMERIC` columns, money and the like. It goes
+/// over json as a string, `"10.05"`, since clients parsing numbers into floats
+/// would lose precision. Documented as a `string($decimal)`, see
+/// [`add_decimal_format`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Decimal(pub rust_decimal::Decimal);
+
+// by hand instead of `#[derive(sqlx::Type)]` since the derive looks for a
+// `postgres` feature on this crate
+
+impl sqlx::Type<sqlx::Postgres> for Decimal {
+    fn type_info() -> sqlx::postgres::PgTypeInfo {
+        <rust_decimal::Decimal as sqlx::Type<sqlx::Postgres>>::type_info()
+    }
+
+    fn compatible(ty: &sqlx::postgres::PgTypeInfo) -> bool {
+        <rust_decimal::Decimal as sqlx::Type<sqlx::Postgres>>::compatible(ty)
+    }
+}
+
+impl<'q> sqlx::Encode<'q, sqlx::Postgres> for Decimal {
+    fn encode_by_ref(&self, buf: &mut sqlx::postgres::PgArgumentBuffer) -> sqlx::encode::IsNull {
+        <rust_decimal::Decimal as sqlx::Encode<'q, sqlx::Postgres>>::encode_by_ref(&self.0, buf)
+    }
+}
+
+impl<'r> sqlx::Decode<'r, sqlx::Postgres> for Decimal {
+    fn decode(value: sqlx::postgres::PgValueRef<'r>) -> Result<Self, sqlx::error::BoxDynError> {
+        <rust_decimal::Decimal as sqlx::Decode<'r, sqlx::Postgres>>::decode(value).map(Self)
+    }
+}
+
+impl From<rust_decimal::Decimal> for Decimal {
+    fn from(val: rust_decimal::Decimal) -> Self {
+        Self(val)
+    }
+}
+
+impl std::str::FromStr for Decimal {
+    type Err = rust_decimal::Error;
+
+    /// Fails instead of rounding if there are more digits than fit.
+    fn from_str(val: &str) -> Result<Self, Self::Err> {
+        rust_decimal::Decimal::from_str_exact(val).map(Self)
+    }
+}
+
+impl std::fmt::Display for Decimal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl serde::Serialize for Decimal {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Decimal {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        struct Visitor;
+
+        impl<'de> serde::de::Visitor<'de> for Visitor {
+            type Value = Decimal;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("a decimal string")
+            }
+
+            fn visit_str<E: serde::de::Error>(self, val: &str) -> Result<Self::Value, E> {
+                val.parse().map_err(E::custom)
+            }
+
+            // integers are exact, floats have already lost whatever precision
+            fn visit_i64<E: serde::de::Error>(self, val: i64) -> Result<Self::Value, E> {
+                Ok(Decimal(val.into()))
+            }
+
+            fn visit_u64<E: serde::de::Error>(self, val: u64) -> Result<Self::Value, E> {
+                Ok(Decimal(val.into()))
+            }
+        }
+
+        deserializer.deserialize_any(Visitor)
+    }
+}
+
+impl utoipa::ToSchema for Decimal {
+    fn schema() -> openapi::Schema {
+        openapi::Schema::Object(
+            openapi::schema::ObjectBuilder::new()
+                .schema_type(openapi::SchemaType::String)
+                .description(Some("Exact decimal number."))
+                .example(Some("10.05".into()))
+                .build(),
+        )
+    }
+}
+
+/// utoipa 2 only knows of a fixed set of formats so the [`DECIMAL_FORMAT`] is
+/// put on the `Decimal` component once the spec is serialized.
+pub fn add_decimal_format(spec: &mut serde_json::Value) {
+    if let Some(serde_json::Value::Object(schema)) = spec.pointer_mut(&format!(
+        "/components/schemas/{}",
+        super::type_name_raw::<Decimal>()
+    )) {
+        schema.insert("format".into(), DECIMAL_FORMAT.into());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use deps::*;
+
+    use super::*;
+    use crate::utils::testing::*;
+
+    #[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+    #[serde(crate = "serde")]
+    struct Invoice {
+        total: Decimal,
+    }
+
+    #[test]
+    fn serializes_as_string() {
+        let invoice = Invoice {
+            total: "10.05".parse().unwrap(),
+        };
+        assert_eq!(
+            serde_json::to_value(&invoice).unwrap(),
+            serde_json::json!({ "total": "10.05" })
+        );
+    }
+
+    #[test]
+    fn round_trips_without_precision_loss() {
+        for total in [
+            "10.05",
+            "0.1",
+            "79228162514264337593543950335",
+            "-0.0000000001",
+        ] {
+            let json = serde_json::json!({ "total": total });
+            let invoice: Invoice = serde_json::from_value(json.clone()).unwrap();
+            assert_eq!(invoice.total.to_string(), total);
+            assert_eq!(serde_json::to_value(&invoice).unwrap(), json);
+        }
+        assert!(serde_json::from_value::<Invoice>(serde_json::json!({ "total": 10.05 })).is_err());
+        assert!(serde_json::from_value::<Invoice>(serde_json::json!({ "total": "ten" })).is_err());
+    }
+
+    #[test]
+    fn documented_as_decimal_string() {
+        let spec = crate::openapi_json();
+        check_json(
+            (
+                "expected",
+                &serde_json::json!({ "type": "string", "format": "decimal" }),
+            ),
+            ("schema", &spec["components"]["schemas"]["Decimal"]),
+        );
+    }
+
+    #[tokio::test]
+    async fn round_trips_through_numeric() {
+        let ctx = TestContext::new(crate::function!()).await;
+        {
+            let total: Decimal = "79228162514264337593543950335".parse().unwrap();
+            let back: Decimal = sqlx::query_scalar("SELECT $1::NUMERIC")
+                .bind(total)
+                .fetch_one(&ctx.ctx().db_pool)
+                .await
+                .unwrap_or_log();
+            assert_eq!(back, total);
+        }
+        ctx.close().await;
+    }
+}
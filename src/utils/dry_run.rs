@@ -0,0 +1,63 @@
+//! Lets clients check a write without applying it, see
+//! [`crate::HttpEndpoint::DRY_RUN`]. Handlers do their writes in a
+//! [`crate::Context::begin`] transaction which rolls back instead of committing
+//! when [`is_dry_run`].
+
+use deps::*;
+
+use std::future::Future;
+
+use utoipa::openapi;
+
+pub const QUERY: &str = "dry_run";
+
+tokio::task_local! {
+    static DRY_RUN: ();
+}
+
+/// Whether the request being handled on this task is a dry run.
+pub fn is_dry_run() -> bool {
+    DRY_RUN.try_with(|_| ()).is_ok()
+}
+
+/// Runs `fut` as a dry run, see [`is_dry_run`].
+pub async fn with_dry_run<F: Future>(fut: F) -> F::Output {
+    DRY_RUN.scope((), fut).await
+}
+
+/// Whether the request asks for a dry run through `?dry_run=true` or
+/// `Prefer: dry-run`.
+pub fn requests_dry_run<B>(req: &axum::http::Request<B>) -> bool {
+    let in_query = req
+        .uri()
+        .query()
+        .into_iter()
+        .flat_map(|query| query.split('&'))
+        .any(|pair| matches!(pair.split_once('='), Some((QUERY, "true"))));
+    in_query
+        || req
+            .headers()
+            .get_all("prefer")
+            .iter()
+            .filter_map(|val| val.to_str().ok())
+            .flat_map(|val| val.split(','))
+            .filter_map(|pref| pref.split(';').next())
+            .any(|pref| pref.trim().eq_ignore_ascii_case("dry-run"))
+}
+
+pub fn parameter() -> openapi::path::Parameter {
+    openapi::path::ParameterBuilder::new()
+        .name(QUERY)
+        .parameter_in(openapi::path::ParameterIn::Query)
+        .required(openapi::Required::False)
+        .description(Some(
+            "Validate and run the operation without applying it, answering with what \
+             would have happened. Same as sending `Prefer: dry-run`.",
+        ))
+        .schema(Some(
+            openapi::schema::ObjectBuilder::new()
+                .schema_type(openapi::SchemaType::Boolean)
+                .default(Some(false.into())),
+        ))
+        .build()
+}
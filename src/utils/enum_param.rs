@@ -0,0 +1,250 @@
+use deps::*;
+
+use axum::{
+    extract::{FromRequest, Path, Query, RequestParts},
+    response::{IntoResponse, Response},
+};
+use std::collections::HashMap;
+use utoipa::openapi;
+
+use super::ApiError;
+use crate::{DocumentedParameter, ParameterDoc};
+
+/// A unit enum used as a path or query parameter through [`EnumQuery`] or
+/// [`EnumPath`]. The allowed values are the `enum` list of its
+/// [`utoipa::ToSchema`] so keep the `serde` and schema renames in sync.
+pub trait EnumParam: serde::de::DeserializeOwned + utoipa::ToSchema + Send {
+    /// Name of the query parameter or path segment.
+    const NAME: &'static str;
+
+    fn allowed() -> Vec<String> {
+        match Self::schema() {
+            openapi::Schema::Object(obj) => obj.enum_values.unwrap_or_default(),
+            _ => vec![],
+        }
+    }
+
+    fn parse(value: &str) -> Result<Self, ApiError> {
+        serde_json::from_value(serde_json::Value::String(value.into())).map_err(|_| {
+            ApiError::InvalidEnum {
+                name: Self::NAME.into(),
+                value: value.into(),
+                allowed: Self::allowed(),
+            }
+        })
+    }
+
+    fn parameter_doc(location: openapi::path::ParameterIn) -> Vec<ParameterDoc> {
+        vec![openapi::path::ParameterBuilder::new()
+            .name(Self::NAME)
+            .parameter_in(location)
+            .required(openapi::Required::True)
+            .schema(Some(
+                openapi::schema::ObjectBuilder::new()
+                    .schema_type(openapi::SchemaType::String)
+                    .enum_values(Some(Self::allowed())),
+            ))
+            .build()
+            .into()]
+    }
+}
+
+/// Required query parameter named [`EnumParam::NAME`].
+#[derive(Debug)]
+pub struct EnumQuery<T>(pub T);
+
+#[async_trait::async_trait]
+impl<T, B> FromRequest<B> for EnumQuery<T>
+where
+    T: EnumParam,
+    B: Send,
+{
+    type Rejection = Response;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let Query(params) = Query::<HashMap<String, String>>::from_request(req)
+            .await
+            .map_err(|err| {
+                ApiError::InvalidQuery {
+                    message: err.to_string(),
+                }
+                .into_response()
+            })?;
+        let value = params.get(T::NAME).ok_or_else(|| {
+            ApiError::InvalidQuery {
+                message: format!("missing field `{}`", T::NAME),
+            }
+            .into_response()
+        })?;
+        T::parse(value)
+            .map(Self)
+            .map_err(IntoResponse::into_response)
+    }
+}
+
+impl<T> DocumentedParameter for EnumQuery<T>
+where
+    T: EnumParam,
+{
+    fn to_openapi(_op_id: &str, _path: &str) -> Vec<ParameterDoc> {
+        T::parameter_doc(openapi::path::ParameterIn::Query)
+    }
+}
+
+/// Path segment named [`EnumParam::NAME`], e.g. `/items/:status`.
+#[derive(Debug)]
+pub struct EnumPath<T>(pub T);
+
+#[async_trait::async_trait]
+impl<T, B> FromRequest<B> for EnumPath<T>
+where
+    T: EnumParam,
+    B: Send,
+{
+    type Rejection = Response;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let Path(params) = Path::<HashMap<String, String>>::from_request(req)
+            .await
+            .map_err(IntoResponse::into_response)?;
+        let value = params.get(T::NAME).ok_or_else(|| {
+            tracing::error!(name = T::NAME, "no such path segment, misconfigured route");
+            super::ApiError::BadRequest {
+                message: format!("missing path parameter {:?}", T::NAME),
+            }
+            .into_response()
+        })?;
+        T::parse(value)
+            .map(Self)
+            .map_err(IntoResponse::into_response)
+    }
+}
+
+impl<T> DocumentedParameter for EnumPath<T>
+where
+    T: EnumParam,
+{
+    fn to_openapi(_op_id: &str, _path: &str) -> Vec<ParameterDoc> {
+        T::parameter_doc(openapi::path::ParameterIn::Path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use deps::*;
+
+    use super::*;
+    use crate::utils::testing::*;
+
+    #[derive(Debug, serde::Deserialize, utoipa::ToSchema)]
+    #[serde(crate = "serde", rename_all = "camelCase")]
+    enum Status {
+        Active,
+        Archived,
+    }
+
+    impl EnumParam for Status {
+        const NAME: &'static str = "status";
+    }
+
+    fn router() -> axum::Router {
+        let handler = |status: Status| async move { format!("{status:?}") };
+        axum::Router::new()
+            .route(
+                "/items",
+                axum::routing::get(move |EnumQuery(status): EnumQuery<Status>| handler(status)),
+            )
+            .route(
+                "/items/:status",
+                axum::routing::get(move |EnumPath(status): EnumPath<Status>| handler(status)),
+            )
+            .route(
+                "/misrouted/:state",
+                axum::routing::get(move |EnumPath(status): EnumPath<Status>| handler(status)),
+            )
+    }
+
+    crate::table_tests! {
+        enum_param tokio,
+        (uri, status, expected),
+        {
+            let resp = router()
+                .oneshot(
+                    http::Request::builder()
+                        .method("GET")
+                        .uri(uri)
+                        .body(Default::default())
+                        .unwrap_or_log(),
+                )
+                .await
+                .unwrap_or_log();
+            assert_eq!(resp.status(), status);
+            let body = hyper::body::to_bytes(resp.into_body())
+                .await
+                .unwrap_or_log();
+            match expected {
+                Some(expected) => {
+                    let body = serde_json::from_slice(&body).unwrap_or_log();
+                    check_json(("expected", &expected), ("response", &body));
+                }
+                None => assert_eq!(&body[..], b"Archived"),
+            }
+        },
+    }
+
+    enum_param! {
+        accepts_allowed_query_values: ("/items?status=archived", StatusCode::OK, None),
+        accepts_allowed_path_values: ("/items/archived", StatusCode::OK, None),
+        rejects_other_query_values: (
+            "/items?status=deleted",
+            StatusCode::BAD_REQUEST,
+            Some(serde_json::json!({
+                "error": "invalidEnum",
+                "name": "status",
+                "value": "deleted",
+                "allowed": ["active", "archived"],
+            })),
+        ),
+        rejects_other_path_values: (
+            "/items/deleted",
+            StatusCode::BAD_REQUEST,
+            Some(serde_json::json!({
+                "error": "invalidEnum",
+                "allowed": ["active", "archived"],
+            })),
+        ),
+        rejects_misconfigured_routes: (
+            "/misrouted/archived",
+            StatusCode::BAD_REQUEST,
+            Some(serde_json::json!({ "error": "badRequest" })),
+        ),
+        rejects_missing_query_values: (
+            "/items",
+            StatusCode::BAD_REQUEST,
+            Some(serde_json::json!({ "error": "invalidQuery" })),
+        ),
+    }
+
+    #[test]
+    fn documents_allowed_values() {
+        let params = <EnumQuery<Status>>::to_openapi("", "/items")
+            .into_iter()
+            .map(|param| match param {
+                ParameterDoc::Param(param) => serde_json::to_value(param).unwrap(),
+                ParameterDoc::Body(_) => panic!("query documented as body"),
+            })
+            .collect::<Vec<_>>();
+        check_json(
+            (
+                "expected",
+                &serde_json::json!([{
+                    "name": "status",
+                    "in": "query",
+                    "required": true,
+                    "schema": { "type": "string", "enum": ["active", "archived"] },
+                }]),
+            ),
+            ("params", &serde_json::Value::Array(params)),
+        );
+    }
+}
@@ -0,0 +1,86 @@
+//! Stable machine readable codes carried by error responses next to their `error`
+//! tag, e.g. `"user.not_found"`. Unlike the status and the message, clients can
+//! rely on these not changing meaning.
+
+use deps::*;
+
+use utoipa::openapi::{
+    self,
+    schema::{Object, RefOr, Schema},
+};
+
+pub trait ErrorCode {
+    /// `snake_case`, namespaced by what's at fault, e.g. `user.` or `auth.`.
+    fn code(&self) -> &'static str;
+}
+
+/// Error bodies carry the id of the request under this for users to quote when
+/// reporting them. Same as the [`super::REQUEST_ID`] header.
+pub const TRACE_ID_FIELD: &str = "trace_id";
+
+/// Serializes as the wrapped error with its [`ErrorCode::code`] added, and the
+/// [`super::current_request_id`] as [`TRACE_ID_FIELD`] if there's one.
+#[derive(Debug, serde::Serialize)]
+#[serde(crate = "serde")]
+pub struct WithCode<'a, E> {
+    code: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    trace_id: Option<std::sync::Arc<str>>,
+    #[serde(flatten)]
+    error: &'a E,
+}
+
+impl<'a, E: ErrorCode> WithCode<'a, E> {
+    pub fn new(error: &'a E) -> Self {
+        Self {
+            code: error.code(),
+            trace_id: super::current_request_id(),
+            error,
+        }
+    }
+}
+
+/// A string enum of the `codes`.
+pub fn codes_schema(codes: impl IntoIterator<Item = &'static str>) -> Schema {
+    let codes = codes.into_iter().collect::<std::collections::BTreeSet<_>>();
+    openapi::ObjectBuilder::new()
+        .schema_type(openapi::SchemaType::String)
+        .enum_values(Some(codes))
+        .build()
+        .into()
+}
+
+/// Adds a required `code` referencing the `codes` [`codes_schema`] and the optional
+/// [`TRACE_ID_FIELD`] to each variant of an error enum's schema.
+pub fn add_code_property(schema: Schema, codes: &str) -> Schema {
+    let add = |obj: &mut Object| {
+        obj.properties
+            .insert("code".into(), openapi::Ref::from_schema_name(codes).into());
+        obj.required.push("code".into());
+        obj.properties.insert(
+            TRACE_ID_FIELD.into(),
+            openapi::ObjectBuilder::new()
+                .schema_type(openapi::SchemaType::String)
+                .description(Some(
+                    "Id of the request, as echoed in the `X-Request-Id` header. Quote it \
+                     when reporting the error.",
+                ))
+                .into(),
+        );
+    };
+    match schema {
+        Schema::OneOf(mut one_of) => {
+            for item in &mut one_of.items {
+                if let RefOr::T(Schema::Object(obj)) = item {
+                    add(obj);
+                }
+            }
+            Schema::OneOf(one_of)
+        }
+        Schema::Object(mut obj) => {
+            add(&mut obj);
+            Schema::Object(obj)
+        }
+        schema => schema,
+    }
+}
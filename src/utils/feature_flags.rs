@@ -0,0 +1,170 @@
+//! Endpoints gated behind [`crate::HttpEndpoint::FEATURE_FLAG`]s. The routers and
+//! the spec are put together before there's a [`crate::Config`] so a gated route
+//! is always there but answers like an unknown one, with a `404`, unless its flag
+//! is in [`crate::Context::enabled_features`]. Its operation carries the flag under
+//! [`EXTENSION`] and is dropped from the served spec by [`strip_disabled`] on the
+//! same terms.
+
+use deps::*;
+
+use axum::{
+    http::{Request, StatusCode},
+    response::{IntoResponse, Json, Response},
+};
+
+use crate::{RoutingError, SharedContext};
+
+pub const EXTENSION: &str = "x-feature-flag";
+
+pub fn is_enabled(ctx: Option<&SharedContext>, flag: &str) -> bool {
+    ctx.map(|ctx| ctx.enabled_features().iter().any(|val| val == flag))
+        .unwrap_or_default()
+}
+
+/// Route layer for the endpoints with a [`crate::HttpEndpoint::FEATURE_FLAG`].
+pub async fn require_feature(
+    flag: &'static str,
+    req: Request<axum::body::Body>,
+    next: axum::middleware::Next<axum::body::Body>,
+) -> Response {
+    if is_enabled(req.extensions().get::<SharedContext>(), flag) {
+        return next.run(req).await;
+    }
+    (
+        StatusCode::NOT_FOUND,
+        Json(RoutingError::NotFound {
+            path: req.uri().path().to_string(),
+        }),
+    )
+        .into_response()
+}
+
+/// Removes the operations whose [`EXTENSION`] names a flag that's not `enabled`,
+/// and the paths left without any. Run after [`super::vendor_extensions::expand`].
+pub fn strip_disabled(spec: &mut serde_json::Value, enabled: &[String]) {
+    let paths = match spec
+        .get_mut("paths")
+        .and_then(|paths| paths.as_object_mut())
+    {
+        Some(paths) => paths,
+        None => return,
+    };
+    for item in paths.values_mut().filter_map(|item| item.as_object_mut()) {
+        item.retain(
+            |_, op| match op.get(EXTENSION).and_then(|flag| flag.as_str()) {
+                Some(flag) => enabled.iter().any(|val| val == flag),
+                None => true,
+            },
+        );
+    }
+    paths.retain(|_, item| {
+        item.as_object()
+            .map(|item| !item.is_empty())
+            .unwrap_or(true)
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use deps::*;
+
+    use crate::utils::testing::*;
+    use crate::*;
+
+    #[derive(Debug, serde::Serialize, utoipa::ToSchema)]
+    #[serde(crate = "serde", rename_all = "camelCase", tag = "error")]
+    pub enum Error {
+        Internal,
+    }
+
+    impl From<&Error> for StatusCode {
+        fn from(_: &Error) -> Self {
+            Self::INTERNAL_SERVER_ERROR
+        }
+    }
+
+    impl ErrorCode for Error {
+        fn code(&self) -> &'static str {
+            "service.internal"
+        }
+    }
+
+    #[derive(Debug, Clone, Copy)]
+    struct Beta;
+
+    #[async_trait::async_trait]
+    impl Endpoint for Beta {
+        type Request = ();
+        type Response = NoContent;
+        type Error = Error;
+
+        async fn handle(
+            &self,
+            _ctx: &crate::Context,
+            _request: Self::Request,
+        ) -> Result<Self::Response, Self::Error> {
+            Ok(NoContent)
+        }
+    }
+
+    impl HttpEndpoint for Beta {
+        const METHOD: Method = Method::Get;
+        const PATH: &'static str = "/beta";
+        const SUCCESS_CODE: StatusCode = StatusCode::NO_CONTENT;
+        const FEATURE_FLAG: Option<&'static str> = Some("beta");
+
+        type HttpRequest = ();
+
+        fn request(_: Self::HttpRequest) -> Result<Self::Request, Self::Error> {
+            Ok(())
+        }
+
+        fn response(_: Self::Response) -> axum::response::Response {
+            StatusCode::NO_CONTENT.into_response()
+        }
+    }
+
+    impl DocumentedEndpoint for Beta {
+        fn errors() -> Vec<ErrorResponse<Self::Error>> {
+            vec![("Internal server error", Error::Internal)]
+        }
+    }
+
+    crate::table_tests! {
+        feature_flag tokio,
+        (enabled_features, status, documented),
+        {
+            setup_tracing_once();
+            let enabled_features: Vec<String> = enabled_features;
+            let mut ctx = crate::Context::mock(InMemoryDb::default());
+            ctx.config.enabled_features = enabled_features.clone();
+            let resp = axum::Router::from(EndpointWrapper::new(Beta))
+                .layer(axum::Extension(std::sync::Arc::new(ctx)))
+                .oneshot(
+                    http::Request::builder()
+                        .method("GET")
+                        .uri("/beta")
+                        .body(Default::default())
+                        .unwrap_or_log(),
+                )
+                .await
+                .unwrap_or_log();
+            assert_eq!(resp.status(), status);
+
+            let SpecPaths { builder, extras } = SpecPaths::new().endpoint::<Beta>();
+            let mut spec = serde_json::to_value(
+                openapi::OpenApiBuilder::new().paths(builder.build()).build(),
+            )
+            .unwrap();
+            vendor_extensions::expand(&mut spec, &extras);
+            crate::feature_flags::strip_disabled(&mut spec, &enabled_features);
+            assert_eq!(spec["paths"].get("/beta").is_some(), documented, "{spec}");
+        },
+    }
+
+    feature_flag! {
+        enabled: (vec!["beta".into()], StatusCode::NO_CONTENT, true),
+        disabled: (vec![], StatusCode::NOT_FOUND, false),
+        other_enabled: (vec!["gamma".into()], StatusCode::NOT_FOUND, false),
+    }
+}
@@ -0,0 +1,228 @@
+use deps::*;
+
+use axum::extract::{FromRequest, RequestParts};
+use utoipa::openapi;
+
+use super::ApiError;
+use crate::{DocumentedParameter, ParameterDoc};
+
+/// A custom request header used through [`RequiredHeader`] or
+/// [`OptionalHeader`], e.g. `X-Tenant-Id`.
+pub trait HeaderParam: Sized + Send {
+    /// Header name, matched case insensitively.
+    const NAME: &'static str;
+    /// Accepted format reported when [`HeaderParam::parse`] fails, e.g. `uuid`.
+    const EXPECTED: &'static str;
+    const DESCRIPTION: &'static str = "";
+
+    fn parse(value: &str) -> Option<Self>;
+
+    fn schema() -> openapi::Schema {
+        openapi::schema::ObjectBuilder::new()
+            .schema_type(openapi::SchemaType::String)
+            .build()
+            .into()
+    }
+
+    fn parameter_doc(required: bool) -> Vec<ParameterDoc> {
+        vec![openapi::path::ParameterBuilder::new()
+            .name(Self::NAME)
+            .parameter_in(openapi::path::ParameterIn::Header)
+            .required(if required {
+                openapi::Required::True
+            } else {
+                openapi::Required::False
+            })
+            .description((!Self::DESCRIPTION.is_empty()).then_some(Self::DESCRIPTION))
+            .schema(Some(Self::schema()))
+            .build()
+            .into()]
+    }
+}
+
+fn extract<T, B>(req: &RequestParts<B>) -> Result<Option<T>, ApiError>
+where
+    T: HeaderParam,
+{
+    let value = match req.headers().get(T::NAME) {
+        Some(value) => value,
+        None => return Ok(None),
+    };
+    value
+        .to_str()
+        .ok()
+        .and_then(T::parse)
+        .map(Some)
+        .ok_or_else(|| ApiError::InvalidHeader {
+            name: T::NAME.into(),
+            value: String::from_utf8_lossy(value.as_bytes()).into_owned(),
+            expected: T::EXPECTED.into(),
+        })
+}
+
+/// Rejects the request if the header named [`HeaderParam::NAME`] is missing or
+/// malformed.
+#[derive(Debug, Clone)]
+pub struct RequiredHeader<T>(pub T);
+
+#[async_trait::async_trait]
+impl<T, B> FromRequest<B> for RequiredHeader<T>
+where
+    T: HeaderParam,
+    B: Send,
+{
+    type Rejection = ApiError;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        extract(req)?.map(Self).ok_or(ApiError::MissingHeader {
+            name: T::NAME.into(),
+        })
+    }
+}
+
+impl<T> DocumentedParameter for RequiredHeader<T>
+where
+    T: HeaderParam,
+{
+    fn to_openapi(_op_id: &str, _path: &str) -> Vec<ParameterDoc> {
+        T::parameter_doc(true)
+    }
+}
+
+/// Unlike `Option<RequiredHeader<T>>`, a malformed header is still rejected
+/// instead of being treated as missing.
+#[derive(Debug, Clone)]
+pub struct OptionalHeader<T>(pub Option<T>);
+
+#[async_trait::async_trait]
+impl<T, B> FromRequest<B> for OptionalHeader<T>
+where
+    T: HeaderParam,
+    B: Send,
+{
+    type Rejection = ApiError;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        extract(req).map(Self)
+    }
+}
+
+impl<T> DocumentedParameter for OptionalHeader<T>
+where
+    T: HeaderParam,
+{
+    fn to_openapi(_op_id: &str, _path: &str) -> Vec<ParameterDoc> {
+        T::parameter_doc(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use deps::*;
+
+    use super::*;
+    use crate::utils::testing::*;
+
+    #[derive(Debug)]
+    struct TenantId(uuid::Uuid);
+
+    impl HeaderParam for TenantId {
+        const NAME: &'static str = "x-tenant-id";
+        const EXPECTED: &'static str = "uuid";
+        const DESCRIPTION: &'static str = "Tenant the request acts within.";
+
+        fn parse(value: &str) -> Option<Self> {
+            value.parse().ok().map(Self)
+        }
+
+        fn schema() -> openapi::Schema {
+            openapi::schema::ObjectBuilder::new()
+                .schema_type(openapi::SchemaType::String)
+                .format(Some(openapi::schema::SchemaFormat::Uuid))
+                .build()
+                .into()
+        }
+    }
+
+    const TENANT: &str = "6c2d7c2e-5d8a-4f0e-9a57-0f6b8d3e2a11";
+
+    crate::table_tests! {
+        header_param tokio,
+        (tenant, status, expected),
+        {
+            let tenant: Option<&str> = tenant;
+            let app: axum::Router = axum::Router::new().route(
+                "/tenant",
+                axum::routing::get(|RequiredHeader(TenantId(id)): RequiredHeader<TenantId>| async move {
+                    axum::Json(serde_json::json!({ "tenantId": id }))
+                }),
+            );
+            let mut req = http::Request::builder().method("GET").uri("/tenant");
+            if let Some(tenant) = tenant {
+                req = req.header(TenantId::NAME, tenant);
+            }
+            let resp = app
+                .oneshot(req.body(Default::default()).unwrap_or_log())
+                .await
+                .unwrap_or_log();
+            assert_eq!(resp.status(), status);
+            let body = hyper::body::to_bytes(resp.into_body())
+                .await
+                .unwrap_or_log();
+            let body = serde_json::from_slice(&body).unwrap_or_log();
+            check_json(("expected", &expected), ("response", &body));
+        },
+    }
+
+    header_param! {
+        accepts_present_header: (
+            Some(TENANT),
+            StatusCode::OK,
+            serde_json::json!({ "tenantId": TENANT }),
+        ),
+        rejects_missing_header: (
+            None,
+            StatusCode::BAD_REQUEST,
+            serde_json::json!({
+                "error": "missingHeader",
+                "code": "request.missing_header",
+                "name": "x-tenant-id",
+            }),
+        ),
+        rejects_malformed_header: (
+            Some("acme"),
+            StatusCode::BAD_REQUEST,
+            serde_json::json!({
+                "error": "invalidHeader",
+                "code": "request.invalid_header",
+                "name": "x-tenant-id",
+                "value": "acme",
+                "expected": "uuid",
+            }),
+        ),
+    }
+
+    #[test]
+    fn documents_header() {
+        let params = <RequiredHeader<TenantId>>::to_openapi("", "/tenant")
+            .into_iter()
+            .map(|param| match param {
+                ParameterDoc::Param(param) => serde_json::to_value(param).unwrap(),
+                ParameterDoc::Body(_) => panic!("header documented as body"),
+            })
+            .collect::<Vec<_>>();
+        check_json(
+            (
+                "expected",
+                &serde_json::json!([{
+                    "name": "x-tenant-id",
+                    "in": "header",
+                    "required": true,
+                    "description": "Tenant the request acts within.",
+                    "schema": { "type": "string", "format": "uuid" },
+                }]),
+            ),
+            ("params", &serde_json::Value::Array(params)),
+        );
+    }
+}
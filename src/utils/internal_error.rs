@@ -0,0 +1,282 @@
+use deps::*;
+
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use futures::FutureExt;
+use serde::ser::SerializeStruct;
+use utoipa::openapi;
+
+use crate::SharedContext;
+
+/// An [`crate::Endpoint::Error`] for handlers that only fail in unexpected ways so
+/// that they can `?` an [`eyre::Report`]. Always a `500`. The full report is logged
+/// on conversion but the chain of causes is only sent to the client if
+/// [`exposes_internal_errors`].
+#[derive(Debug)]
+pub struct InternalError(pub eyre::Report);
+
+const GENERIC_MESSAGE: &str = "internal server error";
+
+tokio::task_local! {
+    static EXPOSE_INTERNAL_ERRORS: bool;
+}
+
+/// [`crate::Config::expose_internal_errors`] of the request being handled on this
+/// task. Outside of [`render_internal_errors`] only debug builds expose them.
+pub fn exposes_internal_errors() -> bool {
+    EXPOSE_INTERNAL_ERRORS
+        .try_with(|expose| *expose)
+        .unwrap_or(cfg!(debug_assertions))
+}
+
+/// Middleware rendering `5xx` json errors and panics according to
+/// [`crate::Config::expose_internal_errors`]. If it's off, their `message` is
+/// replaced with a generic one. Either way the [`super::current_request_id`] is
+/// added as `requestId` for support to find the logs with, so layer it inside
+/// [`super::populate_request_context`]. Expects the [`SharedContext`] in the
+/// request extensions.
+pub async fn render_internal_errors(
+    req: axum::http::Request<axum::body::Body>,
+    next: axum::middleware::Next<axum::body::Body>,
+) -> Response {
+    let expose = req
+        .extensions()
+        .get::<SharedContext>()
+        .map(|ctx| ctx.config.expose_internal_errors)
+        .unwrap_or(cfg!(debug_assertions));
+    let resp = EXPOSE_INTERNAL_ERRORS
+        .scope(
+            expose,
+            std::panic::AssertUnwindSafe(next.run(req)).catch_unwind(),
+        )
+        .await;
+    let resp = match resp {
+        Ok(resp) => resp,
+        Err(panic) => {
+            let message = panic
+                .downcast_ref::<&str>()
+                .map(|msg| msg.to_string())
+                .or_else(|| panic.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "panicked".into());
+            tracing::error!(%message, "handler panicked");
+            super::ApiError::Internal { message }.into_response()
+        }
+    };
+    if !resp.status().is_server_error() || !super::has_json_body(&resp) {
+        return resp;
+    }
+    let request_id = super::current_request_id();
+    super::rewrite_json(resp, "internal error", |json| {
+        if let serde_json::Value::Object(obj) = json {
+            if !expose && obj.contains_key("message") {
+                obj.insert("message".into(), GENERIC_MESSAGE.into());
+            }
+            if let Some(request_id) = request_id {
+                obj.insert("requestId".into(), request_id.to_string().into());
+            }
+        }
+    })
+    .await
+}
+
+impl InternalError {
+    /// The chain of causes, outermost first, if `expose`.
+    pub fn message(&self, expose: bool) -> String {
+        if expose {
+            format!("{:#}", self.0)
+        } else {
+            GENERIC_MESSAGE.into()
+        }
+    }
+}
+
+impl From<eyre::Report> for InternalError {
+    fn from(report: eyre::Report) -> Self {
+        tracing::error!(?report, "internal error");
+        Self(report)
+    }
+}
+
+impl From<&InternalError> for StatusCode {
+    fn from(_: &InternalError) -> Self {
+        Self::INTERNAL_SERVER_ERROR
+    }
+}
+
+impl serde::Serialize for InternalError {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let mut obj = serializer.serialize_struct("InternalError", 2)?;
+        obj.serialize_field("error", "internal")?;
+        obj.serialize_field("message", &self.message(exposes_internal_errors()))?;
+        obj.end()
+    }
+}
+
+impl utoipa::ToSchema for InternalError {
+    fn schema() -> openapi::Schema {
+        openapi::Schema::Object(
+            openapi::schema::ObjectBuilder::new()
+                .property(
+                    "error",
+                    openapi::schema::ObjectBuilder::new()
+                        .schema_type(openapi::SchemaType::String)
+                        .enum_values(Some(["internal"])),
+                )
+                .required("error")
+                .property(
+                    "message",
+                    openapi::schema::ObjectBuilder::new()
+                        .schema_type(openapi::SchemaType::String)
+                        .example(Some(GENERIC_MESSAGE.into())),
+                )
+                .required("message")
+                .build(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use deps::*;
+
+    use super::*;
+    use crate::utils::testing::*;
+
+    fn error() -> InternalError {
+        eyre::eyre!("connection refused")
+            .wrap_err("loading user")
+            .into()
+    }
+
+    #[test]
+    fn debug_exposes_the_chain() {
+        assert_eq!(error().message(true), "loading user: connection refused");
+        if cfg!(debug_assertions) {
+            assert_eq!(
+                serde_json::to_value(error()).unwrap(),
+                serde_json::json!({
+                    "error": "internal",
+                    "message": "loading user: connection refused",
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn release_hides_the_chain() {
+        assert_eq!(error().message(false), GENERIC_MESSAGE);
+        if !cfg!(debug_assertions) {
+            assert_eq!(
+                serde_json::to_value(error()).unwrap(),
+                serde_json::json!({
+                    "error": "internal",
+                    "message": GENERIC_MESSAGE,
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn is_always_500() {
+        assert_eq!(
+            StatusCode::from(&error()),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
+
+    async fn panicking() -> &'static str {
+        panic!("index out of bounds")
+    }
+
+    crate::table_tests! {
+        render_internal_errors tokio,
+        (expose, uri, expected),
+        {
+            let mut ctx = crate::Context::mock(InMemoryDb::default());
+            ctx.config.expose_internal_errors = expose;
+            let app = axum::Router::new()
+                .route(
+                    "/failing",
+                    axum::routing::get(|| async {
+                        crate::utils::ApiError::Internal {
+                            message: "db error: connection refused".into(),
+                        }
+                    }),
+                )
+                .route("/panicking", axum::routing::get(panicking))
+                .layer(axum::middleware::from_fn(render_internal_errors))
+                .layer(axum::middleware::from_fn(crate::utils::populate_request_context))
+                .layer(axum::Extension(std::sync::Arc::new(ctx)));
+            let resp = app
+                .oneshot(
+                    http::Request::builder()
+                        .method("GET")
+                        .uri(uri)
+                        .header(crate::utils::REQUEST_ID, "req-1")
+                        .body(Default::default())
+                        .unwrap_or_log(),
+                )
+                .await
+                .unwrap_or_log();
+            assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+            let body = hyper::body::to_bytes(resp.into_body())
+                .await
+                .unwrap_or_log();
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap_or_log();
+            let expected: serde_json::Value = expected;
+            assert_eq!(body, expected);
+        },
+    }
+
+    render_internal_errors! {
+        exposed_in_dev: (
+            true,
+            "/failing",
+            serde_json::json!({
+                "error": "internal",
+                "code": "service.internal",
+                "message": "db error: connection refused",
+                "requestId": "req-1",
+                "trace_id": "req-1",
+            }),
+        ),
+        generic_in_production: (
+            false,
+            "/failing",
+            serde_json::json!({
+                "error": "internal",
+                "code": "service.internal",
+                "message": GENERIC_MESSAGE,
+                "requestId": "req-1",
+                "trace_id": "req-1",
+            }),
+        ),
+        panic_exposed_in_dev: (
+            true,
+            "/panicking",
+            serde_json::json!({
+                "error": "internal",
+                "code": "service.internal",
+                "message": "index out of bounds",
+                "requestId": "req-1",
+                "trace_id": "req-1",
+            }),
+        ),
+        panic_generic_in_production: (
+            false,
+            "/panicking",
+            serde_json::json!({
+                "error": "internal",
+                "code": "service.internal",
+                "message": GENERIC_MESSAGE,
+                "requestId": "req-1",
+                "trace_id": "req-1",
+            }),
+        ),
+    }
+}
@@ -0,0 +1,645 @@
+//! Swaps axum's plain text [`axum::extract::rejection::JsonRejection`] responses
+//! for [`ApiError`]s that point at the offending part of the body.
+
+use deps::*;
+
+use axum::{
+    body::Bytes,
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Json, Response},
+};
+use futures::StreamExt;
+use serde::Serialize;
+
+use super::ValidationErrors;
+
+/// Errors generated while extracting a request before reaching any
+/// [`crate::Endpoint`]. Handlers that don't need errors of their own can also `?`
+/// their [`sqlx::Error`]s into one.
+#[derive(Debug, Serialize, thiserror::Error, utoipa::ToSchema)]
+#[serde(crate = "serde", rename_all = "camelCase", tag = "error")]
+pub enum ApiError {
+    #[error("invalid json: {message:?} at {line:?}:{column:?}, path: {path:?}")]
+    InvalidJson {
+        message: String,
+        /// 1-based.
+        line: Option<usize>,
+        /// 1-based.
+        column: Option<usize>,
+        /// Dotted path to the value at fault, e.g. `items[0].name`.
+        path: Option<String>,
+    },
+    #[error("json too complex, over the {limit:?} limit of {max:?}")]
+    JsonTooComplex {
+        /// `depth` or `elements`, see [`super::JsonLimits`].
+        limit: String,
+        max: usize,
+    },
+    #[error("error reading request body: {message:?}")]
+    BodyRead { message: String },
+    #[error("invalid query: {message:?}")]
+    InvalidQuery { message: String },
+    #[error("invalid input: {issues:?}")]
+    InvalidInput { issues: ValidationErrors },
+    #[error("invalid value for {name:?}: {value:?}, allowed: {allowed:?}")]
+    InvalidEnum {
+        name: String,
+        value: String,
+        allowed: Vec<String>,
+    },
+    #[error("invalid path parameter {name:?}: {value:?}, expected: {expected:?}")]
+    InvalidPathParameter {
+        name: String,
+        value: String,
+        /// Format the segment should be in, e.g. `uuid`.
+        expected: String,
+    },
+    #[error("missing header: {name:?}")]
+    MissingHeader { name: String },
+    #[error("invalid header {name:?}: {value:?}, expected: {expected:?}")]
+    InvalidHeader {
+        name: String,
+        value: String,
+        expected: String,
+    },
+    #[error("unknown fields: {unknown:?}, allowed: {allowed:?}")]
+    UnknownFields {
+        unknown: Vec<String>,
+        allowed: Vec<String>,
+    },
+    #[error("unsupported media type, expected: {expected:?}")]
+    UnsupportedMediaType {
+        /// The `Content-Type` the body should be sent as.
+        expected: String,
+    },
+    #[error("not found")]
+    NotFound,
+    #[error("conflict on {constraint:?}")]
+    Conflict { constraint: Option<String> },
+    #[error("bad request: {message:?}")]
+    BadRequest { message: String },
+    #[error("database overloaded")]
+    Overloaded,
+    #[error("internal server error: {message:?}")]
+    Internal { message: String },
+}
+
+impl From<&ApiError> for StatusCode {
+    fn from(err: &ApiError) -> Self {
+        use ApiError::*;
+        match err {
+            NotFound => Self::NOT_FOUND,
+            Conflict { .. } => Self::CONFLICT,
+            UnsupportedMediaType { .. } => Self::UNSUPPORTED_MEDIA_TYPE,
+            Overloaded => Self::SERVICE_UNAVAILABLE,
+            Internal { .. } => Self::INTERNAL_SERVER_ERROR,
+            _ => Self::BAD_REQUEST,
+        }
+    }
+}
+
+// SQLSTATEs, see https://www.postgresql.org/docs/current/errcodes-appendix.html
+const UNIQUE_VIOLATION: &str = "23505";
+const FOREIGN_KEY_VIOLATION: &str = "23503";
+
+impl From<sqlx::Error> for ApiError {
+    fn from(err: sqlx::Error) -> Self {
+        match err {
+            sqlx::Error::RowNotFound => Self::NotFound,
+            sqlx::Error::PoolTimedOut => Self::Overloaded,
+            sqlx::Error::Database(err) if err.code().as_deref() == Some(UNIQUE_VIOLATION) => {
+                Self::Conflict {
+                    constraint: err.constraint().map(String::from),
+                }
+            }
+            sqlx::Error::Database(err) if err.code().as_deref() == Some(FOREIGN_KEY_VIOLATION) => {
+                Self::BadRequest {
+                    message: match err.constraint() {
+                        Some(constraint) => format!("referenced row missing: {constraint}"),
+                        None => "referenced row missing".into(),
+                    },
+                }
+            }
+            err => {
+                tracing::error!(?err, "db error");
+                Self::Internal {
+                    message: "internal server error".into(),
+                }
+            }
+        }
+    }
+}
+
+impl ApiError {
+    /// One of each variant, like [`crate::DocumentedEndpoint::errors`].
+    pub fn examples() -> Vec<Self> {
+        let text = || String::from("...");
+        vec![
+            Self::InvalidJson {
+                message: text(),
+                line: None,
+                column: None,
+                path: None,
+            },
+            Self::JsonTooComplex {
+                limit: "depth".into(),
+                max: 0,
+            },
+            Self::BodyRead { message: text() },
+            Self::InvalidQuery { message: text() },
+            Self::InvalidInput {
+                issues: Default::default(),
+            },
+            Self::InvalidEnum {
+                name: text(),
+                value: text(),
+                allowed: vec![],
+            },
+            Self::InvalidPathParameter {
+                name: text(),
+                value: text(),
+                expected: text(),
+            },
+            Self::MissingHeader { name: text() },
+            Self::InvalidHeader {
+                name: text(),
+                value: text(),
+                expected: text(),
+            },
+            Self::UnknownFields {
+                unknown: vec![],
+                allowed: vec![],
+            },
+            Self::UnsupportedMediaType { expected: text() },
+            Self::NotFound,
+            Self::Conflict { constraint: None },
+            Self::BadRequest { message: text() },
+            Self::Overloaded,
+            Self::Internal { message: text() },
+        ]
+    }
+
+    /// The [`super::ErrorCode::code`]s of the [`ApiError::examples`] for the spec.
+    pub fn codes() -> Vec<&'static str> {
+        Self::examples()
+            .iter()
+            .map(super::ErrorCode::code)
+            .collect()
+    }
+}
+
+impl super::ErrorCode for ApiError {
+    fn code(&self) -> &'static str {
+        use ApiError::*;
+        match self {
+            InvalidJson { .. } => "request.invalid_json",
+            JsonTooComplex { .. } => "request.json_too_complex",
+            BodyRead { .. } => "request.body_read",
+            InvalidQuery { .. } => "request.invalid_query",
+            InvalidInput { .. } => "request.invalid_input",
+            InvalidEnum { .. } => "request.invalid_enum",
+            InvalidPathParameter { .. } => "request.invalid_path_parameter",
+            MissingHeader { .. } => "request.missing_header",
+            InvalidHeader { .. } => "request.invalid_header",
+            UnknownFields { .. } => "request.unknown_fields",
+            UnsupportedMediaType { .. } => "request.unsupported_media_type",
+            NotFound => "request.not_found",
+            Conflict { .. } => "request.conflict",
+            BadRequest { .. } => "request.bad_request",
+            Overloaded => "service.overloaded",
+            Internal { .. } => "service.internal",
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (
+            Into::<StatusCode>::into(&self),
+            Json(super::WithCode::new(&self)),
+        )
+            .into_response()
+    }
+}
+
+pub(crate) fn is_json_content_type(headers: &HeaderMap) -> bool {
+    let mime = match headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|val| val.to_str().ok())
+        .and_then(|val| val.split(';').next())
+    {
+        Some(mime) => mime.trim(),
+        None => return false,
+    };
+    mime == "application/json" || (mime.starts_with("application/") && mime.ends_with("+json"))
+}
+
+/// Buffers json bodies so that they can be inspected in case extraction fails.
+/// Other bodies are left untouched so that streaming extractors keep working.
+/// Stops with a `413` once more than `limit` bytes were read so that the buffer
+/// is never larger than what [`super::body_limit`] would let through.
+pub async fn buffer_json_body(
+    req: hyper::Request<hyper::Body>,
+    limit: Option<usize>,
+) -> Result<(hyper::Request<hyper::Body>, Option<Bytes>), Response> {
+    if !is_json_content_type(req.headers()) {
+        return Ok((req, None));
+    }
+    let too_large = |limit| {
+        (
+            StatusCode::PAYLOAD_TOO_LARGE,
+            Json(crate::RoutingError::PayloadTooLarge { limit }),
+        )
+            .into_response()
+    };
+    let (parts, mut body) = req.into_parts();
+    let mut buf = Vec::new();
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk.map_err(|err| {
+            ApiError::BodyRead {
+                message: err.to_string(),
+            }
+            .into_response()
+        })?;
+        if let Some(limit) = limit {
+            if buf.len() + chunk.len() > limit {
+                return Err(too_large(limit));
+            }
+        }
+        buf.extend_from_slice(&chunk);
+    }
+    let body = Bytes::from(buf);
+    Ok((
+        hyper::Request::from_parts(parts, body.clone().into()),
+        Some(body),
+    ))
+}
+
+/// The [`ApiError::InvalidJson`] for `err`, the error `body` failed to deserialize
+/// with. `None` for I/O errors since those aren't the client's json at fault.
+pub fn invalid_json(err: &serde_json::Error, body: &[u8]) -> Option<ApiError> {
+    use serde_json::error::Category;
+    if matches!(err.classify(), Category::Io) {
+        return None;
+    }
+    let (line, column) = (err.line(), err.column());
+    // a line of 0 means serde_json had no position to give
+    let location = (line > 0).then_some((line, column));
+    let message = err.to_string();
+    // the `Display` impl appends the position which is reported separately
+    let message = match location {
+        Some((line, column)) => message
+            .strip_suffix(&format!(" at line {line} column {column}"))
+            .map(str::to_string)
+            .unwrap_or(message),
+        None => message,
+    };
+    Some(ApiError::InvalidJson {
+        message,
+        line: location.map(|(line, _)| line),
+        column: location.map(|(_, column)| column),
+        path: location.and_then(|(line, column)| json_path_at(body, line, column)),
+    })
+}
+
+/// Fails with an [`ApiError::UnknownFields`] if `body` is an object with top level
+/// fields besides the `allowed` ones. Bodies that don't parse are left for the
+/// extractor to reject.
+pub fn reject_unknown_fields(body: &[u8], allowed: &[String]) -> Result<(), ApiError> {
+    let obj = match serde_json::from_slice::<serde_json::Value>(body) {
+        Ok(serde_json::Value::Object(obj)) => obj,
+        _ => return Ok(()),
+    };
+    let unknown = obj
+        .keys()
+        .filter(|key| !allowed.contains(key))
+        .cloned()
+        .collect::<Vec<_>>();
+    if unknown.is_empty() {
+        Ok(())
+    } else {
+        Err(ApiError::UnknownFields {
+            unknown,
+            allowed: allowed.to_vec(),
+        })
+    }
+}
+
+#[derive(Debug)]
+enum Segment {
+    Key(Option<String>),
+    Index(usize),
+}
+
+/// Scans `body` up to, but not including, the given position and returns the path
+/// of the value that was being read there.
+fn json_path_at(body: &[u8], line: usize, column: usize) -> Option<String> {
+    let line_start = if line <= 1 {
+        0
+    } else {
+        body.iter()
+            .enumerate()
+            .filter(|(_, byte)| **byte == b'\n')
+            .nth(line - 2)
+            .map(|(ii, _)| ii + 1)?
+    };
+    let end = (line_start + column.saturating_sub(1)).min(body.len());
+    let text = String::from_utf8_lossy(&body[..end]);
+
+    let mut stack: Vec<Segment> = vec![];
+    let mut expecting_key = false;
+    let mut string: Option<String> = None;
+    let mut escaped = false;
+    for ch in text.chars() {
+        if let Some(buf) = &mut string {
+            if escaped {
+                escaped = false;
+                buf.push(ch);
+            } else if ch == '\\' {
+                escaped = true;
+            } else if ch == '"' {
+                let buf = string.take().unwrap();
+                if expecting_key {
+                    if let Some(Segment::Key(key)) = stack.last_mut() {
+                        *key = Some(buf);
+                    }
+                }
+            } else {
+                buf.push(ch);
+            }
+            continue;
+        }
+        match ch {
+            '"' => string = Some(String::new()),
+            '{' => {
+                stack.push(Segment::Key(None));
+                expecting_key = true;
+            }
+            '[' => {
+                stack.push(Segment::Index(0));
+                expecting_key = false;
+            }
+            '}' | ']' => {
+                stack.pop();
+                expecting_key = false;
+            }
+            ':' => expecting_key = false,
+            ',' => match stack.last_mut() {
+                Some(Segment::Index(ii)) => *ii += 1,
+                Some(Segment::Key(_)) => expecting_key = true,
+                None => {}
+            },
+            _ => {}
+        }
+    }
+
+    let mut path = String::new();
+    for segment in stack {
+        match segment {
+            Segment::Key(Some(key)) => {
+                if !path.is_empty() {
+                    path.push('.');
+                }
+                path.push_str(&key);
+            }
+            Segment::Key(None) => break,
+            Segment::Index(ii) => path.push_str(&format!("[{ii}]")),
+        }
+    }
+    (!path.is_empty()).then_some(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use deps::*;
+
+    use crate::user::testing::*;
+    use crate::utils::testing::*;
+
+    crate::table_tests! {
+        json_error tokio,
+        (body, expected),
+        {
+            let resp = crate::user::router()
+                .oneshot(
+                    http::Request::builder()
+                        .method("POST")
+                        .uri("/users")
+                        .header(http::header::CONTENT_TYPE, "application/json")
+                        .body(body.into())
+                        .unwrap_or_log(),
+                )
+                .await
+                .unwrap_or_log();
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+            let body = hyper::body::to_bytes(resp.into_body())
+                .await
+                .unwrap_or_log();
+            let body = serde_json::from_slice(&body).unwrap_or_log();
+            check_json(("expected", &expected), ("response", &body));
+        },
+    }
+
+    json_error! {
+        reports_syntax_errors: (
+            "{\n  \"username\": ,\n  \"password\": \"lovebite\"\n}",
+            serde_json::json!({
+                "error": "invalidJson",
+                "message": "expected value",
+                "line": 2,
+                "path": "username",
+            }),
+        ),
+        reports_type_mismatches: (
+            r#"{ "username": 1337, "email": "multis@cream.mux", "password": "lovebite" }"#,
+            serde_json::json!({
+                "error": "invalidJson",
+                "line": 1,
+                "path": "username",
+            }),
+        ),
+    }
+
+    crate::table_tests! {
+        strict_bodies tokio,
+        (strict, status, expected),
+        {
+            setup_tracing_once();
+            let mut ctx = crate::Context::mock(
+                InMemoryDb::fixture().with_role(USER_01_ID, crate::auth::Role::SuperAdmin),
+            );
+            ctx.config.strict_request_bodies = strict;
+            let resp = crate::maintenance::router()
+                .layer(axum::Extension(std::sync::Arc::new(ctx)))
+                .oneshot(
+                    http::Request::builder()
+                        .method("PUT")
+                        .uri("/admin/maintenance")
+                        .header(
+                            http::header::AUTHORIZATION,
+                            format!("Bearer {USER_01_SESSION}"),
+                        )
+                        .header(http::header::CONTENT_TYPE, "application/json")
+                        .body(r#"{ "enabled": false, "enabld": true }"#.into())
+                        .unwrap_or_log(),
+                )
+                .await
+                .unwrap_or_log();
+            assert_eq!(resp.status(), status);
+            let body = hyper::body::to_bytes(resp.into_body())
+                .await
+                .unwrap_or_log();
+            let body = serde_json::from_slice(&body).unwrap_or_log();
+            check_json(("expected", &expected), ("response", &body));
+        },
+    }
+
+    strict_bodies! {
+        rejects_unknown_fields_when_strict: (
+            true,
+            StatusCode::BAD_REQUEST,
+            serde_json::json!({
+                "error": "unknownFields",
+                "code": "request.unknown_fields",
+                "unknown": ["enabld"],
+                "allowed": ["enabled", "endsAt"],
+            }),
+        ),
+        ignores_unknown_fields_when_lenient: (
+            false,
+            StatusCode::OK,
+            serde_json::json!({ "enabled": false }),
+        ),
+    }
+
+    #[tokio::test]
+    async fn buffering_stops_at_the_body_limit() {
+        let req = http::Request::builder()
+            .header(http::header::CONTENT_TYPE, "application/json")
+            .body(hyper::Body::from(r#"{ "username": "nightshade" }"#))
+            .unwrap_or_log();
+        let resp = super::buffer_json_body(req, Some(8)).await.unwrap_err();
+        assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        let req = http::Request::builder()
+            .header(http::header::CONTENT_TYPE, "application/json")
+            .body(hyper::Body::from(r#"{ "username": "nightshade" }"#))
+            .unwrap_or_log();
+        let (_, body) = super::buffer_json_body(req, Some(1024))
+            .await
+            .unwrap_or_log();
+        assert_eq!(&body.unwrap()[..], br#"{ "username": "nightshade" }"#);
+    }
+
+    #[test]
+    fn skips_io_errors() {
+        let err = serde_json::Error::io(std::io::Error::new(
+            std::io::ErrorKind::UnexpectedEof,
+            "connection reset",
+        ));
+        assert!(super::invalid_json(&err, b"{").is_none());
+    }
+
+    #[test]
+    fn finds_nested_paths() {
+        let body = br#"{ "items": [ { "name": "a" }, { "name": 1 } ] }"#;
+        let column = body.iter().position(|byte| *byte == b'1').unwrap() + 1;
+        assert_eq!(
+            super::json_path_at(body, 1, column).as_deref(),
+            Some("items[1].name")
+        );
+    }
+
+    /// What the postgres driver would report for a violated constraint.
+    #[derive(Debug, thiserror::Error)]
+    #[error("{code}")]
+    struct DbError {
+        code: &'static str,
+        constraint: &'static str,
+    }
+
+    impl sqlx::error::DatabaseError for DbError {
+        fn message(&self) -> &str {
+            self.code
+        }
+
+        fn code(&self) -> Option<std::borrow::Cow<'_, str>> {
+            Some(self.code.into())
+        }
+
+        fn as_error(&self) -> &(dyn std::error::Error + Send + Sync + 'static) {
+            self
+        }
+
+        fn as_error_mut(&mut self) -> &mut (dyn std::error::Error + Send + Sync + 'static) {
+            self
+        }
+
+        fn into_error(self: Box<Self>) -> Box<dyn std::error::Error + Send + Sync + 'static> {
+            self
+        }
+
+        fn constraint(&self) -> Option<&str> {
+            Some(self.constraint)
+        }
+    }
+
+    fn db_error(code: &'static str) -> sqlx::Error {
+        sqlx::Error::Database(Box::new(DbError {
+            code,
+            constraint: "users_email_key",
+        }))
+    }
+
+    crate::table_tests! {
+        from_sqlx,
+        (err, expected_status, expected),
+        {
+            let err = crate::utils::ApiError::from(err);
+            assert_eq!(StatusCode::from(&err), expected_status);
+            let body = serde_json::to_value(crate::utils::WithCode::new(&err)).unwrap();
+            check_json(("expected", &expected), ("response", &body));
+        }
+    }
+
+    from_sqlx! {
+        row_not_found: (
+            sqlx::Error::RowNotFound,
+            StatusCode::NOT_FOUND,
+            serde_json::json!({ "error": "notFound", "code": "request.not_found" }),
+        ),
+        unique_violation: (
+            db_error("23505"),
+            StatusCode::CONFLICT,
+            serde_json::json!({
+                "error": "conflict",
+                "code": "request.conflict",
+                "constraint": "users_email_key",
+            }),
+        ),
+        foreign_key_violation: (
+            db_error("23503"),
+            StatusCode::BAD_REQUEST,
+            serde_json::json!({ "error": "badRequest", "code": "request.bad_request" }),
+        ),
+        pool_timed_out: (
+            sqlx::Error::PoolTimedOut,
+            StatusCode::SERVICE_UNAVAILABLE,
+            serde_json::json!({ "error": "overloaded", "code": "service.overloaded" }),
+        ),
+        other_db_error: (
+            db_error("42P01"),
+            StatusCode::INTERNAL_SERVER_ERROR,
+            serde_json::json!({
+                "error": "internal",
+                "code": "service.internal",
+                "message": "internal server error",
+            }),
+        ),
+        anything_else: (
+            sqlx::Error::PoolClosed,
+            StatusCode::INTERNAL_SERVER_ERROR,
+            serde_json::json!({ "error": "internal", "code": "service.internal" }),
+        ),
+    }
+}
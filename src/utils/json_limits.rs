@@ -0,0 +1,189 @@
+//! Caps on how nested and how large json bodies may be, checked by scanning the
+//! raw bytes before anything gets deserialized so that a body like `[[[[...]]]]`
+//! can't exhaust the stack or keep serde busy. See [`crate::Config::max_json_depth`]
+//! and [`crate::Config::max_json_elements`].
+
+use super::ApiError;
+
+/// Zero turns the respective check off.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct JsonLimits {
+    pub max_depth: usize,
+    pub max_elements: usize,
+}
+
+impl From<&crate::Config> for JsonLimits {
+    fn from(config: &crate::Config) -> Self {
+        Self {
+            max_depth: config.max_json_depth,
+            max_elements: config.max_json_elements,
+        }
+    }
+}
+
+impl JsonLimits {
+    /// Fails with an [`ApiError::JsonTooComplex`] as soon as a limit is crossed.
+    /// Elements are the values in the body, containers included but object keys
+    /// not. Bodies that aren't valid json are left for the extractor to reject.
+    pub fn check(&self, body: &[u8]) -> Result<(), ApiError> {
+        // `true` for objects
+        let mut stack: Vec<bool> = vec![];
+        let mut elements = 0;
+        let mut expecting_key = false;
+        let mut in_string = false;
+        let mut in_scalar = false;
+        let mut escaped = false;
+        for byte in body {
+            if in_string {
+                if escaped {
+                    escaped = false;
+                } else if *byte == b'\\' {
+                    escaped = true;
+                } else if *byte == b'"' {
+                    in_string = false;
+                }
+                continue;
+            }
+            let starts_value = match byte {
+                b'{' | b'[' => true,
+                b'"' => {
+                    in_string = true;
+                    !expecting_key
+                }
+                b'}' | b']' | b',' | b':' | b' ' | b'\t' | b'\n' | b'\r' => {
+                    in_scalar = false;
+                    false
+                }
+                // the rest of a number or literal
+                _ if in_scalar => false,
+                _ => {
+                    in_scalar = true;
+                    true
+                }
+            };
+            if starts_value {
+                elements += 1;
+                if self.max_elements > 0 && elements > self.max_elements {
+                    return Err(ApiError::JsonTooComplex {
+                        limit: "elements".into(),
+                        max: self.max_elements,
+                    });
+                }
+            }
+            match byte {
+                b'{' | b'[' => {
+                    stack.push(*byte == b'{');
+                    expecting_key = *byte == b'{';
+                    if self.max_depth > 0 && stack.len() > self.max_depth {
+                        return Err(ApiError::JsonTooComplex {
+                            limit: "depth".into(),
+                            max: self.max_depth,
+                        });
+                    }
+                }
+                b'}' | b']' => {
+                    stack.pop();
+                    expecting_key = false;
+                }
+                b':' => expecting_key = false,
+                b',' => expecting_key = stack.last().copied().unwrap_or_default(),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use deps::*;
+
+    use super::*;
+    use crate::user::testing::*;
+    use crate::utils::testing::*;
+
+    fn nested(depth: usize) -> String {
+        format!(
+            r#"{{ "enabled": false, "x": {}1{} }}"#,
+            "[".repeat(depth),
+            "]".repeat(depth)
+        )
+    }
+
+    crate::table_tests! {
+        json_limits tokio,
+        (body, status, expected),
+        {
+            setup_tracing_once();
+            let mut ctx = crate::Context::mock(
+                InMemoryDb::fixture().with_role(USER_01_ID, crate::auth::Role::SuperAdmin),
+            );
+            ctx.config.max_json_depth = 8;
+            ctx.config.max_json_elements = 16;
+            let body: String = body;
+            let resp = crate::maintenance::router()
+                .layer(axum::Extension(std::sync::Arc::new(ctx)))
+                .oneshot(
+                    http::Request::builder()
+                        .method("PUT")
+                        .uri("/admin/maintenance")
+                        .header(
+                            http::header::AUTHORIZATION,
+                            format!("Bearer {USER_01_SESSION}"),
+                        )
+                        .header(http::header::CONTENT_TYPE, "application/json")
+                        .body(body.into())
+                        .unwrap_or_log(),
+                )
+                .await
+                .unwrap_or_log();
+            assert_eq!(resp.status(), status);
+            let body = hyper::body::to_bytes(resp.into_body())
+                .await
+                .unwrap_or_log();
+            let body = serde_json::from_slice(&body).unwrap_or_log();
+            check_json(("expected", &expected), ("response", &body));
+        },
+    }
+
+    json_limits! {
+        accepts_bodies_within_limits: (
+            nested(7),
+            StatusCode::OK,
+            serde_json::json!({ "enabled": false }),
+        ),
+        rejects_deeply_nested_bodies: (
+            nested(1000),
+            StatusCode::BAD_REQUEST,
+            serde_json::json!({
+                "error": "jsonTooComplex",
+                "code": "request.json_too_complex",
+                "limit": "depth",
+                "max": 8,
+            }),
+        ),
+        rejects_bodies_with_too_many_elements: (
+            format!(r#"{{ "enabled": false, "x": [{}] }}"#, ["1"; 20].join(",")),
+            StatusCode::BAD_REQUEST,
+            serde_json::json!({
+                "error": "jsonTooComplex",
+                "code": "request.json_too_complex",
+                "limit": "elements",
+                "max": 16,
+            }),
+        ),
+    }
+
+    #[test]
+    fn counts_values_not_keys() {
+        let limits = JsonLimits {
+            max_depth: 0,
+            max_elements: 4,
+        };
+        // the object, two strings with braces in them and a number
+        assert!(limits
+            .check(br#"{ "a": "}{", "b\"": "[", "c": -1.5e3 }"#)
+            .is_ok());
+        assert!(limits.check(br#"{ "a": [true, null, 1] }"#).is_err());
+    }
+}
@@ -0,0 +1,109 @@
+//! Time based conditional requests (RFC 7232). Endpoints opt in through
+//! [`crate::HttpEndpoint::last_modified`].
+
+use deps::*;
+
+use axum::{
+    http::{self, header},
+    response::Response,
+};
+
+use once_cell::sync::Lazy;
+
+type FormatDescription = Vec<time::format_description::FormatItem<'static>>;
+
+static IMF_FIXDATE: Lazy<FormatDescription> = Lazy::new(|| {
+    time::format_description::parse(
+        "[weekday repr:short], [day] [month repr:short] [year] [hour]:[minute]:[second] GMT",
+    )
+    .expect("invalid IMF-fixdate format description")
+});
+/// `asctime()`'s format which recipients still have to accept.
+static ASCTIME: Lazy<FormatDescription> = Lazy::new(|| {
+    time::format_description::parse(
+        "[weekday repr:short] [month repr:short] [day padding:space] [hour]:[minute]:[second] [year]",
+    )
+    .expect("invalid asctime format description")
+});
+
+/// Formats as an IMF-fixdate, dropping anything below a second.
+pub fn format_http_date(at: time::OffsetDateTime) -> String {
+    at.to_offset(time::UtcOffset::UTC)
+        .format(&*IMF_FIXDATE)
+        .expect("unable to format http date")
+}
+
+pub fn parse_http_date(val: &str) -> Option<time::OffsetDateTime> {
+    [&*IMF_FIXDATE, &*ASCTIME]
+        .into_iter()
+        .find_map(|format| time::PrimitiveDateTime::parse(val.trim(), format).ok())
+        .map(time::PrimitiveDateTime::assume_utc)
+}
+
+/// The `If-Modified-Since` of a `GET` or `HEAD`. Ignored in presence of an
+/// `If-None-Match` as required by the RFC.
+pub fn if_modified_since<B>(req: &http::Request<B>) -> Option<time::OffsetDateTime> {
+    if !matches!(*req.method(), http::Method::GET | http::Method::HEAD)
+        || req.headers().contains_key(header::IF_NONE_MATCH)
+    {
+        return None;
+    }
+    req.headers()
+        .get(header::IF_MODIFIED_SINCE)
+        .and_then(|val| val.to_str().ok())
+        .and_then(parse_http_date)
+}
+
+/// Turns a `200` with a `Last-Modified` no later than `since` into an empty `304`.
+pub fn not_modified_since(resp: Response, since: time::OffsetDateTime) -> Response {
+    let last_modified = resp
+        .headers()
+        .get(header::LAST_MODIFIED)
+        .and_then(|val| val.to_str().ok())
+        .and_then(parse_http_date);
+    match last_modified {
+        Some(last_modified) if resp.status() == http::StatusCode::OK && last_modified <= since => {
+            let (mut parts, _) = resp.into_parts();
+            parts.status = http::StatusCode::NOT_MODIFIED;
+            parts.headers.remove(header::CONTENT_TYPE);
+            parts.headers.remove(header::CONTENT_LENGTH);
+            Response::from_parts(parts, axum::body::boxed(axum::body::Empty::new()))
+        }
+        _ => resp,
+    }
+}
+
+/// Adds the `Last-Modified` header to the response.
+pub fn with_last_modified(mut resp: Response, at: time::OffsetDateTime) -> Response {
+    resp.headers_mut().insert(
+        header::LAST_MODIFIED,
+        http::HeaderValue::from_str(&format_http_date(at))
+            .expect("http dates are valid header values"),
+    );
+    resp
+}
+
+#[cfg(test)]
+mod tests {
+    use deps::*;
+
+    use super::*;
+
+    #[test]
+    fn http_dates_round_trip_to_the_second() {
+        let at = time::OffsetDateTime::from_unix_timestamp_nanos(1_663_588_558_123_456_000)
+            .unwrap()
+            .to_offset(time::UtcOffset::from_hms(3, 0, 0).unwrap());
+        let formatted = format_http_date(at);
+        assert_eq!(formatted, "Mon, 19 Sep 2022 11:55:58 GMT");
+        assert_eq!(
+            parse_http_date(&formatted),
+            Some(time::OffsetDateTime::from_unix_timestamp(1_663_588_558).unwrap())
+        );
+        assert_eq!(
+            parse_http_date("Sun Nov  6 08:49:37 1994"),
+            Some(time::OffsetDateTime::from_unix_timestamp(784_111_777).unwrap())
+        );
+        assert_eq!(parse_http_date("yesterday"), None);
+    }
+}
@@ -0,0 +1,149 @@
+//! Hypermedia links for resource responses. Endpoints opt in through
+//! [`crate::HttpEndpoint::links`] and get a [`LINKS_FIELD`] object put into their
+//! successful json responses pointing at the related endpoints, e.g.
+//! `{ "_links": { "self": { "href": "https://api.example.com/users/42", "method": "GET" } } }`.
+
+use deps::*;
+
+use axum::response::Response;
+
+use crate::Method;
+
+use super::{has_json_body, rewrite_json, UrlBuilder};
+
+pub const LINKS_FIELD: &str = "_links";
+
+/// Vendor extension flagging the operations of endpoints with
+/// [`crate::HttpEndpoint::HAS_LINKS`] for [`document_links`].
+pub const LINKS_EXTENSION: &str = "x-resource-links";
+
+/// A link to the endpoint at `template`, with the `params` put in for its path
+/// segments.
+#[derive(Debug, Clone)]
+pub struct ResourceLink {
+    pub rel: &'static str,
+    pub method: Method,
+    pub template: &'static str,
+    pub params: Vec<(&'static str, String)>,
+}
+
+impl ResourceLink {
+    /// Use the `METHOD` and `PATH` of the related [`crate::HttpEndpoint`].
+    pub fn new(rel: &'static str, method: Method, template: &'static str) -> Self {
+        Self {
+            rel,
+            method,
+            template,
+            params: vec![],
+        }
+    }
+
+    pub fn param(mut self, name: &'static str, val: impl ToString) -> Self {
+        self.params.push((name, val.to_string()));
+        self
+    }
+
+    /// Absolute if the host of the request is known, just the path otherwise.
+    pub fn href(&self, urls: Option<&UrlBuilder>) -> String {
+        let params = self
+            .params
+            .iter()
+            .map(|(name, val)| (*name, &val[..]))
+            .collect::<Vec<_>>();
+        match urls {
+            Some(urls) => urls.url(self.template, &params),
+            None => UrlBuilder::path(self.template, &params),
+        }
+    }
+}
+
+fn method_str(method: &Method) -> &'static str {
+    match method {
+        Method::Get => "GET",
+        Method::Post => "POST",
+        Method::Put => "PUT",
+        Method::Delete => "DELETE",
+        Method::Options => "OPTIONS",
+        Method::Head => "HEAD",
+        Method::Patch => "PATCH",
+        Method::Trace => "TRACE",
+        Method::Connect => "CONNECT",
+    }
+}
+
+/// Puts the `links` into the body if it's a json object. Other responses are
+/// passed through.
+pub async fn inject_links(
+    resp: Response,
+    urls: Option<&UrlBuilder>,
+    links: &[ResourceLink],
+) -> Response {
+    if links.is_empty() || !resp.status().is_success() || !has_json_body(&resp) {
+        return resp;
+    }
+    rewrite_json(resp, "links", |json| {
+        if let serde_json::Value::Object(record) = json {
+            let links = links
+                .iter()
+                .map(|link| {
+                    (
+                        link.rel.to_string(),
+                        serde_json::json!({
+                            "href": link.href(urls),
+                            "method": method_str(&link.method),
+                        }),
+                    )
+                })
+                .collect::<serde_json::Map<_, _>>();
+            record.insert(LINKS_FIELD.into(), links.into());
+        }
+    })
+    .await
+}
+
+/// Adds the [`LINKS_FIELD`] to the success response schemas of the operations
+/// flagged with [`LINKS_EXTENSION`], dropping the flag. Done on the serialized
+/// spec as it takes an `allOf` which utoipa 2 lacks.
+pub fn document_links(spec: &mut serde_json::Value) {
+    let links = serde_json::json!({
+        "type": "object",
+        "required": [LINKS_FIELD],
+        "properties": {
+            LINKS_FIELD: {
+                "type": "object",
+                "description": "Related endpoints by relation, e.g. `self`.",
+                "additionalProperties": {
+                    "type": "object",
+                    "required": ["href", "method"],
+                    "properties": {
+                        "href": { "type": "string" },
+                        "method": { "type": "string" },
+                    },
+                },
+            },
+        },
+    });
+    let operations = spec
+        .get_mut("paths")
+        .and_then(|paths| paths.as_object_mut())
+        .into_iter()
+        .flat_map(|paths| paths.values_mut())
+        .filter_map(|item| item.as_object_mut())
+        .flat_map(|item| item.values_mut())
+        .filter_map(|op| op.as_object_mut());
+    for op in operations {
+        if op.remove(LINKS_EXTENSION).is_none() {
+            continue;
+        }
+        let responses = op
+            .get_mut("responses")
+            .and_then(|responses| responses.as_object_mut())
+            .into_iter()
+            .flat_map(|responses| responses.iter_mut())
+            .filter(|(status, _)| status.starts_with('2'))
+            .filter_map(|(_, resp)| resp.pointer_mut("/content/application~1json/schema"));
+        for schema in responses {
+            *schema = serde_json::json!({ "allOf": [schema.take(), links.clone()] });
+        }
+    }
+}
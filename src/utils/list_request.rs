@@ -1,7 +1,15 @@
 use deps::*;
 
-use crate::user::{User, UserSortingField};
+use axum::{
+    extract::{FromRequest, RequestParts},
+    http::{header, HeaderValue},
+    response::{IntoResponse, Json, Response},
+};
 use serde::{Deserialize, Serialize};
+use utoipa::openapi;
+
+use crate::user::{User, UserSortingField};
+use crate::{DocumentedParameter, ParameterDoc};
 
 pub trait SortingField {
     fn sql_field_name(&self) -> String;
@@ -21,6 +29,13 @@ impl SortingOrder {
             Self::Descending => "desc",
         }
     }
+
+    pub fn reversed(self) -> Self {
+        match self {
+            Self::Ascending => Self::Descending,
+            Self::Descending => Self::Ascending,
+        }
+    }
 }
 
 pub const DEFAULT_LIST_LIMIT: usize = 25;
@@ -42,6 +57,39 @@ where
     pub filter: Option<String>,
     pub sorting_field: Option<S>,
     pub sorting_order: Option<SortingOrder>,
+    /// See [`PageQuery::base_url`].
+    #[serde(skip)]
+    pub base_url: Option<String>,
+    /// See [`PageQuery::envelope`].
+    #[serde(skip)]
+    pub envelope: bool,
+    /// See [`PageQuery::include_total`].
+    #[serde(skip)]
+    pub include_total: bool,
+    /// From the query, see [`TimeRange`].
+    #[serde(skip)]
+    pub created: super::TimeRange,
+}
+
+impl<S> Default for ListRequest<S>
+where
+    S: SortingField + Clone + Copy + Serialize,
+{
+    fn default() -> Self {
+        Self {
+            auth_token: None,
+            limit: None,
+            after_cursor: None,
+            before_cursor: None,
+            filter: None,
+            sorting_field: None,
+            sorting_order: None,
+            base_url: None,
+            envelope: false,
+            include_total: false,
+            created: Default::default(),
+        }
+    }
 }
 
 fn validate_list_req<S>(req: &ListRequest<S>) -> Result<(), validator::ValidationError>
@@ -108,6 +156,239 @@ where
 {
     pub cursor: Option<String>,
     pub items: Vec<T>,
+    /// Only sent through the `Link` header.
+    #[serde(skip)]
+    pub prev_cursor: Option<String>,
+    /// See [`PageQuery::base_url`]. No `Link` header is sent without it.
+    #[serde(skip)]
+    pub base_url: Option<String>,
+    /// Sent as a [`ListEnvelope`] instead if set.
+    #[serde(skip)]
+    pub envelope: Option<Envelope>,
+}
+
+/// What the [`ListEnvelope`] knows besides the [`ListResponse`].
+#[derive(Debug, Clone, Copy)]
+pub struct Envelope {
+    pub limit: usize,
+    pub total: Option<u64>,
+}
+
+/// The `{ data, meta }` shape of a [`ListResponse`] for clients that expect all
+/// lists to look the same. Asked for through [`PageQuery::envelope`].
+#[derive(Debug, Serialize, utoipa::ToSchema)]
+#[serde(crate = "serde", rename_all = "camelCase")]
+#[aliases(ListUsersEnvelope = ListEnvelope<User>)]
+pub struct ListEnvelope<T>
+where
+    T: utoipa::ToSchema,
+{
+    pub data: Vec<T>,
+    pub meta: ListMeta,
+}
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+#[serde(crate = "serde", rename_all = "camelCase")]
+pub struct ListMeta {
+    /// Items matching the filter across all pages. Only counted if asked for
+    /// through `includeTotal` since it's expensive.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total: Option<u64>,
+    pub limit: usize,
+    pub next_cursor: Option<String>,
+}
+
+/// Either the `plain` or the `envelope` schema, for list endpoints that take a
+/// [`PageQuery`].
+pub fn list_schema(plain: &str, envelope: &str) -> openapi::schema::RefOr<openapi::schema::Schema> {
+    openapi::schema::OneOfBuilder::new()
+        .item(openapi::Ref::from_schema_name(plain))
+        .item(openapi::Ref::from_schema_name(envelope))
+        .into()
+}
+
+impl<T> ListResponse<T>
+where
+    T: utoipa::ToSchema,
+{
+    /// `rel="next"` for the `cursor` and `rel="prev"` for the `prev_cursor`, as
+    /// described in [RFC 8288](https://www.rfc-editor.org/rfc/rfc8288).
+    pub fn link_header(&self) -> Option<HeaderValue> {
+        let base_url = self.base_url.as_ref()?;
+        // keeps the other params of the request, e.g. a `TimeRange`
+        let sep = if base_url.contains('?') { '&' } else { '?' };
+        let links = [
+            ("afterCursor", self.cursor.as_ref(), "next"),
+            ("beforeCursor", self.prev_cursor.as_ref(), "prev"),
+        ]
+        .into_iter()
+        .filter_map(|(param, cursor, rel)| {
+            let cursor = cursor?;
+            Some(format!(
+                r#"<{base_url}{sep}{param}={}>; rel="{rel}""#,
+                percent_encode(cursor)
+            ))
+        })
+        .collect::<Vec<_>>();
+        if links.is_empty() {
+            return None;
+        }
+        HeaderValue::from_str(&links.join(", ")).ok()
+    }
+}
+
+impl<T> IntoResponse for ListResponse<T>
+where
+    T: utoipa::ToSchema + Serialize,
+{
+    fn into_response(self) -> Response {
+        let link = self.link_header();
+        let mut resp = match self.envelope {
+            Some(Envelope { limit, total }) => Json(ListEnvelope {
+                data: self.items,
+                meta: ListMeta {
+                    total,
+                    limit,
+                    next_cursor: self.cursor,
+                },
+            })
+            .into_response(),
+            None => Json(self).into_response(),
+        };
+        if let Some(link) = link {
+            resp.headers_mut().insert(header::LINK, link);
+        }
+        resp
+    }
+}
+
+/// `url` with `name=value` added to its query.
+pub fn with_query_param(url: &str, name: &str, value: impl std::fmt::Display) -> String {
+    let sep = if url.contains('?') { '&' } else { '?' };
+    format!("{url}{sep}{name}={}", percent_encode(&value.to_string()))
+}
+
+/// Everything but the unreserved characters of
+/// [RFC 3986](https://www.rfc-editor.org/rfc/rfc3986#section-2.3).
+fn percent_encode(val: &str) -> String {
+    let mut out = String::with_capacity(val.len());
+    for byte in val.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                out.push(byte as char)
+            }
+            _ => out.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    out
+}
+
+/// The cursors a list endpoint accepts as query parameters along with the url
+/// it was requested at. This allows clients to follow the `Link` headers of
+/// [`ListResponse`]s.
+#[derive(Debug, Clone, Default)]
+pub struct PageQuery {
+    /// `scheme://host/path?query` the request was made to, with the cursors left
+    /// out of the query so that the links can put in their own. Only the path and
+    /// query if the host can't be determined.
+    pub base_url: String,
+    pub after_cursor: Option<String>,
+    pub before_cursor: Option<String>,
+    /// `?limit=` takes precedence over the one in the body. Only the query makes
+    /// it into the `Link` headers, see [`with_query_param`].
+    pub limit: Option<usize>,
+    /// `?envelope=true` asks for a [`ListEnvelope`].
+    pub envelope: bool,
+    /// `?includeTotal=true` asks for the [`ListMeta::total`] of the envelope.
+    pub include_total: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(crate = "serde", rename_all = "camelCase")]
+struct PageQueryParams {
+    after_cursor: Option<String>,
+    before_cursor: Option<String>,
+    limit: Option<usize>,
+    #[serde(default)]
+    envelope: bool,
+    #[serde(default)]
+    include_total: bool,
+}
+
+#[async_trait::async_trait]
+impl<B> FromRequest<B> for PageQuery
+where
+    B: Send,
+{
+    type Rejection = Response;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let axum::extract::Query(params) =
+            axum::extract::Query::<PageQueryParams>::from_request(req)
+                .await
+                .map_err(|err| {
+                    super::ApiError::InvalidQuery {
+                        message: err.to_string(),
+                    }
+                    .into_response()
+                })?;
+        let uri = req
+            .extensions()
+            .get::<axum::extract::OriginalUri>()
+            .map(|uri| &uri.0)
+            .unwrap_or_else(|| req.uri());
+        // the cursors are swapped for the linked page's, everything else is kept
+        let query = uri
+            .query()
+            .unwrap_or_default()
+            .split('&')
+            .filter(|pair| {
+                let name = pair.split('=').next().unwrap_or_default();
+                !name.is_empty() && name != "afterCursor" && name != "beforeCursor"
+            })
+            .collect::<Vec<_>>()
+            .join("&");
+        let path = if query.is_empty() {
+            uri.path().to_string()
+        } else {
+            format!("{}?{query}", uri.path())
+        };
+        let trust_forwarded = super::from_trusted_proxy(req.extensions());
+        let base_url = match super::UrlBuilder::from_request_parts(
+            req.headers(),
+            req.uri(),
+            trust_forwarded,
+        ) {
+            Some(urls) => urls.absolute(&path),
+            None => path,
+        };
+        Ok(Self {
+            base_url,
+            after_cursor: params.after_cursor,
+            before_cursor: params.before_cursor,
+            limit: params.limit,
+            envelope: params.envelope,
+            include_total: params.include_total,
+        })
+    }
+}
+
+impl DocumentedParameter for PageQuery {
+    fn to_openapi(_op_id: &str, _path: &str) -> Vec<ParameterDoc> {
+        [
+            super::QueryParamDoc::new("afterCursor", openapi::SchemaType::String),
+            super::QueryParamDoc::new("beforeCursor", openapi::SchemaType::String),
+            super::QueryParamDoc::new("limit", openapi::SchemaType::Integer)
+                .description("Page size, takes precedence over the one in the body."),
+            super::QueryParamDoc::new("envelope", openapi::SchemaType::Boolean)
+                .description("Wrap the items in a `{ data, meta }` envelope."),
+            super::QueryParamDoc::new("includeTotal", openapi::SchemaType::Boolean)
+                .description("Count all matching items into the envelope's `meta.total`."),
+        ]
+        .into_iter()
+        .map(|param| param.parameter().into())
+        .collect()
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -0,0 +1,80 @@
+use deps::*;
+
+/// Applies an [RFC 7386](https://www.rfc-editor.org/rfc/rfc7386) JSON Merge Patch
+/// to `target` in place.
+///
+/// - Fields present on the `patch` replace those on the `target`.
+/// - Fields set to `null` on the `patch` are removed from the `target`.
+/// - Fields absent on the `patch` are left alone.
+/// - Objects are merged recursively, anything else (arrays included) is replaced wholesale.
+pub fn apply_merge_patch(target: &mut serde_json::Value, patch: &serde_json::Value) {
+    use serde_json::Value;
+    let patch = match patch {
+        Value::Object(patch) => patch,
+        patch => {
+            *target = patch.clone();
+            return;
+        }
+    };
+    if !target.is_object() {
+        *target = Value::Object(Default::default());
+    }
+    let target = target.as_object_mut().unwrap();
+    for (key, value) in patch {
+        if value.is_null() {
+            target.remove(key);
+        } else {
+            apply_merge_patch(target.entry(key.clone()).or_insert(Value::Null), value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use deps::*;
+
+    use super::apply_merge_patch;
+
+    crate::table_tests! {
+        merge_patch,
+        (target, patch, expected),
+        {
+            let mut target = target;
+            apply_merge_patch(&mut target, &patch);
+            assert_eq!(target, expected);
+        }
+    }
+
+    merge_patch! {
+        sets_fields: (
+            serde_json::json!({ "username": "sabrina", "picUrl": null }),
+            serde_json::json!({ "picUrl": "https://obj.teen.dj/d78xas" }),
+            serde_json::json!({ "username": "sabrina", "picUrl": "https://obj.teen.dj/d78xas" }),
+        ),
+        clears_nulled_fields: (
+            serde_json::json!({ "username": "sabrina", "picUrl": "https://obj.teen.dj/d78xas" }),
+            serde_json::json!({ "picUrl": null }),
+            serde_json::json!({ "username": "sabrina" }),
+        ),
+        leaves_omitted_fields_unchanged: (
+            serde_json::json!({ "username": "sabrina", "email": "hex.queen@teen.dj" }),
+            serde_json::json!({ "email": "archie1941@poetry.ybn" }),
+            serde_json::json!({ "username": "sabrina", "email": "archie1941@poetry.ybn" }),
+        ),
+        merges_nested_objects: (
+            serde_json::json!({ "a": { "b": 1, "c": 2 } }),
+            serde_json::json!({ "a": { "c": null, "d": 3 } }),
+            serde_json::json!({ "a": { "b": 1, "d": 3 } }),
+        ),
+        replaces_arrays: (
+            serde_json::json!({ "a": [1, 2, 3] }),
+            serde_json::json!({ "a": [4] }),
+            serde_json::json!({ "a": [4] }),
+        ),
+        replaces_non_object_targets: (
+            serde_json::json!([1, 2]),
+            serde_json::json!({ "a": 1 }),
+            serde_json::json!({ "a": 1 }),
+        ),
+    }
+}
@@ -0,0 +1,191 @@
+//! `multipart/mixed` bodies bundling several responses, for batch endpoints whose
+//! clients ask for them through `Accept` instead of a json array. Each part is an
+//! `application/http` message carrying the status line, headers and body of one
+//! sub-response, in order.
+
+use deps::*;
+
+use axum::{
+    body::{boxed, Full},
+    http::{self, header, HeaderMap, HeaderValue},
+    response::{IntoResponse, Response},
+};
+
+pub const MULTIPART_MIXED: &str = "multipart/mixed";
+
+/// Whether `multipart/mixed` is among the types listed in the `Accept` header.
+/// Doesn't weigh `q`, batch endpoints prefer json unless asked otherwise.
+pub fn accepts_multipart_mixed(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|val| val.to_str().ok())
+        .flat_map(|val| val.split(','))
+        .filter_map(|val| val.split(';').next())
+        .any(|mime| mime.trim().eq_ignore_ascii_case(MULTIPART_MIXED))
+}
+
+/// Use [`MultipartMixed::part`] for each sub-response then
+/// [`MultipartMixed::encode`].
+#[derive(Debug)]
+pub struct MultipartMixed {
+    boundary: String,
+    parts: Vec<Response>,
+}
+
+impl MultipartMixed {
+    /// The `boundary` mustn't occur in any of the parts, an id from
+    /// [`crate::Context::ids`] will do.
+    pub fn new(boundary: impl Into<String>) -> Self {
+        Self {
+            boundary: boundary.into(),
+            parts: vec![],
+        }
+    }
+
+    pub fn part(mut self, resp: Response) -> Self {
+        self.parts.push(resp);
+        self
+    }
+
+    /// Buffers the bodies of the parts. A part whose body fails to read is put in
+    /// as a bodiless `500`.
+    pub async fn encode(self) -> Response {
+        let mut body = Vec::new();
+        for resp in self.parts {
+            let (mut parts, part_body) = resp.into_parts();
+            let part_body = match hyper::body::to_bytes(part_body).await {
+                Ok(part_body) => part_body,
+                Err(err) => {
+                    tracing::error!(?err, "unable to read batch part");
+                    parts.status = http::StatusCode::INTERNAL_SERVER_ERROR;
+                    parts.headers.clear();
+                    Default::default()
+                }
+            };
+            body.extend_from_slice(
+                format!(
+                    "--{}\r\n{}: application/http\r\n\r\nHTTP/1.1 {}\r\n",
+                    self.boundary,
+                    header::CONTENT_TYPE,
+                    parts.status
+                )
+                .as_bytes(),
+            );
+            parts
+                .headers
+                .insert(header::CONTENT_LENGTH, HeaderValue::from(part_body.len()));
+            for (name, val) in &parts.headers {
+                body.extend_from_slice(name.as_str().as_bytes());
+                body.extend_from_slice(b": ");
+                body.extend_from_slice(val.as_bytes());
+                body.extend_from_slice(b"\r\n");
+            }
+            body.extend_from_slice(b"\r\n");
+            body.extend_from_slice(&part_body);
+            body.extend_from_slice(b"\r\n");
+        }
+        body.extend_from_slice(format!("--{}--\r\n", self.boundary).as_bytes());
+        (
+            [(
+                header::CONTENT_TYPE,
+                format!("{MULTIPART_MIXED}; boundary={}", self.boundary),
+            )],
+            boxed(Full::from(body)),
+        )
+            .into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use deps::*;
+
+    use super::*;
+    use crate::utils::testing::*;
+
+    fn app() -> axum::Router {
+        axum::Router::new().route(
+            "/batch",
+            axum::routing::post(|headers: HeaderMap| async move {
+                if !accepts_multipart_mixed(&headers) {
+                    return StatusCode::NOT_ACCEPTABLE.into_response();
+                }
+                [
+                    (StatusCode::OK, axum::Json(serde_json::json!({ "id": 1 }))).into_response(),
+                    (
+                        StatusCode::NOT_FOUND,
+                        axum::Json(serde_json::json!({ "error": "notFound" })),
+                    )
+                        .into_response(),
+                ]
+                .into_iter()
+                .fold(MultipartMixed::new("b0undary"), MultipartMixed::part)
+                .encode()
+                .await
+            }),
+        )
+    }
+
+    #[tokio::test]
+    async fn encodes_each_response_as_a_part() {
+        let resp = app()
+            .oneshot(
+                http::Request::builder()
+                    .method("POST")
+                    .uri("/batch")
+                    .header(header::ACCEPT, "application/json;q=0.5, multipart/mixed")
+                    .body(Default::default())
+                    .unwrap_or_log(),
+            )
+            .await
+            .unwrap_or_log();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(
+            resp.headers()[header::CONTENT_TYPE],
+            "multipart/mixed; boundary=b0undary"
+        );
+        let body = hyper::body::to_bytes(resp.into_body())
+            .await
+            .unwrap_or_log();
+        let body = std::str::from_utf8(&body).unwrap();
+        assert!(body.ends_with("--b0undary--\r\n"), "{body}");
+        let parts = body
+            .trim_end_matches("--b0undary--\r\n")
+            .split("--b0undary\r\n")
+            .skip(1)
+            .collect::<Vec<_>>();
+        assert_eq!(parts.len(), 2, "{body}");
+        for (part, (status, json)) in parts.iter().zip([
+            ("200 OK", r#"{"id":1}"#),
+            ("404 Not Found", r#"{"error":"notFound"}"#),
+        ]) {
+            let (head, content) = part.split_once("\r\n\r\n").unwrap();
+            assert_eq!(head, "content-type: application/http");
+            let (head, content) = content.split_once("\r\n\r\n").unwrap();
+            assert!(
+                head.starts_with(&format!("HTTP/1.1 {status}\r\n")),
+                "{head}"
+            );
+            assert!(head.contains("content-type: application/json"), "{head}");
+            assert!(
+                head.contains(&format!("content-length: {}", json.len())),
+                "{head}"
+            );
+            assert_eq!(content, format!("{json}\r\n"));
+        }
+    }
+
+    #[test]
+    fn checks_accept() {
+        let mut headers = HeaderMap::new();
+        assert!(!accepts_multipart_mixed(&headers));
+        headers.insert(header::ACCEPT, HeaderValue::from_static("application/json"));
+        assert!(!accepts_multipart_mixed(&headers));
+        headers.insert(
+            header::ACCEPT,
+            HeaderValue::from_static("Multipart/Mixed; boundary=x"),
+        );
+        assert!(accepts_multipart_mixed(&headers));
+    }
+}
@@ -0,0 +1,82 @@
+//! utoipa 2 has no `examples` map on [`openapi::Content`](utoipa::openapi::Content),
+//! only the single `example`. Named examples are gathered in the
+//! [`OperationExtras`](crate::OperationExtras) of the [`SpecPaths`](crate::SpecPaths)
+//! instead and added by [`expand`] once the spec is serialized.
+
+use deps::*;
+
+use crate::NamedExample;
+
+/// The `examples` map of a media type object.
+pub fn examples<T>(
+    examples: impl IntoIterator<Item = NamedExample<T>>,
+) -> serde_json::Map<String, serde_json::Value>
+where
+    T: serde::Serialize,
+{
+    examples
+        .into_iter()
+        .map(|(name, summary, value)| {
+            (
+                name.to_string(),
+                serde_json::json!({
+                    "summary": summary,
+                    "value": serde_json::to_value(value).unwrap(),
+                }),
+            )
+        })
+        .collect()
+}
+
+/// Sets the `examples` from the `extras` on the request body and responses of
+/// every operation in the spec, looked up by its `operationId`.
+pub fn expand(spec: &mut serde_json::Value, extras: &crate::SpecExtras) {
+    let operations = spec
+        .get_mut("paths")
+        .and_then(|paths| paths.as_object_mut())
+        .into_iter()
+        .flat_map(|paths| paths.values_mut())
+        .filter_map(|item| item.as_object_mut())
+        .flat_map(|item| item.values_mut())
+        .filter_map(|op| op.as_object_mut());
+    for op in operations {
+        let extras = match op
+            .get("operationId")
+            .and_then(|id| id.as_str())
+            .and_then(|id| extras.get(id))
+        {
+            Some(extras) => extras,
+            None => continue,
+        };
+        if !extras.request_examples.is_empty() {
+            if let Some(body) = op.get_mut("requestBody") {
+                set_examples(body, &extras.request_examples);
+            }
+        }
+        for (status, examples) in &extras.response_examples {
+            if let Some(resp) = op
+                .get_mut("responses")
+                .and_then(|responses| responses.get_mut(status))
+            {
+                set_examples(resp, examples);
+            }
+        }
+    }
+}
+
+/// On each media type of the request body or response object.
+fn set_examples(
+    object: &mut serde_json::Value,
+    examples: &serde_json::Map<String, serde_json::Value>,
+) {
+    let contents = object
+        .get_mut("content")
+        .and_then(|content| content.as_object_mut())
+        .into_iter()
+        .flat_map(|content| content.values_mut())
+        .filter_map(|media| media.as_object_mut());
+    for media in contents {
+        media.remove("example");
+        media.insert("examples".into(), examples.clone().into());
+    }
+}
@@ -0,0 +1,296 @@
+//! Streaming extractor for `application/x-ndjson` request bodies.
+
+use deps::*;
+
+use axum::{
+    extract::{BodyStream, FromRequest, RequestParts},
+    http,
+    response::{IntoResponse, Response},
+};
+use futures::{Stream, StreamExt};
+
+use crate::{DocumentedParameter, ParameterDoc, ToRefOrSchema};
+
+pub const NDJSON_CONTENT_TYPE: &str = "application/x-ndjson";
+
+/// Longest line accepted when there's no [`crate::Config::body_limit`] to go by.
+pub const DEFAULT_MAX_LINE_LEN: usize = 64 * 1024;
+
+/// Newline delimited JSON body where every line is a `T`. Unlike [`axum::Json`],
+/// the body isn't buffered. Use [`NdJson::records`] to decode the records as they
+/// arrive. A single line may not be longer than the [`crate::Config::body_limit`]
+/// of a json body.
+pub struct NdJson<T> {
+    body: BodyStream,
+    max_line_len: usize,
+    _record: std::marker::PhantomData<fn() -> T>,
+}
+
+/// Ends the [`NdJson::records`] stream.
+#[derive(Debug, thiserror::Error)]
+#[error("line {line} is longer than {max} bytes")]
+pub struct LineTooLong {
+    pub line: usize,
+    pub max: usize,
+}
+
+impl<T> std::fmt::Debug for NdJson<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NdJson")
+            .field("record", &std::any::type_name::<T>())
+            .finish()
+    }
+}
+
+/// A record that failed to decode doesn't end the stream but a failure to read
+/// the body does.
+pub type NdJsonItem<T> = Result<(usize, Result<T, serde_json::Error>), axum::Error>;
+
+struct RecordsState {
+    body: BodyStream,
+    buf: Vec<u8>,
+    max_line_len: usize,
+    line_no: usize,
+    eof: bool,
+}
+
+impl RecordsState {
+    fn line_too_long(&mut self) -> axum::Error {
+        self.eof = true;
+        self.buf.clear();
+        axum::Error::new(LineTooLong {
+            line: self.line_no + 1,
+            max: self.max_line_len,
+        })
+    }
+}
+
+impl<T> NdJson<T>
+where
+    T: serde::de::DeserializeOwned + Send,
+{
+    /// Yields each record along with its 1-based line number. The next chunk of the
+    /// body is only read once the previous records have been consumed so slow
+    /// consumers exert backpressure on the client. Blank lines are skipped and
+    /// a line that's too long ends the stream with a [`LineTooLong`].
+    pub fn records(self) -> impl Stream<Item = NdJsonItem<T>> + Send {
+        let state = RecordsState {
+            body: self.body,
+            buf: vec![],
+            max_line_len: self.max_line_len,
+            line_no: 0,
+            eof: false,
+        };
+        futures::stream::unfold(state, |mut state| async move {
+            loop {
+                let newline = state.buf.iter().position(|byte| *byte == b'\n');
+                if newline.unwrap_or(state.buf.len()) > state.max_line_len {
+                    let err = state.line_too_long();
+                    return Some((Err(err), state));
+                }
+                if let Some(pos) = newline {
+                    let rest = state.buf.split_off(pos + 1);
+                    let line = std::mem::replace(&mut state.buf, rest);
+                    state.line_no += 1;
+                    if let Some(record) = parse_line(&line) {
+                        return Some((Ok((state.line_no, record)), state));
+                    }
+                    continue;
+                }
+                if state.eof {
+                    let line = std::mem::take(&mut state.buf);
+                    state.line_no += 1;
+                    return parse_line(&line).map(|record| (Ok((state.line_no, record)), state));
+                }
+                match state.body.next().await {
+                    Some(Ok(chunk)) => state.buf.extend_from_slice(&chunk),
+                    Some(Err(err)) => {
+                        state.eof = true;
+                        state.buf.clear();
+                        return Some((Err(err), state));
+                    }
+                    None => state.eof = true,
+                }
+            }
+        })
+    }
+}
+
+fn parse_line<T>(line: &[u8]) -> Option<Result<T, serde_json::Error>>
+where
+    T: serde::de::DeserializeOwned,
+{
+    if line.iter().all(u8::is_ascii_whitespace) {
+        None
+    } else {
+        Some(serde_json::from_slice(line))
+    }
+}
+
+#[async_trait::async_trait]
+impl<T> FromRequest<axum::body::Body> for NdJson<T> {
+    type Rejection = Response;
+
+    async fn from_request(
+        req: &mut RequestParts<axum::body::Body>,
+    ) -> Result<Self, Self::Rejection> {
+        let is_ndjson = req
+            .headers()
+            .get(http::header::CONTENT_TYPE)
+            .and_then(|val| val.to_str().ok())
+            .map(|val| val.starts_with(NDJSON_CONTENT_TYPE))
+            .unwrap_or_default();
+        if !is_ndjson {
+            return Err(crate::utils::ApiError::UnsupportedMediaType {
+                expected: NDJSON_CONTENT_TYPE.into(),
+            }
+            .into_response());
+        }
+        let max_line_len = req
+            .extensions()
+            .get::<crate::SharedContext>()
+            .map(|ctx| ctx.config.body_limit)
+            .unwrap_or(DEFAULT_MAX_LINE_LEN);
+        let body = BodyStream::from_request(req)
+            .await
+            .map_err(IntoResponse::into_response)?;
+        Ok(Self {
+            body,
+            max_line_len,
+            _record: Default::default(),
+        })
+    }
+}
+
+impl<T> DocumentedParameter for NdJson<T>
+where
+    T: ToRefOrSchema,
+{
+    fn to_openapi(_op_id: &str, _path: &str) -> Vec<ParameterDoc> {
+        vec![utoipa::openapi::request_body::RequestBodyBuilder::new()
+            .description(Some("Newline delimited JSON: one record per line."))
+            .content(
+                NDJSON_CONTENT_TYPE,
+                utoipa::openapi::ContentBuilder::new()
+                    .schema(match T::ref_or_schema() {
+                        utoipa::openapi::schema::RefOr::T(schema) => {
+                            if T::schema_name() == "Request" {
+                                schema.into()
+                            } else {
+                                utoipa::openapi::Ref::from_schema_name(T::schema_name().to_string())
+                                    .into()
+                            }
+                        }
+                        ref_or => ref_or,
+                    })
+                    .build(),
+            )
+            .build()
+            .into()]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use deps::*;
+
+    use super::*;
+    use crate::utils::testing::*;
+    use axum::body::Bytes;
+
+    #[derive(Debug, PartialEq, serde::Deserialize)]
+    #[serde(crate = "serde")]
+    struct Record {
+        a: u32,
+    }
+
+    #[tokio::test]
+    async fn decodes_records_split_across_chunks() {
+        setup_tracing_once();
+        let chunks: Vec<Result<Bytes, std::io::Error>> = vec![
+            Ok(Bytes::from_static(b"{\"a\": 1}\n{\"a\"")),
+            Ok(Bytes::from_static(b": 2}\n\n not json\n")),
+            Ok(Bytes::from_static(b"{\"a\": 3}")),
+        ];
+        let mut req = RequestParts::new(
+            http::Request::builder()
+                .header(http::header::CONTENT_TYPE, NDJSON_CONTENT_TYPE)
+                .body(axum::body::Body::wrap_stream(futures::stream::iter(chunks)))
+                .unwrap_or_log(),
+        );
+        let records = NdJson::<Record>::from_request(&mut req)
+            .await
+            .unwrap()
+            .records()
+            .map(|item| {
+                let (line_no, record) = item.unwrap_or_log();
+                (line_no, record.ok())
+            })
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(
+            records,
+            vec![
+                (1, Some(Record { a: 1 })),
+                (2, Some(Record { a: 2 })),
+                (4, None),
+                (5, Some(Record { a: 3 })),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn ends_at_overlong_lines() {
+        setup_tracing_once();
+        let body = format!(
+            "{{\"a\": 1}}\n{{\"a\": \"{}\"}}\n{{\"a\": 3}}\n",
+            "x".repeat(DEFAULT_MAX_LINE_LEN)
+        );
+        let mut req = RequestParts::new(
+            http::Request::builder()
+                .header(http::header::CONTENT_TYPE, NDJSON_CONTENT_TYPE)
+                .body(axum::body::Body::from(body))
+                .unwrap_or_log(),
+        );
+        let records = NdJson::<Record>::from_request(&mut req)
+            .await
+            .unwrap()
+            .records()
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(records.len(), 2, "{records:?}");
+        assert_eq!(records[0].as_ref().unwrap().0, 1);
+        let err = records[1].as_ref().unwrap_err().to_string();
+        assert_eq!(
+            err,
+            format!("line 2 is longer than {DEFAULT_MAX_LINE_LEN} bytes")
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_other_content_types() {
+        let mut req = RequestParts::new(
+            http::Request::builder()
+                .header(http::header::CONTENT_TYPE, "application/json")
+                .body(axum::body::Body::from("{\"a\": 1}"))
+                .unwrap_or_log(),
+        );
+        let resp = NdJson::<Record>::from_request(&mut req).await.unwrap_err();
+        assert_eq!(resp.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        let body = hyper::body::to_bytes(resp.into_body())
+            .await
+            .unwrap_or_log();
+        let body = serde_json::from_slice(&body).unwrap_or_log();
+        check_json(
+            (
+                "expected",
+                &serde_json::json!({
+                    "error": "unsupportedMediaType",
+                    "code": "request.unsupported_media_type",
+                    "expected": NDJSON_CONTENT_TYPE,
+                }),
+            ),
+            ("response", &body),
+        );
+    }
+}
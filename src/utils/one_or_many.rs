@@ -0,0 +1,218 @@
+//! For endpoints that share their logic between a collection route like `/users`
+//! and an item route like `/users/:id`. The shared code returns the items and
+//! each route wraps them in a [`OneOrMany`] pinned to [`One`] or [`Many`] so
+//! that it's documented as the item or an array of them. Only the constructor
+//! matching the pin is there so what's served can't differ from the docs.
+
+use deps::*;
+
+use std::marker::PhantomData;
+
+use axum::response::{IntoResponse, Json, Response};
+use utoipa::openapi;
+
+use crate::{type_name_raw, ToRefOrSchema};
+
+/// Which shape of [`OneOrMany`] a route documents.
+pub trait Cardinality: Send + Sync + 'static {
+    fn schema(item: openapi::Ref) -> openapi::schema::RefOr<openapi::schema::Schema>;
+}
+
+/// Documented as the item itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct One;
+
+/// Documented as an array of items.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Many;
+
+impl Cardinality for One {
+    fn schema(item: openapi::Ref) -> openapi::schema::RefOr<openapi::schema::Schema> {
+        item.into()
+    }
+}
+
+impl Cardinality for Many {
+    fn schema(item: openapi::Ref) -> openapi::schema::RefOr<openapi::schema::Schema> {
+        openapi::schema::ArrayBuilder::new().items(item).into()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+#[serde(crate = "serde", untagged)]
+enum Shape<T> {
+    One(T),
+    Many(Vec<T>),
+}
+
+/// A single `T` or a list of them, serialized and documented as the `C` of the
+/// route.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OneOrMany<T, C> {
+    shape: Shape<T>,
+    _cardinality: PhantomData<C>,
+}
+
+impl<T> OneOrMany<T, One> {
+    pub fn one(item: T) -> Self {
+        Self {
+            shape: Shape::One(item),
+            _cardinality: PhantomData,
+        }
+    }
+}
+
+impl<T> OneOrMany<T, Many> {
+    pub fn many(items: Vec<T>) -> Self {
+        Self {
+            shape: Shape::Many(items),
+            _cardinality: PhantomData,
+        }
+    }
+}
+
+impl<T, C> OneOrMany<T, C> {
+    pub fn into_vec(self) -> Vec<T> {
+        match self.shape {
+            Shape::One(item) => vec![item],
+            Shape::Many(items) => items,
+        }
+    }
+}
+
+impl<T> From<T> for OneOrMany<T, One> {
+    fn from(item: T) -> Self {
+        Self::one(item)
+    }
+}
+
+impl<T> From<Vec<T>> for OneOrMany<T, Many> {
+    fn from(items: Vec<T>) -> Self {
+        Self::many(items)
+    }
+}
+
+impl<T, C> serde::Serialize for OneOrMany<T, C>
+where
+    T: serde::Serialize,
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        self.shape.serialize(serializer)
+    }
+}
+
+impl<T, C> IntoResponse for OneOrMany<T, C>
+where
+    T: serde::Serialize,
+{
+    fn into_response(self) -> Response {
+        Json(self).into_response()
+    }
+}
+
+impl<T, C> ToRefOrSchema for OneOrMany<T, C>
+where
+    T: utoipa::ToSchema,
+    C: Cardinality,
+{
+    fn schema_name() -> &'static str {
+        type_name_raw::<T>()
+    }
+
+    fn ref_or_schema() -> openapi::schema::RefOr<openapi::schema::Schema> {
+        C::schema(openapi::Ref::from_schema_name(type_name_raw::<T>()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use deps::*;
+
+    use super::*;
+    use crate::utils::testing::*;
+
+    #[derive(Debug, Clone, serde::Serialize, utoipa::ToSchema)]
+    #[serde(crate = "serde")]
+    struct Pet {
+        name: String,
+    }
+
+    /// What the list and detail handlers would share.
+    fn pets(name: Option<String>) -> Vec<Pet> {
+        let all = vec![
+            Pet {
+                name: "archie".into(),
+            },
+            Pet {
+                name: "betty".into(),
+            },
+        ];
+        all.into_iter()
+            .filter(|pet| name.as_ref().is_none_or(|name| &pet.name == name))
+            .collect()
+    }
+
+    fn app() -> axum::Router {
+        axum::Router::new()
+            .route(
+                "/pets",
+                axum::routing::get(|| async { OneOrMany::many(pets(None)) }),
+            )
+            .route(
+                "/pets/:name",
+                axum::routing::get(
+                    |axum::extract::Path(name): axum::extract::Path<String>| async move {
+                        OneOrMany::one(pets(Some(name)).remove(0))
+                    },
+                ),
+            )
+    }
+
+    async fn get(uri: &str) -> serde_json::Value {
+        let resp = app()
+            .oneshot(
+                http::Request::builder()
+                    .method("GET")
+                    .uri(uri)
+                    .body(Default::default())
+                    .unwrap_or_log(),
+            )
+            .await
+            .unwrap_or_log();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(resp.into_body())
+            .await
+            .unwrap_or_log();
+        serde_json::from_slice(&body).unwrap_or_log()
+    }
+
+    #[tokio::test]
+    async fn single_route_returns_an_object() {
+        assert_eq!(
+            get("/pets/betty").await,
+            serde_json::json!({ "name": "betty" })
+        );
+        assert_eq!(
+            serde_json::to_value(<OneOrMany<Pet, One>>::ref_or_schema()).unwrap(),
+            serde_json::json!({ "$ref": "#/components/schemas/Pet" })
+        );
+    }
+
+    #[tokio::test]
+    async fn list_route_returns_an_array() {
+        assert_eq!(
+            get("/pets").await,
+            serde_json::json!([{ "name": "archie" }, { "name": "betty" }])
+        );
+        assert_eq!(
+            serde_json::to_value(<OneOrMany<Pet, Many>>::ref_or_schema()).unwrap(),
+            serde_json::json!({
+                "type": "array",
+                "items": { "$ref": "#/components/schemas/Pet" },
+            })
+        );
+    }
+}
@@ -0,0 +1,53 @@
+use deps::*;
+
+use axum::{
+    extract::{FromRequest, Json, RequestParts},
+    http::header,
+    response::{IntoResponse, Response},
+};
+
+use crate::{DocumentedParameter, ParameterDoc, ToRefOrSchema};
+
+/// A json body that may be left out entirely. Requests without a `Content-Type`
+/// extract as `None`. Anything else goes through [`Json`] so that bad bodies are
+/// still rejected instead of being silently dropped like with `Option<Json<T>>`.
+#[derive(Debug)]
+pub struct OptionalJson<T>(pub Option<T>);
+
+#[async_trait::async_trait]
+impl<T, B> FromRequest<B> for OptionalJson<T>
+where
+    T: serde::de::DeserializeOwned,
+    B: axum::body::HttpBody + Send,
+    B::Data: Send,
+    B::Error: Into<axum::BoxError>,
+{
+    type Rejection = Response;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        if !req.headers().contains_key(header::CONTENT_TYPE) {
+            return Ok(Self(None));
+        }
+        let Json(body) = Json::<T>::from_request(req)
+            .await
+            .map_err(IntoResponse::into_response)?;
+        Ok(Self(Some(body)))
+    }
+}
+
+impl<T> DocumentedParameter for OptionalJson<T>
+where
+    T: ToRefOrSchema + serde::de::DeserializeOwned,
+{
+    fn to_openapi(op_id: &str, path: &str) -> Vec<ParameterDoc> {
+        <Option<Json<T>> as DocumentedParameter>::to_openapi(op_id, path)
+    }
+
+    fn body_fields() -> Option<Vec<String>> {
+        Json::<T>::body_fields()
+    }
+
+    fn json_body_error(body: &[u8]) -> Option<serde_json::Error> {
+        Json::<T>::json_body_error(body)
+    }
+}
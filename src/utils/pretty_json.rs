@@ -0,0 +1,167 @@
+use deps::*;
+
+use axum::response::Response;
+
+/// The query parameter asking for [`pretty_json`] output. Only honored in debug
+/// builds. Release builds need [`crate::Config::pretty_json`].
+pub const PRETTY_PARAM: &str = "pretty";
+
+/// Whether the response to `req` should go through [`pretty_json`].
+pub fn wants_pretty_json<B>(req: &axum::http::Request<B>) -> bool {
+    let configured = req
+        .extensions()
+        .get::<crate::SharedContext>()
+        .map(|ctx| ctx.config.pretty_json)
+        .unwrap_or_default();
+    let asked = || {
+        req.uri()
+            .query()
+            .into_iter()
+            .flat_map(|query| query.split('&'))
+            .any(|pair| {
+                matches!(
+                    pair.split_once('=').unwrap_or((pair, "true")),
+                    (PRETTY_PARAM, "true")
+                )
+            })
+    };
+    configured || (cfg!(debug_assertions) && asked())
+}
+
+/// Indents `application/json` bodies, leaving everything else be. The status and
+/// the other headers are kept as they are.
+pub async fn pretty_json(resp: Response) -> Response {
+    if !super::has_json_body(&resp) {
+        return resp;
+    }
+    super::rewrite_body(resp, "pretty json", |body| Ok(indent(&body))).await
+}
+
+/// Re-indents compact json without going through [`serde_json::Value`] so that
+/// the order of the keys is kept.
+fn indent(json: &[u8]) -> Vec<u8> {
+    const INDENT: &[u8] = b"  ";
+    let mut out = Vec::with_capacity(json.len() * 2);
+    let mut depth = 0usize;
+    let mut in_string = false;
+    let mut escaped = false;
+    let newline = |out: &mut Vec<u8>, depth: usize| {
+        out.push(b'\n');
+        for _ in 0..depth {
+            out.extend_from_slice(INDENT);
+        }
+    };
+    let mut bytes = json.iter().copied().peekable();
+    while let Some(byte) = bytes.next() {
+        if in_string {
+            out.push(byte);
+            match byte {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match byte {
+            b'"' => {
+                in_string = true;
+                out.push(byte);
+            }
+            b'{' | b'[' => {
+                out.push(byte);
+                // keep empty objects and arrays on one line
+                if matches!(bytes.peek(), Some(b'}' | b']')) {
+                    out.push(bytes.next().unwrap());
+                } else {
+                    depth += 1;
+                    newline(&mut out, depth);
+                }
+            }
+            b'}' | b']' => {
+                depth = depth.saturating_sub(1);
+                newline(&mut out, depth);
+                out.push(byte);
+            }
+            b',' => {
+                out.push(byte);
+                newline(&mut out, depth);
+            }
+            b':' => out.extend_from_slice(b": "),
+            byte if byte.is_ascii_whitespace() => {}
+            _ => out.push(byte),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use deps::*;
+
+    use super::*;
+
+    use crate::user::testing::*;
+    use crate::utils::testing::*;
+
+    crate::table_tests! {
+        pretty_json tokio,
+        (configured, query, pretty),
+        {
+            setup_tracing_once();
+            let mut ctx = crate::Context::mock(InMemoryDb::fixture());
+            ctx.config.pretty_json = configured;
+            let app = crate::user::router().layer(axum::Extension(std::sync::Arc::new(ctx)));
+            let resp = app
+                .oneshot(
+                    http::Request::builder()
+                        .method("GET")
+                        .uri(format!("/users/{USER_01_ID}{query}"))
+                        .header(
+                            http::header::AUTHORIZATION,
+                            format!("Bearer {USER_01_SESSION}"),
+                        )
+                        .body(Default::default())
+                        .unwrap_or_log(),
+                )
+                .await
+                .unwrap_or_log();
+            assert_eq!(resp.status(), StatusCode::OK);
+            let body = hyper::body::to_bytes(resp.into_body())
+                .await
+                .unwrap_or_log();
+            let body = std::str::from_utf8(&body).unwrap_or_log();
+            let json: serde_json::Value = serde_json::from_str(body).unwrap_or_log();
+            assert_eq!(json["id"], USER_01_ID.to_string());
+            let pretty = pretty && (configured || cfg!(debug_assertions));
+            assert_eq!(body.starts_with("{\n  \""), pretty, "{body}");
+            assert_eq!(body.contains('\n'), pretty, "{body}");
+        },
+    }
+
+    pretty_json! {
+        compact_by_default: (false, "", false),
+        pretty_when_configured: (true, "", true),
+        pretty_when_asked: (false, "?pretty=true", true),
+        compact_when_not_asked: (false, "?pretty=false", false),
+    }
+
+    #[test]
+    fn indents_and_keeps_key_order() {
+        let json = serde_json::json!({
+            "zeta": [1, { "b": "x, y: {z}", "a": [] }],
+            "alpha": "quote \" and \\",
+            "empty": {},
+        });
+        let compact = serde_json::to_vec(&json).unwrap();
+        let pretty = indent(&compact);
+        assert_eq!(
+            std::str::from_utf8(&pretty).unwrap(),
+            serde_json::to_string_pretty(&json).unwrap()
+        );
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&pretty).unwrap(),
+            json
+        );
+    }
+}
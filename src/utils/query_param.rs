@@ -0,0 +1,153 @@
+use deps::*;
+
+use utoipa::openapi;
+
+/// Declares a query parameter for the [`crate::DocumentedParameter::to_openapi`]
+/// of extractors that don't get their parameters from a schema.
+#[derive(Debug, Clone)]
+pub struct QueryParamDoc {
+    pub name: &'static str,
+    pub schema_type: openapi::SchemaType,
+    pub required: bool,
+    pub description: Option<&'static str>,
+    /// Still accepted but on its way out. Flagged in the spec and noted in the
+    /// description.
+    pub deprecated: bool,
+}
+
+impl QueryParamDoc {
+    /// Optional and not deprecated.
+    pub fn new(name: &'static str, schema_type: openapi::SchemaType) -> Self {
+        Self {
+            name,
+            schema_type,
+            required: false,
+            description: None,
+            deprecated: false,
+        }
+    }
+
+    pub fn description(mut self, description: &'static str) -> Self {
+        self.description = Some(description);
+        self
+    }
+
+    pub fn deprecated(mut self) -> Self {
+        self.deprecated = true;
+        self
+    }
+
+    pub fn parameter(&self) -> openapi::path::Parameter {
+        let description = match (self.description, self.deprecated) {
+            (Some(description), true) => Some(format!(
+                "{description} Deprecated: still accepted but will be removed."
+            )),
+            (None, true) => Some("Deprecated: still accepted but will be removed.".into()),
+            (description, false) => description.map(String::from),
+        };
+        openapi::path::ParameterBuilder::new()
+            .name(self.name)
+            .parameter_in(openapi::path::ParameterIn::Query)
+            .required(if self.required {
+                openapi::Required::True
+            } else {
+                openapi::Required::False
+            })
+            .description(description)
+            .deprecated(self.deprecated.then_some(openapi::Deprecated::True))
+            .schema(Some(
+                openapi::schema::ObjectBuilder::new().schema_type(self.schema_type.clone()),
+            ))
+            .build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use deps::*;
+
+    use super::*;
+    use crate::utils::testing::*;
+    use crate::{DocumentedParameter, ParameterDoc};
+
+    /// `perPage` replaced by `limit` but still honored.
+    #[derive(Debug, serde::Deserialize)]
+    #[serde(crate = "serde", rename_all = "camelCase")]
+    struct Paging {
+        limit: Option<u32>,
+        per_page: Option<u32>,
+    }
+
+    #[async_trait::async_trait]
+    impl<B: Send> axum::extract::FromRequest<B> for Paging {
+        type Rejection = crate::utils::ApiError;
+
+        async fn from_request(
+            req: &mut axum::extract::RequestParts<B>,
+        ) -> Result<Self, Self::Rejection> {
+            let axum::extract::Query(paging) = axum::extract::Query::<Self>::from_request(req)
+                .await
+                .map_err(|err| crate::utils::ApiError::InvalidQuery {
+                    message: err.to_string(),
+                })?;
+            Ok(paging)
+        }
+    }
+
+    impl DocumentedParameter for Paging {
+        fn to_openapi(_op_id: &str, _path: &str) -> Vec<ParameterDoc> {
+            vec![
+                QueryParamDoc::new("limit", openapi::SchemaType::Integer)
+                    .parameter()
+                    .into(),
+                QueryParamDoc::new("perPage", openapi::SchemaType::Integer)
+                    .description("Use `limit`.")
+                    .deprecated()
+                    .parameter()
+                    .into(),
+            ]
+        }
+    }
+
+    #[tokio::test]
+    async fn deprecated_params_are_flagged_but_accepted() {
+        let params = Paging::to_openapi("", "/items")
+            .into_iter()
+            .map(|param| match param {
+                ParameterDoc::Param(param) => serde_json::to_value(param).unwrap(),
+                ParameterDoc::Body(_) => panic!("query documented as body"),
+            })
+            .collect::<Vec<_>>();
+        assert!(params[0].get("deprecated").is_none(), "{params:?}");
+        assert_eq!(params[1]["deprecated"], true);
+        assert!(
+            params[1]["description"]
+                .as_str()
+                .unwrap()
+                .contains("Deprecated"),
+            "{params:?}"
+        );
+
+        let resp = axum::Router::<axum::body::Body>::new()
+            .route(
+                "/items",
+                axum::routing::get(|paging: Paging| async move {
+                    axum::Json(paging.limit.or(paging.per_page))
+                }),
+            )
+            .oneshot(
+                http::Request::builder()
+                    .method("GET")
+                    .uri("/items?perPage=20")
+                    .body(Default::default())
+                    .unwrap_or_log(),
+            )
+            .await
+            .unwrap_or_log();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(resp.into_body())
+            .await
+            .unwrap_or_log();
+        assert_eq!(&body[..], b"20");
+    }
+}
@@ -0,0 +1,406 @@
+//! Fixed window rate limiting per client, enabled through
+//! [`crate::Config::rate_limit`]. Every response carries the `X-RateLimit-*`
+//! headers describing the client's window so they can pace themselves before
+//! running into a `429`.
+
+use deps::*;
+
+use axum::{
+    http::{HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+
+use std::collections::HashMap;
+
+use crate::SharedContext;
+
+pub const LIMIT_HEADER: &str = "x-ratelimit-limit";
+pub const REMAINING_HEADER: &str = "x-ratelimit-remaining";
+/// Seconds until the window resets.
+pub const RESET_HEADER: &str = "x-ratelimit-reset";
+
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimit {
+    /// Requests allowed per `window`.
+    pub limit: u32,
+    pub window: std::time::Duration,
+}
+
+/// State of a client's window after counting a request against it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quota {
+    pub allowed: bool,
+    pub limit: u32,
+    pub remaining: u32,
+    pub reset_secs: u64,
+}
+
+impl Quota {
+    fn insert_headers(&self, headers: &mut HeaderMap) {
+        headers.insert(LIMIT_HEADER, HeaderValue::from(self.limit));
+        headers.insert(REMAINING_HEADER, HeaderValue::from(self.remaining));
+        headers.insert(RESET_HEADER, HeaderValue::from(self.reset_secs));
+    }
+}
+
+#[derive(Debug)]
+struct Window {
+    started: time::OffsetDateTime,
+    used: u32,
+}
+
+/// Past this many clients the expired windows are swept, at most once a window.
+const SWEEP_AT: usize = 10_000;
+/// Most clients tracked by default, see [`RateLimiter::with_capacity`].
+const MAX_CLIENTS: usize = 100_000;
+/// Shared by the clients that find the [`RateLimiter`] full.
+const OVERFLOW_KEY: &str = "overflow";
+
+#[derive(Debug, Default)]
+struct Windows {
+    by_client: HashMap<String, Window>,
+    last_sweep: Option<time::OffsetDateTime>,
+}
+
+#[derive(Debug)]
+pub struct RateLimiter {
+    windows: parking_lot::Mutex<Windows>,
+    capacity: usize,
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::with_capacity(MAX_CLIENTS)
+    }
+}
+
+impl RateLimiter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Tracks at most `capacity` clients. New ones past that share a single window
+    /// until the expired ones are swept.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            windows: Default::default(),
+            capacity,
+        }
+    }
+
+    /// Counts a request by `key` at `now`.
+    pub fn check(&self, key: &str, limit: RateLimit, now: time::OffsetDateTime) -> Quota {
+        let window_len: time::Duration = limit.window.try_into().unwrap_or(time::Duration::MAX);
+        let mut windows = self.windows.lock();
+        let Windows {
+            by_client,
+            last_sweep,
+        } = &mut *windows;
+        let sweep_due = last_sweep.is_none_or(|at| now - at >= window_len);
+        if (by_client.len() >= SWEEP_AT || by_client.len() >= self.capacity) && sweep_due {
+            by_client.retain(|_, window| now - window.started < window_len);
+            *last_sweep = Some(now);
+        }
+        let key = if by_client.len() >= self.capacity && !by_client.contains_key(key) {
+            OVERFLOW_KEY
+        } else {
+            key
+        };
+        let window = by_client.entry(key.into()).or_insert(Window {
+            started: now,
+            used: 0,
+        });
+        if now - window.started >= window_len {
+            *window = Window {
+                started: now,
+                used: 0,
+            };
+        }
+        let allowed = window.used < limit.limit;
+        if allowed {
+            window.used += 1;
+        }
+        let reset = window.started + window_len - now;
+        Quota {
+            allowed,
+            limit: limit.limit,
+            // a reload may have lowered the limit below what the window used
+            remaining: limit.limit.saturating_sub(window.used),
+            reset_secs: super::retry_after_secs(reset),
+        }
+    }
+}
+
+/// Clients are told apart by the hash of their bearer token, falling back to their
+/// [`super::ClientIp`]. The token isn't resolved to its user so that the requests
+/// turned away don't cost a session lookup. Made up tokens each get a window of
+/// their own but no further than the lookup they'd have cost without the limit.
+fn client_key<B>(req: &axum::http::Request<B>) -> String {
+    if let Some(token) = super::bearer_token(req.headers()) {
+        return format!("token:{}", crate::auth::api_key::hash_key(token));
+    }
+    match super::ClientIp::from_parts(req) {
+        Some(super::ClientIp(ip)) => format!("ip:{ip}"),
+        None => "anonymous".into(),
+    }
+}
+
+/// Middleware enforcing [`crate::Config::rate_limit`]. Expects the [`SharedContext`]
+/// in the request extensions so it has to be layered before the `Extension`. Layer
+/// it outside [`super::populate_request_context`] so that it turns requests away
+/// before their session is looked up.
+pub async fn rate_limit(
+    req: axum::http::Request<axum::body::Body>,
+    next: axum::middleware::Next<axum::body::Body>,
+) -> Response {
+    let ctx = match req.extensions().get::<SharedContext>() {
+        Some(ctx) => ctx.clone(),
+        None => return next.run(req).await,
+    };
+    let limit = match ctx.rate_limit() {
+        Some(limit) => limit,
+        None => return next.run(req).await,
+    };
+    let quota = ctx
+        .rate_limiter
+        .check(&client_key(&req), limit, ctx.clock.now());
+    let mut resp = if quota.allowed {
+        next.run(req).await
+    } else {
+        let mut resp = (
+            StatusCode::TOO_MANY_REQUESTS,
+            axum::Json(crate::RoutingError::RateLimited),
+        )
+            .into_response();
+        super::set_retry_after(
+            resp.headers_mut(),
+            ctx.retry_after(super::Backoff::RateLimit {
+                reset: time::Duration::seconds(quota.reset_secs as i64),
+            }),
+        );
+        resp
+    };
+    quota.insert_headers(resp.headers_mut());
+    resp
+}
+
+#[cfg(test)]
+mod tests {
+    use deps::*;
+
+    use super::*;
+    use crate::user::testing::*;
+    use crate::utils::testing::*;
+
+    #[tokio::test]
+    async fn headers_track_the_window() {
+        setup_tracing_once();
+        let clock = std::sync::Arc::new(FixedClock::new(
+            time::OffsetDateTime::from_unix_timestamp(1_663_599_358).unwrap(),
+        ));
+        let mut ctx = crate::Context::mock(InMemoryDb::fixture());
+        ctx.clock = clock.clone();
+        ctx.config.rate_limit = Some(RateLimit {
+            limit: 2,
+            window: std::time::Duration::from_secs(60),
+        });
+        let app = crate::user::router()
+            .layer(axum::middleware::from_fn(rate_limit))
+            .layer(axum::Extension(std::sync::Arc::new(ctx)));
+        let get_user = || async {
+            let resp = app
+                .clone()
+                .oneshot(
+                    http::Request::builder()
+                        .method("GET")
+                        .uri(format!("/users/{USER_01_ID}"))
+                        .header(
+                            http::header::AUTHORIZATION,
+                            format!("Bearer {USER_01_SESSION}"),
+                        )
+                        .body(Default::default())
+                        .unwrap_or_log(),
+                )
+                .await
+                .unwrap_or_log();
+            let header = |name: &str| resp.headers()[name].to_str().unwrap().to_string();
+            let headers = (
+                header(LIMIT_HEADER),
+                header(REMAINING_HEADER),
+                header(RESET_HEADER),
+            );
+            (resp.status(), headers)
+        };
+        let headers = |limit: &str, remaining: &str, reset: &str| {
+            (limit.to_string(), remaining.to_string(), reset.to_string())
+        };
+
+        assert_eq!(get_user().await, (StatusCode::OK, headers("2", "1", "60")));
+        clock.advance(time::Duration::seconds(10));
+        assert_eq!(get_user().await, (StatusCode::OK, headers("2", "0", "50")));
+        assert_eq!(
+            get_user().await,
+            (StatusCode::TOO_MANY_REQUESTS, headers("2", "0", "50"))
+        );
+        clock.advance(time::Duration::seconds(50));
+        assert_eq!(get_user().await, (StatusCode::OK, headers("2", "1", "60")));
+    }
+
+    #[tokio::test]
+    async fn reloading_a_lower_limit_mid_window() {
+        setup_tracing_once();
+        let mut ctx = crate::Context::mock(InMemoryDb::fixture());
+        ctx.config.rate_limit = Some(RateLimit {
+            limit: 3,
+            window: std::time::Duration::from_secs(60),
+        });
+        ctx.live_config = crate::reload::LiveConfig::new(
+            Box::new(|| {
+                Ok(crate::reload::Reloadable {
+                    log_level: "info".into(),
+                    enabled_features: vec![],
+                    rate_limit: Some(RateLimit {
+                        limit: 1,
+                        window: std::time::Duration::from_secs(60),
+                    }),
+                })
+            }),
+            None,
+        );
+        let ctx = std::sync::Arc::new(ctx);
+        let app = crate::user::router()
+            .layer(axum::middleware::from_fn(rate_limit))
+            .layer(axum::Extension(ctx.clone()));
+        let get_user = || async {
+            let resp = app
+                .clone()
+                .oneshot(
+                    http::Request::builder()
+                        .method("GET")
+                        .uri(format!("/users/{USER_01_ID}"))
+                        .header(
+                            http::header::AUTHORIZATION,
+                            format!("Bearer {USER_01_SESSION}"),
+                        )
+                        .body(Default::default())
+                        .unwrap_or_log(),
+                )
+                .await
+                .unwrap_or_log();
+            (
+                resp.status(),
+                resp.headers()[REMAINING_HEADER]
+                    .to_str()
+                    .unwrap()
+                    .to_string(),
+            )
+        };
+        assert_eq!(get_user().await, (StatusCode::OK, "2".into()));
+        assert_eq!(get_user().await, (StatusCode::OK, "1".into()));
+        ctx.live_config.reload().unwrap_or_log();
+        assert_eq!(
+            get_user().await,
+            (StatusCode::TOO_MANY_REQUESTS, "0".into())
+        );
+    }
+
+    /// Counts the session lookups.
+    #[derive(Debug)]
+    struct CountingDb {
+        inner: InMemoryDb,
+        session_lookups: std::sync::atomic::AtomicUsize,
+    }
+
+    #[async_trait::async_trait]
+    impl crate::db::DbBackend for CountingDb {
+        async fn session(&self, token: &str) -> Result<crate::auth::Session, sqlx::Error> {
+            self.session_lookups
+                .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            self.inner.session(token).await
+        }
+
+        async fn user(&self, id: uuid::Uuid) -> Result<crate::user::User, sqlx::Error> {
+            self.inner.user(id).await
+        }
+
+        async fn user_roles(
+            &self,
+            user_id: uuid::Uuid,
+        ) -> Result<Vec<crate::auth::Role>, sqlx::Error> {
+            self.inner.user_roles(user_id).await
+        }
+
+        async fn api_key(&self, key_hash: &str) -> Result<crate::auth::ApiKeyRecord, sqlx::Error> {
+            self.inner.api_key(key_hash).await
+        }
+    }
+
+    #[tokio::test]
+    async fn limited_requests_skip_the_session_lookup() {
+        setup_tracing_once();
+        let db = std::sync::Arc::new(CountingDb {
+            inner: InMemoryDb::fixture(),
+            session_lookups: Default::default(),
+        });
+        let mut ctx = crate::Context::mock(InMemoryDb::default());
+        ctx.db = db.clone();
+        ctx.config.rate_limit = Some(RateLimit {
+            limit: 1,
+            window: std::time::Duration::from_secs(60),
+        });
+        let app = crate::user::router()
+            .layer(axum::middleware::from_fn(
+                super::super::populate_request_context,
+            ))
+            .layer(axum::middleware::from_fn(rate_limit))
+            .layer(axum::Extension(std::sync::Arc::new(ctx)));
+        let get_user =
+            |token: &'static str| {
+                let mut req = http::Request::builder()
+                    .method("GET")
+                    .uri(format!("/users/{USER_01_ID}"))
+                    .header(http::header::AUTHORIZATION, format!("Bearer {token}"))
+                    .body(Default::default())
+                    .unwrap_or_log();
+                req.extensions_mut().insert(axum::extract::ConnectInfo(
+                    std::net::SocketAddr::from(([203, 0, 113, 7], 41_000)),
+                ));
+                let app = app.clone();
+                async move { app.oneshot(req).await.unwrap_or_log().status() }
+            };
+        let lookups = || db.session_lookups.load(std::sync::atomic::Ordering::SeqCst);
+        assert_eq!(get_user(USER_01_SESSION).await, StatusCode::OK);
+        let looked_up = lookups();
+        assert!(looked_up > 0);
+        assert_eq!(
+            get_user(USER_01_SESSION).await,
+            StatusCode::TOO_MANY_REQUESTS
+        );
+        assert_eq!(lookups(), looked_up);
+        // a different token from the same address gets its own window
+        assert_ne!(get_user("made-up-1").await, StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[test]
+    fn overflowing_clients_share_a_window() {
+        let limiter = RateLimiter::with_capacity(2);
+        let limit = RateLimit {
+            limit: 1,
+            window: std::time::Duration::from_secs(60),
+        };
+        let now = time::OffsetDateTime::from_unix_timestamp(1_663_599_358).unwrap();
+        assert!(limiter.check("a", limit, now).allowed);
+        assert!(limiter.check("b", limit, now).allowed);
+        assert!(limiter.check("c", limit, now).allowed);
+        assert!(
+            !limiter.check("d", limit, now).allowed,
+            "got its own window"
+        );
+        assert_eq!(limiter.windows.lock().by_client.len(), 3);
+
+        // expired windows make room again
+        let later = now + time::Duration::seconds(60);
+        assert!(limiter.check("d", limit, later).allowed);
+        assert_eq!(limiter.windows.lock().by_client.len(), 1);
+    }
+}
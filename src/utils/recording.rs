@@ -0,0 +1,396 @@
+//! Records each request along with the response it got, as one json [`Exchange`]
+//! per line, to reproduce issues locally. Opt in through
+//! [`crate::Config::record_exchanges`], only honored by debug builds. Credentials
+//! in the [`REDACTED_HEADERS`] and the values of the
+//! [`crate::Config::redacted_fields`] are masked, so are left out on replay. The
+//! `replay` binary re-issues the requests of a recording against a server.
+
+use deps::*;
+
+use std::collections::BTreeMap;
+
+use axum::{
+    body::{boxed, Bytes, Full, HttpBody},
+    http::{self, HeaderMap, Request},
+    response::{IntoResponse, Response},
+};
+
+use crate::SharedContext;
+
+pub const TARGET: &str = "recording";
+
+pub const REDACTED_HEADERS: &[&str] = &[
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+    "set-cookie",
+    crate::auth::api_key::HEADER,
+    crate::auth::signature::SIGNATURE,
+];
+
+const REDACTED: &str = "[redacted]";
+
+/// Where [`record_exchanges`] puts the [`Exchange`]s.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecordTo {
+    /// Events under the [`TARGET`] tracing target, the line in the `exchange`
+    /// field.
+    Log,
+    /// Appended to the file, created if missing.
+    File(std::path::PathBuf),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(crate = "serde", rename_all = "camelCase")]
+pub struct Exchange {
+    pub request_id: Option<String>,
+    pub request: RecordedRequest,
+    pub response: RecordedResponse,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(crate = "serde", rename_all = "camelCase")]
+pub struct RecordedRequest {
+    pub method: String,
+    /// Path and query.
+    pub uri: String,
+    /// Repeated headers are joined by commas.
+    pub headers: BTreeMap<String, String>,
+    pub body: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(crate = "serde", rename_all = "camelCase")]
+pub struct RecordedResponse {
+    pub status: u16,
+    pub headers: BTreeMap<String, String>,
+    /// Not captured for streamed responses.
+    pub body: Option<String>,
+}
+
+impl RecordedRequest {
+    /// The request again, sent to `base_url` instead. Leaves out the redacted
+    /// headers along with `host` and `content-length`.
+    pub fn to_request(&self, base_url: &str) -> Result<Request<hyper::Body>, http::Error> {
+        let mut req = Request::builder().method(self.method.as_str()).uri(format!(
+            "{}{}",
+            base_url.trim_end_matches('/'),
+            self.uri
+        ));
+        for (name, val) in &self.headers {
+            if val == REDACTED || name == "host" || name == "content-length" {
+                continue;
+            }
+            req = req.header(name, val);
+        }
+        req.body(self.body.clone().unwrap_or_default().into())
+    }
+}
+
+/// Middleware, layer it inside [`super::populate_request_context`] so that the
+/// exchanges carry the request id. Expects the [`SharedContext`] in the request
+/// extensions.
+pub async fn record_exchanges(
+    req: Request<axum::body::Body>,
+    next: axum::middleware::Next<axum::body::Body>,
+) -> Response {
+    let ctx = match req.extensions().get::<SharedContext>() {
+        Some(ctx) => ctx.clone(),
+        None => return next.run(req).await,
+    };
+    let record_to = match &ctx.config.record_exchanges {
+        Some(record_to) if cfg!(debug_assertions) => record_to,
+        _ => return next.run(req).await,
+    };
+    let redacted = &ctx.config.redacted_fields;
+    let (parts, body) = req.into_parts();
+    let body = match hyper::body::to_bytes(body).await {
+        Ok(body) => body,
+        Err(err) => {
+            return super::ApiError::BodyRead {
+                message: err.to_string(),
+            }
+            .into_response()
+        }
+    };
+    // the body is captured once the handler is done so that the [`super::Sensitive`]
+    // values it deserialized are known
+    let mut request = RecordedRequest {
+        method: parts.method.to_string(),
+        uri: parts
+            .uri
+            .path_and_query()
+            .map(|val| val.to_string())
+            .unwrap_or_else(|| parts.uri.path().to_string()),
+        headers: capture_headers(&parts.headers),
+        body: None,
+    };
+
+    let resp = next
+        .run(Request::from_parts(parts, body.clone().into()))
+        .await;
+    request.body = capture_body(&body, redacted);
+
+    let (parts, body) = resp.into_parts();
+    let (resp, body) = if body.size_hint().exact().is_some() {
+        match hyper::body::to_bytes(body).await {
+            Ok(body) => (
+                Response::from_parts(parts, boxed(Full::from(body.clone()))),
+                Some(body),
+            ),
+            Err(err) => {
+                tracing::error!(?err, "unable to read recorded response");
+                (
+                    http::StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+                    None,
+                )
+            }
+        }
+    } else {
+        (Response::from_parts(parts, body), None)
+    };
+    let exchange = Exchange {
+        request_id: super::current_request_id().map(|id| id.to_string()),
+        request,
+        response: RecordedResponse {
+            status: resp.status().as_u16(),
+            headers: capture_headers(resp.headers()),
+            body: body.and_then(|body: Bytes| capture_body(&body, redacted)),
+        },
+    };
+    record(record_to, &exchange).await;
+    resp
+}
+
+/// Serializes the file appends.
+static FILE_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+async fn record(record_to: &RecordTo, exchange: &Exchange) {
+    let line = match serde_json::to_string(exchange) {
+        Ok(line) => line,
+        Err(err) => {
+            tracing::error!(?err, "unable to serialize exchange");
+            return;
+        }
+    };
+    match record_to {
+        RecordTo::Log => {
+            tracing::info!(target: TARGET, exchange = line.as_str(), "recorded exchange")
+        }
+        RecordTo::File(path) => {
+            use tokio::io::AsyncWriteExt;
+            let _guard = FILE_LOCK.lock().await;
+            let result = async {
+                let mut file = tokio::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .await?;
+                file.write_all(format!("{line}\n").as_bytes()).await?;
+                file.flush().await
+            }
+            .await;
+            if let Err(err) = result {
+                tracing::error!(?err, ?path, "unable to record exchange");
+            }
+        }
+    }
+}
+
+fn capture_headers(headers: &HeaderMap) -> BTreeMap<String, String> {
+    let mut captured = BTreeMap::<String, String>::new();
+    for (name, val) in headers {
+        let val = if REDACTED_HEADERS.contains(&name.as_str()) {
+            REDACTED.into()
+        } else {
+            String::from_utf8_lossy(val.as_bytes()).into_owned()
+        };
+        captured
+            .entry(name.as_str().into())
+            .and_modify(|prev| {
+                if *prev != REDACTED {
+                    *prev = format!("{prev}, {val}");
+                }
+            })
+            .or_insert(val);
+    }
+    captured
+}
+
+/// `None` if empty, the `redacted` fields and [`super::Sensitive`] values masked
+/// if it's json.
+fn capture_body(body: &[u8], redacted: &[String]) -> Option<String> {
+    if body.is_empty() {
+        return None;
+    }
+    Some(match serde_json::from_slice::<serde_json::Value>(body) {
+        Ok(mut json) => {
+            super::slow_requests::redact(&mut json, redacted);
+            super::mask_sensitive(&mut json);
+            json.to_string()
+        }
+        Err(_) => String::from_utf8_lossy(body).into_owned(),
+    })
+}
+
+#[cfg(all(test, debug_assertions))]
+mod tests {
+    use deps::*;
+
+    use super::*;
+    use crate::utils::testing::*;
+
+    fn app(record_to: RecordTo) -> axum::Router {
+        let mut ctx = crate::Context::mock(InMemoryDb::default());
+        ctx.config.record_exchanges = Some(record_to);
+        axum::Router::new()
+            .route(
+                "/echo",
+                axum::routing::post(
+                    |axum::Json(body): axum::Json<serde_json::Value>| async move {
+                        (StatusCode::CREATED, axum::Json(body))
+                    },
+                ),
+            )
+            .layer(axum::middleware::from_fn(record_exchanges))
+            .layer(axum::Extension(std::sync::Arc::new(ctx)))
+    }
+
+    fn request() -> http::Request<axum::body::Body> {
+        http::Request::builder()
+            .method("POST")
+            .uri("/echo?dry=true")
+            .header(http::header::AUTHORIZATION, "Bearer whish_box_session")
+            .header(http::header::CONTENT_TYPE, "application/json")
+            .body(r#"{ "username": "whish_box", "password": "lovebite" }"#.into())
+            .unwrap_or_log()
+    }
+
+    fn check_exchange(exchange: &Exchange) {
+        let request = &exchange.request;
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.uri, "/echo?dry=true");
+        assert_eq!(request.headers["authorization"], REDACTED);
+        assert_eq!(request.headers["content-type"], "application/json");
+        let response = &exchange.response;
+        assert_eq!(response.status, 201);
+        assert_eq!(response.headers["content-type"], "application/json");
+        for body in [&request.body, &response.body] {
+            let body: serde_json::Value =
+                serde_json::from_str(body.as_deref().unwrap()).unwrap_or_log();
+            assert_eq!(
+                body,
+                serde_json::json!({ "username": "whish_box", "password": REDACTED })
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn records_to_the_log() {
+        use tracing_subscriber::layer::SubscriberExt;
+        let events = CapturedEvents::new(TARGET);
+        let _guard =
+            tracing::subscriber::set_default(tracing_subscriber::registry().with(events.clone()));
+        let resp = app(RecordTo::Log).oneshot(request()).await.unwrap_or_log();
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        // the body makes it through the capture intact
+        let body = hyper::body::to_bytes(resp.into_body())
+            .await
+            .unwrap_or_log();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap_or_log();
+        assert_eq!(body["password"], "lovebite");
+
+        let events = events.events();
+        assert_eq!(events.len(), 1, "{events:?}");
+        let exchange: Exchange =
+            serde_json::from_str(events[0]["exchange"].as_str().unwrap()).unwrap_or_log();
+        check_exchange(&exchange);
+
+        let replayed = exchange
+            .request
+            .to_request("http://localhost:8080/")
+            .unwrap_or_log();
+        assert_eq!(replayed.method(), http::Method::POST);
+        assert_eq!(
+            replayed.uri().to_string(),
+            "http://localhost:8080/echo?dry=true"
+        );
+        assert!(replayed
+            .headers()
+            .get(http::header::AUTHORIZATION)
+            .is_none());
+    }
+
+    #[derive(Debug, serde::Serialize, serde::Deserialize)]
+    #[serde(crate = "serde", rename_all = "camelCase")]
+    struct Signup {
+        username: String,
+        recovery_code: crate::utils::Sensitive<String>,
+    }
+
+    #[tokio::test]
+    async fn masks_sensitive_request_values() {
+        use tracing_subscriber::layer::SubscriberExt;
+        let events = CapturedEvents::new(TARGET);
+        let _guard =
+            tracing::subscriber::set_default(tracing_subscriber::registry().with(events.clone()));
+        let mut ctx = crate::Context::mock(InMemoryDb::default());
+        ctx.config.record_exchanges = Some(RecordTo::Log);
+        // the response leaves the value out so only the request can have noted it
+        let app = axum::Router::new()
+            .route(
+                "/signups",
+                axum::routing::post(|axum::Json(signup): axum::Json<Signup>| async move {
+                    (StatusCode::CREATED, signup.username)
+                }),
+            )
+            .layer(axum::middleware::from_fn(record_exchanges))
+            .layer(axum::middleware::from_fn(
+                crate::utils::populate_request_context,
+            ))
+            .layer(axum::Extension(std::sync::Arc::new(ctx)));
+        let resp = app
+            .oneshot(
+                http::Request::builder()
+                    .method("POST")
+                    .uri("/signups")
+                    .header(http::header::CONTENT_TYPE, "application/json")
+                    .body(r#"{ "username": "whish_box", "recoveryCode": "hunter2" }"#.into())
+                    .unwrap_or_log(),
+            )
+            .await
+            .unwrap_or_log();
+        assert_eq!(resp.status(), StatusCode::CREATED);
+
+        let events = events.events();
+        assert_eq!(events.len(), 1, "{events:?}");
+        let exchange: Exchange =
+            serde_json::from_str(events[0]["exchange"].as_str().unwrap()).unwrap_or_log();
+        let body: serde_json::Value =
+            serde_json::from_str(exchange.request.body.as_deref().unwrap()).unwrap_or_log();
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "username": "whish_box",
+                "recoveryCode": crate::utils::MASK,
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn appends_to_the_file() {
+        let path = std::env::temp_dir().join(format!("recording-{}.jsonl", uuid::Uuid::new_v4()));
+        let app = app(RecordTo::File(path.clone()));
+        for _ in 0..2 {
+            let resp = app.clone().oneshot(request()).await.unwrap_or_log();
+            assert_eq!(resp.status(), StatusCode::CREATED);
+        }
+        let recording = tokio::fs::read_to_string(&path).await.unwrap_or_log();
+        tokio::fs::remove_file(&path).await.unwrap_or_log();
+        let lines = recording.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2, "{recording}");
+        for line in lines {
+            check_exchange(&serde_json::from_str(line).unwrap_or_log());
+        }
+    }
+}
@@ -0,0 +1,294 @@
+//! Request scoped data that handlers would otherwise have to extract piece by piece.
+//! Populated by the [`populate_request_context`] middleware.
+
+use deps::*;
+
+use axum::{
+    extract::{FromRequest, RequestParts},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+
+use crate::{auth::AuthUser, DocumentedParameter, ParameterDoc, SharedContext};
+
+/// Taken from the request if the client set a sane one. Echoed on the response.
+pub const REQUEST_ID: &str = "x-request-id";
+
+tokio::task_local! {
+    static CURRENT_REQUEST_ID: std::sync::Arc<str>;
+    static CURRENT_AUTH: Option<AuthUser>;
+}
+
+/// The id of the request being handled on this task if any. For code that doesn't
+/// get to see the [`RequestContext`], like [`crate::Context::acquire`].
+pub fn current_request_id() -> Option<std::sync::Arc<str>> {
+    CURRENT_REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
+/// The user [`populate_request_context`] resolved the bearer token to if `token`
+/// is the one it saw. Saves [`crate::auth::authorize::Authorize`] a second session
+/// lookup.
+pub fn current_auth_user(token: &str) -> Option<uuid::Uuid> {
+    CURRENT_AUTH
+        .try_with(|auth| {
+            auth.as_ref()
+                .filter(|auth| &auth.token[..] == token)
+                .map(|auth| auth.id)
+        })
+        .ok()
+        .flatten()
+}
+
+/// Runs `fut` with `request_id` visible to [`current_request_id`].
+pub async fn with_request_id<F: std::future::Future>(
+    request_id: std::sync::Arc<str>,
+    fut: F,
+) -> F::Output {
+    CURRENT_REQUEST_ID.scope(request_id, fut).await
+}
+
+#[derive(Debug, Clone)]
+pub struct RequestContext {
+    pub request_id: std::sync::Arc<str>,
+    /// `None` for anonymous requests and invalid or expired tokens. Endpoints that
+    /// require auth should still go through [`crate::AuthenticatedEndpoint`].
+    pub auth: Option<AuthUser>,
+    /// One of [`crate::Config::locales`].
+    pub locale: std::sync::Arc<str>,
+}
+
+/// Middleware that builds the [`RequestContext`]. Expects the [`SharedContext`]
+/// in the request extensions so it has to be layered before the `Extension`.
+pub async fn populate_request_context(
+    mut req: axum::http::Request<axum::body::Body>,
+    next: axum::middleware::Next<axum::body::Body>,
+) -> Response {
+    let ctx = match req.extensions().get::<SharedContext>() {
+        Some(ctx) => ctx.clone(),
+        None => {
+            tracing::error!("context missing from request extensions");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    let request_id: std::sync::Arc<str> = match req
+        .headers()
+        .get(REQUEST_ID)
+        .and_then(|val| val.to_str().ok())
+        .filter(|val| {
+            !val.is_empty() && val.len() <= 128 && val.bytes().all(|byte| byte.is_ascii_graphic())
+        }) {
+        Some(id) => id.into(),
+        None => ctx.ids.new_id().to_string().into(),
+    };
+    let auth = match bearer_token(req.headers()) {
+        Some(token) => match crate::auth::authorize::session_user(&ctx, token).await {
+            Ok(id) => Some(AuthUser {
+                id,
+                token: token.into(),
+            }),
+            Err(crate::auth::authorize::Error::InvalidToken) => None,
+            Err(err) => {
+                tracing::warn!(?err, "unable to resolve session");
+                None
+            }
+        },
+        None => None,
+    };
+    let locale = negotiate_locale(req.headers(), &ctx.config.locales);
+    req.extensions_mut().insert(RequestContext {
+        request_id: request_id.clone(),
+        auth: auth.clone(),
+        locale,
+    });
+    let mut resp = with_request_id(
+        request_id.clone(),
+        CURRENT_AUTH.scope(auth, super::collect_sensitive(next.run(req))),
+    )
+    .await;
+    if let Ok(val) = HeaderValue::from_str(&request_id) {
+        resp.headers_mut().insert(REQUEST_ID, val);
+    }
+    resp
+}
+
+pub(crate) fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+}
+
+/// Picks the supported locale with the highest `q` in the `Accept-Language` header,
+/// matching on the primary subtag if there's no exact match. Falls back to the
+/// first supported one.
+fn negotiate_locale(headers: &HeaderMap, supported: &[String]) -> std::sync::Arc<str> {
+    let fallback = supported.first().map(String::as_str).unwrap_or("en");
+    let header = match headers
+        .get(header::ACCEPT_LANGUAGE)
+        .and_then(|val| val.to_str().ok())
+    {
+        Some(header) => header,
+        None => return fallback.into(),
+    };
+    let mut ranges = header
+        .split(',')
+        .filter_map(|range| {
+            let mut parts = range.split(';');
+            let tag = parts.next()?.trim();
+            let q = parts
+                .find_map(|param| param.trim().strip_prefix("q="))
+                .map(|q| q.parse::<f32>().unwrap_or(0.))
+                .unwrap_or(1.);
+            (!tag.is_empty() && q > 0.).then_some((tag, q))
+        })
+        .collect::<Vec<_>>();
+    // stable so that ties keep the client's order
+    ranges.sort_by(|(_, a), (_, b)| b.partial_cmp(a).unwrap_or(std::cmp::Ordering::Equal));
+    ranges
+        .into_iter()
+        .find_map(|(tag, _)| {
+            if tag == "*" {
+                return Some(fallback);
+            }
+            let primary = tag.split('-').next().unwrap_or(tag);
+            supported
+                .iter()
+                .find(|locale| locale.eq_ignore_ascii_case(tag))
+                .or_else(|| {
+                    supported
+                        .iter()
+                        .find(|locale| locale.eq_ignore_ascii_case(primary))
+                })
+                .map(String::as_str)
+        })
+        .unwrap_or(fallback)
+        .into()
+}
+
+#[async_trait::async_trait]
+impl<B> FromRequest<B> for RequestContext
+where
+    B: Send,
+{
+    type Rejection = Response;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        req.extensions().get::<Self>().cloned().ok_or_else(|| {
+            tracing::error!("request context middleware not applied");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        })
+    }
+}
+
+impl DocumentedParameter for RequestContext {
+    fn to_openapi(_op_id: &str, _path: &str) -> Vec<ParameterDoc> {
+        vec![]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use deps::*;
+
+    use super::*;
+    use crate::user::testing::*;
+    use crate::utils::testing::*;
+
+    fn app() -> axum::Router {
+        let ctx = std::sync::Arc::new(crate::Context::mock(InMemoryDb::fixture()));
+        axum::Router::new()
+            .route(
+                "/whoami",
+                axum::routing::get(|rctx: RequestContext| async move {
+                    axum::Json(serde_json::json!({
+                        "requestId": &rctx.request_id[..],
+                        "userId": rctx.auth.as_ref().map(|auth| auth.id),
+                        "locale": &rctx.locale[..],
+                        "reusedUserId": rctx
+                            .auth
+                            .and_then(|auth| current_auth_user(&auth.token[..])),
+                        "otherTokenUserId": current_auth_user("not-a-session"),
+                    }))
+                }),
+            )
+            .layer(axum::middleware::from_fn(populate_request_context))
+            .layer(axum::Extension(ctx))
+    }
+
+    crate::table_tests! {
+        request_context tokio,
+        (headers, expected),
+        {
+            let mut req = http::Request::builder().method("GET").uri("/whoami");
+            for (name, val) in headers {
+                req = req.header(name, val);
+            }
+            let resp = app()
+                .oneshot(req.body(Default::default()).unwrap_or_log())
+                .await
+                .unwrap_or_log();
+            assert_eq!(resp.status(), StatusCode::OK);
+            let echoed = resp.headers().get(REQUEST_ID).cloned();
+            let body = hyper::body::to_bytes(resp.into_body())
+                .await
+                .unwrap_or_log();
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap_or_log();
+            check_json(("expected", &expected), ("response", &body));
+            assert_eq!(echoed.unwrap(), body["requestId"].as_str().unwrap());
+        },
+    }
+
+    request_context! {
+        bundles_all_fields: (
+            [
+                (REQUEST_ID.to_string(), "req-1337".to_string()),
+                (header::AUTHORIZATION.to_string(), format!("Bearer {USER_01_SESSION}")),
+                (header::ACCEPT_LANGUAGE.to_string(), "fr-CH, de-AT;q=0.9, en;q=0.8".to_string()),
+            ],
+            serde_json::json!({
+                "requestId": "req-1337",
+                "userId": USER_01_ID,
+                "locale": "de",
+                "reusedUserId": USER_01_ID,
+                "otherTokenUserId": null,
+            }),
+        ),
+        defaults_for_anonymous_requests: (
+            [(header::AUTHORIZATION.to_string(), "Bearer not-a-session".to_string())],
+            serde_json::json!({
+                "userId": null,
+                "locale": "en",
+            }),
+        ),
+    }
+
+    #[tokio::test]
+    async fn error_bodies_carry_the_trace_id() {
+        let ctx = std::sync::Arc::new(crate::Context::mock(InMemoryDb::fixture()));
+        let resp = crate::user::router()
+            .layer(axum::middleware::from_fn(populate_request_context))
+            .layer(axum::Extension(ctx))
+            .oneshot(
+                http::Request::builder()
+                    .method("GET")
+                    .uri(format!("/users/{USER_01_ID}"))
+                    .header(header::AUTHORIZATION, "Bearer not-a-session")
+                    .body(Default::default())
+                    .unwrap_or_log(),
+            )
+            .await
+            .unwrap_or_log();
+        assert!(resp.status().is_client_error(), "{}", resp.status());
+        let request_id = resp.headers().get(REQUEST_ID).cloned().unwrap();
+        let body = hyper::body::to_bytes(resp.into_body())
+            .await
+            .unwrap_or_log();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap_or_log();
+        assert_eq!(
+            body[crate::utils::TRACE_ID_FIELD],
+            request_id.to_str().unwrap(),
+            "{body}"
+        );
+    }
+}
@@ -0,0 +1,400 @@
+//! In-memory LRU of serialized responses used by endpoints that set
+//! [`crate::HttpEndpoint::CACHE_TTL`]. Entries are only invalidated by age.
+
+use deps::*;
+
+use axum::{
+    body::Bytes,
+    http::{self, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+
+#[derive(Debug)]
+struct Entry {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+    stored_at: Instant,
+    ttl: Duration,
+    last_used: u64,
+}
+
+#[derive(Debug, Default)]
+struct Lru {
+    entries: HashMap<String, Entry>,
+    /// `last_used` tick to key, oldest first.
+    recency: BTreeMap<u64, String>,
+    tick: u64,
+}
+
+#[derive(Debug)]
+pub struct ResponseCache {
+    capacity: usize,
+    inner: parking_lot::Mutex<Lru>,
+}
+
+impl ResponseCache {
+    /// A `capacity` of zero disables caching.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            inner: Default::default(),
+        }
+    }
+
+    /// Keyed by tenant, method, path and query. The `Authorization` and api key
+    /// headers are included if `per_auth` is set so that users don't get served
+    /// each other's responses, as are the `vary` ones, see
+    /// [`crate::HttpEndpoint::VARY`].
+    pub fn key<B>(
+        req: &http::Request<B>,
+        per_auth: bool,
+        vary: &[http::header::HeaderName],
+    ) -> String {
+        let auth = if per_auth {
+            [
+                http::header::AUTHORIZATION,
+                http::header::HeaderName::from_static(crate::auth::api_key::HEADER),
+            ]
+            .iter()
+            .map(|name| {
+                req.headers()
+                    .get(name)
+                    .and_then(|val| val.to_str().ok())
+                    .unwrap_or_default()
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+        } else {
+            String::new()
+        };
+        // tenants share paths but not data
+        let tenant = req
+            .extensions()
+            .get::<crate::tenant::Tenant>()
+            .map(|tenant| tenant.as_str())
+            .unwrap_or_default();
+        let varied = vary
+            .iter()
+            .map(|name| {
+                let val = req
+                    .headers()
+                    .get(name)
+                    .and_then(|val| val.to_str().ok())
+                    .unwrap_or_default();
+                format!(" {name}={val}")
+            })
+            .collect::<String>();
+        format!("{tenant} {} {} {auth}{varied}", req.method(), req.uri())
+    }
+
+    /// Returns the stored response with an `Age` header set if there's a fresh one.
+    pub fn get(&self, key: &str) -> Option<Response> {
+        let mut lru = self.inner.lock();
+        let lru = &mut *lru;
+        let entry = lru.entries.get_mut(key)?;
+        let age = entry.stored_at.elapsed();
+        if age >= entry.ttl {
+            lru.recency.remove(&entry.last_used);
+            lru.entries.remove(key);
+            return None;
+        }
+        lru.tick += 1;
+        lru.recency.remove(&entry.last_used);
+        lru.recency.insert(lru.tick, key.to_string());
+        entry.last_used = lru.tick;
+
+        let mut resp = Response::new(axum::body::boxed(axum::body::Full::from(
+            entry.body.clone(),
+        )));
+        *resp.status_mut() = entry.status;
+        *resp.headers_mut() = entry.headers.clone();
+        resp.headers_mut()
+            .insert(http::header::AGE, http::HeaderValue::from(age.as_secs()));
+        Some(resp)
+    }
+
+    pub fn insert(
+        &self,
+        key: String,
+        status: StatusCode,
+        headers: HeaderMap,
+        body: Bytes,
+        ttl: Duration,
+    ) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut lru = self.inner.lock();
+        let lru = &mut *lru;
+        lru.tick += 1;
+        if let Some(old) = lru.entries.get(&key) {
+            lru.recency.remove(&old.last_used);
+        }
+        lru.recency.insert(lru.tick, key.clone());
+        lru.entries.insert(
+            key,
+            Entry {
+                status,
+                headers,
+                body,
+                stored_at: Instant::now(),
+                ttl,
+                last_used: lru.tick,
+            },
+        );
+        while lru.entries.len() > self.capacity {
+            let oldest = match lru.recency.keys().next() {
+                Some(tick) => *tick,
+                None => break,
+            };
+            if let Some(key) = lru.recency.remove(&oldest) {
+                lru.entries.remove(&key);
+            }
+        }
+    }
+
+    /// Buffers and stores successful responses, passing through the rest.
+    pub async fn store(&self, key: String, ttl: Duration, resp: Response) -> Response {
+        if !resp.status().is_success() || self.capacity == 0 {
+            return resp;
+        }
+        let (parts, body) = resp.into_parts();
+        let body = match hyper::body::to_bytes(body).await {
+            Ok(body) => body,
+            Err(err) => {
+                tracing::error!(?err, "unable to buffer response for caching");
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        };
+        self.insert(key, parts.status, parts.headers.clone(), body.clone(), ttl);
+        Response::from_parts(parts, axum::body::boxed(axum::body::Full::from(body)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use deps::*;
+
+    use crate::utils::testing::*;
+    use crate::*;
+
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    /// Counts how many times it was actually invoked.
+    #[derive(Debug, Clone, Default)]
+    struct Counter(Arc<AtomicUsize>);
+
+    #[async_trait::async_trait]
+    impl Endpoint for Counter {
+        type Request = ();
+        type Response = usize;
+        type Error = Infallible;
+
+        async fn handle(
+            &self,
+            _ctx: &crate::Context,
+            _request: Self::Request,
+        ) -> Result<Self::Response, Self::Error> {
+            Ok(self.0.fetch_add(1, Ordering::SeqCst) + 1)
+        }
+    }
+
+    impl HttpEndpoint for Counter {
+        const METHOD: Method = Method::Get;
+        const PATH: &'static str = "/count";
+        const CACHE_TTL: Option<std::time::Duration> = Some(std::time::Duration::from_millis(250));
+
+        type HttpRequest = ();
+
+        fn request(_: Self::HttpRequest) -> Result<Self::Request, Self::Error> {
+            Ok(())
+        }
+
+        fn response(resp: Self::Response) -> axum::response::Response {
+            Json(resp).into_response()
+        }
+    }
+
+    /// A [`Counter`] that's meant to be cached across users.
+    #[derive(Debug, Clone, Default)]
+    struct SharedCounter(Counter);
+
+    #[async_trait::async_trait]
+    impl Endpoint for SharedCounter {
+        type Request = ();
+        type Response = usize;
+        type Error = Infallible;
+
+        async fn handle(
+            &self,
+            ctx: &crate::Context,
+            request: Self::Request,
+        ) -> Result<Self::Response, Self::Error> {
+            self.0.handle(ctx, request).await
+        }
+    }
+
+    impl HttpEndpoint for SharedCounter {
+        const METHOD: Method = Method::Get;
+        const PATH: &'static str = "/shared";
+        const CACHE_TTL: Option<std::time::Duration> = Some(std::time::Duration::from_secs(60));
+        const CACHE_PER_AUTH: bool = false;
+
+        type HttpRequest = (BearerToken,);
+
+        fn request(_: Self::HttpRequest) -> Result<Self::Request, Self::Error> {
+            Ok(())
+        }
+
+        fn response(resp: Self::Response) -> axum::response::Response {
+            Json(resp).into_response()
+        }
+    }
+
+    /// A [`Counter`] taking `POST`s.
+    #[derive(Debug, Clone, Default)]
+    struct PostCounter(Counter);
+
+    #[async_trait::async_trait]
+    impl Endpoint for PostCounter {
+        type Request = ();
+        type Response = usize;
+        type Error = Infallible;
+
+        async fn handle(
+            &self,
+            ctx: &crate::Context,
+            request: Self::Request,
+        ) -> Result<Self::Response, Self::Error> {
+            self.0.handle(ctx, request).await
+        }
+    }
+
+    impl HttpEndpoint for PostCounter {
+        const METHOD: Method = Method::Post;
+        const PATH: &'static str = "/count";
+        const CACHE_TTL: Option<std::time::Duration> = Some(std::time::Duration::from_secs(60));
+
+        type HttpRequest = ();
+
+        fn request(_: Self::HttpRequest) -> Result<Self::Request, Self::Error> {
+            Ok(())
+        }
+
+        fn response(resp: Self::Response) -> axum::response::Response {
+            Json(resp).into_response()
+        }
+    }
+
+    async fn get_count(app: &axum::Router, uri: &str) -> (usize, Option<String>) {
+        request_count(app, "GET", uri, None).await
+    }
+
+    async fn request_count(
+        app: &axum::Router,
+        method: &str,
+        uri: &str,
+        token: Option<&str>,
+    ) -> (usize, Option<String>) {
+        let mut req = http::Request::builder().method(method).uri(uri);
+        if let Some(token) = token {
+            req = req.header(http::header::AUTHORIZATION, format!("Bearer {token}"));
+        }
+        let resp = app
+            .clone()
+            .oneshot(req.body(Default::default()).unwrap_or_log())
+            .await
+            .unwrap_or_log();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let age = resp
+            .headers()
+            .get(http::header::AGE)
+            .map(|val| val.to_str().unwrap().to_string());
+        let body = hyper::body::to_bytes(resp.into_body())
+            .await
+            .unwrap_or_log();
+        (serde_json::from_slice(&body).unwrap_or_log(), age)
+    }
+
+    fn counter_app() -> axum::Router {
+        endpoint_app(Counter::default())
+    }
+
+    #[tokio::test]
+    async fn credentials_are_keyed_even_when_shared() {
+        setup_tracing_once();
+        let app = endpoint_app(SharedCounter::default());
+        assert_eq!(
+            request_count(&app, "GET", "/shared", Some("one")).await,
+            (1, None)
+        );
+        assert_eq!(
+            request_count(&app, "GET", "/shared", Some("one")).await,
+            (1, Some("0".into()))
+        );
+        assert_eq!(
+            request_count(&app, "GET", "/shared", Some("other")).await,
+            (2, None)
+        );
+    }
+
+    #[tokio::test]
+    async fn only_safe_methods_are_cached() {
+        setup_tracing_once();
+        let app = endpoint_app(PostCounter::default());
+        assert_eq!(request_count(&app, "POST", "/count", None).await, (1, None));
+        assert_eq!(request_count(&app, "POST", "/count", None).await, (2, None));
+    }
+
+    #[tokio::test]
+    async fn hits_return_stored_body_with_age() {
+        setup_tracing_once();
+        let app = counter_app();
+        assert_eq!(get_count(&app, "/count").await, (1, None));
+        assert_eq!(get_count(&app, "/count").await, (1, Some("0".into())));
+        // the query is part of the key
+        assert_eq!(get_count(&app, "/count?fresh=1").await, (2, None));
+    }
+
+    #[tokio::test]
+    async fn entries_expire_after_ttl() {
+        setup_tracing_once();
+        let app = counter_app();
+        assert_eq!(get_count(&app, "/count").await, (1, None));
+        tokio::time::sleep(Counter::CACHE_TTL.unwrap()).await;
+        assert_eq!(get_count(&app, "/count").await, (2, None));
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let cache = super::ResponseCache::new(2);
+        let ttl = std::time::Duration::from_secs(60);
+        for key in ["a", "b"] {
+            cache.insert(
+                key.into(),
+                StatusCode::OK,
+                Default::default(),
+                Default::default(),
+                ttl,
+            );
+        }
+        assert!(cache.get("a").is_some());
+        cache.insert(
+            "c".into(),
+            StatusCode::OK,
+            Default::default(),
+            Default::default(),
+            ttl,
+        );
+        assert!(cache.get("a").is_some());
+        assert!(cache.get("b").is_none());
+        assert!(cache.get("c").is_some());
+    }
+}
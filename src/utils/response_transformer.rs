@@ -0,0 +1,103 @@
+use deps::*;
+
+use axum::response::Response;
+
+/// Post-processes every response of the endpoints that don't opt out through
+/// [`crate::HttpEndpoint::TRANSFORM_RESPONSE`], e.g. to wrap the bodies in an
+/// envelope. Runs after the response has been serialized and cached so it sees the
+/// final status, headers and body. Registered in
+/// [`crate::Context::response_transformers`] and applied in order.
+#[async_trait::async_trait]
+pub trait ResponseTransformer: std::fmt::Debug + Send + Sync + 'static {
+    async fn transform(&self, resp: Response) -> Response;
+}
+
+/// Runs `resp` through all the [`ResponseTransformer`]s of the context.
+pub async fn transform_response(ctx: &crate::Context, mut resp: Response) -> Response {
+    for transformer in &ctx.response_transformers {
+        resp = transformer.transform(resp).await;
+    }
+    resp
+}
+
+#[cfg(test)]
+mod tests {
+    use deps::*;
+
+    use super::*;
+
+    use crate::user::testing::*;
+    use crate::utils::testing::*;
+
+    use axum::{
+        body::{boxed, Full},
+        http::header,
+    };
+
+    /// Wraps json bodies in `{ "data": ... }`.
+    #[derive(Debug)]
+    struct Envelope;
+
+    #[async_trait::async_trait]
+    impl ResponseTransformer for Envelope {
+        async fn transform(&self, resp: Response) -> Response {
+            let is_json = resp
+                .headers()
+                .get(header::CONTENT_TYPE)
+                .map(|val| val.as_bytes().starts_with(b"application/json"))
+                .unwrap_or_default();
+            if !is_json {
+                return resp;
+            }
+            let (mut parts, body) = resp.into_parts();
+            let body = hyper::body::to_bytes(body).await.unwrap_or_log();
+            let data: serde_json::Value = serde_json::from_slice(&body).unwrap_or_log();
+            parts.headers.remove(header::CONTENT_LENGTH);
+            let body = serde_json::to_vec(&serde_json::json!({ "data": data })).unwrap_or_log();
+            Response::from_parts(parts, boxed(Full::from(body)))
+        }
+    }
+
+    #[tokio::test]
+    async fn wraps_in_envelope() {
+        setup_tracing_once();
+        let mut ctx = crate::Context::mock(InMemoryDb::fixture());
+        ctx.response_transformers
+            .push(std::sync::Arc::new(Envelope));
+        let app = crate::user::router().layer(axum::Extension(std::sync::Arc::new(ctx)));
+        let resp = app
+            .oneshot(
+                http::Request::builder()
+                    .method("GET")
+                    .uri(format!("/users/{USER_01_ID}"))
+                    .header(
+                        http::header::AUTHORIZATION,
+                        format!("Bearer {USER_01_SESSION}"),
+                    )
+                    .body(Default::default())
+                    .unwrap_or_log(),
+            )
+            .await
+            .unwrap_or_log();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(resp.into_body())
+            .await
+            .unwrap_or_log();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap_or_log();
+        let envelope = body.as_object().unwrap();
+        assert_eq!(envelope.len(), 1, "{body}");
+        check_json(
+            (
+                "expected",
+                &serde_json::json!({
+                    "data": {
+                        "id": USER_01_ID,
+                        "username": USER_01_USERNAME,
+                        "email": USER_01_EMAIL,
+                    }
+                }),
+            ),
+            ("response", &body),
+        );
+    }
+}
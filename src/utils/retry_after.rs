@@ -0,0 +1,167 @@
+//! The `Retry-After` of the `429`s and `503`s, worked out from whatever is
+//! holding the request back so that clients don't hammer a service that won't
+//! be back for a while nor wait on one that will be right away.
+
+use deps::*;
+
+use axum::http::{header, HeaderMap, HeaderValue};
+
+/// What a request was turned away by.
+#[derive(Debug, Clone, Copy)]
+pub enum Backoff {
+    /// The client's [`super::RateLimit`] window, resetting in this long.
+    RateLimit { reset: time::Duration },
+    /// Until the end announced through [`crate::maintenance::SetMaintenance`] or
+    /// [`crate::maintenance::RETRY_AFTER_SECS`] if there's none.
+    Maintenance,
+    /// Until the [`super::CircuitBreaker`] lets the db be probed again or
+    /// [`crate::RETRY_AFTER_SECS`] if it's not open.
+    Overloaded,
+}
+
+impl crate::Context {
+    /// How long to tell the client to wait.
+    pub fn retry_after(&self, backoff: Backoff) -> time::Duration {
+        let now = self.clock.now();
+        match backoff {
+            Backoff::RateLimit { reset } => reset,
+            Backoff::Maintenance => self
+                .maintenance
+                .ends_at()
+                .map(|ends_at| ends_at - now)
+                .filter(|wait| wait.is_positive())
+                .unwrap_or_else(|| {
+                    time::Duration::seconds(crate::maintenance::RETRY_AFTER_SECS as i64)
+                }),
+            Backoff::Overloaded => self
+                .db_breaker
+                .cooldown_left(now)
+                .unwrap_or_else(|| time::Duration::seconds(crate::RETRY_AFTER_SECS.into())),
+        }
+    }
+}
+
+/// Whole seconds, rounded up so that clients don't come back a moment too early
+/// and never zero which would read as right away.
+pub fn retry_after_secs(wait: time::Duration) -> u64 {
+    (wait.as_seconds_f64().ceil() as u64).max(1)
+}
+
+pub fn set_retry_after(headers: &mut HeaderMap, wait: time::Duration) {
+    headers.insert(
+        header::RETRY_AFTER,
+        HeaderValue::from(retry_after_secs(wait)),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use deps::*;
+
+    use crate::user::testing::*;
+    use crate::utils::testing::*;
+    use crate::utils::Clock;
+
+    fn ctx() -> (crate::Context, std::sync::Arc<FixedClock>) {
+        setup_tracing_once();
+        let clock = std::sync::Arc::new(FixedClock::new(
+            time::OffsetDateTime::from_unix_timestamp(1_663_599_358).unwrap(),
+        ));
+        let mut ctx = crate::Context::mock(InMemoryDb::fixture());
+        ctx.clock = clock.clone();
+        (ctx, clock)
+    }
+
+    fn app(ctx: crate::SharedContext) -> axum::Router {
+        crate::user::router()
+            .layer(axum::middleware::from_fn(crate::utils::rate_limit))
+            .layer(axum::middleware::from_fn(
+                crate::maintenance::reject_during_maintenance,
+            ))
+            .layer(axum::Extension(ctx))
+    }
+
+    /// The status of getting `USER_01` and its `Retry-After`, if any.
+    async fn get_user(app: &axum::Router) -> (StatusCode, Option<String>) {
+        let resp = app
+            .clone()
+            .oneshot(
+                http::Request::builder()
+                    .method("GET")
+                    .uri(format!("/users/{USER_01_ID}"))
+                    .header(
+                        http::header::AUTHORIZATION,
+                        format!("Bearer {USER_01_SESSION}"),
+                    )
+                    .body(Default::default())
+                    .unwrap_or_log(),
+            )
+            .await
+            .unwrap_or_log();
+        let retry_after = resp
+            .headers()
+            .get(http::header::RETRY_AFTER)
+            .map(|val| val.to_str().unwrap().to_string());
+        (resp.status(), retry_after)
+    }
+
+    #[tokio::test]
+    async fn rate_limit_waits_out_the_window() {
+        let (mut ctx, clock) = ctx();
+        ctx.config.rate_limit = Some(crate::utils::RateLimit {
+            limit: 1,
+            window: std::time::Duration::from_secs(60),
+        });
+        let app = app(std::sync::Arc::new(ctx));
+        assert_eq!(get_user(&app).await, (StatusCode::OK, None));
+        clock.advance(time::Duration::milliseconds(17_500));
+        assert_eq!(
+            get_user(&app).await,
+            (StatusCode::TOO_MANY_REQUESTS, Some("43".into()))
+        );
+    }
+
+    #[tokio::test]
+    async fn maintenance_waits_until_its_end() {
+        let (ctx, clock) = ctx();
+        ctx.maintenance.set(true);
+        let ctx = std::sync::Arc::new(ctx);
+        let app = app(ctx.clone());
+        let unavailable = |secs: u64| (StatusCode::SERVICE_UNAVAILABLE, Some(secs.to_string()));
+
+        assert_eq!(
+            get_user(&app).await,
+            unavailable(crate::maintenance::RETRY_AFTER_SECS)
+        );
+        ctx.maintenance
+            .set_ends_at(Some(clock.now() + time::Duration::minutes(15)));
+        assert_eq!(get_user(&app).await, unavailable(900));
+        clock.advance(time::Duration::minutes(10));
+        assert_eq!(get_user(&app).await, unavailable(300));
+        // overran the estimate
+        clock.advance(time::Duration::minutes(10));
+        assert_eq!(
+            get_user(&app).await,
+            unavailable(crate::maintenance::RETRY_AFTER_SECS)
+        );
+    }
+
+    #[tokio::test]
+    async fn overloaded_waits_out_the_breaker_cooldown() {
+        let (ctx, clock) = ctx();
+        for _ in 0..ctx.config.db_breaker_threshold {
+            ctx.db_breaker.record_failure(clock.now());
+        }
+        let app = app(std::sync::Arc::new(ctx));
+        // `test_config` cools down for 5s
+        assert_eq!(
+            get_user(&app).await,
+            (StatusCode::SERVICE_UNAVAILABLE, Some("5".into()))
+        );
+        clock.advance(time::Duration::seconds(3));
+        assert_eq!(
+            get_user(&app).await,
+            (StatusCode::SERVICE_UNAVAILABLE, Some("2".into()))
+        );
+    }
+}
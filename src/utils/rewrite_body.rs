@@ -0,0 +1,90 @@
+//! Buffering a response body to change it after the fact, shared by the layers
+//! and endpoint steps that edit json responses like [`super::inject_links`].
+
+use deps::*;
+
+use axum::{
+    body::{boxed, Bytes, Full},
+    http::{header, StatusCode},
+    response::Response,
+};
+
+/// Whether `resp` claims an `application/json` body.
+pub fn has_json_body(resp: &Response) -> bool {
+    resp.headers()
+        .get(header::CONTENT_TYPE)
+        .map(|val| val.as_bytes().starts_with(b"application/json"))
+        .unwrap_or_default()
+}
+
+/// Buffers the body of `resp` and replaces it with what `rewrite` makes of it,
+/// dropping the stale `Content-Length`. A body that can't be read or rewritten
+/// is logged as being for `purpose` and turns into an empty `500`.
+pub async fn rewrite_body<F>(resp: Response, purpose: &str, rewrite: F) -> Response
+where
+    F: FnOnce(Bytes) -> Result<Vec<u8>, String>,
+{
+    let (mut parts, body) = resp.into_parts();
+    parts.headers.remove(header::CONTENT_LENGTH);
+    let rewritten = hyper::body::to_bytes(body)
+        .await
+        .map_err(|err| err.to_string())
+        .and_then(rewrite);
+    match rewritten {
+        Ok(body) => Response::from_parts(parts, boxed(Full::from(body))),
+        Err(err) => {
+            tracing::error!(?err, purpose, "unable to rewrite response body");
+            parts.status = StatusCode::INTERNAL_SERVER_ERROR;
+            Response::from_parts(parts, boxed(Full::default()))
+        }
+    }
+}
+
+/// [`rewrite_body`] for json, `edit` gets the parsed body to change in place.
+pub async fn rewrite_json<F>(resp: Response, purpose: &str, edit: F) -> Response
+where
+    F: FnOnce(&mut serde_json::Value),
+{
+    rewrite_body(resp, purpose, |body| {
+        let mut json =
+            serde_json::from_slice::<serde_json::Value>(&body).map_err(|err| err.to_string())?;
+        edit(&mut json);
+        serde_json::to_vec(&json).map_err(|err| err.to_string())
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use deps::*;
+
+    use super::*;
+
+    use axum::response::IntoResponse;
+
+    #[tokio::test]
+    async fn edits_json_in_place() {
+        let resp = axum::Json(serde_json::json!({ "id": 1 })).into_response();
+        let resp = rewrite_json(resp, "test", |json| {
+            json["extra"] = true.into();
+        })
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(resp.headers().get(header::CONTENT_LENGTH).is_none());
+        let body = hyper::body::to_bytes(resp.into_body())
+            .await
+            .unwrap_or_log();
+        assert_eq!(&body[..], br#"{"extra":true,"id":1}"#);
+    }
+
+    #[tokio::test]
+    async fn unparsable_bodies_are_500() {
+        let resp = ([(header::CONTENT_TYPE, "application/json")], "{ not json").into_response();
+        let resp = rewrite_json(resp, "test", |_| unreachable!()).await;
+        assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let body = hyper::body::to_bytes(resp.into_body())
+            .await
+            .unwrap_or_log();
+        assert!(body.is_empty());
+    }
+}
@@ -0,0 +1,59 @@
+//! Serde module for [`time::OffsetDateTime`] fields that (de)serializes them
+//! as [RFC 3339](https://www.rfc-editor.org/rfc/rfc3339) strings. This matches
+//! the `string($date-time)` schema `utoipa` generates for them so use it on
+//! all time fields exposed through the API.
+//!
+//! ```rust,ignore
+//! #[serde(with = "crate::utils::rfc3339")]
+//! pub created_at: time::OffsetDateTime,
+//! ```
+
+use deps::*;
+
+pub fn serialize<S>(datetime: &time::OffsetDateTime, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    time::serde::rfc3339::serialize(datetime, serializer)
+}
+
+pub fn deserialize<'de, D>(deserializer: D) -> Result<time::OffsetDateTime, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    time::serde::rfc3339::deserialize(deserializer)
+}
+
+#[cfg(test)]
+mod tests {
+    use deps::*;
+
+    #[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+    #[serde(crate = "serde")]
+    struct Stamped {
+        #[serde(with = "crate::utils::rfc3339")]
+        at: time::OffsetDateTime,
+    }
+
+    #[test]
+    fn serializes_as_rfc3339_string() {
+        let stamped = Stamped {
+            at: time::OffsetDateTime::from_unix_timestamp(1_663_599_358).unwrap(),
+        };
+        assert_eq!(
+            serde_json::to_value(&stamped).unwrap(),
+            serde_json::json!({ "at": "2022-09-19T14:55:58Z" })
+        );
+    }
+
+    #[test]
+    fn round_trips() {
+        let stamped = Stamped {
+            at: time::OffsetDateTime::from_unix_timestamp_nanos(1_663_599_358_123_456_000)
+                .unwrap()
+                .to_offset(time::UtcOffset::from_hms(3, 0, 0).unwrap()),
+        };
+        let json = serde_json::to_string(&stamped).unwrap();
+        assert_eq!(serde_json::from_str::<Stamped>(&json).unwrap(), stamped);
+    }
+}
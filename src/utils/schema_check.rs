@@ -0,0 +1,325 @@
+//! Debug builds only. Checks incoming json request bodies against the request
+//! schema documented in the OpenAPI spec and logs a warning for any divergence.
+//! Requests are always passed through regardless of the outcome.
+//!
+//! Only understands the subset of JSON schema that we actually emit: `type`,
+//! `properties`, `required`, `items`, `oneOf` and local `$ref`s.
+
+use deps::*;
+
+use axum::{http, response::Response};
+
+use std::sync::Arc;
+
+/// Tracing target of the divergence warnings.
+pub const TARGET: &str = "schema_check";
+
+/// Apply this using `route_layer` so that [`axum::extract::MatchedPath`] is
+/// available.
+pub fn layer(
+    spec: Arc<serde_json::Value>,
+) -> axum::middleware::FromFnLayer<
+    impl FnMut(
+            http::Request<axum::body::Body>,
+            axum::middleware::Next<axum::body::Body>,
+        ) -> futures::future::BoxFuture<'static, Response>
+        + Clone
+        + Send
+        + Sync,
+> {
+    axum::middleware::from_fn(move |req, next| {
+        let spec = spec.clone();
+        Box::pin(check_request_body(spec, req, next)) as futures::future::BoxFuture<_>
+    })
+}
+
+/// [`layer`] using the spec from [`crate::ApiDoc`].
+pub fn api_doc_layer() -> axum::middleware::FromFnLayer<
+    impl FnMut(
+            http::Request<axum::body::Body>,
+            axum::middleware::Next<axum::body::Body>,
+        ) -> futures::future::BoxFuture<'static, Response>
+        + Clone
+        + Send
+        + Sync,
+> {
+    use once_cell::sync::Lazy;
+    static SPEC: Lazy<Arc<serde_json::Value>> = Lazy::new(|| {
+        Arc::new(
+            serde_json::to_value(<crate::ApiDoc as utoipa::OpenApi>::openapi())
+                .expect("unable to serialize the OpenAPI spec"),
+        )
+    });
+    layer(SPEC.clone())
+}
+
+async fn check_request_body(
+    spec: Arc<serde_json::Value>,
+    req: http::Request<axum::body::Body>,
+    next: axum::middleware::Next<axum::body::Body>,
+) -> Response {
+    let is_json = req
+        .headers()
+        .get(http::header::CONTENT_TYPE)
+        .and_then(|val| val.to_str().ok())
+        .map(|val| val.starts_with("application/json"))
+        .unwrap_or_default();
+    let schema = req
+        .extensions()
+        .get::<axum::extract::MatchedPath>()
+        .and_then(|path| {
+            request_schema(
+                &spec,
+                &crate::axum_path_str_to_openapi(path.as_str()),
+                req.method(),
+            )
+        });
+    let schema = match (is_json, schema) {
+        (true, Some(schema)) => schema,
+        _ => return next.run(req).await,
+    };
+
+    let (parts, body) = req.into_parts();
+    let bytes = match hyper::body::to_bytes(body).await {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            tracing::warn!(?err, "unable to buffer request body for schema check");
+            return next
+                .run(http::Request::from_parts(parts, Default::default()))
+                .await;
+        }
+    };
+    if let Ok(json) = serde_json::from_slice::<serde_json::Value>(&bytes) {
+        for divergence in divergences(&spec, schema, &json, "body") {
+            tracing::warn!(
+                target: TARGET,
+                method = %parts.method,
+                uri = %parts.uri,
+                "request diverges from documented schema: {divergence}"
+            );
+        }
+    }
+    next.run(http::Request::from_parts(parts, bytes.into()))
+        .await
+}
+
+/// Looks up the `application/json` request body schema of the operation.
+pub fn request_schema<'a>(
+    spec: &'a serde_json::Value,
+    openapi_path: &str,
+    method: &http::Method,
+) -> Option<&'a serde_json::Value> {
+    spec.get("paths")?
+        .get(openapi_path)?
+        .get(method.as_str().to_lowercase())?
+        .get("requestBody")?
+        .get("content")?
+        .get("application/json")?
+        .get("schema")
+}
+
+/// Returns a description for every place the `value` doesn't match the `schema`.
+pub fn divergences(
+    spec: &serde_json::Value,
+    schema: &serde_json::Value,
+    value: &serde_json::Value,
+    location: &str,
+) -> Vec<String> {
+    use serde_json::Value;
+    let schema = match resolve_ref(spec, schema) {
+        Some(schema) => schema,
+        None => return vec![format!("{location}: unresolvable schema {schema}")],
+    };
+    if let Some(Value::Array(variants)) = schema.get("oneOf") {
+        return if variants
+            .iter()
+            .any(|variant| divergences(spec, variant, value, location).is_empty())
+        {
+            vec![]
+        } else {
+            vec![format!("{location}: matches none of the oneOf variants")]
+        };
+    }
+    if value.is_null() {
+        // we're not tracking `nullable` so we let all nulls slide
+        return vec![];
+    }
+    let mut out = vec![];
+    match (schema.get("type").and_then(Value::as_str), value) {
+        (Some("object"), Value::Object(map)) => {
+            let properties = schema.get("properties").and_then(Value::as_object);
+            for (key, val) in map {
+                match properties.and_then(|props| props.get(key)) {
+                    Some(prop_schema) => out.append(&mut divergences(
+                        spec,
+                        prop_schema,
+                        val,
+                        &format!("{location}.{key}"),
+                    )),
+                    None if properties.is_some() => {
+                        out.push(format!("{location}.{key}: undocumented field"))
+                    }
+                    None => {}
+                }
+            }
+            if let Some(Value::Array(required)) = schema.get("required") {
+                for key in required.iter().filter_map(Value::as_str) {
+                    if !map.contains_key(key) {
+                        out.push(format!("{location}.{key}: required field missing"));
+                    }
+                }
+            }
+        }
+        (Some("array"), Value::Array(arr)) => {
+            if let Some(items) = schema.get("items") {
+                for (ii, item) in arr.iter().enumerate() {
+                    out.append(&mut divergences(
+                        spec,
+                        items,
+                        item,
+                        &format!("{location}[{ii}]"),
+                    ));
+                }
+            }
+        }
+        (Some("string"), Value::String(_))
+        | (Some("boolean"), Value::Bool(_))
+        | (Some("number"), Value::Number(_))
+        | (None, _) => {}
+        (Some("integer"), Value::Number(num)) if num.is_i64() || num.is_u64() => {}
+        (Some(ty), value) => out.push(format!("{location}: expected {ty}, found {value}")),
+    }
+    out
+}
+
+fn resolve_ref<'a>(
+    spec: &'a serde_json::Value,
+    schema: &'a serde_json::Value,
+) -> Option<&'a serde_json::Value> {
+    match schema.get("$ref").and_then(serde_json::Value::as_str) {
+        Some(location) => {
+            let pointer = location.strip_prefix('#')?;
+            resolve_ref(spec, spec.pointer(pointer)?)
+        }
+        None => Some(schema),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use deps::*;
+
+    use super::{divergences, request_schema, TARGET};
+    use crate::utils::testing::*;
+
+    fn fixture_spec() -> serde_json::Value {
+        serde_json::json!({
+            "paths": {
+                "/sum": {
+                    "post": {
+                        "requestBody": {
+                            "content": {
+                                "application/json": {
+                                    "schema": { "$ref": "#/components/schemas/Args" }
+                                }
+                            }
+                        }
+                    }
+                }
+            },
+            "components": {
+                "schemas": {
+                    "Args": {
+                        "type": "object",
+                        "required": ["a", "b"],
+                        "properties": {
+                            "a": { "type": "integer" },
+                            "b": { "type": "integer" }
+                        }
+                    }
+                }
+            }
+        })
+    }
+
+    fn sum_router() -> axum::Router {
+        #[derive(serde::Deserialize)]
+        #[serde(crate = "serde")]
+        struct Args {
+            a: u32,
+            b: u32,
+        }
+        use axum::Json;
+        axum::Router::new()
+            .route(
+                "/sum",
+                axum::routing::post(|Json(args): Json<Args>| async move {
+                    Json(serde_json::json!({ "c": (args.a + args.b) }))
+                }),
+            )
+            .route_layer(super::layer(std::sync::Arc::new(fixture_spec())))
+    }
+
+    crate::table_tests! {
+        schema_divergences,
+        (body, expected),
+        {
+            let spec = fixture_spec();
+            let schema = request_schema(&spec, "/sum", &http::Method::POST).unwrap();
+            assert_eq!(divergences(&spec, schema, &body, "body"), expected);
+        }
+    }
+
+    schema_divergences! {
+        accepts_matching: (
+            serde_json::json!({ "a": 1, "b": 2 }),
+            Vec::<String>::new(),
+        ),
+        flags_extra_fields: (
+            serde_json::json!({ "a": 1, "b": 2, "c": 3 }),
+            vec!["body.c: undocumented field".to_string()],
+        ),
+        flags_missing_fields: (
+            serde_json::json!({ "a": 1 }),
+            vec!["body.b: required field missing".to_string()],
+        ),
+        flags_wrong_types: (
+            serde_json::json!({ "a": 1, "b": "2" }),
+            vec!["body.b: expected integer, found \"2\"".to_string()],
+        ),
+    }
+
+    #[tokio::test]
+    async fn divergent_requests_are_still_accepted() {
+        use tracing_subscriber::layer::SubscriberExt;
+        let events = CapturedEvents::new(TARGET);
+        let _guard =
+            tracing::subscriber::set_default(tracing_subscriber::registry().with(events.clone()));
+        let resp = sum_router()
+            .oneshot(
+                http::Request::builder()
+                    .method("POST")
+                    .uri("/sum")
+                    .header(http::header::CONTENT_TYPE, "application/json")
+                    .body(
+                        serde_json::to_vec(&serde_json::json!({ "a": 1, "b": 2, "c": 3 }))
+                            .unwrap()
+                            .into(),
+                    )
+                    .unwrap_or_log(),
+            )
+            .await
+            .unwrap_or_log();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(resp.into_body())
+            .await
+            .unwrap_or_log();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap_or_log();
+        assert_eq!(body, serde_json::json!({ "c": 3 }));
+
+        let events = events.events();
+        assert_eq!(events.len(), 1, "{events:?}");
+        let message = events[0]["message"].as_str().unwrap();
+        assert!(message.contains("body.c: undocumented field"), "{message}");
+    }
+}
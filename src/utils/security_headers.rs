@@ -0,0 +1,103 @@
+//! Security related response headers applied at bootstrap if
+//! [`crate::Config::security_headers`] is set.
+
+use deps::*;
+
+use axum::http::{header, HeaderValue};
+use tower_http::set_header::SetResponseHeaderLayer;
+
+/// Nothing but json is served outside of the docs.
+pub const DEFAULT_CSP: &str = "default-src 'none'; frame-ancestors 'none'";
+/// Swagger UI needs its bundled scripts, styles and inline images.
+pub const DOCS_CSP: &str = "default-src 'self'; script-src 'self' 'unsafe-inline'; \
+     style-src 'self' 'unsafe-inline'; img-src 'self' data:; frame-ancestors 'none'";
+
+/// Adds the headers to every response that doesn't already have them set so that
+/// routes can opt into something else, see [`docs_layer`].
+pub fn layer(router: axum::Router) -> axum::Router {
+    router
+        .layer(SetResponseHeaderLayer::if_not_present(
+            header::X_CONTENT_TYPE_OPTIONS,
+            HeaderValue::from_static("nosniff"),
+        ))
+        .layer(SetResponseHeaderLayer::if_not_present(
+            header::X_FRAME_OPTIONS,
+            HeaderValue::from_static("DENY"),
+        ))
+        .layer(SetResponseHeaderLayer::if_not_present(
+            header::REFERRER_POLICY,
+            HeaderValue::from_static("no-referrer"),
+        ))
+        .layer(SetResponseHeaderLayer::if_not_present(
+            header::CONTENT_SECURITY_POLICY,
+            HeaderValue::from_static(DEFAULT_CSP),
+        ))
+}
+
+/// Relaxes the CSP for the docs router. Apply this before merging it.
+pub fn docs_layer(router: axum::Router) -> axum::Router {
+    router.layer(SetResponseHeaderLayer::overriding(
+        header::CONTENT_SECURITY_POLICY,
+        HeaderValue::from_static(DOCS_CSP),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use deps::*;
+
+    use super::*;
+    use crate::utils::testing::*;
+
+    fn app() -> axum::Router {
+        layer(
+            axum::Router::new()
+                .merge(docs_layer(crate::docs_router()))
+                .merge(crate::health::router()),
+        )
+    }
+
+    async fn headers(uri: &str) -> http::HeaderMap {
+        app()
+            .oneshot(
+                http::Request::builder()
+                    .method("GET")
+                    .uri(uri)
+                    .body(Default::default())
+                    .unwrap_or_log(),
+            )
+            .await
+            .unwrap_or_log()
+            .headers()
+            .clone()
+    }
+
+    #[tokio::test]
+    async fn present_on_normal_responses() {
+        setup_tracing_once();
+        // there's no `Extension` layer so this fails but the headers are still set
+        let headers = headers("/ready").await;
+        for (name, val) in [
+            (header::X_CONTENT_TYPE_OPTIONS, "nosniff"),
+            (header::X_FRAME_OPTIONS, "DENY"),
+            (header::REFERRER_POLICY, "no-referrer"),
+            (header::CONTENT_SECURITY_POLICY, DEFAULT_CSP),
+        ] {
+            assert_eq!(headers.get(&name).unwrap(), val, "failed on {name}");
+        }
+    }
+
+    #[tokio::test]
+    async fn docs_get_relaxed_csp() {
+        setup_tracing_once();
+        let headers = headers("/api-doc/openapi.json").await;
+        assert_eq!(
+            headers.get(header::CONTENT_SECURITY_POLICY).unwrap(),
+            DOCS_CSP
+        );
+        assert_eq!(
+            headers.get(header::X_CONTENT_TYPE_OPTIONS).unwrap(),
+            "nosniff"
+        );
+    }
+}
@@ -0,0 +1,188 @@
+//! Marks values that the client gets to see but the logs mustn't. The bodies
+//! captured by [`super::slow_requests`] and [`super::recording`] are serialized
+//! json by the time they're logged so [`Sensitive`] fields note their strings as
+//! they're (de)serialized, within the [`collect_sensitive`] scope that
+//! [`super::populate_request_context`] opens for each request, and
+//! [`mask_sensitive`] masks them wherever they turn up in a capture.
+
+use deps::*;
+
+/// What the [`Sensitive`] values are replaced with.
+pub const MASK: &str = "***";
+
+tokio::task_local! {
+    static SENSITIVE_STRINGS: parking_lot::Mutex<Vec<String>>;
+}
+
+/// Runs `fut` noting the strings of the [`Sensitive`] values it (de)serializes.
+pub async fn collect_sensitive<F: std::future::Future>(fut: F) -> F::Output {
+    SENSITIVE_STRINGS.scope(Default::default(), fut).await
+}
+
+/// (De)serializes as `T` does. Its `Debug` is the [`MASK`]. Document the field
+/// with `#[schema(value_type = ...)]` as utoipa would otherwise refer to a
+/// `Sensitive` component.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct Sensitive<T>(pub T);
+
+impl<T> std::ops::Deref for Sensitive<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<T> std::fmt::Debug for Sensitive<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(MASK)
+    }
+}
+
+impl<T: serde::Serialize> serde::Serialize for Sensitive<T> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        note(&self.0);
+        self.0.serialize(serializer)
+    }
+}
+
+impl<'de, T: serde::Serialize + serde::Deserialize<'de>> serde::Deserialize<'de> for Sensitive<T> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let val = T::deserialize(deserializer)?;
+        note(&val);
+        Ok(Self(val))
+    }
+}
+
+fn note<T: serde::Serialize>(val: &T) {
+    fn strings(val: serde_json::Value, out: &mut Vec<String>) {
+        match val {
+            serde_json::Value::String(val) if !val.is_empty() => out.push(val),
+            serde_json::Value::Array(items) => items.into_iter().for_each(|val| strings(val, out)),
+            serde_json::Value::Object(obj) => {
+                obj.into_iter().for_each(|(_, val)| strings(val, out))
+            }
+            _ => {}
+        }
+    }
+    SENSITIVE_STRINGS
+        .try_with(|noted| {
+            if let Ok(val) = serde_json::to_value(val) {
+                strings(val, &mut noted.lock());
+            }
+        })
+        .ok();
+}
+
+/// Replaces the strings noted in the current [`collect_sensitive`] scope with the
+/// [`MASK`], at any depth.
+pub fn mask_sensitive(json: &mut serde_json::Value) {
+    SENSITIVE_STRINGS
+        .try_with(|noted| {
+            let noted = noted.lock();
+            if !noted.is_empty() {
+                mask(json, &noted);
+            }
+        })
+        .ok();
+}
+
+fn mask(json: &mut serde_json::Value, noted: &[String]) {
+    match json {
+        serde_json::Value::String(val) if noted.contains(val) => *val = MASK.into(),
+        serde_json::Value::Array(items) => items.iter_mut().for_each(|val| mask(val, noted)),
+        serde_json::Value::Object(obj) => obj.values_mut().for_each(|val| mask(val, noted)),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use deps::*;
+
+    use super::*;
+    use crate::utils::testing::*;
+
+    #[derive(Debug, serde::Serialize, serde::Deserialize)]
+    #[serde(crate = "serde", rename_all = "camelCase")]
+    struct Account {
+        username: String,
+        recovery_code: Sensitive<String>,
+    }
+
+    fn app() -> axum::Router {
+        let mut ctx = crate::Context::mock(InMemoryDb::default());
+        ctx.config.slow_request_threshold = Some(std::time::Duration::ZERO);
+        axum::Router::new()
+            .route(
+                "/accounts",
+                axum::routing::post(|axum::Json(account): axum::Json<Account>| async move {
+                    axum::Json(account)
+                }),
+            )
+            .layer(axum::middleware::from_fn(
+                crate::utils::slow_requests::log_slow_requests,
+            ))
+            .layer(axum::middleware::from_fn(
+                crate::utils::populate_request_context,
+            ))
+            .layer(axum::Extension(std::sync::Arc::new(ctx)))
+    }
+
+    #[tokio::test]
+    async fn shown_to_the_client_masked_in_captures() {
+        use tracing_subscriber::layer::SubscriberExt;
+        let events = CapturedEvents::new(crate::utils::slow_requests::TARGET);
+        let _guard =
+            tracing::subscriber::set_default(tracing_subscriber::registry().with(events.clone()));
+        let resp = app()
+            .oneshot(
+                http::Request::builder()
+                    .method("POST")
+                    .uri("/accounts")
+                    .header(http::header::CONTENT_TYPE, "application/json")
+                    .body(r#"{ "username": "whish_box", "recoveryCode": "lovebite-0451" }"#.into())
+                    .unwrap_or_log(),
+            )
+            .await
+            .unwrap_or_log();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(resp.into_body())
+            .await
+            .unwrap_or_log();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap_or_log();
+        assert_eq!(
+            body,
+            serde_json::json!({ "username": "whish_box", "recoveryCode": "lovebite-0451" })
+        );
+
+        let events = events.events();
+        assert_eq!(events.len(), 1, "{events:?}");
+        if cfg!(debug_assertions) {
+            for field in ["request_body", "response_body"] {
+                let body: serde_json::Value =
+                    serde_json::from_str(events[0][field].as_str().unwrap()).unwrap_or_log();
+                assert_eq!(
+                    body,
+                    serde_json::json!({ "username": "whish_box", "recoveryCode": MASK }),
+                    "{field}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn debug_is_masked() {
+        let account = Account {
+            username: "whish_box".into(),
+            recovery_code: Sensitive("lovebite-0451".into()),
+        };
+        assert!(!format!("{account:?}").contains("lovebite"));
+    }
+}
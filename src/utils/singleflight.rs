@@ -0,0 +1,209 @@
+//! Coalesces concurrent identical requests to endpoints that set
+//! [`crate::HttpEndpoint::SINGLEFLIGHT`] so that a burst of them only runs the
+//! handler once.
+
+use deps::*;
+
+use axum::{
+    body::Bytes,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use futures::future::{BoxFuture, FutureExt, Shared};
+
+use std::collections::HashMap;
+use std::future::Future;
+
+#[derive(Debug, Clone)]
+struct Buffered {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+impl Buffered {
+    async fn new(resp: Response) -> Self {
+        let (parts, body) = resp.into_parts();
+        match hyper::body::to_bytes(body).await {
+            Ok(body) => Self {
+                status: parts.status,
+                headers: parts.headers,
+                body,
+            },
+            Err(err) => {
+                tracing::error!(?err, "unable to buffer response for sharing");
+                Self {
+                    status: StatusCode::INTERNAL_SERVER_ERROR,
+                    headers: Default::default(),
+                    body: Default::default(),
+                }
+            }
+        }
+    }
+}
+
+impl IntoResponse for Buffered {
+    fn into_response(self) -> Response {
+        let mut resp = Response::new(axum::body::boxed(axum::body::Full::from(self.body)));
+        *resp.status_mut() = self.status;
+        *resp.headers_mut() = self.headers;
+        resp
+    }
+}
+
+#[derive(Default)]
+struct Flights {
+    /// Tagged with an id so that a finished flight doesn't remove the one that
+    /// replaced it.
+    inflight: HashMap<String, (u64, Shared<BoxFuture<'static, Buffered>>)>,
+    next_id: u64,
+}
+
+#[derive(Default)]
+pub struct Singleflight {
+    flights: parking_lot::Mutex<Flights>,
+}
+
+impl std::fmt::Debug for Singleflight {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Singleflight")
+            .field("inflight", &self.flights.lock().inflight.len())
+            .finish()
+    }
+}
+
+impl Singleflight {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Runs `fut` unless there's already one in flight under `key` in which case
+    /// its response is shared instead. Keys are the same as for the
+    /// [`super::ResponseCache`].
+    pub async fn run<F>(&self, key: String, fut: F) -> Response
+    where
+        F: Future<Output = Response> + Send + 'static,
+    {
+        let (id, flight) = {
+            let mut flights = self.flights.lock();
+            let flights = &mut *flights;
+            match flights.inflight.get(&key) {
+                Some((id, flight)) => (*id, flight.clone()),
+                None => {
+                    flights.next_id += 1;
+                    let flight = async move { Buffered::new(fut.await).await }
+                        .boxed()
+                        .shared();
+                    flights
+                        .inflight
+                        .insert(key.clone(), (flights.next_id, flight.clone()));
+                    (flights.next_id, flight)
+                }
+            }
+        };
+        let resp = flight.await;
+        let mut flights = self.flights.lock();
+        if matches!(flights.inflight.get(&key), Some((landed, _)) if *landed == id) {
+            flights.inflight.remove(&key);
+        }
+        resp.into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use deps::*;
+
+    use crate::utils::testing::*;
+    use crate::*;
+
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    /// Counts how many times it was actually invoked.
+    #[derive(Debug, Clone, Default)]
+    struct SlowCounter(Arc<AtomicUsize>);
+
+    #[async_trait::async_trait]
+    impl Endpoint for SlowCounter {
+        type Request = ();
+        type Response = usize;
+        type Error = Infallible;
+
+        async fn handle(
+            &self,
+            _ctx: &crate::Context,
+            _request: Self::Request,
+        ) -> Result<Self::Response, Self::Error> {
+            let count = self.0.fetch_add(1, Ordering::SeqCst) + 1;
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+            Ok(count)
+        }
+    }
+
+    impl HttpEndpoint for SlowCounter {
+        const METHOD: Method = Method::Get;
+        const PATH: &'static str = "/count";
+        const SINGLEFLIGHT: bool = true;
+
+        type HttpRequest = ();
+
+        fn request(_: Self::HttpRequest) -> Result<Self::Request, Self::Error> {
+            Ok(())
+        }
+
+        fn response(resp: Self::Response) -> axum::response::Response {
+            Json(resp).into_response()
+        }
+    }
+
+    async fn get_count(app: axum::Router, uri: &str) -> usize {
+        let resp = app
+            .oneshot(
+                http::Request::builder()
+                    .method("GET")
+                    .uri(uri)
+                    .body(Default::default())
+                    .unwrap_or_log(),
+            )
+            .await
+            .unwrap_or_log();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(resp.into_body())
+            .await
+            .unwrap_or_log();
+        serde_json::from_slice(&body).unwrap_or_log()
+    }
+
+    #[tokio::test]
+    async fn coalesces_concurrent_requests() {
+        setup_tracing_once();
+        let counter = SlowCounter::default();
+        let ctx = Arc::new(crate::Context::mock(InMemoryDb::default()));
+        let app = endpoint_app_with(counter.clone(), ctx.clone());
+
+        let counts = futures::future::join_all(
+            (0..16).map(|_| tokio::spawn(get_count(app.clone(), "/count"))),
+        )
+        .await;
+        for count in counts {
+            assert_eq!(count.unwrap(), 1);
+        }
+        assert_eq!(counter.0.load(Ordering::SeqCst), 1);
+        assert_eq!(
+            format!("{:?}", ctx.singleflight),
+            "Singleflight { inflight: 0 }"
+        );
+
+        // nothing is kept around once landed
+        assert_eq!(get_count(app.clone(), "/count").await, 2);
+        // the query is part of the key
+        let (a, b) = tokio::join!(
+            get_count(app.clone(), "/count?a"),
+            get_count(app.clone(), "/count?b")
+        );
+        assert_eq!(a + b, 3 + 4);
+    }
+}
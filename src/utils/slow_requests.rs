@@ -0,0 +1,232 @@
+//! Logs the requests taking longer than [`crate::Config::slow_request_threshold`]
+//! as structured events under the [`TARGET`] tracing target, apart from the access
+//! log so that the outliers are easy to find. Debug builds also capture the json
+//! bodies, cut to [`CAPTURED_BODY_LEN`] and with the values of the
+//! [`crate::Config::redacted_fields`] and the [`super::Sensitive`] ones masked.
+
+use deps::*;
+
+use axum::{
+    body::{boxed, Bytes, Full},
+    http::{Request, StatusCode},
+    response::{IntoResponse, Response},
+};
+
+use crate::SharedContext;
+
+pub const TARGET: &str = "slow_request";
+
+/// Bytes of each body kept in the event.
+pub const CAPTURED_BODY_LEN: usize = 1024;
+
+/// Defaults for [`crate::Config::redacted_fields`].
+pub const DEFAULT_REDACTED_FIELDS: &[&str] = &["password", "passHash", "token", "secret", "apiKey"];
+
+const REDACTED: &str = "[redacted]";
+
+/// Middleware, layer it inside [`super::populate_request_context`] so that the
+/// events carry the request id. Expects the [`SharedContext`] in the request
+/// extensions.
+pub async fn log_slow_requests(
+    req: Request<axum::body::Body>,
+    next: axum::middleware::Next<axum::body::Body>,
+) -> Response {
+    let ctx = match req.extensions().get::<SharedContext>() {
+        Some(ctx) => ctx.clone(),
+        None => return next.run(req).await,
+    };
+    let threshold = match ctx.config.slow_request_threshold {
+        Some(threshold) => threshold,
+        None => return next.run(req).await,
+    };
+    let method = req.method().clone();
+    let path = req.uri().path().to_string();
+    let (req, request_body) =
+        if cfg!(debug_assertions) && super::is_json_content_type(req.headers()) {
+            let (parts, body) = req.into_parts();
+            match hyper::body::to_bytes(body).await {
+                Ok(body) => (Request::from_parts(parts, body.clone().into()), Some(body)),
+                Err(err) => {
+                    return super::ApiError::BodyRead {
+                        message: err.to_string(),
+                    }
+                    .into_response()
+                }
+            }
+        } else {
+            (req, None)
+        };
+
+    let start = std::time::Instant::now();
+    let resp = next.run(req).await;
+    let latency = start.elapsed();
+    if latency < threshold {
+        return resp;
+    }
+
+    let (resp, response_body) =
+        if cfg!(debug_assertions) && super::is_json_content_type(resp.headers()) {
+            let (parts, body) = resp.into_parts();
+            match hyper::body::to_bytes(body).await {
+                Ok(body) => (
+                    Response::from_parts(parts, boxed(Full::from(body.clone()))),
+                    Some(body),
+                ),
+                Err(err) => {
+                    tracing::error!(?err, "unable to read slow response");
+                    (StatusCode::INTERNAL_SERVER_ERROR.into_response(), None)
+                }
+            }
+        } else {
+            (resp, None)
+        };
+    let redact = |body: Option<Bytes>| body.map(|body| capture(&body, &ctx.config.redacted_fields));
+    tracing::warn!(
+        target: TARGET,
+        %method,
+        %path,
+        status = resp.status().as_u16(),
+        latency_ms = latency.as_millis() as u64,
+        request_id = super::current_request_id().as_deref(),
+        request_body = redact(request_body).as_deref(),
+        response_body = redact(response_body).as_deref(),
+        "slow request"
+    );
+    resp
+}
+
+/// The `body` with the `redacted` fields masked if it's json, truncated.
+fn capture(body: &[u8], redacted: &[String]) -> String {
+    let text = match serde_json::from_slice::<serde_json::Value>(body) {
+        Ok(mut json) => {
+            redact(&mut json, redacted);
+            super::mask_sensitive(&mut json);
+            json.to_string()
+        }
+        Err(_) => String::from_utf8_lossy(body).into_owned(),
+    };
+    if text.len() <= CAPTURED_BODY_LEN {
+        return text;
+    }
+    let mut end = CAPTURED_BODY_LEN;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}…", &text[..end])
+}
+
+/// Masks the values of the `fields`, matched case insensitively, at any depth.
+pub fn redact(json: &mut serde_json::Value, fields: &[String]) {
+    match json {
+        serde_json::Value::Object(obj) => {
+            for (key, val) in obj.iter_mut() {
+                if fields.iter().any(|field| field.eq_ignore_ascii_case(key)) {
+                    *val = REDACTED.into();
+                } else {
+                    redact(val, fields);
+                }
+            }
+        }
+        serde_json::Value::Array(items) => {
+            for item in items {
+                redact(item, fields);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use deps::*;
+
+    use super::*;
+    use crate::utils::testing::*;
+
+    fn app() -> axum::Router {
+        let mut ctx = crate::Context::mock(InMemoryDb::default());
+        ctx.config.slow_request_threshold = Some(std::time::Duration::from_millis(50));
+        let echo = |sleep: u64| {
+            move |axum::Json(body): axum::Json<serde_json::Value>| async move {
+                tokio::time::sleep(std::time::Duration::from_millis(sleep)).await;
+                axum::Json(body)
+            }
+        };
+        axum::Router::new()
+            .route("/slow", axum::routing::post(echo(100)))
+            .route("/fast", axum::routing::post(echo(0)))
+            .layer(axum::middleware::from_fn(log_slow_requests))
+            .layer(axum::Extension(std::sync::Arc::new(ctx)))
+    }
+
+    crate::table_tests! {
+        slow_requests tokio,
+        (path, logged),
+        {
+            use tracing_subscriber::layer::SubscriberExt;
+            let events = CapturedEvents::new(TARGET);
+            let _guard = tracing::subscriber::set_default(
+                tracing_subscriber::registry().with(events.clone()),
+            );
+            let resp = app()
+                .oneshot(
+                    http::Request::builder()
+                        .method("POST")
+                        .uri(path)
+                        .header(http::header::CONTENT_TYPE, "application/json")
+                        .body(r#"{ "username": "whish_box", "password": "lovebite" }"#.into())
+                        .unwrap_or_log(),
+                )
+                .await
+                .unwrap_or_log();
+            assert_eq!(resp.status(), StatusCode::OK);
+            // the body makes it through the capture intact
+            let body = hyper::body::to_bytes(resp.into_body())
+                .await
+                .unwrap_or_log();
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap_or_log();
+            assert_eq!(body["password"], "lovebite");
+
+            let events = events.events();
+            if !logged {
+                assert!(events.is_empty(), "{events:?}");
+                return Ok(());
+            }
+            assert_eq!(events.len(), 1, "{events:?}");
+            let event = &events[0];
+            check_json(
+                ("expected", &serde_json::json!({ "method": "POST", "path": path, "status": 200 })),
+                ("event", &serde_json::Value::Object(event.clone())),
+            );
+            assert!(event["latency_ms"].as_u64().unwrap() >= 50, "{event:?}");
+            if cfg!(debug_assertions) {
+                for field in ["request_body", "response_body"] {
+                    let body = event[field].as_str().unwrap();
+                    assert!(body.contains("whish_box"), "{body}");
+                    assert!(!body.contains("lovebite"), "{body}");
+                }
+            }
+        },
+    }
+
+    slow_requests! {
+        logs_slow_requests: ("/slow", true),
+        skips_fast_requests: ("/fast", false),
+    }
+
+    #[test]
+    fn redacts_nested_fields() {
+        let mut json = serde_json::json!({
+            "items": [{ "Password": "a", "name": "b" }],
+            "token": { "value": "c" },
+        });
+        redact(&mut json, &["password".into(), "token".into()]);
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "items": [{ "Password": REDACTED, "name": "b" }],
+                "token": REDACTED,
+            })
+        );
+    }
+}
@@ -0,0 +1,294 @@
+//! JSON:API style sparse fieldsets: `?fields=id,username` prunes the records of a
+//! response down to the listed fields. Fields of embedded objects are selected
+//! with dots, `?fields=id,profile.avatar` keeping only the `avatar` of the
+//! `profile` where `?fields=id,profile` keeps all of it. Endpoints opt in through
+//! [`crate::HttpEndpoint::sparse_fields`].
+
+use deps::*;
+
+use std::collections::BTreeMap;
+
+use axum::{http, response::Response};
+use utoipa::openapi;
+
+use super::{has_json_body, rewrite_json, ApiError};
+
+pub const FIELDS_PARAM: &str = "fields";
+
+#[derive(Debug, Clone)]
+pub struct SparseFields {
+    /// JSON pointer to the record, or the array of records, in the response body.
+    /// `""` for the whole body.
+    pub at: &'static str,
+    /// Nested fields are joined to their parents by dots.
+    pub allowed: Vec<String>,
+}
+
+impl SparseFields {
+    /// Allows the properties of `T`'s schema along with those of the objects it
+    /// embeds inline. See [`SparseFields::embedding`] for referenced ones.
+    pub fn of<T: utoipa::ToSchema>(at: &'static str) -> Self {
+        let mut allowed = vec![];
+        schema_fields(&T::schema(), "", &mut allowed);
+        Self { at, allowed }
+    }
+
+    /// Allows the properties of `T`, the schema `field` refers to, as nested
+    /// fields of it.
+    pub fn embedding<T: utoipa::ToSchema>(mut self, field: &str) -> Self {
+        schema_fields(&T::schema(), &format!("{field}."), &mut self.allowed);
+        self
+    }
+
+    /// The fields asked for by `req`, `None` if it doesn't ask for any in particular.
+    pub fn requested<B>(&self, req: &http::Request<B>) -> Result<Option<Vec<String>>, ApiError> {
+        let val = req
+            .uri()
+            .query()
+            .into_iter()
+            .flat_map(|query| query.split('&'))
+            .find_map(|pair| pair.strip_prefix(FIELDS_PARAM)?.strip_prefix('='));
+        // field names are plain identifiers so the comma is all that can be encoded
+        let fields = val
+            .map(|val| val.replace("%2C", ",").replace("%2c", ","))
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|field| !field.is_empty())
+            .map(String::from)
+            .collect::<Vec<_>>();
+        if fields.is_empty() {
+            return Ok(None);
+        }
+        let unknown = fields
+            .iter()
+            .filter(|field| !self.allowed.contains(field))
+            .cloned()
+            .collect::<Vec<_>>();
+        if !unknown.is_empty() {
+            return Err(ApiError::UnknownFields {
+                unknown,
+                allowed: self.allowed.clone(),
+            });
+        }
+        Ok(Some(fields))
+    }
+
+    /// Drops everything but the `fields` from the records of a successful json
+    /// response.
+    pub async fn prune(&self, resp: Response, fields: &[String]) -> Response {
+        if !resp.status().is_success() || !has_json_body(&resp) {
+            return resp;
+        }
+        rewrite_json(resp, "sparse fields", |json| {
+            if let Some(records) = json.pointer_mut(self.at) {
+                Selection::new(fields).prune(records);
+            }
+        })
+        .await
+    }
+
+    pub fn parameter(&self) -> openapi::path::Parameter {
+        openapi::path::ParameterBuilder::new()
+            .name(FIELDS_PARAM)
+            .parameter_in(openapi::path::ParameterIn::Query)
+            .required(openapi::Required::False)
+            .description(Some(format!(
+                "Comma separated fields to limit the response to, any of: {}.",
+                self.allowed.join(", ")
+            )))
+            .schema(Some(
+                openapi::schema::ObjectBuilder::new().schema_type(openapi::SchemaType::String),
+            ))
+            .build()
+    }
+}
+
+fn schema_fields(schema: &openapi::Schema, prefix: &str, out: &mut Vec<String>) {
+    let schema = match schema {
+        openapi::Schema::Array(arr) => match &*arr.items {
+            openapi::schema::RefOr::T(schema) => schema,
+            openapi::schema::RefOr::Ref(_) => return,
+        },
+        schema => schema,
+    };
+    if let openapi::Schema::Object(obj) = schema {
+        for (key, prop) in &obj.properties {
+            let field = format!("{prefix}{key}");
+            if let openapi::schema::RefOr::T(prop) = prop {
+                schema_fields(prop, &format!("{field}."), out);
+            }
+            out.push(field);
+        }
+    }
+}
+
+/// The requested fields as a tree, `None` keeping the whole value.
+#[derive(Debug, Default)]
+struct Selection(BTreeMap<String, Option<Selection>>);
+
+impl Selection {
+    fn new(fields: &[String]) -> Self {
+        let mut root = Self::default();
+        for field in fields {
+            let mut node = &mut root;
+            let mut segments = field.split('.').peekable();
+            while let Some(segment) = segments.next() {
+                let entry = node
+                    .0
+                    .entry(segment.to_string())
+                    .or_insert_with(|| Some(Self::default()));
+                if segments.peek().is_none() {
+                    *entry = None;
+                }
+                match entry {
+                    Some(child) => node = child,
+                    // all of it's kept already
+                    None => break,
+                }
+            }
+        }
+        root
+    }
+
+    /// Prunes each of the records if it's an array.
+    fn prune(&self, val: &mut serde_json::Value) {
+        match val {
+            serde_json::Value::Object(obj) => obj.retain(|key, val| match self.0.get(key) {
+                Some(Some(selection)) => {
+                    selection.prune(val);
+                    true
+                }
+                Some(None) => true,
+                None => key == super::LINKS_FIELD,
+            }),
+            serde_json::Value::Array(items) => items.iter_mut().for_each(|item| self.prune(item)),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use deps::*;
+
+    use crate::user::testing::*;
+    use crate::utils::testing::*;
+
+    crate::table_tests! {
+        sparse_fields tokio,
+        (query, status, keys),
+        {
+            setup_tracing_once();
+            let ctx = std::sync::Arc::new(crate::Context::mock(InMemoryDb::fixture()));
+            let app = crate::user::router().layer(axum::Extension(ctx));
+            let resp = app
+                .oneshot(
+                    http::Request::builder()
+                        .method("GET")
+                        .uri(format!("/users/{USER_01_ID}{query}"))
+                        .header(
+                            http::header::AUTHORIZATION,
+                            format!("Bearer {USER_01_SESSION}"),
+                        )
+                        .body(Default::default())
+                        .unwrap_or_log(),
+                )
+                .await
+                .unwrap_or_log();
+            assert_eq!(resp.status(), status);
+            let body = hyper::body::to_bytes(resp.into_body())
+                .await
+                .unwrap_or_log();
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap_or_log();
+            let mut found = body.as_object().unwrap().keys().cloned().collect::<Vec<_>>();
+            found.sort();
+            let keys: &[&str] = keys;
+            assert_eq!(found, keys, "{body}");
+        },
+    }
+
+    sparse_fields! {
+        all_by_default: (
+            "",
+            StatusCode::OK,
+            &["_links", "createdAt", "email", "id", "picUrl", "updatedAt", "username"],
+        ),
+        valid_subset: ("?fields=id,username", StatusCode::OK, &["_links", "id", "username"]),
+        encoded_commas: ("?fields=id%2Cemail", StatusCode::OK, &["_links", "email", "id"]),
+        unknown_field: (
+            "?fields=id,password",
+            StatusCode::BAD_REQUEST,
+            &["allowed", "code", "error", "unknown"],
+        ),
+    }
+
+    #[derive(utoipa::ToSchema)]
+    #[allow(dead_code)]
+    struct Profile {
+        avatar: String,
+        bio: String,
+    }
+
+    #[derive(utoipa::ToSchema)]
+    #[allow(dead_code)]
+    struct Account {
+        id: String,
+        profile: Profile,
+    }
+
+    crate::table_tests! {
+        nested_fields tokio,
+        (query, expected),
+        {
+            use axum::response::IntoResponse;
+            let sparse = crate::utils::sparse_fields::SparseFields::of::<Account>("/items").embedding::<Profile>("profile");
+            let req = http::Request::builder()
+                .uri(format!("/accounts{query}"))
+                .body(())
+                .unwrap_or_log();
+            let expected: Option<serde_json::Value> = expected;
+            let (fields, expected) = match (sparse.requested(&req), expected) {
+                (Ok(Some(fields)), Some(expected)) => (fields, expected),
+                (Err(err), None) => {
+                    assert_eq!(err.into_response().status(), StatusCode::BAD_REQUEST);
+                    return Ok(());
+                }
+                (fields, _) => panic!("unexpected {fields:?}"),
+            };
+            let account = serde_json::json!({
+                "id": "1",
+                "profile": { "avatar": "whish_box.png", "bio": "hi" },
+                "_links": { "self": { "href": "/accounts/1" } },
+            });
+            let resp = axum::Json(serde_json::json!({ "items": [account.clone(), account] }))
+                .into_response();
+            let resp = sparse.prune(resp, &fields).await;
+            let body = hyper::body::to_bytes(resp.into_body())
+                .await
+                .unwrap_or_log();
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap_or_log();
+            assert_eq!(body, serde_json::json!({ "items": [expected.clone(), expected] }));
+        },
+    }
+
+    nested_fields! {
+        nested_field: (
+            "?fields=id,profile.avatar",
+            Some(serde_json::json!({
+                "id": "1",
+                "profile": { "avatar": "whish_box.png" },
+                "_links": { "self": { "href": "/accounts/1" } },
+            })),
+        ),
+        whole_sub_object: (
+            "?fields=profile.bio,profile",
+            Some(serde_json::json!({
+                "profile": { "avatar": "whish_box.png", "bio": "hi" },
+                "_links": { "self": { "href": "/accounts/1" } },
+            })),
+        ),
+        unknown_nested_field: ("?fields=id,profile.email", None),
+        not_an_object: ("?fields=id.value", None),
+    }
+}
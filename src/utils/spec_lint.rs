@@ -0,0 +1,243 @@
+//! Checks a serialized OpenAPI spec for the mistakes that the endpoint traits
+//! can't rule out at compile time and that break codegen downstream. See
+//! [`crate::ApiDoc::validate`].
+
+use deps::*;
+
+use serde_json::Value;
+
+const METHODS: &[&str] = &[
+    "get", "put", "post", "delete", "options", "head", "patch", "trace",
+];
+const MUTATING: &[&str] = &["put", "post", "patch"];
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum SpecIssue {
+    #[error("{method} {path}: path parameter {param:?} is not documented")]
+    UndocumentedPathParam {
+        path: String,
+        method: String,
+        param: String,
+    },
+    #[error("{method} {path}: no request body")]
+    MissingRequestBody { path: String, method: String },
+    #[error("operationId {id:?} is used more than once")]
+    DuplicateOperationId { id: String },
+    #[error("{reference:?} doesn't point at anything")]
+    DanglingRef { reference: String },
+}
+
+/// All the [`SpecIssue`]s found in `spec`.
+pub fn lint(spec: &Value) -> Vec<SpecIssue> {
+    let mut issues = vec![];
+    let paths = spec
+        .get("paths")
+        .and_then(Value::as_object)
+        .into_iter()
+        .flatten();
+    for (path, item) in paths {
+        let path_params = item.get("parameters");
+        for (method, op) in METHODS
+            .iter()
+            .filter_map(|method| Some((*method, item.get(*method)?)))
+        {
+            let documented = |name: &str| {
+                [path_params, op.get("parameters")]
+                    .into_iter()
+                    .flatten()
+                    .filter_map(Value::as_array)
+                    .flatten()
+                    .any(|param| param["in"] == "path" && param["name"] == name)
+            };
+            for param in path_param_names(path) {
+                if !documented(param) {
+                    issues.push(SpecIssue::UndocumentedPathParam {
+                        path: path.clone(),
+                        method: method.into(),
+                        param: param.into(),
+                    });
+                }
+            }
+            if MUTATING.contains(&method) && op.get("requestBody").is_none() {
+                issues.push(SpecIssue::MissingRequestBody {
+                    path: path.clone(),
+                    method: method.into(),
+                });
+            }
+        }
+    }
+    issues.extend(
+        duplicate_operation_ids(spec)
+            .into_iter()
+            .map(|id| SpecIssue::DuplicateOperationId { id }),
+    );
+    let mut refs = std::collections::BTreeSet::new();
+    collect_refs(spec, &mut refs);
+    issues.extend(
+        refs.into_iter()
+            .filter(|reference| {
+                reference
+                    .strip_prefix('#')
+                    .and_then(|pointer| spec.pointer(pointer))
+                    .is_none()
+            })
+            .map(|reference| SpecIssue::DanglingRef {
+                reference: reference.into(),
+            }),
+    );
+    issues
+}
+
+/// Both the `{id}` and the axum `:id` forms.
+fn path_param_names(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter_map(|segment| {
+        segment
+            .strip_prefix('{')
+            .and_then(|segment| segment.strip_suffix('}'))
+            .or_else(|| segment.strip_prefix(':'))
+    })
+}
+
+fn collect_refs<'a>(json: &'a Value, refs: &mut std::collections::BTreeSet<&'a str>) {
+    match json {
+        Value::Object(obj) => {
+            if let Some(Value::String(reference)) = obj.get("$ref") {
+                refs.insert(reference);
+            }
+            obj.values().for_each(|val| collect_refs(val, refs));
+        }
+        Value::Array(arr) => arr.iter().for_each(|val| collect_refs(val, refs)),
+        _ => {}
+    }
+}
+
+/// Returns every `operationId` that's used by more than one operation in the spec.
+pub fn duplicate_operation_ids(spec: &Value) -> Vec<String> {
+    let mut seen = std::collections::HashSet::new();
+    let mut dupes = std::collections::BTreeSet::new();
+    let operations = spec
+        .get("paths")
+        .and_then(Value::as_object)
+        .into_iter()
+        .flat_map(|paths| paths.values())
+        .filter_map(Value::as_object)
+        .flat_map(|item| item.values());
+    for id in operations.filter_map(|op| op.get("operationId").and_then(Value::as_str)) {
+        if !seen.insert(id) {
+            dupes.insert(id.to_string());
+        }
+    }
+    dupes.into_iter().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use deps::*;
+
+    use super::*;
+    use crate::*;
+
+    #[derive(Debug, serde::Serialize, utoipa::ToSchema)]
+    #[serde(crate = "serde", rename_all = "camelCase", tag = "error")]
+    enum Error {
+        Internal,
+    }
+
+    impl From<&Error> for StatusCode {
+        fn from(_: &Error) -> Self {
+            Self::INTERNAL_SERVER_ERROR
+        }
+    }
+
+    impl ErrorCode for Error {
+        fn code(&self) -> &'static str {
+            "service.internal"
+        }
+    }
+
+    /// Takes an id in the path and a body but documents neither.
+    #[derive(Debug, Clone, Copy)]
+    struct Broken;
+
+    #[async_trait::async_trait]
+    impl Endpoint for Broken {
+        type Request = ();
+        type Response = NoContent;
+        type Error = Error;
+
+        async fn handle(
+            &self,
+            _ctx: &crate::Context,
+            _request: Self::Request,
+        ) -> Result<Self::Response, Self::Error> {
+            Ok(NoContent)
+        }
+    }
+
+    impl HttpEndpoint for Broken {
+        const METHOD: Method = Method::Post;
+        const PATH: &'static str = "/things/:id";
+        const SUCCESS_CODE: StatusCode = StatusCode::NO_CONTENT;
+
+        type HttpRequest = ();
+
+        fn request(_: Self::HttpRequest) -> Result<Self::Request, Self::Error> {
+            Ok(())
+        }
+
+        fn response(resp: Self::Response) -> axum::response::Response {
+            resp.into_response()
+        }
+    }
+
+    impl DocumentedEndpoint for Broken {
+        fn errors() -> Vec<ErrorResponse<Self::Error>> {
+            vec![("Internal server error", Error::Internal)]
+        }
+    }
+
+    #[test]
+    fn api_doc_is_clean() {
+        assert_eq!(ApiDoc::validate(), Ok(()));
+    }
+
+    #[test]
+    fn finds_broken_endpoint_issues() {
+        // the components with the error schema are left out on purpose
+        let spec = serde_json::to_value(
+            utoipa::openapi::OpenApiBuilder::new()
+                .paths(
+                    utoipa::openapi::PathsBuilder::new()
+                        .path(axum_path_str_to_openapi(Broken::PATH), Broken::path_item())
+                        .path("/copy", Broken::path_item())
+                        .build(),
+                )
+                .build(),
+        )
+        .unwrap();
+        let issues = lint(&spec);
+        for expected in [
+            SpecIssue::UndocumentedPathParam {
+                path: "/things/{id}".into(),
+                method: "post".into(),
+                param: "id".into(),
+            },
+            SpecIssue::MissingRequestBody {
+                path: "/things/{id}".into(),
+                method: "post".into(),
+            },
+            SpecIssue::MissingRequestBody {
+                path: "/copy".into(),
+                method: "post".into(),
+            },
+            SpecIssue::DuplicateOperationId {
+                id: "Broken".into(),
+            },
+            SpecIssue::DanglingRef {
+                reference: "#/components/schemas/BrokenError".into(),
+            },
+        ] {
+            assert!(issues.contains(&expected), "{expected} not in {issues:#?}");
+        }
+    }
+}
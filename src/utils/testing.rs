@@ -5,7 +5,13 @@ pub use axum::http;
 pub use axum::http::StatusCode;
 pub use tower::ServiceExt;
 
-use crate::{Context, SharedContext};
+use crate::{
+    auth::{ApiKeyRecord, Role, Session},
+    db::DbBackend,
+    user::User,
+    utils::{Clock, IdGen},
+    Context, SharedContext,
+};
 
 pub fn setup_tracing() -> eyre::Result<()> {
     color_eyre::install()?;
@@ -35,6 +41,321 @@ pub fn setup_tracing_once() {
     Lazy::force(&TRACING);
 }
 
+pub fn test_config() -> crate::Config {
+    crate::Config {
+        pass_salt_hash: b"sea brine".to_vec(),
+        argon2_conf: argon2::Config::default(),
+        auth_token_lifespan: time::Duration::seconds_f64(60. * 60. * 24. * 30.),
+        permissions: None,
+        trim_trailing_slash: true,
+        db_max_connections: 5,
+        pool_metrics_interval: std::time::Duration::from_secs(1),
+        response_cache_capacity: 64,
+        security_headers: true,
+        slow_query_threshold: std::time::Duration::from_millis(100),
+        tls: None,
+        locales: vec!["en".into(), "de".into()],
+        bulk_delete_max: 3,
+        pretty_json: false,
+        db_breaker_threshold: 3,
+        db_breaker_cooldown: std::time::Duration::from_secs(5),
+        max_uri_len: 1024,
+        rate_limit: None,
+        request_timeout: None,
+        metrics_bind_addr: None,
+        metrics_required: false,
+        redis_url: None,
+        cache_capacity: 64,
+        shutdown_timeout: std::time::Duration::from_secs(1),
+        cors_origins: vec![],
+        multi_tenant: false,
+        tenant_base_domain: None,
+        max_tenants: 8,
+        body_limit: 64 * 1024,
+        upload_body_limit: 1024 * 1024,
+        maintenance_mode: false,
+        auto_migrate: false,
+        strict_request_bodies: false,
+        trusted_proxies: vec![],
+        request_signing_key: None,
+        signature_window: std::time::Duration::from_secs(300),
+        tcp_keepalive: None,
+        http2_keepalive_interval: None,
+        max_connections: None,
+        counter_flush_interval: std::time::Duration::from_secs(60),
+        counter_flush_size: 1000,
+        expose_internal_errors: true,
+        max_json_depth: 64,
+        max_json_elements: 10_000,
+        enabled_features: vec![],
+        slow_request_threshold: None,
+        redacted_fields: crate::utils::slow_requests::DEFAULT_REDACTED_FIELDS
+            .iter()
+            .map(|field| field.to_string())
+            .collect(),
+        compress_responses: false,
+        uncompressed_content_types: vec![],
+        record_exchanges: None,
+        metrics_token: None,
+        warmup_connections: 0,
+        dependency_timeout: std::time::Duration::from_millis(500),
+        required_dependencies: vec![],
+    }
+}
+
+/// A [`DbBackend`] that keeps everything in memory. Use it through
+/// [`Context::mock`] to run endpoint logic without a live Postgres.
+#[derive(Debug, Default)]
+pub struct InMemoryDb {
+    pub sessions: parking_lot::RwLock<std::collections::HashMap<String, Session>>,
+    pub users: parking_lot::RwLock<std::collections::HashMap<uuid::Uuid, User>>,
+    pub roles: parking_lot::RwLock<std::collections::HashMap<uuid::Uuid, Vec<Role>>>,
+    /// Keyed by [`crate::auth::ApiKeyRecord::key_hash`].
+    pub api_keys: parking_lot::RwLock<std::collections::HashMap<String, ApiKeyRecord>>,
+}
+
+impl InMemoryDb {
+    pub fn with_user(self, user: User) -> Self {
+        self.users.write().insert(user.id, user);
+        self
+    }
+
+    pub fn with_session(self, session: Session) -> Self {
+        self.sessions.write().insert(session.token.clone(), session);
+        self
+    }
+
+    /// Seeded with `USER_01` and their session.
+    pub fn fixture() -> Self {
+        use crate::user::testing::*;
+        let now = time::OffsetDateTime::now_utc();
+        Self::default()
+            .with_user(User {
+                id: USER_01_ID,
+                created_at: now,
+                updated_at: now,
+                email: USER_01_EMAIL.into(),
+                username: USER_01_USERNAME.into(),
+                pic_url: None,
+            })
+            .with_session(Session {
+                token: USER_01_SESSION.into(),
+                user_id: USER_01_ID,
+                expires_at: now + time::Duration::hours(1),
+                created_at: now,
+                updated_at: now,
+            })
+    }
+
+    pub fn with_role(self, user_id: uuid::Uuid, role: Role) -> Self {
+        self.roles.write().entry(user_id).or_default().push(role);
+        self
+    }
+
+    pub fn with_api_key(self, record: ApiKeyRecord) -> Self {
+        self.api_keys
+            .write()
+            .insert(record.key_hash.clone(), record);
+        self
+    }
+}
+
+#[async_trait::async_trait]
+impl DbBackend for InMemoryDb {
+    async fn session(&self, token: &str) -> Result<Session, sqlx::Error> {
+        self.sessions
+            .read()
+            .get(token)
+            .cloned()
+            .ok_or(sqlx::Error::RowNotFound)
+    }
+
+    async fn user(&self, id: uuid::Uuid) -> Result<User, sqlx::Error> {
+        self.users
+            .read()
+            .get(&id)
+            .cloned()
+            .ok_or(sqlx::Error::RowNotFound)
+    }
+
+    async fn user_roles(&self, user_id: uuid::Uuid) -> Result<Vec<Role>, sqlx::Error> {
+        Ok(self.roles.read().get(&user_id).cloned().unwrap_or_default())
+    }
+
+    async fn api_key(&self, key_hash: &str) -> Result<ApiKeyRecord, sqlx::Error> {
+        self.api_keys
+            .read()
+            .get(key_hash)
+            .cloned()
+            .ok_or(sqlx::Error::RowNotFound)
+    }
+}
+
+/// A [`Clock`] that only moves when told to.
+#[derive(Debug)]
+pub struct FixedClock(parking_lot::Mutex<time::OffsetDateTime>);
+
+impl FixedClock {
+    pub fn new(now: time::OffsetDateTime) -> Self {
+        Self(parking_lot::Mutex::new(now))
+    }
+
+    pub fn advance(&self, by: time::Duration) {
+        *self.0.lock() += by;
+    }
+}
+
+impl Clock for FixedClock {
+    fn now(&self) -> time::OffsetDateTime {
+        *self.0.lock()
+    }
+}
+
+/// An [`IdGen`] handing out `00000000-0000-0000-0000-000000000001` and onwards.
+#[derive(Debug, Default)]
+pub struct SequentialIds(std::sync::atomic::AtomicU64);
+
+impl IdGen for SequentialIds {
+    fn new_id(&self) -> uuid::Uuid {
+        let next = self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst) + 1;
+        uuid::Uuid::from_u128(next as u128)
+    }
+}
+
+/// A [`tracing_subscriber::Layer`] that keeps the fields of the events under
+/// `target`. Install it with [`tracing::subscriber::set_default`].
+#[derive(Debug, Clone)]
+pub struct CapturedEvents {
+    target: &'static str,
+    events: std::sync::Arc<parking_lot::Mutex<Vec<serde_json::Map<String, serde_json::Value>>>>,
+}
+
+impl CapturedEvents {
+    pub fn new(target: &'static str) -> Self {
+        Self {
+            target,
+            events: Default::default(),
+        }
+    }
+
+    pub fn events(&self) -> Vec<serde_json::Map<String, serde_json::Value>> {
+        self.events.lock().clone()
+    }
+}
+
+struct FieldsVisitor(serde_json::Map<String, serde_json::Value>);
+
+impl tracing::field::Visit for FieldsVisitor {
+    fn record_str(&mut self, field: &tracing::field::Field, value: &str) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_i64(&mut self, field: &tracing::field::Field, value: i64) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_u64(&mut self, field: &tracing::field::Field, value: u64) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_bool(&mut self, field: &tracing::field::Field, value: bool) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+        self.0
+            .insert(field.name().into(), format!("{value:?}").into());
+    }
+}
+
+impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for CapturedEvents {
+    fn on_event(
+        &self,
+        event: &tracing::Event<'_>,
+        _ctx: tracing_subscriber::layer::Context<'_, S>,
+    ) {
+        if event.metadata().target() != self.target {
+            return;
+        }
+        let mut visitor = FieldsVisitor(Default::default());
+        event.record(&mut visitor);
+        self.events.lock().push(visitor.0);
+    }
+}
+
+impl Context {
+    /// The `db_pool` is lazy and never connected so anything that bypasses
+    /// [`Context::db`] will error out.
+    pub fn mock(db: impl DbBackend) -> Self {
+        let config = test_config();
+        let clock: std::sync::Arc<dyn crate::utils::Clock> =
+            std::sync::Arc::new(crate::utils::SystemClock);
+        Self {
+            db_pool: sqlx::postgres::PgPoolOptions::new()
+                .connect_lazy_with(sqlx::postgres::PgConnectOptions::default()),
+            db: std::sync::Arc::new(db),
+            response_cache: crate::utils::ResponseCache::new(64),
+            cache: crate::utils::cache_from_config(&config, clock.clone()),
+            db_breaker: crate::utils::CircuitBreaker::new(
+                config.db_breaker_threshold,
+                config.db_breaker_cooldown,
+            ),
+            config,
+            metrics: Default::default(),
+            clock,
+            ids: std::sync::Arc::new(crate::utils::RandomIds),
+            response_transformers: vec![],
+            dependency_checks: vec![],
+            live_config: Default::default(),
+            singleflight: Default::default(),
+            rate_limiter: Default::default(),
+            maintenance: Default::default(),
+            counters: Default::default(),
+            warmup: Default::default(),
+        }
+    }
+}
+
+/// The error of test endpoints that can't fail.
+#[derive(Debug, serde::Serialize, utoipa::ToSchema)]
+#[serde(crate = "serde")]
+pub enum Infallible {}
+
+impl From<&Infallible> for StatusCode {
+    fn from(err: &Infallible) -> Self {
+        match *err {}
+    }
+}
+
+impl crate::utils::ErrorCode for Infallible {
+    fn code(&self) -> &'static str {
+        match *self {}
+    }
+}
+
+/// Route `endpoint` on its own with a [`Context::mock`] over an empty [`InMemoryDb`].
+pub fn endpoint_app<T>(endpoint: T) -> axum::Router
+where
+    T: crate::HttpEndpoint + Clone,
+    T::Error: serde::Serialize + crate::utils::ErrorCode,
+    for<'a> &'a T::Error: Into<StatusCode>,
+{
+    endpoint_app_with(
+        endpoint,
+        std::sync::Arc::new(Context::mock(InMemoryDb::default())),
+    )
+}
+
+/// Like [`endpoint_app`] but for when the test needs to look at the context.
+pub fn endpoint_app_with<T>(endpoint: T, ctx: SharedContext) -> axum::Router
+where
+    T: crate::HttpEndpoint + Clone,
+    T::Error: serde::Serialize + crate::utils::ErrorCode,
+    for<'a> &'a T::Error: Into<StatusCode>,
+{
+    axum::Router::from(crate::EndpointWrapper::new(endpoint)).layer(axum::Extension(ctx))
+}
+
 pub struct ExtraAssertionAgs<'a> {
     pub ctx: &'a mut TestContext,
     pub auth_token: Option<String>,
@@ -49,6 +370,9 @@ pub type LocalBoxFuture<'a, T> = std::pin::Pin<Box<dyn futures::Future<Output =
 
 pub type ExtraAssertions<'c, 'f> = dyn Fn(ExtraAssertionAgs<'c>) -> LocalBoxFuture<'f, ()>;
 
+/// Run against the database before the request is sent, e.g. to grant roles.
+pub type TestSetup<'c, 'f> = dyn Fn(&'c TestContext) -> LocalBoxFuture<'f, ()>;
+
 pub struct TestContext {
     pub test_name: String,
     ctx: Option<SharedContext>,
@@ -61,11 +385,7 @@ impl TestContext {
         setup_tracing_once();
         let test_name = test_name.replace("::tests::", "").replace("::", "_");
 
-        let config = crate::Config {
-            pass_salt_hash: b"sea brine".to_vec(),
-            argon2_conf: argon2::Config::default(),
-            auth_token_lifespan: time::Duration::seconds_f64(60. * 60. * 24. * 30.),
-        };
+        let config = test_config();
 
         use sqlx::prelude::*;
         let opts = sqlx::postgres::PgConnectOptions::default()
@@ -92,6 +412,7 @@ impl TestContext {
             opts
         };
         opts.log_statements("DEBUG".parse().unwrap());
+        crate::db::log_slow_queries(&mut opts, &config);
 
         let mut connection = opts
             .connect()
@@ -111,11 +432,13 @@ impl TestContext {
         let opts = opts.database(&test_name[..]);
 
         // migrate database
-        let db_pool = sqlx::PgPool::connect_with(opts)
+        let db_pool = sqlx::postgres::PgPoolOptions::new()
+            .max_connections(config.db_max_connections)
+            .connect_with(opts)
             .await
             .expect("Failed to connect to Postgres as test db.");
 
-        sqlx::migrate!("./migrations")
+        crate::db::MIGRATOR
             .run(&db_pool)
             .await
             .expect("Failed to migrate the database");
@@ -126,7 +449,7 @@ impl TestContext {
             .await
             .expect("Failed to add test data");
 
-        let ctx = Context { db_pool, config };
+        let ctx = Context::new(db_pool, config);
         Self {
             test_name: test_name.clone(), // someone needs it downwind
             ctx: Some(std::sync::Arc::new(ctx)),
@@ -142,10 +465,42 @@ impl TestContext {
         }
     }
 
+    /// Like [`TestContext::new`] but also loads the given [`Fixture`]s on top of
+    /// the default test data. Every test gets its own database which is dropped on
+    /// [`TestContext::close`] so nothing seeded here leaks into other tests.
+    pub async fn with_fixtures(test_name: &'static str, fixtures: &[Fixture<'_>]) -> Self {
+        let this = Self::new(test_name).await;
+        this.load_fixtures(fixtures).await;
+        this
+    }
+
+    /// Loads all the fixtures in a single transaction, panicking if any fail.
+    pub async fn load_fixtures(&self, fixtures: &[Fixture<'_>]) {
+        let ctx = self.ctx();
+        let mut tx = ctx
+            .db_pool
+            .begin()
+            .await
+            .expect("Failed to begin fixture transaction");
+        for fixture in fixtures {
+            fixture.load(&mut tx).await;
+        }
+        tx.commit()
+            .await
+            .expect("Failed to commit fixture transaction");
+    }
+
     pub fn ctx(&self) -> SharedContext {
         self.ctx.clone().unwrap_or_log()
     }
 
+    /// For swapping out parts of the [`Context`] like the [`Context::clock`]. Panics
+    /// if the [`SharedContext`] has been cloned and is still held elsewhere.
+    pub fn ctx_mut(&mut self) -> &mut Context {
+        std::sync::Arc::get_mut(self.ctx.as_mut().unwrap_or_log())
+            .expect("the shared context is still held elsewhere")
+    }
+
     /// Call this after all holders of the [`SharedContext`] have been dropped.
     pub async fn close(mut self) {
         let ctx = self.ctx.take().unwrap_or_log();
@@ -181,6 +536,54 @@ impl Drop for TestContext {
     }
 }
 
+/// Test data to be loaded through [`TestContext::with_fixtures`].
+#[derive(Debug, Clone)]
+pub enum Fixture<'a> {
+    /// Raw SQL, may contain multiple statements.
+    Sql(&'a str),
+    /// An array of objects to be inserted into `table`. Only the columns present
+    /// on the objects are inserted so the rest get their defaults.
+    Json {
+        table: &'a str,
+        rows: serde_json::Value,
+    },
+}
+
+impl Fixture<'_> {
+    async fn load(&self, tx: &mut sqlx::Transaction<'_, sqlx::Postgres>) {
+        use sqlx::prelude::*;
+        match self {
+            Fixture::Sql(sql) => {
+                tx.execute(*sql).await.expect("Failed to load sql fixture");
+            }
+            Fixture::Json { table, rows } => {
+                let columns = rows
+                    .as_array()
+                    .expect("json fixture rows weren't an array")
+                    .iter()
+                    .flat_map(|row| {
+                        row.as_object()
+                            .expect("json fixture row wasn't an object")
+                            .keys()
+                    })
+                    .collect::<std::collections::BTreeSet<_>>()
+                    .into_iter()
+                    .map(|col| format!(r#""{col}""#))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                sqlx::query(&format!(
+                    r#"INSERT INTO {table} ({columns})
+                    SELECT {columns} FROM jsonb_populate_recordset(NULL::{table}, $1)"#
+                ))
+                .bind(rows)
+                .execute(&mut *tx)
+                .await
+                .unwrap_or_else(|err| panic!("Failed to load json fixture into {table}: {err}"));
+            }
+        }
+    }
+}
+
 /// Not deep equality but deep "`is_subset_of`" check.
 pub fn check_json(
     (check_name, check): (&str, &serde_json::Value),
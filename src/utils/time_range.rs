@@ -0,0 +1,183 @@
+//! `?since=` and `?until=` for list endpoints over time series like records. Both
+//! are optional RFC 3339 timestamps and inclusive.
+
+use deps::*;
+
+use axum::extract::{FromRequest, RequestParts};
+use time::format_description::well_known::Rfc3339;
+use utoipa::openapi;
+
+use crate::{DocumentedParameter, ParameterDoc};
+
+use super::ApiError;
+
+pub const SINCE_PARAM: &str = "since";
+pub const UNTIL_PARAM: &str = "until";
+
+/// Unbounded on the sides that aren't set.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TimeRange {
+    pub since: Option<time::OffsetDateTime>,
+    pub until: Option<time::OffsetDateTime>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(crate = "serde")]
+struct TimeRangeParams {
+    since: Option<String>,
+    until: Option<String>,
+}
+
+fn parse(param: &str, val: Option<String>) -> Result<Option<time::OffsetDateTime>, ApiError> {
+    val.map(|val| {
+        time::OffsetDateTime::parse(&val, &Rfc3339).map_err(|err| ApiError::InvalidQuery {
+            message: format!("`{param}` isn't an RFC 3339 timestamp: {err}"),
+        })
+    })
+    .transpose()
+}
+
+impl TimeRange {
+    pub fn contains(&self, at: time::OffsetDateTime) -> bool {
+        self.since.map(|since| since <= at).unwrap_or(true)
+            && self.until.map(|until| at <= until).unwrap_or(true)
+    }
+
+    /// `{column} BETWEEN` the `since` and `until` bound to the given positional
+    /// params, for a `WHERE` clause. Bind them as `Option`s, unset sides turn into
+    /// `-infinity` and `infinity`.
+    pub fn sql_condition(column: &str, since: usize, until: usize) -> String {
+        format!(
+            "{column} BETWEEN COALESCE(${since}::TIMESTAMPTZ, '-infinity') \
+             AND COALESCE(${until}::TIMESTAMPTZ, 'infinity')"
+        )
+    }
+}
+
+#[async_trait::async_trait]
+impl<B> FromRequest<B> for TimeRange
+where
+    B: Send,
+{
+    type Rejection = ApiError;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let axum::extract::Query(params) =
+            axum::extract::Query::<TimeRangeParams>::from_request(req)
+                .await
+                .map_err(|err| ApiError::InvalidQuery {
+                    message: err.to_string(),
+                })?;
+        let range = Self {
+            since: parse(SINCE_PARAM, params.since)?,
+            until: parse(UNTIL_PARAM, params.until)?,
+        };
+        if let (Some(since), Some(until)) = (range.since, range.until) {
+            if since > until {
+                return Err(ApiError::InvalidQuery {
+                    message: format!("`{SINCE_PARAM}` is after `{UNTIL_PARAM}`"),
+                });
+            }
+        }
+        Ok(range)
+    }
+}
+
+impl DocumentedParameter for TimeRange {
+    fn to_openapi(_op_id: &str, _path: &str) -> Vec<ParameterDoc> {
+        [
+            super::QueryParamDoc::new(SINCE_PARAM, openapi::SchemaType::String)
+                .description("RFC 3339 timestamp, inclusive. Unbounded if not set."),
+            super::QueryParamDoc::new(UNTIL_PARAM, openapi::SchemaType::String).description(
+                "RFC 3339 timestamp, inclusive, not before `since`. Unbounded if not set.",
+            ),
+        ]
+        .iter()
+        .map(|param| param.parameter().into())
+        .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use deps::*;
+
+    use super::*;
+    use crate::utils::testing::*;
+
+    crate::table_tests! {
+        time_range tokio,
+        (query, expected),
+        {
+            let query: &str = query;
+            let resp = axum::Router::<axum::body::Body>::new()
+                .route(
+                    "/items",
+                    axum::routing::get(|range: TimeRange| async move {
+                        axum::Json(serde_json::json!({
+                            "since": range.since.map(|at| at.unix_timestamp()),
+                            "until": range.until.map(|at| at.unix_timestamp()),
+                        }))
+                    }),
+                )
+                .oneshot(
+                    http::Request::builder()
+                        .method("GET")
+                        .uri(format!("/items{query}"))
+                        .body(Default::default())
+                        .unwrap_or_log(),
+                )
+                .await
+                .unwrap_or_log();
+            let expected: Result<serde_json::Value, ()> = expected;
+            match expected {
+                Ok(expected) => {
+                    assert_eq!(resp.status(), StatusCode::OK);
+                    let body = hyper::body::to_bytes(resp.into_body())
+                        .await
+                        .unwrap_or_log();
+                    let body: serde_json::Value = serde_json::from_slice(&body).unwrap_or_log();
+                    assert_eq!(body, expected);
+                }
+                Err(()) => assert_eq!(resp.status(), StatusCode::BAD_REQUEST),
+            }
+        },
+    }
+
+    time_range! {
+        valid_range: (
+            "?since=2022-09-19T00:00:00Z&until=2022-09-20T00:00:00%2B02:00",
+            Ok(serde_json::json!({ "since": 1663545600, "until": 1663624800 })),
+        ),
+        inverted_range: (
+            "?since=2022-09-20T00:00:00Z&until=2022-09-19T00:00:00Z",
+            Err(()),
+        ),
+        only_since: (
+            "?since=2022-09-19T00:00:00Z",
+            Ok(serde_json::json!({ "since": 1663545600, "until": null })),
+        ),
+        only_until: (
+            "?until=2022-09-19T00:00:00Z",
+            Ok(serde_json::json!({ "since": null, "until": 1663545600 })),
+        ),
+        unbounded: ("", Ok(serde_json::json!({ "since": null, "until": null }))),
+        not_rfc3339: ("?since=yesterday", Err(())),
+    }
+
+    #[test]
+    fn contains_is_inclusive() {
+        let at = time::OffsetDateTime::from_unix_timestamp(1_663_545_600).unwrap();
+        assert!(TimeRange {
+            since: Some(at),
+            until: Some(at),
+        }
+        .contains(at));
+        assert!(!TimeRange {
+            since: Some(at + time::Duration::SECOND),
+            until: None,
+        }
+        .contains(at));
+        assert!(TimeRange::default().contains(at));
+    }
+}
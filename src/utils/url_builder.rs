@@ -0,0 +1,252 @@
+//! Absolute urls for `Location` and `Link` headers and the like. The scheme and
+//! host are those the client used, as told by the proxies through the
+//! `Forwarded`, `X-Forwarded-Proto` and `X-Forwarded-Host` headers. Those are only
+//! listened to if the request came from one of the
+//! [`crate::Config::trusted_proxies`], see [`super::from_trusted_proxy`].
+
+use deps::*;
+
+use axum::{
+    extract::{FromRequest, RequestParts},
+    http::{header, uri::Authority, HeaderMap, Uri},
+};
+
+use crate::{DocumentedParameter, ParameterDoc};
+
+use super::ApiError;
+
+pub const X_FORWARDED_PROTO: &str = "x-forwarded-proto";
+pub const X_FORWARDED_HOST: &str = "x-forwarded-host";
+
+/// Builds urls under the `scheme://host` the request was made to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UrlBuilder {
+    pub scheme: String,
+    pub host: String,
+}
+
+/// The value of `key` in the first element of the `Forwarded` header, the one
+/// closest to the client.
+fn forwarded(headers: &HeaderMap, key: &str) -> Option<String> {
+    headers
+        .get(header::FORWARDED)?
+        .to_str()
+        .ok()?
+        .split(',')
+        .next()?
+        .split(';')
+        .find_map(|pair| {
+            let (name, val) = pair.split_once('=')?;
+            name.trim()
+                .eq_ignore_ascii_case(key)
+                .then(|| val.trim().trim_matches('"').to_string())
+        })
+}
+
+/// The first value of a comma separated header like `X-Forwarded-Proto`.
+fn first_value(headers: &HeaderMap, name: &str) -> Option<String> {
+    let val = headers.get(name)?.to_str().ok()?.split(',').next()?.trim();
+    (!val.is_empty()).then(|| val.to_string())
+}
+
+/// Percent encodes all but the unreserved characters of RFC 3986 so that the value
+/// stays a single path segment.
+fn encode_segment(segment: &str) -> String {
+    segment
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{byte:02X}"),
+        })
+        .collect()
+}
+
+impl UrlBuilder {
+    pub fn new(scheme: impl Into<String>, host: impl Into<String>) -> Self {
+        Self {
+            scheme: scheme.into(),
+            host: host.into(),
+        }
+    }
+
+    /// `None` if the host can't be determined. The scheme defaults to `http`. The
+    /// forwarding headers are ignored unless `trust_forwarded`.
+    pub fn from_request_parts(
+        headers: &HeaderMap,
+        uri: &Uri,
+        trust_forwarded: bool,
+    ) -> Option<Self> {
+        // anything else would end up verbatim in `Location` and `Link` headers
+        let forwarded_proto = || {
+            forwarded(headers, "proto")
+                .or_else(|| first_value(headers, X_FORWARDED_PROTO))
+                .map(|proto| proto.to_ascii_lowercase())
+                .filter(|proto| proto == "http" || proto == "https")
+        };
+        let forwarded_host = || {
+            forwarded(headers, "host")
+                .or_else(|| first_value(headers, X_FORWARDED_HOST))
+                .filter(|host| host.parse::<Authority>().is_ok())
+        };
+        let scheme = trust_forwarded
+            .then(forwarded_proto)
+            .flatten()
+            .or_else(|| uri.scheme_str().map(String::from))
+            .unwrap_or_else(|| "http".into());
+        let host = trust_forwarded
+            .then(forwarded_host)
+            .flatten()
+            .or_else(|| {
+                first_value(headers, header::HOST.as_str())
+                    .filter(|host| host.parse::<Authority>().is_ok())
+            })
+            .or_else(|| uri.authority().map(|authority| authority.to_string()))?;
+        Some(Self::new(scheme, host))
+    }
+
+    /// `path` under the base, as is.
+    pub fn absolute(&self, path: &str) -> String {
+        format!("{}://{}{path}", self.scheme, self.host)
+    }
+
+    /// `template` in the axum route syntax, e.g. `/users/:id`, with the `params`
+    /// put in for its segments.
+    pub fn url(&self, template: &str, params: &[(&str, &str)]) -> String {
+        self.absolute(&Self::path(template, params))
+    }
+
+    /// The path part of [`UrlBuilder::url`]. Panics if one of the segments has no
+    /// param, that's a misconfigured route.
+    pub fn path(template: &str, params: &[(&str, &str)]) -> String {
+        template
+            .split('/')
+            .map(|segment| match segment.strip_prefix(':') {
+                Some(name) => params
+                    .iter()
+                    .find(|(param, _)| *param == name)
+                    .map(|(_, val)| encode_segment(val))
+                    .unwrap_or_else(|| panic!("no param given for `:{name}` in {template}")),
+                None => segment.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+}
+
+#[async_trait::async_trait]
+impl<B> FromRequest<B> for UrlBuilder
+where
+    B: Send,
+{
+    type Rejection = ApiError;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let trust_forwarded = super::from_trusted_proxy(req.extensions());
+        Self::from_request_parts(req.headers(), req.uri(), trust_forwarded).ok_or_else(|| {
+            ApiError::MissingHeader {
+                name: header::HOST.to_string(),
+            }
+        })
+    }
+}
+
+impl DocumentedParameter for UrlBuilder {
+    fn to_openapi(_op_id: &str, _path: &str) -> Vec<ParameterDoc> {
+        vec![]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    crate::table_tests! {
+        from_request_parts,
+        (headers, uri, trust_forwarded, expected),
+        {
+            let headers: &[(&str, &str)] = headers;
+            let uri: &str = uri;
+            let headers = headers
+                .iter()
+                .map(|(name, val)| {
+                    (
+                        header::HeaderName::from_static(name),
+                        header::HeaderValue::from_static(val),
+                    )
+                })
+                .collect::<HeaderMap>();
+            let urls = UrlBuilder::from_request_parts(&headers, &uri.parse().unwrap(), trust_forwarded);
+            assert_eq!(urls.map(|urls| urls.absolute("/users")).as_deref(), expected);
+        }
+    }
+
+    from_request_parts! {
+        uses_the_host: (
+            &[("host", "api.example.com")],
+            "/users",
+            false,
+            Some("http://api.example.com/users"),
+        ),
+        honors_forwarded_proto: (
+            &[("host", "10.0.0.7:8080"), (X_FORWARDED_PROTO, "https"), (X_FORWARDED_HOST, "api.example.com")],
+            "/users",
+            true,
+            Some("https://api.example.com/users"),
+        ),
+        ignores_forwarded_headers_from_untrusted_peers: (
+            &[("host", "10.0.0.7:8080"), (X_FORWARDED_PROTO, "https"), (X_FORWARDED_HOST, "evil.example.com")],
+            "/users",
+            false,
+            Some("http://10.0.0.7:8080/users"),
+        ),
+        prefers_forwarded: (
+            &[
+                ("host", "10.0.0.7:8080"),
+                ("forwarded", r#"proto=https;host="api.example.com", proto=http"#),
+                (X_FORWARDED_PROTO, "http"),
+            ],
+            "/users",
+            true,
+            Some("https://api.example.com/users"),
+        ),
+        falls_back_to_the_authority: (
+            &[],
+            "https://api.example.com/users",
+            false,
+            Some("https://api.example.com/users"),
+        ),
+        none_without_host: (&[], "/users", false, None),
+        only_takes_web_schemes: (
+            &[("host", "api.example.com"), (X_FORWARDED_PROTO, "javascript")],
+            "/users",
+            true,
+            Some("http://api.example.com/users"),
+        ),
+        skips_malformed_hosts: (
+            &[("host", "api.example.com"), (X_FORWARDED_HOST, "evil.example.com/>; rel=next")],
+            "/users",
+            true,
+            Some("http://api.example.com/users"),
+        ),
+    }
+
+    #[test]
+    fn substitutes_path_params() {
+        let urls = UrlBuilder::new("https", "api.example.com");
+        assert_eq!(
+            urls.url(
+                "/users/:id/posts/:slug",
+                &[("slug", "hello world/2"), ("id", "42")]
+            ),
+            "https://api.example.com/users/42/posts/hello%20world%2F2"
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "no param given for `:id`")]
+    fn panics_on_missing_params() {
+        UrlBuilder::new("https", "api.example.com").url("/users/:id", &[]);
+    }
+}
@@ -0,0 +1,99 @@
+use deps::*;
+
+use axum::{
+    extract::{FromRequest, Path, RequestParts},
+    response::{IntoResponse, Response},
+};
+
+use super::ApiError;
+use crate::{DocumentedParameter, ParameterDoc};
+
+/// The single path segment of routes like `/users/:id` parsed as a
+/// [`uuid::Uuid`]. Unlike [`Path`], malformed ids are rejected with an
+/// [`ApiError::InvalidPathParameter`] naming the segment.
+#[derive(Debug, Clone, Copy)]
+pub struct UuidPath(pub uuid::Uuid);
+
+#[async_trait::async_trait]
+impl<B> FromRequest<B> for UuidPath
+where
+    B: Send,
+{
+    type Rejection = Response;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let Path(params) = Path::<Vec<(String, String)>>::from_request(req)
+            .await
+            .map_err(IntoResponse::into_response)?;
+        let (name, value) = match &params[..] {
+            [param] => param,
+            _ => {
+                tracing::error!(
+                    count = params.len(),
+                    "expected a single path segment, misconfigured route"
+                );
+                return Err(ApiError::BadRequest {
+                    message: format!("expected a single path parameter, got {}", params.len()),
+                }
+                .into_response());
+            }
+        };
+        value.parse().map(Self).map_err(|_| {
+            ApiError::InvalidPathParameter {
+                name: name.clone(),
+                value: value.clone(),
+                expected: "uuid".into(),
+            }
+            .into_response()
+        })
+    }
+}
+
+impl DocumentedParameter for UuidPath {
+    fn to_openapi(op_id: &str, path: &str) -> Vec<ParameterDoc> {
+        <Path<uuid::Uuid>>::to_openapi(op_id, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use deps::*;
+
+    use super::*;
+    use crate::utils::testing::*;
+
+    crate::table_tests! {
+        uuid_path tokio,
+        (uri, status),
+        {
+            let resp = axum::Router::<axum::body::Body>::new()
+                .route(
+                    "/items/:id",
+                    axum::routing::get(|UuidPath(id): UuidPath| async move { id.to_string() }),
+                )
+                .route(
+                    "/items/:id/:rev",
+                    axum::routing::get(|UuidPath(id): UuidPath| async move { id.to_string() }),
+                )
+                .oneshot(
+                    http::Request::builder()
+                        .method("GET")
+                        .uri(uri)
+                        .body(Default::default())
+                        .unwrap_or_log(),
+                )
+                .await
+                .unwrap_or_log();
+            assert_eq!(resp.status(), status);
+        },
+    }
+
+    uuid_path! {
+        parses_the_id: ("/items/6ecd8c99-4036-403d-bf84-cf8400f67836", StatusCode::OK),
+        rejects_malformed_ids: ("/items/1337", StatusCode::BAD_REQUEST),
+        rejects_misconfigured_routes: (
+            "/items/6ecd8c99-4036-403d-bf84-cf8400f67836/2",
+            StatusCode::BAD_REQUEST,
+        ),
+    }
+}
@@ -0,0 +1,169 @@
+use deps::*;
+
+use axum::extract::{FromRequest, Query, RequestParts};
+use utoipa::openapi;
+
+use crate::{DocumentedParameter, ParameterDoc};
+
+use super::{ApiError, ValidationErrors};
+
+/// Deserializes the query string into `T` and runs its [`validator::Validate`]
+/// impl, rejecting with an [`ApiError`]. `T`'s schema is what gets documented
+/// as the query parameters so it has to be a flat object.
+#[derive(Debug, Clone)]
+pub struct ValidatedQuery<T>(pub T);
+
+#[async_trait::async_trait]
+impl<T, B> FromRequest<B> for ValidatedQuery<T>
+where
+    T: serde::de::DeserializeOwned + validator::Validate + Send,
+    B: Send,
+{
+    type Rejection = ApiError;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let Query(query) =
+            Query::<T>::from_request(req)
+                .await
+                .map_err(|err| ApiError::InvalidQuery {
+                    message: err.to_string(),
+                })?;
+        query.validate().map_err(|err| ApiError::InvalidInput {
+            issues: ValidationErrors::from(err),
+        })?;
+        Ok(Self(query))
+    }
+}
+
+impl<T> DocumentedParameter for ValidatedQuery<T>
+where
+    T: utoipa::ToSchema,
+{
+    fn to_openapi(_op_id: &str, _path: &str) -> Vec<ParameterDoc> {
+        let obj = match T::schema() {
+            openapi::Schema::Object(obj) => obj,
+            _ => panic!(
+                "{} is not an Object schema: not allowed as query parameters",
+                std::any::type_name::<T>()
+            ),
+        };
+        let required = obj.required;
+        obj.properties
+            .into_iter()
+            .map(|(name, schema)| {
+                let is_required = required.contains(&name);
+                openapi::path::ParameterBuilder::new()
+                    .name(name)
+                    .parameter_in(openapi::path::ParameterIn::Query)
+                    .required(if is_required {
+                        openapi::Required::True
+                    } else {
+                        openapi::Required::False
+                    })
+                    .schema(Some(schema))
+                    .build()
+                    .into()
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use deps::*;
+
+    use super::ValidatedQuery;
+    use crate::utils::testing::*;
+    use crate::{DocumentedParameter, ParameterDoc};
+
+    #[derive(Debug, serde::Deserialize, validator::Validate, utoipa::ToSchema)]
+    #[serde(crate = "serde", rename_all = "camelCase")]
+    struct Search {
+        #[validate(length(min = 3))]
+        query: String,
+        #[validate(range(min = 1, max = 100))]
+        limit: Option<u32>,
+    }
+
+    fn search_router() -> axum::Router {
+        axum::Router::new().route(
+            "/search",
+            axum::routing::get(
+                |ValidatedQuery(search): ValidatedQuery<Search>| async move {
+                    axum::Json(serde_json::json!({
+                        "query": search.query,
+                        "limit": search.limit,
+                    }))
+                },
+            ),
+        )
+    }
+
+    crate::table_tests! {
+        validated_query tokio,
+        (uri, status, expected),
+        {
+            let resp = search_router()
+                .oneshot(
+                    http::Request::builder()
+                        .method("GET")
+                        .uri(uri)
+                        .body(Default::default())
+                        .unwrap_or_log(),
+                )
+                .await
+                .unwrap_or_log();
+            assert_eq!(resp.status(), status);
+            let body = hyper::body::to_bytes(resp.into_body())
+                .await
+                .unwrap_or_log();
+            let body = serde_json::from_slice(&body).unwrap_or_log();
+            check_json(("expected", &expected), ("response", &body));
+        },
+    }
+
+    validated_query! {
+        accepts_valid_queries: (
+            "/search?query=archie&limit=10",
+            StatusCode::OK,
+            serde_json::json!({ "query": "archie", "limit": 10 }),
+        ),
+        rejects_missing_params: (
+            "/search?limit=10",
+            StatusCode::BAD_REQUEST,
+            serde_json::json!({ "error": "invalidQuery" }),
+        ),
+        rejects_invalid_params: (
+            "/search?query=ar&limit=1000",
+            StatusCode::BAD_REQUEST,
+            serde_json::json!({
+                "error": "invalidInput",
+                "issues": {
+                    "query": [{ "code": "length" }],
+                    "limit": [{ "code": "range" }],
+                },
+            }),
+        ),
+    }
+
+    #[test]
+    fn documents_fields_as_query_params() {
+        let params = <ValidatedQuery<Search>>::to_openapi("", "/search")
+            .into_iter()
+            .map(|param| match param {
+                ParameterDoc::Param(param) => serde_json::to_value(param).unwrap(),
+                ParameterDoc::Body(_) => panic!("query documented as body"),
+            })
+            .collect::<Vec<_>>();
+        check_json(
+            (
+                "expected",
+                &serde_json::json!([
+                    { "name": "limit", "in": "query", "required": false },
+                    { "name": "query", "in": "query", "required": true },
+                ]),
+            ),
+            ("params", &serde_json::Value::Array(params)),
+        );
+    }
+}
@@ -0,0 +1,156 @@
+//! `Vary` bookkeeping so that caches between us and the clients key responses by
+//! the request headers that shaped them. [`crate::HttpEndpoint::http`] lists the
+//! ones each endpoint depends on and [`super::compression`] adds `Accept-Encoding`.
+
+use deps::*;
+
+use axum::http::{
+    header::{self, HeaderName},
+    HeaderMap, HeaderValue,
+};
+
+/// Merges the `names` into the `Vary` header, keeping the ones already there and
+/// dropping duplicates. Left alone if it's `*`.
+pub fn add_vary(headers: &mut HeaderMap, names: &[HeaderName]) {
+    if names.is_empty() {
+        return;
+    }
+    let mut vary = headers
+        .get_all(header::VARY)
+        .iter()
+        .filter_map(|val| val.to_str().ok())
+        .flat_map(|val| val.split(','))
+        .map(|name| name.trim().to_ascii_lowercase())
+        .filter(|name| !name.is_empty())
+        .collect::<Vec<_>>();
+    if vary.iter().any(|name| name == "*") {
+        return;
+    }
+    for name in names {
+        if !vary.iter().any(|val| val == name.as_str()) {
+            vary.push(name.as_str().to_string());
+        }
+    }
+    if let Ok(val) = HeaderValue::from_str(&vary.join(", ")) {
+        headers.insert(header::VARY, val);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use deps::*;
+
+    use super::*;
+    use crate::utils::testing::*;
+    use crate::*;
+
+    struct Accept(String);
+
+    impl HeaderParam for Accept {
+        const NAME: &'static str = "accept";
+        const EXPECTED: &'static str = "media types";
+
+        fn parse(value: &str) -> Option<Self> {
+            Some(Self(value.into()))
+        }
+    }
+
+    /// Answers in plain text unless asked for json.
+    #[derive(Debug, Clone)]
+    struct Greeting;
+
+    #[async_trait::async_trait]
+    impl Endpoint for Greeting {
+        type Request = bool;
+        type Response = (bool, String);
+        type Error = Infallible;
+
+        async fn handle(
+            &self,
+            _ctx: &crate::Context,
+            json: Self::Request,
+        ) -> Result<Self::Response, Self::Error> {
+            Ok((json, "hello there, ".repeat(16)))
+        }
+    }
+
+    impl HttpEndpoint for Greeting {
+        const METHOD: Method = Method::Get;
+        const PATH: &'static str = "/greeting";
+        const VARY: &'static [HeaderName] = &[header::ACCEPT];
+
+        type HttpRequest = (OptionalHeader<Accept>,);
+
+        fn request(
+            (OptionalHeader(accept),): Self::HttpRequest,
+        ) -> Result<Self::Request, Self::Error> {
+            Ok(accept
+                .map(|Accept(accept)| accept.contains("application/json"))
+                .unwrap_or_default())
+        }
+
+        fn response((json, greeting): Self::Response) -> axum::response::Response {
+            if json {
+                Json(serde_json::json!({ "greeting": greeting })).into_response()
+            } else {
+                greeting.into_response()
+            }
+        }
+    }
+
+    fn app() -> axum::Router {
+        crate::utils::compression::layer(endpoint_app(Greeting), &[])
+    }
+
+    crate::table_tests! {
+        vary tokio,
+        (headers, expected_encoding, expected_vary),
+        {
+            let mut req = http::Request::builder().method("GET").uri("/greeting");
+            for (name, val) in headers {
+                req = req.header(name, val);
+            }
+            let resp = app()
+                .oneshot(req.body(Default::default()).unwrap_or_log())
+                .await
+                .unwrap_or_log();
+            assert_eq!(resp.status(), StatusCode::OK);
+            let expected_encoding: Option<&str> = expected_encoding;
+            assert_eq!(
+                resp.headers()
+                    .get(header::CONTENT_ENCODING)
+                    .map(|val| val.to_str().unwrap()),
+                expected_encoding
+            );
+            assert_eq!(resp.headers()[header::VARY], expected_vary);
+        },
+    }
+
+    vary! {
+        negotiated_content_varies_by_accept: (
+            [(header::ACCEPT, "application/json")],
+            None,
+            "accept, accept-encoding",
+        ),
+        compressed_content_varies_by_accept_encoding: (
+            [(header::ACCEPT_ENCODING, "gzip")],
+            Some("gzip"),
+            "accept, accept-encoding",
+        ),
+    }
+
+    #[test]
+    fn merges_with_existing() {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::VARY, HeaderValue::from_static("Origin"));
+        add_vary(
+            &mut headers,
+            &[header::ACCEPT, header::ORIGIN, header::AUTHORIZATION],
+        );
+        assert_eq!(headers[header::VARY], "origin, accept, authorization");
+
+        headers.insert(header::VARY, HeaderValue::from_static("*"));
+        add_vary(&mut headers, &[header::ACCEPT]);
+        assert_eq!(headers[header::VARY], "*");
+    }
+}
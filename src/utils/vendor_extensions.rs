@@ -0,0 +1,47 @@
+//! utoipa 2 can't put `x-` fields on an [`openapi::path::Operation`](utoipa::openapi::path::Operation).
+//! [`DocumentedEndpoint::extensions`](crate::DocumentedEndpoint::extensions) are
+//! gathered in the [`OperationExtras`](crate::OperationExtras) of the
+//! [`SpecPaths`](crate::SpecPaths) instead and added by [`expand`] once the spec
+//! is serialized.
+
+use deps::*;
+
+/// The extensions as the fields of the operation object. Panics if any of the
+/// keys lacks the `x-` prefix.
+pub fn fields(
+    op_id: &str,
+    extensions: Vec<(&'static str, serde_json::Value)>,
+) -> serde_json::Map<String, serde_json::Value> {
+    extensions
+        .into_iter()
+        .map(|(key, val)| {
+            assert!(
+                key.starts_with("x-"),
+                "{op_id} has vendor extension {key:?} not prefixed with `x-`"
+            );
+            (key.to_string(), val)
+        })
+        .collect()
+}
+
+/// Adds the extensions from the `extras` to every operation in the spec, looked up
+/// by its `operationId`.
+pub fn expand(spec: &mut serde_json::Value, extras: &crate::SpecExtras) {
+    let operations = spec
+        .get_mut("paths")
+        .and_then(|paths| paths.as_object_mut())
+        .into_iter()
+        .flat_map(|paths| paths.values_mut())
+        .filter_map(|item| item.as_object_mut())
+        .flat_map(|item| item.values_mut())
+        .filter_map(|op| op.as_object_mut());
+    for op in operations {
+        let extras = op
+            .get("operationId")
+            .and_then(|id| id.as_str())
+            .and_then(|id| extras.get(id));
+        if let Some(extras) = extras {
+            op.extend(extras.extensions.clone());
+        }
+    }
+}